@@ -0,0 +1,30 @@
+use std::process::ExitCode;
+
+use executor::{
+    bench_util::{self, Workload},
+    test_util::CountingAllocator,
+    Executor,
+};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator::new();
+
+const ITERATIONS: usize = 10_000;
+
+fn main() -> ExitCode {
+    bench_util::count_allocations();
+    let mut regressed = false;
+    for workload in Workload::ALL {
+        // A fresh executor each, so that none runs on another's warmed-up
+        // queues.
+        let executor = Executor::new();
+        let result = bench_util::run(workload, &executor, ITERATIONS);
+        println!("{result}");
+        regressed |= !result.regressions().is_empty();
+    }
+    if regressed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+use executor::{Executor, Handle, TimerFuture};
+
+/// Pretend to fetch a page, slower for longer ones.
+async fn fetch(page: u64) -> usize {
+    TimerFuture::new(Duration::from_millis(10 * page)).await;
+    println!("fetched page {page}");
+    page as usize * 100
+}
+
+/// Fetch every page in a subtask of its own, then add up their sizes.
+async fn crawl(pages: u64) -> usize {
+    let handles = Handle::current().spawn_all((1..=pages).map(fetch));
+    let mut total = 0;
+    for handle in handles {
+        total += handle.await.unwrap();
+    }
+    total
+}
+
+fn main() {
+    let executor = Executor::new();
+    let totals = executor.block_on_all([crawl(3), crawl(5)]);
+    println!("crawled {} and {} bytes", totals[0], totals[1]);
+}
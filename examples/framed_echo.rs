@@ -0,0 +1,39 @@
+use executor::{
+    codec::LengthDelimited,
+    io::{self, DuplexStream},
+    sink::SinkExt,
+    stream::StreamExt,
+    Executor,
+};
+
+/// Send every frame back until the client goes away.
+async fn echo(transport: DuplexStream) -> std::io::Result<()> {
+    let mut frames = LengthDelimited::new().framed(transport);
+    while let Some(frame) = frames.next().await {
+        frames.send(frame?).await?;
+    }
+    Ok(())
+}
+
+async fn client(transport: DuplexStream) -> std::io::Result<()> {
+    let mut frames = LengthDelimited::new().framed(transport);
+    for message in ["hello", "world"] {
+        frames.send(message.as_bytes().to_vec()).await?;
+        let reply = frames.next().await.expect("server hung up")?;
+        println!("echoed {:?}", String::from_utf8_lossy(&reply));
+    }
+    Ok(())
+}
+
+fn main() {
+    let executor = Executor::new();
+    // An in-memory pipe stands in for a socket.
+    let (server_end, client_end) = io::duplex(64);
+    let server = executor.spawn(echo(server_end));
+    executor.spawn(async move {
+        client(client_end).await.unwrap();
+        server.await.unwrap().unwrap();
+        println!("server done");
+    });
+    executor.run();
+}
@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+use executor::{Executor, TimerFuture};
+
+async fn hello(i: u64) {
+    println!("Hello {i}!");
+    world(i).await
+}
+
+async fn world(i: u64) {
+    println!("waiting {i}");
+    TimerFuture::new(Duration::from_secs(i)).await;
+    println!("World {i}!");
+}
+
+fn main() {
+    let executor = Executor::new();
+    executor.spawn(hello(10));
+    executor.spawn(hello(5));
+
+    executor.spawn(hello(2));
+
+    executor.spawn(hello(1));
+
+    executor.run();
+}
@@ -0,0 +1,127 @@
+use std::{
+    cell::Cell,
+    sync::{atomic::Ordering, PoisonError, RwLock},
+};
+
+use crate::task::{TaskCounters, TaskId};
+
+type HookFn = dyn Fn(TaskId, isize) + Send + Sync;
+
+/// Set by `set`.
+static HOOK: RwLock<Option<Box<HookFn>>> = RwLock::new(None);
+
+thread_local! {
+    /// The task being polled on this thread with `Builder::track_task_memory`,
+    /// with its counters. A raw pointer rather than an `Arc`, as nothing in
+    /// here may need a destructor: allocating from a global allocator must
+    /// work at any point of a thread's life.
+    static CURRENT: Cell<Option<(TaskId, *const TaskCounters)>> = const { Cell::new(None) };
+
+    /// Set while the hook runs, so what it allocates is not reported to it.
+    static IN_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Attribute what is allocated on this thread to `id` from now on, returning
+/// the attribution to restore once its poll returned.
+///
+/// `counters` must stay alive until then.
+pub(crate) fn enter(id: TaskId, counters: &TaskCounters) -> Option<(TaskId, *const TaskCounters)> {
+    CURRENT.with(|current| current.replace(Some((id, counters))))
+}
+
+pub(crate) fn restore(previous: Option<(TaskId, *const TaskCounters)>) {
+    CURRENT.with(|current| current.set(previous));
+}
+
+/// Report `delta` bytes allocated, or freed when negative, by this thread.
+///
+/// Meant to be called by a global allocator shim on every allocation and
+/// deallocation, such as `test_util::CountingAllocator`: within a poll of a
+/// task of an executor built with `Builder::track_task_memory`, the bytes
+/// count towards its `TaskStats`. They are then handed to the hook of `set`
+/// along with the task's ID, or with `TaskId::NONE` outside of such polls.
+///
+/// Never allocates itself.
+pub fn record(delta: isize) {
+    // Gone while the thread exits.
+    let Ok(current) = CURRENT.try_with(Cell::get) else {
+        return;
+    };
+    let id = match current {
+        Some((id, counters)) => {
+            // SAFETY: only set for the duration of the task's poll, during
+            // which the executor holds on to the task and its counters.
+            let counters = unsafe { &*counters };
+            if delta >= 0 {
+                counters
+                    .allocated
+                    .fetch_add(delta as u64, Ordering::Relaxed);
+            } else {
+                counters
+                    .freed
+                    .fetch_add(delta.unsigned_abs() as u64, Ordering::Relaxed);
+            }
+            id
+        }
+        None => TaskId::NONE,
+    };
+    if IN_HOOK.try_with(|in_hook| in_hook.replace(true)) != Ok(false) {
+        return;
+    }
+    // Skipped while `set` swaps the hook, rather than deadlocking on the
+    // old one being freed.
+    if let Ok(hook) = HOOK.try_read() {
+        if let Some(hook) = &*hook {
+            hook(id, delta);
+        }
+    }
+    IN_HOOK.with(|in_hook| in_hook.set(false));
+}
+
+/// Install `hook`, replacing any previous one, to be called with every
+/// `record`ed allocation and the task it is attributed to, e.g. to keep
+/// per-task totals of your own.
+///
+/// What the hook itself allocates is not reported to it.
+pub fn set(hook: impl Fn(TaskId, isize) + Send + Sync + 'static) {
+    let hook: Box<HookFn> = Box::new(hook);
+    *HOOK.write().unwrap_or_else(PoisonError::into_inner) = Some(hook);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{hint::black_box, sync::Arc};
+
+    use crate::{sync::Event, Builder, Handle};
+
+    #[test]
+    fn a_snapshot_attributes_a_large_allocation_to_its_task() {
+        const MB: u64 = 1 << 20;
+        let executor = Builder::new().track_task_memory(true).build();
+        let handle = executor.handle();
+        let done = Arc::new(Event::new());
+        let kept = done.clone();
+        drop(handle.spawn_named("builder", async move {
+            let buffer = black_box(vec![1u8; MB as usize]);
+            kept.wait().await;
+            drop(buffer);
+        }));
+        let kept = done.clone();
+        drop(handle.spawn_named("idler", async move { kept.wait().await }));
+        let snapshot = executor.spawn(async move {
+            let snapshot = Handle::current().snapshot();
+            done.set();
+            snapshot
+        });
+        executor.run();
+
+        let snapshot = snapshot.join_blocking().unwrap();
+        let allocated = |name: &str| {
+            let task = snapshot.tasks.iter();
+            let mut task = task.filter(|task| task.name.as_deref() == Some(name));
+            task.next().unwrap().stats.unwrap().allocated
+        };
+        assert!((MB..MB + MB / 8).contains(&allocated("builder")));
+        assert!(allocated("idler") < MB / 64);
+    }
+}
@@ -0,0 +1,392 @@
+use std::{
+    fmt::{self, Display},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{counters, stream::StreamExt, sync::Event, test_util, Executor, TimerFuture};
+
+/* Counts */
+/// Operations an executor performed, counted rather than timed, so that they
+/// can be checked against baselines without flaking on a loaded machine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpCounts {
+    pub spawns: u64,
+    pub polls: u64,
+    pub wakes: u64,
+    /// Timers fired by the executor's timer thread.
+    pub timers_fired: u64,
+    /// Allocations by the thread running the workload, only counted once
+    /// `count_allocations` was called with `test_util::CountingAllocator` as
+    /// the global allocator: those of the timer thread are left out.
+    pub allocations: u64,
+    /// Wakers cloned by the scheduler and the primitives, on the thread
+    /// running the workload.
+    pub waker_clones: u64,
+    /// Locks taken by the scheduler and the primitives, on the thread
+    /// running the workload.
+    pub lock_acquisitions: u64,
+}
+impl OpCounts {
+    fn of(executor: &Executor) -> Self {
+        let metrics = executor.metrics();
+        OpCounts {
+            spawns: metrics.tasks_spawned,
+            polls: metrics.polls_total,
+            wakes: metrics.wakes_same_thread + metrics.wakes_external,
+            timers_fired: executor.snapshot().timers.fired_total,
+            allocations: test_util::thread_allocations(),
+            waker_clones: counters::waker_clones(),
+            lock_acquisitions: counters::lock_acquisitions(),
+        }
+    }
+    fn since(self, earlier: OpCounts) -> Self {
+        OpCounts {
+            spawns: self.spawns - earlier.spawns,
+            polls: self.polls - earlier.polls,
+            wakes: self.wakes - earlier.wakes,
+            timers_fired: self.timers_fired - earlier.timers_fired,
+            allocations: self.allocations - earlier.allocations,
+            waker_clones: self.waker_clones - earlier.waker_clones,
+            lock_acquisitions: self.lock_acquisitions - earlier.lock_acquisitions,
+        }
+    }
+    fn fields(&self) -> [(&'static str, u64); 7] {
+        [
+            ("spawns", self.spawns),
+            ("polls", self.polls),
+            ("wakes", self.wakes),
+            ("timers_fired", self.timers_fired),
+            ("allocations", self.allocations),
+            ("waker_clones", self.waker_clones),
+            ("lock_acquisitions", self.lock_acquisitions),
+        ]
+    }
+    /// The counters above those of `baseline` times `iterations` plus those
+    /// of `setup`, by name.
+    fn exceeding(
+        &self,
+        baseline: &OpCounts,
+        setup: &OpCounts,
+        iterations: u64,
+    ) -> Vec<&'static str> {
+        self.fields()
+            .into_iter()
+            .zip(baseline.fields().into_iter().zip(setup.fields()))
+            .filter(|&((_, count), ((_, per_iteration), (_, setup)))| {
+                count > per_iteration * iterations + setup
+            })
+            .map(|((name, _), _)| name)
+            .collect()
+    }
+}
+
+/// Count allocations into `OpCounts::allocations` from now on, replacing any
+/// hook installed with `alloc_hooks::set`.
+pub fn count_allocations() {
+    test_util::count_thread_allocations();
+}
+
+/* Workloads */
+/// What `run` measures, each iteration being one unit of the work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Workload {
+    /// Spawn tasks which return at once, then run them.
+    Spawn,
+    /// Two tasks signalling each other in turn through `Event`s, an
+    /// iteration being a round trip.
+    PingPong,
+    /// Tasks each sleeping on a timer of a millisecond.
+    Timers,
+    /// Tasks waiting on one `Event`, woken at once by setting it.
+    WakeFanOut,
+    /// A task sending progress reports through a channel of one report,
+    /// and another receiving them, an iteration being a report.
+    Channel,
+}
+impl Workload {
+    pub const ALL: [Workload; 5] = [
+        Workload::Spawn,
+        Workload::PingPong,
+        Workload::Timers,
+        Workload::WakeFanOut,
+        Workload::Channel,
+    ];
+    /// The most of each operation an iteration may take, as recorded when
+    /// the workload was added, rounded up; changes to the scheduler or the
+    /// primitives which need more fail `BenchResult::regressions`.
+    pub const fn baseline(self) -> OpCounts {
+        match self {
+            Workload::Spawn => BASELINE_SPAWN,
+            Workload::PingPong => BASELINE_PING_PONG,
+            Workload::Timers => BASELINE_TIMERS,
+            Workload::WakeFanOut => BASELINE_WAKE_FAN_OUT,
+            Workload::Channel => BASELINE_CHANNEL,
+        }
+    }
+    /// The most of each operation a run may take on top of its iterations,
+    /// for the workload's set-up: e.g. the task setting the `Event` of
+    /// `Workload::WakeFanOut`, or the executor's queues growing.
+    pub const fn setup(self) -> OpCounts {
+        match self {
+            Workload::Spawn => SETUP_SPAWN,
+            Workload::PingPong => SETUP_PING_PONG,
+            Workload::Timers => SETUP_TIMERS,
+            Workload::WakeFanOut => SETUP_WAKE_FAN_OUT,
+            Workload::Channel => SETUP_CHANNEL,
+        }
+    }
+    fn drive(self, executor: &Executor, iterations: usize) {
+        match self {
+            Workload::Spawn => {
+                for _ in 0..iterations {
+                    drop(executor.spawn(async {}));
+                }
+                executor.run();
+            }
+            Workload::PingPong => {
+                let ping = Arc::new(Event::new());
+                let pong = Arc::new(Event::new());
+                let (ping2, pong2) = (ping.clone(), pong.clone());
+                drop(executor.spawn(async move {
+                    for _ in 0..iterations {
+                        ping2.wait().await;
+                        ping2.reset();
+                        pong2.set();
+                    }
+                }));
+                drop(executor.spawn(async move {
+                    for _ in 0..iterations {
+                        ping.set();
+                        pong.wait().await;
+                        pong.reset();
+                    }
+                }));
+                executor.run();
+            }
+            Workload::Timers => {
+                for _ in 0..iterations {
+                    // Created on the first poll, so that none is due by then.
+                    drop(
+                        executor.spawn(async { TimerFuture::new(Duration::from_millis(1)).await }),
+                    );
+                }
+                executor.run();
+            }
+            Workload::WakeFanOut => {
+                let event = Arc::new(Event::new());
+                for _ in 0..iterations {
+                    let event = event.clone();
+                    drop(executor.spawn(async move { event.wait().await }));
+                }
+                // Every waiter waits before the event is set.
+                let setter = event.clone();
+                drop(executor.spawn(async move { setter.set() }));
+                executor.run();
+            }
+            Workload::Channel => {
+                let (sender, mut receiver) =
+                    executor
+                        .handle()
+                        .spawn_with_bounded_progress(1, |sender| async move {
+                            for report in 0..iterations {
+                                sender.send(report).await.unwrap();
+                            }
+                        });
+                drop(sender);
+                drop(executor.spawn(async move { while receiver.next().await.is_some() {} }));
+                executor.run();
+            }
+        }
+    }
+}
+impl Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Workload::Spawn => "spawn",
+            Workload::PingPong => "ping_pong",
+            Workload::Timers => "timers",
+            Workload::WakeFanOut => "wake_fan_out",
+            Workload::Channel => "channel",
+        })
+    }
+}
+
+const BASELINE_SPAWN: OpCounts = OpCounts {
+    spawns: 1,
+    polls: 1,
+    wakes: 0,
+    timers_fired: 0,
+    allocations: 1,
+    waker_clones: 0,
+    lock_acquisitions: 10,
+};
+const BASELINE_PING_PONG: OpCounts = OpCounts {
+    spawns: 0,
+    polls: 2,
+    wakes: 2,
+    timers_fired: 0,
+    allocations: 8,
+    waker_clones: 2,
+    lock_acquisitions: 20,
+};
+const BASELINE_TIMERS: OpCounts = OpCounts {
+    spawns: 1,
+    polls: 2,
+    wakes: 1,
+    timers_fired: 1,
+    allocations: 5,
+    waker_clones: 1,
+    lock_acquisitions: 24,
+};
+const BASELINE_WAKE_FAN_OUT: OpCounts = OpCounts {
+    spawns: 1,
+    polls: 2,
+    wakes: 1,
+    timers_fired: 0,
+    allocations: 3,
+    waker_clones: 1,
+    lock_acquisitions: 16,
+};
+const BASELINE_CHANNEL: OpCounts = OpCounts {
+    spawns: 0,
+    polls: 2,
+    wakes: 2,
+    timers_fired: 0,
+    allocations: 1,
+    waker_clones: 2,
+    lock_acquisitions: 14,
+};
+const SETUP_SPAWN: OpCounts = OpCounts {
+    spawns: 0,
+    polls: 0,
+    wakes: 0,
+    timers_fired: 0,
+    allocations: 32,
+    waker_clones: 0,
+    lock_acquisitions: 16,
+};
+const SETUP_PING_PONG: OpCounts = OpCounts {
+    spawns: 2,
+    polls: 2,
+    wakes: 0,
+    timers_fired: 0,
+    allocations: 48,
+    waker_clones: 0,
+    lock_acquisitions: 40,
+};
+const SETUP_TIMERS: OpCounts = OpCounts {
+    spawns: 0,
+    polls: 0,
+    wakes: 0,
+    timers_fired: 0,
+    allocations: 32,
+    waker_clones: 0,
+    lock_acquisitions: 16,
+};
+const SETUP_WAKE_FAN_OUT: OpCounts = OpCounts {
+    spawns: 1,
+    polls: 1,
+    wakes: 0,
+    timers_fired: 0,
+    allocations: 40,
+    waker_clones: 0,
+    lock_acquisitions: 32,
+};
+const SETUP_CHANNEL: OpCounts = OpCounts {
+    spawns: 2,
+    polls: 2,
+    wakes: 0,
+    timers_fired: 0,
+    allocations: 40,
+    waker_clones: 0,
+    lock_acquisitions: 40,
+};
+
+/* Run */
+/// Run `iterations` of `workload` on `executor`, counting what it took.
+///
+/// The executor must have nothing else to run, or its work is counted too.
+pub fn run(workload: Workload, executor: &Executor, iterations: usize) -> BenchResult {
+    let before = OpCounts::of(executor);
+    let started = Instant::now();
+    workload.drive(executor, iterations);
+    let elapsed = started.elapsed();
+    BenchResult {
+        workload,
+        iterations: iterations as u64,
+        elapsed,
+        ops: OpCounts::of(executor).since(before),
+    }
+}
+
+/// What one `run` measured.
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    pub workload: Workload,
+    pub iterations: u64,
+    /// Wall-clock time, for information only.
+    pub elapsed: Duration,
+    pub ops: OpCounts,
+}
+impl BenchResult {
+    /// The counters of `ops` above the workload's baseline, by name.
+    pub fn regressions(&self) -> Vec<&'static str> {
+        self.ops.exceeding(
+            &self.workload.baseline(),
+            &self.workload.setup(),
+            self.iterations,
+        )
+    }
+}
+impl Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let per = |count: u64| count as f64 / self.iterations.max(1) as f64;
+        write!(
+            f,
+            "{}: {} iterations in {:?}, per iteration: {:.2} spawns, {:.2} polls, \
+             {:.2} wakes, {:.2} timers fired, {:.2} allocations, {:.2} waker clones, \
+             {:.2} lock acquisitions",
+            self.workload,
+            self.iterations,
+            self.elapsed,
+            per(self.ops.spawns),
+            per(self.ops.polls),
+            per(self.ops.wakes),
+            per(self.ops.timers_fired),
+            per(self.ops.allocations),
+            per(self.ops.waker_clones),
+            per(self.ops.lock_acquisitions),
+        )?;
+        let regressions = self.regressions();
+        if !regressions.is_empty() {
+            write!(f, " (above baseline: {})", regressions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workloads_stay_within_their_baselines() {
+        const ITERATIONS: usize = 1000;
+        count_allocations();
+        for workload in Workload::ALL {
+            let result = run(workload, &Executor::new(), ITERATIONS);
+            assert!(result.regressions().is_empty(), "{result}");
+        }
+    }
+
+    #[test]
+    fn a_waker_clone_per_poll_is_a_regression() {
+        count_allocations();
+        for workload in Workload::ALL {
+            let mut result = run(workload, &Executor::new(), 1000);
+            result.ops.waker_clones += result.ops.polls;
+            assert_eq!(result.regressions(), ["waker_clones"], "{result}");
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+};
+
+use crate::{counters::clone_waker, lock, task::tag_wakes};
+
+/// A queued closure, sharing its allocation with its result.
+type Job = Arc<dyn RunJob>;
+
+trait RunJob: Send + Sync {
+    fn run(&self);
+}
+
+/// Threads running the closures given to `Handle::spawn_blocking`, so they
+/// do not hold up the executor thread.
+///
+/// Threads are started as jobs come in, up to `max_threads`; past that, jobs
+/// wait for one to be free. A thread left idle for `keep_alive` exits.
+pub(crate) struct BlockingPool {
+    max_threads: usize,
+    keep_alive: Duration,
+    state: Arc<(Mutex<PoolState>, Condvar)>,
+}
+
+struct PoolState {
+    queue: VecDeque<Job>,
+    threads: usize,
+    /// Threads waiting for a job.
+    idle: usize,
+}
+
+impl BlockingPool {
+    pub(crate) fn new(max_threads: usize, keep_alive: Duration) -> Self {
+        BlockingPool {
+            max_threads,
+            keep_alive,
+            state: Arc::new((
+                Mutex::new(PoolState {
+                    queue: VecDeque::new(),
+                    threads: 0,
+                    idle: 0,
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+    /// Run `f` on a pool thread, returning a future of its result, which
+    /// resumes its panic if it panicked.
+    ///
+    /// The closure, its result and the waker waiting for it share a single
+    /// allocation.
+    pub(crate) fn run<F, T>(&self, f: F) -> BlockingJoin<F, T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let job = Arc::new(BlockingJob {
+            state: Mutex::new(JobState {
+                f: Some(f),
+                output: None,
+                waker: None,
+            }),
+        });
+        self.submit(job.clone());
+        BlockingJoin { job }
+    }
+    /// Whether a closure is running or waiting for a thread.
+    pub(crate) fn busy(&self) -> bool {
+        let pool = lock(&self.state.0);
+        !pool.queue.is_empty() || pool.threads > pool.idle
+    }
+    fn submit(&self, job: Job) {
+        let (state, job_ready) = &*self.state;
+        let mut pool = lock(state);
+        pool.queue.push_back(job);
+        if pool.idle > 0 {
+            job_ready.notify_one();
+            return;
+        }
+        if pool.threads >= self.max_threads {
+            return;
+        }
+        pool.threads += 1;
+        drop(pool);
+        let shared = self.state.clone();
+        let keep_alive = self.keep_alive;
+        thread::Builder::new()
+            .name("executor-blocking".to_string())
+            .spawn(move || work(&shared, keep_alive))
+            .expect("failed to spawn a blocking pool thread");
+    }
+    /// Number of pool threads, and of jobs waiting for one.
+    pub(crate) fn load(&self) -> (usize, usize) {
+        let pool = lock(&self.state.0);
+        (pool.threads, pool.queue.len())
+    }
+}
+
+/// Body of a pool thread: run jobs until none came for `keep_alive`.
+fn work(shared: &(Mutex<PoolState>, Condvar), keep_alive: Duration) {
+    let (state, job_ready) = shared;
+    let mut pool = lock(state);
+    loop {
+        if let Some(job) = pool.queue.pop_front() {
+            drop(pool);
+            job.run();
+            pool = lock(state);
+            continue;
+        }
+        pool.idle += 1;
+        let (guard, wait) = job_ready
+            .wait_timeout_while(pool, keep_alive, |pool| pool.queue.is_empty())
+            .unwrap_or_else(PoisonError::into_inner);
+        pool = guard;
+        pool.idle -= 1;
+        if wait.timed_out() {
+            pool.threads -= 1;
+            return;
+        }
+    }
+}
+
+struct BlockingJob<F, T> {
+    state: Mutex<JobState<F, T>>,
+}
+
+struct JobState<F, T> {
+    /// Taken by the pool thread to run it.
+    f: Option<F>,
+    /// The closure's result, or its panic.
+    output: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<F, T> RunJob for BlockingJob<F, T>
+where
+    F: FnOnce() -> T + Send,
+    T: Send,
+{
+    fn run(&self) {
+        let Some(f) = lock(&self.state).f.take() else {
+            return;
+        };
+        // Run without the lock, so polling the result does not block.
+        let output = panic::catch_unwind(AssertUnwindSafe(f));
+        let waker = {
+            let mut state = lock(&self.state);
+            state.output = Some(output);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            tag_wakes("spawn_blocking", || waker.wake());
+        }
+    }
+}
+
+/// Future of a job's result, see `BlockingPool::run`.
+pub(crate) struct BlockingJoin<F, T> {
+    job: Arc<BlockingJob<F, T>>,
+}
+impl<F, T> Future for BlockingJoin<F, T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.job.state);
+        match state.output.take() {
+            Some(Ok(output)) => Poll::Ready(output),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                // The same task polling again keeps the waker it left.
+                if !state
+                    .waker
+                    .as_ref()
+                    .is_some_and(|w| w.will_wake(cx.waker()))
+                {
+                    state.waker = Some(clone_waker(cx.waker()));
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Barrier,
+        },
+    };
+
+    use super::*;
+    use crate::{
+        assert_pending,
+        test_util::{allocations_during, thread_allocations, MockTask},
+        Builder,
+    };
+
+    #[test]
+    fn pool_stays_within_its_cap_then_shrinks() {
+        let executor = Builder::new()
+            .max_blocking_threads(8)
+            .blocking_thread_keep_alive(Duration::from_millis(50))
+            .build();
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let handle = executor.handle();
+        let jobs: Vec<_> = (0..100)
+            .map(|i| {
+                let (running, peak) = (running.clone(), peak.clone());
+                handle.spawn_blocking(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(50));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                })
+            })
+            .collect();
+        let metrics = executor.metrics();
+        assert_eq!(metrics.blocking_threads, 8);
+        assert!(metrics.blocking_queue_depth >= 90);
+
+        executor.run();
+        let outputs: Vec<_> = jobs
+            .into_iter()
+            .map(|job| job.join_blocking().unwrap())
+            .collect();
+        assert_eq!(outputs, (0..100).collect::<Vec<_>>());
+        assert_eq!(peak.load(Ordering::SeqCst), 8);
+
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(executor.metrics().blocking_threads, 0);
+    }
+
+    #[test]
+    fn a_job_takes_a_single_allocation() {
+        const JOBS: u64 = 10_000;
+        let pool = BlockingPool::new(1, Duration::from_secs(10));
+        let barrier = Arc::new(Barrier::new(2));
+        let ((first, mut jobs, last), submitting) = allocations_during(|| {
+            let barrier = barrier.clone();
+            // Holds the pool thread until every job has a waker to wake.
+            let first = pool.run(move || {
+                barrier.wait();
+                thread_allocations()
+            });
+            let jobs: Vec<_> = (0..JOBS).map(|_| pool.run(|| ())).collect();
+            (first, jobs, pool.run(thread_allocations))
+        });
+        let mut task = MockTask::new();
+        let (mut first, mut last) = (pin!(first), pin!(last));
+        for job in &mut jobs {
+            assert_pending!(task.poll(Pin::new(job)));
+        }
+        assert_pending!(task.poll(first.as_mut()));
+        barrier.wait();
+        let running = loop {
+            if let Poll::Ready(last) = task.poll(last.as_mut()) {
+                let Poll::Ready(first) = task.poll(first.as_mut()) else {
+                    unreachable!("ran before the last job");
+                };
+                break last - first;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        // Beyond the jobs: the pool thread, and the queue and `jobs` growing.
+        assert!(submitting <= JOBS + 64, "{submitting} allocations");
+        assert_eq!(running, 0);
+        assert!(jobs
+            .iter()
+            .all(|job| job.job.state.lock().unwrap().output.is_some()));
+    }
+}
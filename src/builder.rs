@@ -0,0 +1,534 @@
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    blocking::BlockingPool,
+    chaos::{Chaos, ChaosConfig},
+    leak::LeakReport,
+    permit::Permits,
+    scheduler::{Aging, FifoScheduler, Scheduler, SeededScheduler, WakePlacement, WakeRouter},
+    task::{self, TaskMeta},
+    time::TimerDriver,
+    Admission, Executor, Inner, LeakHookFn,
+};
+
+/// Configures and creates an `Executor`.
+#[derive(Default)]
+pub struct Builder {
+    scheduler: Option<Box<dyn Scheduler + Send + Sync>>,
+    hooks: Hooks,
+    chaos: Option<ChaosConfig>,
+    max_block_in_task_depth: Option<usize>,
+    capture_spawn_backtraces: bool,
+    task_stats: bool,
+    track_task_memory: bool,
+    record_wakes: bool,
+    detect_lost_wakes: bool,
+    panic_behavior: PanicBehavior,
+    max_schedule_age: Option<usize>,
+    wake_placement: WakePlacement,
+    max_concurrent_tasks: Option<usize>,
+    spawn_high_water_mark: Option<usize>,
+    max_blocking_threads: Option<usize>,
+    blocking_thread_keep_alive: Option<Duration>,
+    shutdown_grace_period: Duration,
+    defer_timeout: Option<Duration>,
+    leak_hook: Option<Box<LeakHookFn>>,
+    #[cfg(any(test, feature = "test-util"))]
+    start_paused: bool,
+}
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How deeply `Handle::block_in_task` calls may nest within each other
+    /// before panicking; 16 by default.
+    pub fn max_block_in_task_depth(mut self, depth: usize) -> Self {
+        self.max_block_in_task_depth = Some(depth);
+        self
+    }
+
+    /// Record the full backtrace of every spawn, for tracking down where a
+    /// leaked task came from when its `TaskMeta::location` is only a helper
+    /// that spawns on behalf of others. Off by default, since capturing costs
+    /// a stack walk per spawn.
+    pub fn capture_spawn_backtraces(mut self, enabled: bool) -> Self {
+        self.capture_spawn_backtraces = enabled;
+        self
+    }
+
+    /// Keep per-task counts of polls, busy time and queueing time, see
+    /// `TaskMeta::stats`. Off by default, as it reads the clock on every
+    /// wake and poll.
+    pub fn task_stats(mut self, enabled: bool) -> Self {
+        self.task_stats = enabled;
+        self
+    }
+
+    /// Count the bytes each task allocates and frees during its polls, in
+    /// its `TaskStats`, which this turns on too. Off by default.
+    ///
+    /// The executor cannot see allocations by itself: this only works with
+    /// a global allocator calling `alloc_hooks::record`, such as
+    /// `test_util::CountingAllocator`.
+    pub fn track_task_memory(mut self, enabled: bool) -> Self {
+        self.track_task_memory = enabled;
+        self
+    }
+
+    /// Keep a record of each task's latest wakes, with where they came from,
+    /// see `JoinHandle::recent_wakes`: handy to find out who wakes a task far
+    /// more often than expected. Off by default.
+    ///
+    /// Only tasks of this executor keep a record, but once one executor is
+    /// built with it, the crate's primitives label their wakes (see
+    /// `task::tag_wakes`) for every executor of the process: a thread-local
+    /// write per wake, which executors without it would otherwise skip.
+    pub fn record_wakes(mut self, enabled: bool) -> Self {
+        self.record_wakes = enabled;
+        self
+    }
+
+    /// Report a task to the error handler (see `set_error_handler`), once,
+    /// when a poll returns `Pending` without the future waking its task or
+    /// keeping a clone of its waker, so nothing could ever wake it: a likely
+    /// bug in a hand-written future. Off by default, as every task gets a
+    /// waker of its own to track.
+    pub fn detect_lost_wakes(mut self, enabled: bool) -> Self {
+        self.detect_lost_wakes = enabled;
+        self
+    }
+
+    /// What to do when a task panics; `PanicBehavior::Isolate` by default.
+    pub fn panic_behavior(mut self, behavior: PanicBehavior) -> Self {
+        self.panic_behavior = behavior;
+        self
+    }
+
+    /// Let at most `limit` tasks run at a time. Tasks spawned beyond that are
+    /// held back, unpolled, until one of those running finishes; `spawn`
+    /// still returns their `JoinHandle` right away, and aborting one drops it
+    /// without it ever running.
+    ///
+    /// Panics if `limit` is zero.
+    pub fn max_concurrent_tasks(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "at least one task must be allowed to run");
+        self.max_concurrent_tasks = Some(limit);
+        self
+    }
+
+    /// Have `Handle::spawn_permit` wait while `mark` tasks or more are queued,
+    /// counting those held back by `max_concurrent_tasks` and the permits
+    /// handed out already. Unlimited by default.
+    ///
+    /// Panics if `mark` is zero.
+    pub fn spawn_high_water_mark(mut self, mark: usize) -> Self {
+        assert!(mark > 0, "a high-water mark of zero would allow no spawn");
+        self.spawn_high_water_mark = Some(mark);
+        self
+    }
+
+    /// How many threads the `Handle::spawn_blocking` pool may grow to;
+    /// closures beyond that wait for a free thread. 512 by default.
+    ///
+    /// Panics if `limit` is zero.
+    pub fn max_blocking_threads(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "the blocking pool needs at least one thread");
+        self.max_blocking_threads = Some(limit);
+        self
+    }
+
+    /// How long a blocking pool thread stays around without work before it
+    /// exits; 10 seconds by default.
+    pub fn blocking_thread_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.blocking_thread_keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// How long tasks may go on once the signal of
+    /// `Executor::run_until_signal` completed, before those still alive are
+    /// aborted; none by default.
+    pub fn shutdown_grace_period(mut self, grace: Duration) -> Self {
+        self.shutdown_grace_period = grace;
+        self
+    }
+
+    /// How long each cleanup registered with `task::defer` may run before
+    /// it is dropped, reported to the error handler; 10 seconds by default.
+    pub fn defer_timeout(mut self, timeout: Duration) -> Self {
+        self.defer_timeout = Some(timeout);
+        self
+    }
+
+    /// Decide which ready task runs next; `FifoScheduler` by default.
+    pub fn scheduler(mut self, scheduler: impl Scheduler + Send + Sync + 'static) -> Self {
+        self.scheduler = Some(Box::new(scheduler));
+        self
+    }
+
+    /// Poll a task at the latest `age` scheduling decisions after it became
+    /// runnable, ahead of whatever the scheduler would pick, so that no
+    /// priority or policy can starve it. Overdue tasks go oldest first. Off
+    /// by default; see `MetricsSnapshot::schedule_age_overrides`.
+    ///
+    /// Panics if `age` is zero.
+    pub fn max_schedule_age(mut self, age: usize) -> Self {
+        assert!(age > 0, "a schedule age of zero would override every pick");
+        self.max_schedule_age = Some(age);
+        self
+    }
+
+    /// Where woken tasks wait to be polled; `WakePlacement::Fifo` by
+    /// default. See `MetricsSnapshot::wakes_same_thread` for how wakes split
+    /// between the two origins.
+    pub fn wake_placement(mut self, placement: WakePlacement) -> Self {
+        self.wake_placement = placement;
+        self
+    }
+
+    /// Pick the next ready task with an RNG seeded by `seed`, so that a
+    /// scheduling order which triggers a bug can be replayed by reusing the
+    /// seed. Shorthand for `scheduler(SeededScheduler::new(seed))`.
+    pub fn deterministic(self, seed: u64) -> Self {
+        self.scheduler(SeededScheduler::new(seed))
+    }
+
+    /// Inject spurious, delayed and duplicated wakes, to shake out futures
+    /// which miss a wakeup or cannot handle being polled for nothing. See
+    /// `Executor::chaos_stats` for what was actually injected.
+    pub fn chaos(mut self, config: ChaosConfig) -> Self {
+        self.chaos = Some(config);
+        self
+    }
+
+    /// Called whenever a task is spawned, on the spawning thread.
+    ///
+    /// Like every hook, it runs synchronously and must be fast. A hook that
+    /// panics is disabled for the rest of the executor's life.
+    pub fn on_task_spawn(mut self, hook: impl Fn(&TaskMeta) + Send + Sync + 'static) -> Self {
+        self.hooks.spawn = Hook::new(hook);
+        self
+    }
+
+    /// Called on the executor thread right before a task is first polled.
+    pub fn on_task_start(mut self, hook: impl Fn(&TaskMeta) + Send + Sync + 'static) -> Self {
+        self.hooks.start = Hook::new(hook);
+        self
+    }
+
+    /// Called on the executor thread once a task is done, whether it completed,
+    /// panicked or was aborted.
+    pub fn on_task_complete(mut self, hook: impl Fn(&TaskMeta) + Send + Sync + 'static) -> Self {
+        self.hooks.complete = Hook::new(hook);
+        self
+    }
+
+    /// Called on the executor thread when a task panics, before
+    /// `on_task_complete`.
+    pub fn on_task_panic(mut self, hook: impl Fn(&TaskMeta) + Send + Sync + 'static) -> Self {
+        self.hooks.panic = Hook::new(hook);
+        self
+    }
+
+    /// Called with the tasks still alive when the executor is shut down or
+    /// dropped, if any. By default, debug builds hand each of them to the
+    /// error handler (see `set_error_handler`) and release builds do
+    /// nothing.
+    pub fn on_leaked_tasks(mut self, hook: impl Fn(&LeakReport) + Send + Sync + 'static) -> Self {
+        self.leak_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Start the executor's clock paused, as `time::pause` would from its
+    /// first task, for tests. Off by default.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn start_paused(mut self, enabled: bool) -> Self {
+        self.start_paused = enabled;
+        self
+    }
+
+    pub fn build(self) -> Executor {
+        if self.record_wakes {
+            task::RECORDING_WAKES.store(true, Ordering::Relaxed);
+        }
+        let timers = Arc::new(TimerDriver::new());
+        #[cfg(any(test, feature = "test-util"))]
+        if self.start_paused {
+            timers.pause();
+        }
+        Executor {
+            inner: Arc::new(Inner {
+                scheduler: self
+                    .scheduler
+                    .unwrap_or_else(|| Box::new(FifoScheduler::new())),
+                aging: self.max_schedule_age.map(|age| Aging::new(age as u64)),
+                router: WakeRouter::new(self.wake_placement),
+                hooks: self.hooks,
+                chaos: self.chaos.map(Chaos::new),
+                metrics: Default::default(),
+                polling: Mutex::new(Vec::new()),
+                max_block_in_task_depth: self.max_block_in_task_depth.unwrap_or(16),
+                capture_spawn_backtraces: self.capture_spawn_backtraces,
+                task_stats: self.task_stats || self.track_task_memory,
+                track_task_memory: self.track_task_memory,
+                record_wakes: self.record_wakes,
+                detect_lost_wakes: self.detect_lost_wakes,
+                panic_behavior: self.panic_behavior,
+                first_panic: Mutex::new(None),
+                admission: self.max_concurrent_tasks.map(Admission::new),
+                permits: self.spawn_high_water_mark.map(Permits::new),
+                limiters: Mutex::new(HashMap::new()),
+                blocking: BlockingPool::new(
+                    self.max_blocking_threads.unwrap_or(512),
+                    self.blocking_thread_keep_alive
+                        .unwrap_or(Duration::from_secs(10)),
+                ),
+                timers,
+                shutdown_grace_period: self.shutdown_grace_period,
+                defer_timeout: self.defer_timeout.unwrap_or(Duration::from_secs(10)),
+                leak_hook: self.leak_hook,
+                tasks: Mutex::new(HashMap::new()),
+                children: Mutex::new(Vec::new()),
+                shut_down: AtomicBool::new(false),
+                alive: AtomicUsize::new(0),
+                unparked: Mutex::new(false),
+                unpark_signal: Condvar::new(),
+            }),
+        }
+    }
+}
+
+/// What the executor does when a task panics, see `Builder::panic_behavior`.
+///
+/// Panics of `spawn_blocking` closures count as panics of their task. A
+/// panicking hook is disabled whatever the behavior, and `Drop` panics of
+/// futures always go to the panic handler.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PanicBehavior {
+    /// Catch the panic and hand it to the task's `JoinHandle`, or to the
+    /// panic handler once the handle is dropped, while the other tasks keep
+    /// running.
+    #[default]
+    Isolate,
+    /// Abort every task and cancel any spawned after, as `Executor::shutdown`
+    /// would. Once they are dropped, `Executor::run` resumes the first panic,
+    /// and `Executor::try_run` returns it.
+    ShutdownRuntime,
+    /// Resume the panic out of `Executor::run` right away. The other tasks
+    /// stay alive, for `run` to go on with if called again.
+    Resume,
+}
+
+/// Task lifecycle callbacks installed through the `Builder`.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    pub(crate) spawn: Hook,
+    pub(crate) start: Hook,
+    pub(crate) complete: Hook,
+    pub(crate) panic: Hook,
+}
+
+type HookFn = dyn Fn(&TaskMeta) + Send + Sync;
+
+#[derive(Default)]
+pub(crate) struct Hook {
+    f: Option<Box<HookFn>>,
+    /// Set once `f` panicked, so a broken hook cannot take the executor down.
+    disabled: AtomicBool,
+}
+impl Hook {
+    fn new(f: impl Fn(&TaskMeta) + Send + Sync + 'static) -> Self {
+        Hook {
+            f: Some(Box::new(f)),
+            disabled: AtomicBool::new(false),
+        }
+    }
+    pub(crate) fn call(&self, meta: &TaskMeta) {
+        let Some(f) = &self.f else {
+            return;
+        };
+        if self.disabled.load(Ordering::Relaxed) {
+            return;
+        }
+        if panic::catch_unwind(AssertUnwindSafe(|| f(meta))).is_err() {
+            self.disabled.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::{sync::Event, TimerFuture};
+
+    async fn hello(i: u64) {
+        world(i).await
+    }
+
+    async fn world(i: u64) {
+        TimerFuture::new(Duration::from_millis(i)).await;
+    }
+
+    fn counter() -> (Arc<AtomicUsize>, impl Fn(&TaskMeta) + Send + Sync + 'static) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counted = count.clone();
+        (count, move |_: &TaskMeta| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    #[test]
+    fn lifecycle_hooks_count_the_hello_workload() {
+        let (spawned, on_spawn) = counter();
+        let (started, on_start) = counter();
+        let (completed, on_complete) = counter();
+        let (panicked, on_panic) = counter();
+        let executor = Builder::new()
+            .on_task_spawn(on_spawn)
+            .on_task_start(on_start)
+            .on_task_complete(on_complete)
+            .on_task_panic(on_panic)
+            .build();
+        for i in [10, 5, 2, 1] {
+            executor.spawn(hello(i));
+        }
+        executor.run();
+        assert_eq!(spawned.load(Ordering::SeqCst), 4);
+        assert_eq!(started.load(Ordering::SeqCst), 4);
+        assert_eq!(completed.load(Ordering::SeqCst), 4);
+        assert_eq!(panicked.load(Ordering::SeqCst), 0);
+
+        let handle = executor.spawn(async { panic!("deliberate") });
+        executor.run();
+        assert!(handle.join_blocking().unwrap_err().is_panic());
+        assert_eq!(panicked.load(Ordering::SeqCst), 1);
+        assert_eq!(completed.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn panicking_hook_is_disabled() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let executor = Builder::new()
+            .on_task_spawn(move |_| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                panic!("broken hook");
+            })
+            .build();
+        let first = executor.spawn(async { 1 });
+        let second = executor.spawn(async { 2 });
+        executor.run();
+        assert_eq!(first.join_blocking().unwrap(), 1);
+        assert_eq!(second.join_blocking().unwrap(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn max_concurrent_tasks_holds_back_the_rest() {
+        let executor = Builder::new().max_concurrent_tasks(3).build();
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..10)
+            .map(|i| {
+                let (running, most) = (running.clone(), most.clone());
+                executor.spawn(async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    TimerFuture::new(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                })
+            })
+            .collect();
+        assert_eq!(executor.metrics().admission_queue_depth, 7);
+        // Aborted while held back, it is never polled.
+        let polled = Arc::new(AtomicUsize::new(0));
+        let aborted = executor.spawn({
+            let polled = polled.clone();
+            async move {
+                polled.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        aborted.abort();
+        executor.run();
+
+        assert_eq!(most.load(Ordering::SeqCst), 3);
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join_blocking().unwrap(), i);
+        }
+        assert!(aborted.join_blocking().unwrap_err().is_cancelled());
+        assert_eq!(polled.load(Ordering::SeqCst), 0);
+        assert_eq!(executor.metrics().admission_queue_depth, 0);
+    }
+
+    fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+        payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or(payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap()
+    }
+
+    #[test]
+    fn isolate_keeps_the_siblings_running() {
+        let executor = Builder::new()
+            .panic_behavior(PanicBehavior::Isolate)
+            .build();
+        let panicked = executor.spawn(async {
+            TimerFuture::new(Duration::from_millis(1)).await;
+            panic!("isolated")
+        });
+        let sibling = executor.spawn(async {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            "sibling"
+        });
+        executor.run();
+        assert!(panicked.join_blocking().unwrap_err().is_panic());
+        assert_eq!(sibling.join_blocking().unwrap(), "sibling");
+        assert_eq!(executor.metrics().tasks_panicked, 1);
+    }
+
+    #[test]
+    fn shutdown_runtime_aborts_the_siblings_and_returns_the_panic() {
+        let executor = Builder::new()
+            .panic_behavior(PanicBehavior::ShutdownRuntime)
+            .build();
+        let sleeper = executor.spawn(TimerFuture::new(Duration::from_secs(10)));
+        executor.spawn(async {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            panic!("fail fast")
+        });
+        let started = Instant::now();
+        let error = executor.try_run().unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(panic_message(&*error.into_panic()), "fail fast");
+        assert!(sleeper.join_blocking().unwrap_err().is_cancelled());
+        assert_eq!(executor.metrics().tasks_panicked, 1);
+    }
+
+    #[test]
+    fn resume_propagates_out_of_run() {
+        let executor = Builder::new().panic_behavior(PanicBehavior::Resume).build();
+        let event = Arc::new(Event::new());
+        let waited = event.clone();
+        let sibling = executor.spawn(async move { waited.wait().await });
+        executor.spawn(async { panic!("resumed") });
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| executor.run())).unwrap_err();
+        assert_eq!(panic_message(&*payload), "resumed");
+        assert_eq!(executor.metrics().tasks_panicked, 1);
+        // The sibling was left alive, for `run` to go on with.
+        event.set();
+        executor.run();
+        assert!(sibling.join_blocking().is_ok());
+    }
+}
@@ -0,0 +1,224 @@
+//! Each cancel-safe async method of the crate, raced against a short timer
+//! in a loop, dropping it whenever the timer wins, must lose nothing.
+//!
+//! Those documented as not cancel safe are left out: `io::copy`,
+//! `io::copy_buf`, `SinkExt::send` and `ProgressSender::send`.
+
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    coop::maybe_yield, io::LineReader, signal::SignalNotifier, stream::StreamExt, sync::Event,
+    time::DelayQueue, Builder, Executor, TimerFuture,
+};
+
+/// How long each attempt gets before it is dropped.
+const ATTEMPT: Duration = Duration::from_micros(200);
+
+/// Await `$future` afresh until it beats a timer of `ATTEMPT`, counting the
+/// attempts dropped into `$dropped`.
+macro_rules! until_ready {
+    ($dropped:ident, $future:expr) => {
+        loop {
+            crate::select! {
+                output = $future => { break output }
+                _ = TimerFuture::new(ATTEMPT) => { $dropped += 1 }
+            }
+        }
+    };
+}
+
+#[test]
+fn event_wait() {
+    let executor = Executor::new();
+    let event = Arc::new(Event::new());
+    let setter = event.clone();
+    executor.spawn(async move {
+        TimerFuture::new(Duration::from_millis(10)).await;
+        setter.set();
+    });
+    let dropped = executor.block_on_all([async move {
+        let mut dropped = 0;
+        until_ready!(dropped, event.wait());
+        dropped
+    }]);
+    assert!(dropped[0] > 0);
+}
+
+#[test]
+fn signal_notifier_notified() {
+    const NOTIFICATIONS: usize = 20;
+    let notifier = Arc::new(SignalNotifier::new().unwrap());
+    let seen = Arc::new(AtomicUsize::new(0));
+    let notifying = {
+        let (notifier, seen) = (notifier.clone(), seen.clone());
+        thread::spawn(move || {
+            for i in 0..NOTIFICATIONS {
+                thread::sleep(Duration::from_millis(1));
+                notifier.notify();
+                // One at a time, for none to coalesce with the next.
+                while seen.load(Ordering::SeqCst) == i {
+                    thread::yield_now();
+                }
+            }
+        })
+    };
+    let executor = Executor::new();
+    let dropped = executor.block_on_all([async move {
+        let mut dropped = 0;
+        for _ in 0..NOTIFICATIONS {
+            until_ready!(dropped, notifier.notified());
+            seen.fetch_add(1, Ordering::SeqCst);
+        }
+        dropped
+    }]);
+    notifying.join().unwrap();
+    assert!(dropped[0] > 0);
+}
+
+#[test]
+fn handle_spawn_permit() {
+    let executor = Builder::new().spawn_high_water_mark(1).build();
+    let handle = executor.handle();
+    let ran = Arc::new(AtomicUsize::new(0));
+    let dropped = executor.block_on_all([{
+        let ran = ran.clone();
+        async move {
+            let mut dropped = 0;
+            for _ in 0..20 {
+                let permit = until_ready!(dropped, handle.spawn_permit());
+                let ran = ran.clone();
+                drop(permit.spawn(async move {
+                    // Ready all along, keeping the queue at the mark.
+                    let started = Instant::now();
+                    while started.elapsed() < Duration::from_millis(1) {
+                        maybe_yield().await;
+                    }
+                    ran.fetch_add(1, Ordering::SeqCst);
+                }));
+            }
+            dropped
+        }
+    }]);
+    executor.run();
+    assert!(dropped[0] > 0);
+    assert_eq!(ran.load(Ordering::SeqCst), 20);
+    assert_eq!(executor.metrics().spawn_permits_reserved, 0);
+}
+
+#[test]
+fn delay_queue_next_expired() {
+    let executor = Executor::new();
+    let received = executor.block_on_all([async {
+        let mut queue = DelayQueue::new();
+        for i in 0..20 {
+            queue.insert(i, Duration::from_millis(1 + (i * 7) % 20));
+        }
+        let (mut dropped, mut received) = (0, Vec::new());
+        while let Some(expired) = until_ready!(dropped, queue.next_expired()) {
+            received.push(expired.into_inner());
+        }
+        assert!(dropped > 0);
+        received
+    }]);
+    let mut received = received.into_iter().next().unwrap();
+    received.sort_unstable();
+    assert_eq!(received, (0..20).collect::<Vec<_>>());
+}
+
+#[test]
+fn stream_next() {
+    let executor = Executor::new();
+    let (_, mut reports) = executor
+        .handle()
+        .spawn_with_bounded_progress(4, |sender| async move {
+            for i in 0..50 {
+                if i % 5 == 0 {
+                    TimerFuture::new(Duration::from_millis(1)).await;
+                }
+                sender.send(i).await.unwrap();
+            }
+        });
+    let received = executor.block_on_all([async move {
+        let (mut dropped, mut received) = (0, Vec::new());
+        while let Some(report) = until_ready!(dropped, reports.next()) {
+            received.push(report);
+        }
+        assert!(dropped > 0);
+        received
+    }]);
+    assert_eq!(received[0], (0..50).collect::<Vec<_>>());
+}
+
+#[test]
+fn line_reader_read_line() {
+    let (reader, mut writer) = io::pipe().unwrap();
+    let writing = thread::spawn(move || {
+        for i in 0..20 {
+            thread::sleep(Duration::from_millis(1));
+            writeln!(writer, "line {i}").unwrap();
+        }
+    });
+    let mut lines = LineReader::new(io::BufReader::new(reader));
+    let executor = Executor::new();
+    let received = executor.block_on_all([async move {
+        let (mut dropped, mut received) = (0, Vec::new());
+        while let Some(line) = until_ready!(dropped, lines.read_line()).unwrap() {
+            received.push(line);
+        }
+        assert!(dropped > 0);
+        received
+    }]);
+    writing.join().unwrap();
+    let expected: Vec<_> = (0..20).map(|i| format!("line {i}")).collect();
+    assert_eq!(received[0], expected);
+}
+
+#[test]
+fn interval_tick() {
+    const PERIOD: Duration = Duration::from_millis(2);
+    let executor = Executor::new();
+    let ticks = executor.block_on_all([async {
+        let mut interval = crate::time::interval(PERIOD);
+        let (mut dropped, mut ticks) = (0, Vec::new());
+        for _ in 0..10 {
+            ticks.push(until_ready!(dropped, interval.tick()));
+        }
+        assert!(dropped > 0);
+        ticks
+    }]);
+    // None skipped, none repeated.
+    for pair in ticks[0].windows(2) {
+        assert_eq!(pair[1] - pair[0], PERIOD);
+    }
+}
+
+#[test]
+fn join_handle_and_shared_output() {
+    let executor = Executor::new();
+    let mut joined = executor.spawn(async {
+        TimerFuture::new(Duration::from_millis(5)).await;
+        "joined"
+    });
+    let shared = executor.handle().spawn_shared(async {
+        TimerFuture::new(Duration::from_millis(5)).await;
+        vec![1, 2, 3]
+    });
+    let started = Instant::now();
+    let outputs = executor.block_on_all([async move {
+        let mut dropped = 0;
+        let output = until_ready!(dropped, &mut joined).unwrap();
+        until_ready!(dropped, shared.ready()).unwrap();
+        assert!(dropped > 0);
+        (output, shared.to_vec())
+    }]);
+    assert!(started.elapsed() >= Duration::from_millis(5));
+    assert_eq!(outputs[0], ("joined", vec![1, 2, 3]));
+}
@@ -0,0 +1,186 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::lock;
+
+/// Which faults `Builder::chaos` injects, and how often.
+///
+/// Each probability is rolled independently with an RNG seeded by `seed`, so a
+/// run that exposes a bug can be replayed. They must lie within `0.0..1.0`.
+#[derive(Clone, Debug)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    /// Chance that a task which returned `Pending` is scheduled again without
+    /// having been woken.
+    pub spurious_wake: f64,
+    /// Chance, each time a task comes out of the ready queue, that it is sent
+    /// to the back of the queue instead of being polled.
+    pub delay_wake: f64,
+    /// Chance that a wake schedules the task twice.
+    pub duplicate_wake: f64,
+}
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig {
+            seed: 0,
+            spurious_wake: 0.1,
+            delay_wake: 0.1,
+            duplicate_wake: 0.1,
+        }
+    }
+}
+
+/// Number of faults injected by `Builder::chaos` so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    pub spurious_wakes: u64,
+    pub delayed_wakes: u64,
+    pub duplicated_wakes: u64,
+}
+
+pub(crate) struct Chaos {
+    config: ChaosConfig,
+    rng: Mutex<StdRng>,
+    spurious_wakes: AtomicU64,
+    delayed_wakes: AtomicU64,
+    duplicated_wakes: AtomicU64,
+}
+impl Chaos {
+    pub(crate) fn new(config: ChaosConfig) -> Self {
+        for p in [
+            config.spurious_wake,
+            config.delay_wake,
+            config.duplicate_wake,
+        ] {
+            assert!(
+                (0.0..1.0).contains(&p),
+                "chaos probability {p} not in 0.0..1.0"
+            );
+        }
+        Chaos {
+            rng: Mutex::new(StdRng::seed_from_u64(config.seed)),
+            config,
+            spurious_wakes: AtomicU64::new(0),
+            delayed_wakes: AtomicU64::new(0),
+            duplicated_wakes: AtomicU64::new(0),
+        }
+    }
+    pub(crate) fn stats(&self) -> ChaosStats {
+        ChaosStats {
+            spurious_wakes: self.spurious_wakes.load(Ordering::Relaxed),
+            delayed_wakes: self.delayed_wakes.load(Ordering::Relaxed),
+            duplicated_wakes: self.duplicated_wakes.load(Ordering::Relaxed),
+        }
+    }
+    pub(crate) fn spurious_wake(&self) -> bool {
+        self.roll(self.config.spurious_wake, &self.spurious_wakes)
+    }
+    pub(crate) fn delay_wake(&self) -> bool {
+        self.roll(self.config.delay_wake, &self.delayed_wakes)
+    }
+    pub(crate) fn duplicate_wake(&self) -> bool {
+        self.roll(self.config.duplicate_wake, &self.duplicated_wakes)
+    }
+    fn roll(&self, probability: f64, counter: &AtomicU64) -> bool {
+        let hit = lock(&self.rng).gen_bool(probability);
+        if hit {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{atomic::AtomicUsize, Arc},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        sync::{Event, OnceCell},
+        Builder, TimerFuture,
+    };
+
+    /// The crate's primitives under chaos: every task must complete, once.
+    fn primitives_workload(seed: u64) -> ChaosStats {
+        let executor = Builder::new()
+            .chaos(ChaosConfig {
+                seed,
+                spurious_wake: 0.3,
+                delay_wake: 0.3,
+                duplicate_wake: 0.3,
+            })
+            .build();
+        let completed = Arc::new(AtomicUsize::new(0));
+        let event = Arc::new(Event::new());
+        let cell = Arc::new(OnceCell::new());
+        let inits = Arc::new(AtomicUsize::new(0));
+        let mut handles = Vec::new();
+        for i in 0..20u64 {
+            let (completed, event, cell, inits) = (
+                completed.clone(),
+                event.clone(),
+                cell.clone(),
+                inits.clone(),
+            );
+            handles.push(executor.spawn(async move {
+                TimerFuture::new(Duration::from_millis(i % 5)).await;
+                let value = *cell
+                    .get_or_init(|| async {
+                        inits.fetch_add(1, Ordering::SeqCst);
+                        TimerFuture::new(Duration::from_millis(2)).await;
+                        7
+                    })
+                    .await;
+                event.wait().await;
+                completed.fetch_add(1, Ordering::SeqCst);
+                value + i
+            }));
+        }
+        let setter = event.clone();
+        executor.spawn(async move {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            setter.set();
+        });
+        let joined = executor.spawn(async move {
+            let mut total = 0;
+            for handle in handles {
+                total += handle.await.unwrap();
+            }
+            total
+        });
+        executor.run();
+        assert_eq!(
+            joined.join_blocking().unwrap(),
+            20 * 7 + (0..20).sum::<u64>()
+        );
+        assert_eq!(completed.load(Ordering::SeqCst), 20);
+        assert_eq!(inits.load(Ordering::SeqCst), 1);
+        executor.chaos_stats().unwrap()
+    }
+
+    #[test]
+    fn primitives_survive_chaos() {
+        for seed in 0..5 {
+            let stats = primitives_workload(seed);
+            assert!(stats.spurious_wakes > 0, "{stats:?}");
+            assert!(stats.delayed_wakes > 0, "{stats:?}");
+            assert!(stats.duplicated_wakes > 0, "{stats:?}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not in 0.0..1.0")]
+    fn probabilities_are_checked() {
+        Chaos::new(ChaosConfig {
+            spurious_wake: 1.5,
+            ..ChaosConfig::default()
+        });
+    }
+}
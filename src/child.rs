@@ -0,0 +1,261 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    future::Future,
+    panic::Location,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    lock,
+    metrics::RuntimeMetrics,
+    task::{JoinHandle, Priority, Task, TaskId, TaskMeta},
+    Handle,
+};
+
+/// Tasks of a `ChildRuntime`, which the tasks they spawn join too.
+pub(crate) struct Group {
+    name: String,
+    /// Unfinished tasks; `None` once the child was shut down.
+    tasks: Mutex<Option<HashMap<TaskId, Arc<Task>>>>,
+    spawned: AtomicU64,
+    polls: AtomicU64,
+    panicked: AtomicU64,
+}
+impl Group {
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+    /// Start tracking a new task. Returns false if the child was shut down
+    /// already, for the task to be aborted right away.
+    pub(crate) fn track(&self, task: &Arc<Task>) -> bool {
+        self.spawned.fetch_add(1, Ordering::Relaxed);
+        match &mut *lock(&self.tasks) {
+            Some(tasks) => {
+                tasks.insert(task.meta.id(), task.clone());
+                true
+            }
+            None => false,
+        }
+    }
+    pub(crate) fn untrack(&self, id: TaskId) {
+        if let Some(tasks) = &mut *lock(&self.tasks) {
+            tasks.remove(&id);
+        }
+    }
+    pub(crate) fn count_poll(&self) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn count_panic(&self) {
+        self.panicked.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn metrics(&self) -> RuntimeMetrics {
+        RuntimeMetrics {
+            name: self.name.clone(),
+            tasks_alive: self.len(),
+            tasks_spawned: self.spawned.load(Ordering::Relaxed),
+            polls_total: self.polls.load(Ordering::Relaxed),
+            tasks_panicked: self.panicked.load(Ordering::Relaxed),
+        }
+    }
+    fn len(&self) -> usize {
+        lock(&self.tasks).as_ref().map_or(0, HashMap::len)
+    }
+    fn shutdown(&self) {
+        // Taken out first: finishing tasks untrack themselves.
+        let tasks = lock(&self.tasks).take();
+        for task in tasks.into_iter().flat_map(HashMap::into_values) {
+            task.abort();
+        }
+    }
+}
+
+/// A named subset of an executor's tasks, shut down together, e.g. those of
+/// a plugin. See `Handle::child_runtime`.
+///
+/// Tasks spawned by its tasks belong to it too, whichever way they are
+/// spawned. Shutting it down, or dropping it, aborts all of them and no
+/// other; the executor shutting down drops them with every other task.
+pub struct ChildRuntime {
+    handle: Handle,
+    group: Arc<Group>,
+}
+impl ChildRuntime {
+    pub(crate) fn new(handle: Handle, name: String) -> Self {
+        let group = Arc::new(Group {
+            name,
+            tasks: Mutex::new(Some(HashMap::new())),
+            spawned: AtomicU64::new(0),
+            polls: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+        });
+        handle.inner.adopt(&group);
+        ChildRuntime { handle, group }
+    }
+    pub fn name(&self) -> &str {
+        &self.group.name
+    }
+    /// Spawn a task belonging to this child onto the parent executor.
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let meta =
+            TaskMeta::new(None, Priority::Normal, Location::caller()).in_group(self.group.clone());
+        self.handle.inner.spawn(meta, future)
+    }
+    /// Number of its tasks which have not finished yet.
+    pub fn tasks_alive(&self) -> usize {
+        self.group.len()
+    }
+    /// Its counters, as listed in `MetricsSnapshot::runtimes`.
+    pub fn metrics(&self) -> RuntimeMetrics {
+        self.group.metrics()
+    }
+    /// Abort every task of this child. Tasks they spawn before their abort
+    /// takes effect are aborted right away. Dropping the child does the same.
+    pub fn shutdown(self) {
+        drop(self);
+    }
+}
+impl Drop for ChildRuntime {
+    fn drop(&mut self) {
+        self.group.shutdown();
+    }
+}
+impl Debug for ChildRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChildRuntime")
+            .field("name", &self.group.name)
+            .field("tasks_alive", &self.tasks_alive())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future, time::Duration};
+
+    use super::*;
+    use crate::{sync::Event, test_util::DropTracker, Executor, TimerFuture};
+
+    #[test]
+    fn shutting_a_child_down_aborts_its_tasks_only() {
+        let executor = Executor::new();
+        let handle = executor.handle();
+        let gate = Arc::new(Event::new());
+        let tracker = DropTracker::new();
+        let (plugin, other) = (
+            handle.child_runtime("plugin"),
+            handle.child_runtime("other"),
+        );
+
+        let waits = |gate: &Arc<Event>| {
+            let gate = gate.clone();
+            async move { gate.wait().await }
+        };
+        let plugin_task = plugin.spawn(waits(&gate));
+        let guard = tracker.guard("grandchild");
+        // Spawned by a task of the child, so it belongs to the child too.
+        let spawner = plugin.spawn(async move {
+            drop(crate::task::spawn(async move {
+                let _guard = guard;
+                future::pending::<()>().await
+            }));
+        });
+        let other_task = other.spawn(waits(&gate));
+        let root_task = handle.spawn(waits(&gate));
+
+        let outputs = executor.block_on_all([async move {
+            spawner.await.unwrap();
+            TimerFuture::new(Duration::from_millis(10)).await;
+            assert_eq!(plugin.tasks_alive(), 2);
+            plugin.shutdown();
+            gate.set();
+            (
+                plugin_task.await.unwrap_err().is_cancelled(),
+                other_task.await.is_ok(),
+                root_task.await.is_ok(),
+            )
+        }]);
+        assert_eq!(outputs, [(true, true, true)]);
+        assert!(tracker.is_dropped("grandchild"));
+        assert_eq!(other.tasks_alive(), 0);
+
+        drop(other.spawn(future::pending::<()>()));
+        assert_eq!(other.tasks_alive(), 1);
+
+        let report = executor.shutdown();
+        assert_eq!(report.tasks.len(), 1);
+        assert_eq!(report.tasks[0].runtime.as_deref(), Some("other"));
+        assert_eq!(other.tasks_alive(), 0);
+    }
+
+    #[test]
+    fn metrics_and_snapshots_group_tasks_by_child() {
+        let executor = Executor::new();
+        let handle = executor.handle();
+        let (plugin, other) = (
+            handle.child_runtime("plugin \"a\""),
+            handle.child_runtime("other"),
+        );
+        for _ in 0..3 {
+            drop(plugin.spawn(async {
+                crate::task::yield_every(1).checkpoint().await;
+            }));
+        }
+        drop(plugin.spawn(async { panic!("plugin task") }));
+        drop(handle.spawn(async {}));
+        crate::handlers::tests::install();
+        executor.run();
+        // Left pending, for `run` not to wait for it.
+        drop(other.spawn(future::pending::<()>()));
+
+        let metrics = executor.metrics();
+        let runtimes: Vec<_> = metrics.runtimes.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(runtimes, ["plugin \"a\"", "other"]);
+        assert_eq!(metrics.runtimes[0], plugin.metrics());
+        let plugin_metrics = plugin.metrics();
+        assert_eq!(plugin_metrics.tasks_alive, 0);
+        assert_eq!(plugin_metrics.tasks_spawned, 4);
+        // Three yielding once, and the one panicking.
+        assert_eq!(plugin_metrics.polls_total, 7);
+        assert_eq!(plugin_metrics.tasks_panicked, 1);
+        let other_metrics = other.metrics();
+        assert_eq!(
+            (other_metrics.tasks_alive, other_metrics.tasks_spawned),
+            (1, 1)
+        );
+
+        let text = crate::metrics::prometheus_text(&metrics, "app_");
+        assert!(
+            text.contains("app_runtime_polls_total{runtime=\"plugin \\\"a\\\"\"} 7\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("app_runtime_tasks_alive{runtime=\"other\"} 1\n"),
+            "{text}"
+        );
+
+        let snapshot = executor.snapshot();
+        let by_runtime = snapshot.tasks_by_runtime();
+        assert_eq!(by_runtime[&Some("other")].len(), 1);
+        assert!(!by_runtime.contains_key(&Some("plugin \"a\"")));
+
+        // Dropped children leave the metrics.
+        drop(plugin);
+        let runtimes: Vec<_> = executor
+            .metrics()
+            .runtimes
+            .into_iter()
+            .map(|r| r.name)
+            .collect();
+        assert_eq!(runtimes, ["other"]);
+        drop(other);
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    fmt::{self, Debug},
+    io::{self, ErrorKind},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    io::{AsyncRead, AsyncWrite},
+    sink::Sink,
+    stream::Stream,
+};
+
+/// Bytes read from the transport at a time.
+const READ_CHUNK: usize = 8 * 1024;
+/// Encoded bytes `Framed` buffers before `poll_ready` waits for a flush.
+const WRITE_BACKPRESSURE: usize = 8 * 1024;
+
+/* LengthDelimited */
+/// Frames messages with a length prefix: a 4 byte big endian length field
+/// by default, followed by that many bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct LengthDelimited {
+    length_field_len: usize,
+    big_endian: bool,
+    max_frame_len: usize,
+}
+impl LengthDelimited {
+    pub fn new() -> Self {
+        LengthDelimited {
+            length_field_len: 4,
+            big_endian: true,
+            max_frame_len: 8 * 1024 * 1024,
+        }
+    }
+    /// Size of the length field in bytes, from 1 to 8.
+    pub fn length_field_len(mut self, len: usize) -> Self {
+        assert!(
+            (1..=8).contains(&len),
+            "length field must be 1 to 8 bytes long"
+        );
+        self.length_field_len = len;
+        self
+    }
+    pub fn big_endian(mut self) -> Self {
+        self.big_endian = true;
+        self
+    }
+    pub fn little_endian(mut self) -> Self {
+        self.big_endian = false;
+        self
+    }
+    /// Largest frame accepted, 8 MiB by default. Receiving a longer one is
+    /// an `InvalidData` error, raised before anything is allocated for it,
+    /// and sending one is an `InvalidInput` error.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = len;
+        self
+    }
+    /// Wrap `transport`, reading and writing frames of this format.
+    pub fn framed<T>(self, transport: T) -> Framed<T> {
+        Framed {
+            transport,
+            codec: self,
+            read_buf: Vec::new(),
+            eof: false,
+            write_buf: Vec::new(),
+            written: 0,
+        }
+    }
+
+    /// Take the first whole frame out of `buf`, if there is one yet.
+    fn decode(&self, buf: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        let header = self.length_field_len;
+        if buf.len() < header {
+            return Ok(None);
+        }
+        let mut field = [0; 8];
+        let len = if self.big_endian {
+            field[8 - header..].copy_from_slice(&buf[..header]);
+            u64::from_be_bytes(field)
+        } else {
+            field[..header].copy_from_slice(&buf[..header]);
+            u64::from_le_bytes(field)
+        };
+        let len = match usize::try_from(len) {
+            Ok(len) if len <= self.max_frame_len => len,
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("frame of {len} bytes is over the limit"),
+                ))
+            }
+        };
+        if buf.len() < header + len {
+            buf.reserve(header + len - buf.len());
+            return Ok(None);
+        }
+        let frame = buf[header..header + len].to_vec();
+        buf.drain(..header + len);
+        Ok(Some(frame))
+    }
+    fn encode(&self, frame: &[u8], buf: &mut Vec<u8>) -> io::Result<()> {
+        let header = self.length_field_len;
+        let fits = header == 8 || (frame.len() as u64) < 1 << (header * 8);
+        if frame.len() > self.max_frame_len || !fits {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("frame of {} bytes is over the limit", frame.len()),
+            ));
+        }
+        let len = frame.len() as u64;
+        if self.big_endian {
+            buf.extend_from_slice(&len.to_be_bytes()[8 - header..]);
+        } else {
+            buf.extend_from_slice(&len.to_le_bytes()[..header]);
+        }
+        buf.extend_from_slice(frame);
+        Ok(())
+    }
+}
+impl Default for LengthDelimited {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/* Framed */
+/// A transport read as a `Stream` of frames and written as a `Sink` of
+/// them, see [`LengthDelimited::framed`].
+///
+/// Reads are buffered until a frame is whole, however the bytes come in.
+/// Sent frames are encoded into a buffer, written out by `poll_flush`.
+pub struct Framed<T> {
+    transport: T,
+    codec: LengthDelimited,
+    read_buf: Vec<u8>,
+    /// Set once the transport reached end of file.
+    eof: bool,
+    write_buf: Vec<u8>,
+    /// Bytes of `write_buf` already written.
+    written: usize,
+}
+impl<T> Framed<T> {
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+    /// Give back the transport; buffered bytes, either way, are lost.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+impl<T: AsyncWrite + Unpin> Framed<T> {
+    /// Write out `write_buf`, without flushing the transport.
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.write_buf.len() {
+            let buf = &self.write_buf[self.written..];
+            match Pin::new(&mut self.transport).poll_write(cx, buf) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(written)) => self.written += written,
+                Poll::Ready(Err(error)) if error.kind() == ErrorKind::Interrupted => {}
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.write_buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+impl<T: AsyncRead + Unpin> Stream for Framed<T> {
+    type Item = io::Result<Vec<u8>>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(frame) = this.codec.decode(&mut this.read_buf).transpose() {
+                return Poll::Ready(Some(frame));
+            }
+            if this.eof {
+                if this.read_buf.is_empty() {
+                    return Poll::Ready(None);
+                }
+                // Reported once; the partial frame is dropped.
+                this.read_buf.clear();
+                return Poll::Ready(Some(Err(ErrorKind::UnexpectedEof.into())));
+            }
+            let filled = this.read_buf.len();
+            this.read_buf.resize(filled + READ_CHUNK, 0);
+            let read = Pin::new(&mut this.transport).poll_read(cx, &mut this.read_buf[filled..]);
+            let read_len = match &read {
+                Poll::Ready(Ok(read)) => *read,
+                _ => 0,
+            };
+            this.read_buf.truncate(filled + read_len);
+            match read {
+                Poll::Ready(Ok(0)) => this.eof = true,
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(error)) if error.kind() == ErrorKind::Interrupted => {}
+                Poll::Ready(Err(error)) => return Poll::Ready(Some(Err(error))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+impl<T: AsyncWrite + Unpin> Sink<Vec<u8>> for Framed<T> {
+    type Error = io::Error;
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.write_buf.len() - this.written < WRITE_BACKPRESSURE {
+            return Poll::Ready(Ok(()));
+        }
+        this.poll_write_buf(cx)
+    }
+    fn start_send(self: Pin<&mut Self>, frame: Vec<u8>) -> io::Result<()> {
+        let this = self.get_mut();
+        this.codec.encode(&frame, &mut this.write_buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_write_buf(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.transport).poll_flush(cx),
+            poll => poll,
+        }
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_write_buf(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.transport).poll_close(cx),
+            poll => poll,
+        }
+    }
+}
+impl<T: Debug> Debug for Framed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Framed")
+            .field("transport", &self.transport)
+            .field("codec", &self.codec)
+            .field("read_buffered", &self.read_buf.len())
+            .field("write_buffered", &(self.write_buf.len() - self.written))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+
+    use super::*;
+    use crate::{
+        io::{duplex, DuplexStream},
+        sink::SinkExt,
+        stream::StreamExt,
+        Executor,
+    };
+
+    /// Write `bytes` to `stream` as they are, then close it.
+    async fn write_raw(mut stream: DuplexStream, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let written = poll_fn(|cx| Pin::new(&mut stream).poll_write(cx, bytes)).await;
+            bytes = &bytes[written.unwrap()..];
+        }
+        poll_fn(|cx| Pin::new(&mut stream).poll_close(cx))
+            .await
+            .unwrap();
+    }
+
+    fn frames() -> Vec<Vec<u8>> {
+        (0..50u8).map(|i| vec![i; i as usize * 37]).collect()
+    }
+
+    #[test]
+    fn frames_survive_three_byte_chunks() {
+        let executor = Executor::new();
+        let codec = LengthDelimited::new().length_field_len(2).little_endian();
+        // At most three bytes in flight, so frames and even length fields
+        // arrive in many reads.
+        let (near, far) = duplex(3);
+        let handle = executor.handle();
+        let writer = handle.spawn(async move {
+            let mut framed = codec.framed(near);
+            for frame in frames() {
+                framed.send(frame).await.unwrap();
+            }
+            framed.close().await.unwrap();
+        });
+        let reader = handle.spawn(async move {
+            let received = codec.framed(far).collect::<Vec<_>>().await;
+            received.into_iter().map(Result::unwrap).collect::<Vec<_>>()
+        });
+        executor.run();
+        writer.join_blocking().unwrap();
+        assert_eq!(reader.join_blocking().unwrap(), frames());
+    }
+
+    #[test]
+    fn several_frames_in_one_read() {
+        let executor = Executor::new();
+        let codec = LengthDelimited::new();
+        let mut bytes = Vec::new();
+        for frame in frames() {
+            codec.encode(&frame, &mut bytes).unwrap();
+        }
+        let (near, far) = duplex(1 << 20);
+        let outputs = executor.block_on_all([async move {
+            write_raw(near, &bytes).await;
+            let received = codec.framed(far).collect::<Vec<_>>().await;
+            received.into_iter().map(Result::unwrap).collect::<Vec<_>>()
+        }]);
+        assert_eq!(outputs, [frames()]);
+    }
+
+    #[test]
+    fn oversized_length_fails_before_allocating() {
+        let executor = Executor::new();
+        let codec = LengthDelimited::new().max_frame_len(1024);
+        let (near, far) = duplex(64);
+        let outputs = executor.block_on_all([async move {
+            write_raw(near, &u32::MAX.to_be_bytes()).await;
+            let mut framed = codec.framed(far);
+            let error = framed.next().await.unwrap().unwrap_err();
+            (error.kind(), framed.read_buf.capacity())
+        }]);
+        let [(kind, capacity)] = outputs[..] else {
+            unreachable!()
+        };
+        assert_eq!(kind, ErrorKind::InvalidData);
+        assert!(capacity <= READ_CHUNK + 4);
+    }
+
+    #[test]
+    fn truncated_frame_is_an_unexpected_eof() {
+        let executor = Executor::new();
+        let (near, far) = duplex(64);
+        let outputs = executor.block_on_all([async move {
+            write_raw(near, &[0, 0, 0, 10, 1, 2, 3]).await;
+            LengthDelimited::new().framed(far).collect::<Vec<_>>().await
+        }]);
+        let [received] = &outputs[..] else {
+            unreachable!()
+        };
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].as_ref().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn sending_an_oversized_frame_fails() {
+        let (near, _far) = duplex(64);
+        let mut framed = LengthDelimited::new().length_field_len(1).framed(near);
+        let error = Pin::new(&mut framed).start_send(vec![0; 256]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
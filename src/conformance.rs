@@ -0,0 +1,497 @@
+use std::{
+    fmt::{self, Display},
+    future::{poll_fn, Future},
+    panic::{self, AssertUnwindSafe},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{future::pending, lock, sync::Event, test_util::noop_waker, Executor, TimerFuture};
+
+/// How long a property may take before it counts as hung, e.g. on a lost
+/// wake.
+const PROPERTY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tasks spawned by the properties which spawn many.
+const TASKS: usize = 100;
+
+type Property = fn(Executor) -> Result<(), String>;
+
+/// Every property of the executor's contract checked by `run_all`, by name.
+const PROPERTIES: [(&str, Property); 9] = [
+    ("spawned_tasks_are_polled", spawned_tasks_are_polled),
+    ("wakes_are_not_lost", wakes_are_not_lost),
+    (
+        "completed_tasks_are_not_polled",
+        completed_tasks_are_not_polled,
+    ),
+    ("yielding_does_not_starve", yielding_does_not_starve),
+    ("abort_terminates", abort_terminates),
+    ("shutdown_returns", shutdown_returns),
+    ("timers_fire", timers_fire),
+    ("event_wait_is_cancel_safe", event_wait_is_cancel_safe),
+    ("limiter_is_cancel_safe", limiter_is_cancel_safe),
+];
+
+/// Check that executors built by `make_runtime` keep the contract every
+/// part of this crate relies on, e.g. with a `Scheduler` of your own:
+/// spawned tasks get polled, wakes are not lost, finished tasks are not
+/// polled again, a yielding task does not starve the others, abort and
+/// shutdown terminate, and timers and the cancel-safe primitives work.
+///
+/// Each property runs on an executor of its own, on a thread of its own,
+/// and fails if it panics or takes more than ten seconds; a hung property's
+/// thread is leaked. Only the real clock is used.
+pub fn run_all<F>(make_runtime: F) -> ConformanceReport
+where
+    F: Fn() -> Executor + Send + Sync + 'static,
+{
+    let make_runtime = Arc::new(make_runtime);
+    let results = PROPERTIES
+        .iter()
+        .map(|&(property, check)| {
+            let make_runtime = make_runtime.clone();
+            let (done, outcome) = mpsc::channel();
+            let started = Instant::now();
+            let spawned = thread::Builder::new()
+                .name(format!("conformance-{property}"))
+                .spawn(move || {
+                    let checked = panic::catch_unwind(AssertUnwindSafe(|| check(make_runtime())));
+                    let _ = done.send(checked.unwrap_or_else(|payload| {
+                        Err(format!(
+                            "panicked: {}",
+                            crate::task::JoinError::panic_message(&*payload)
+                        ))
+                    }));
+                });
+            let outcome = match spawned {
+                Ok(_) => outcome
+                    .recv_timeout(PROPERTY_TIMEOUT)
+                    .unwrap_or_else(|_| Err(format!("did not finish within {PROPERTY_TIMEOUT:?}"))),
+                Err(error) => Err(format!("could not spawn its thread: {error}")),
+            };
+            PropertyResult {
+                property,
+                outcome,
+                elapsed: started.elapsed(),
+            }
+        })
+        .collect();
+    ConformanceReport { results }
+}
+
+/// What `run_all` found, one result per property.
+#[derive(Clone, Debug)]
+pub struct ConformanceReport {
+    pub results: Vec<PropertyResult>,
+}
+impl ConformanceReport {
+    /// Whether every property held.
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.outcome.is_ok())
+    }
+    pub fn failures(&self) -> impl Iterator<Item = &PropertyResult> {
+        self.results.iter().filter(|result| result.outcome.is_err())
+    }
+}
+impl Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = self.failures().count();
+        write!(
+            f,
+            "{} of {} properties held",
+            self.results.len() - failed,
+            self.results.len()
+        )?;
+        for result in &self.results {
+            write!(f, "\n  {result}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether one property held, with why not otherwise.
+#[derive(Clone, Debug)]
+pub struct PropertyResult {
+    pub property: &'static str,
+    pub outcome: Result<(), String>,
+    pub elapsed: Duration,
+}
+impl Display for PropertyResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Ok(()) => write!(f, "ok   {} ({:?})", self.property, self.elapsed),
+            Err(reason) => write!(f, "FAIL {}: {reason}", self.property),
+        }
+    }
+}
+
+fn expect_eq<T: PartialEq + fmt::Debug>(what: &str, actual: T, expected: T) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("{what}: expected {expected:?}, got {actual:?}"))
+    }
+}
+
+/// A one-off flag a task can wait on, woken by hand rather than through
+/// any primitive of the crate.
+#[derive(Default)]
+struct Flag {
+    set: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+impl Flag {
+    fn set(&self) {
+        self.set.store(true, Ordering::SeqCst);
+        let waker = lock(&self.waker).take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+    async fn wait(&self) {
+        poll_fn(|cx| {
+            if self.set.load(Ordering::SeqCst) {
+                return Poll::Ready(());
+            }
+            *lock(&self.waker) = Some(cx.waker().clone());
+            // Checked again, in case it was set before the waker was in.
+            if self.set.load(Ordering::SeqCst) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// Return `Pending` once, having woken the task, to let the others run.
+async fn yield_once() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+fn spawned_tasks_are_polled(executor: Executor) -> Result<(), String> {
+    let polled = Arc::new(AtomicUsize::new(0));
+    for _ in 0..TASKS {
+        let polled = polled.clone();
+        drop(executor.spawn(async move {
+            polled.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    executor.run();
+    expect_eq("tasks polled", polled.load(Ordering::SeqCst), TASKS)
+}
+
+/// Tasks woken by each other in a chain, and others woken from another
+/// thread: `run` only returns if none of the wakes was lost.
+fn wakes_are_not_lost(executor: Executor) -> Result<(), String> {
+    let chain: Arc<Vec<Flag>> = Arc::new((0..=TASKS).map(|_| Flag::default()).collect());
+    let remote: Arc<Vec<Flag>> = Arc::new((0..TASKS).map(|_| Flag::default()).collect());
+    let done = Arc::new(AtomicUsize::new(0));
+    for index in 0..TASKS {
+        let (chain, remote, done) = (chain.clone(), remote.clone(), done.clone());
+        drop(executor.spawn(async move {
+            chain[index].wait().await;
+            chain[index + 1].set();
+            remote[index].wait().await;
+            done.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    let waker_thread = thread::spawn({
+        let remote = remote.clone();
+        move || {
+            for flag in remote.iter() {
+                thread::sleep(Duration::from_micros(50));
+                flag.set();
+            }
+        }
+    });
+    chain[0].set();
+    executor.run();
+    let _ = waker_thread.join();
+    expect_eq(
+        "tasks woken to completion",
+        done.load(Ordering::SeqCst),
+        TASKS,
+    )
+}
+
+/// Completes on its second poll, counting any poll after that.
+struct CompletesOnce {
+    polls: usize,
+    polled_after_ready: Arc<AtomicUsize>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+impl Future for CompletesOnce {
+    type Output = ();
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.polls += 1;
+        match self.polls {
+            1 => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            2 => {
+                // Kept to wake the task again once it completed.
+                *lock(&self.waker) = Some(cx.waker().clone());
+                Poll::Ready(())
+            }
+            _ => {
+                self.polled_after_ready.fetch_add(1, Ordering::SeqCst);
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+fn completed_tasks_are_not_polled(executor: Executor) -> Result<(), String> {
+    let polled_after_ready = Arc::new(AtomicUsize::new(0));
+    let wakers = Arc::new(Mutex::new(Vec::new()));
+    for _ in 0..TASKS {
+        let waker = Arc::new(Mutex::new(None));
+        lock(&wakers).push(waker.clone());
+        drop(executor.spawn(CompletesOnce {
+            polls: 0,
+            polled_after_ready: polled_after_ready.clone(),
+            waker,
+        }));
+    }
+    executor.run();
+    // Wakes of finished tasks, both from a task and from this thread.
+    let stale: Vec<_> = lock(&wakers)
+        .iter()
+        .filter_map(|waker| lock(waker).clone())
+        .collect();
+    for waker in &stale {
+        waker.wake_by_ref();
+    }
+    let waker_task = executor.spawn(async move {
+        for waker in &stale {
+            waker.wake_by_ref();
+        }
+        yield_once().await;
+    });
+    executor.run();
+    if !waker_task.is_finished() {
+        return Err("the waking task did not finish".to_string());
+    }
+    expect_eq(
+        "polls after completion",
+        polled_after_ready.load(Ordering::SeqCst),
+        0,
+    )
+}
+
+/// A task yielding until another one ran: a scheduler always picking the
+/// latest scheduled task would never get to the other.
+fn yielding_does_not_starve(executor: Executor) -> Result<(), String> {
+    const MAX_YIELDS: usize = 100_000;
+    let ran = Arc::new(AtomicBool::new(false));
+    // Spawned first, so it is the one left waiting.
+    drop(executor.spawn({
+        let ran = ran.clone();
+        async move {
+            ran.store(true, Ordering::SeqCst);
+        }
+    }));
+    let yielder = executor.spawn({
+        let ran = ran.clone();
+        async move {
+            for yields in 0..MAX_YIELDS {
+                if ran.load(Ordering::SeqCst) {
+                    return Some(yields);
+                }
+                yield_once().await;
+            }
+            None
+        }
+    });
+    let outputs = executor.block_on_all([yielder]);
+    match outputs.into_iter().next() {
+        Some(Ok(Some(_))) => Ok(()),
+        Some(Ok(None)) => Err(format!("the other task did not run in {MAX_YIELDS} yields")),
+        _ => Err("the yielding task failed".to_string()),
+    }
+}
+
+fn abort_terminates(executor: Executor) -> Result<(), String> {
+    let handles = vec![
+        executor.spawn(pending::<()>()),
+        executor.spawn(TimerFuture::new(Duration::from_secs(3600))),
+        // Aborted before it was ever polled.
+        executor.spawn(async {}),
+    ];
+    handles[2].abort();
+    let outcomes = executor.block_on_all([async move {
+        yield_once().await;
+        let mut cancelled = 0;
+        for handle in handles {
+            handle.abort();
+            if handle.await.is_err_and(|error| error.is_cancelled()) {
+                cancelled += 1;
+            }
+        }
+        cancelled
+    }]);
+    expect_eq(
+        "aborted tasks cancelled",
+        outcomes.first().copied(),
+        Some(3),
+    )
+}
+
+fn shutdown_returns(executor: Executor) -> Result<(), String> {
+    let mut handles = Vec::new();
+    for index in 0..TASKS {
+        handles.push(if index % 2 == 0 {
+            executor.spawn(pending::<()>())
+        } else {
+            executor.spawn(TimerFuture::new(Duration::from_secs(3600)))
+        });
+    }
+    let report = executor.shutdown();
+    expect_eq("tasks reported leaked", report.tasks.len(), TASKS)?;
+    let cancelled = handles
+        .iter_mut()
+        .filter_map(|handle| handle.try_take_output())
+        .filter(|output| output.as_ref().is_err_and(|error| error.is_cancelled()))
+        .count();
+    expect_eq("tasks cancelled by the shutdown", cancelled, TASKS)
+}
+
+fn timers_fire(executor: Executor) -> Result<(), String> {
+    let delay = Duration::from_millis(20);
+    let started = Instant::now();
+    let elapsed = executor.block_on_all((0..TASKS).map(|index| async move {
+        let duration = delay + Duration::from_micros(index as u64 * 100);
+        let started = Instant::now();
+        TimerFuture::new(duration).await;
+        (started.elapsed(), duration)
+    }));
+    if let Some((elapsed, duration)) = elapsed
+        .iter()
+        .find(|(elapsed, duration)| elapsed < duration)
+    {
+        return Err(format!("a {duration:?} timer fired after {elapsed:?}"));
+    }
+    if started.elapsed() > PROPERTY_TIMEOUT / 2 {
+        return Err(format!("timers took {:?} in all", started.elapsed()));
+    }
+    Ok(())
+}
+
+/// A waiter dropped after its first poll must not keep the others from
+/// being woken.
+fn event_wait_is_cancel_safe(executor: Executor) -> Result<(), String> {
+    let event = Arc::new(Event::new());
+    let waited = Arc::new(AtomicUsize::new(0));
+    for _ in 0..TASKS / 2 {
+        let dropped = event.clone();
+        drop(executor.spawn(async move {
+            let mut wait = pin!(dropped.wait());
+            let _ = wait.as_mut().poll(&mut Context::from_waker(&noop_waker()));
+        }));
+        let (event, waited) = (event.clone(), waited.clone());
+        drop(executor.spawn(async move {
+            event.wait().await;
+            waited.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    drop(executor.spawn({
+        let event = event.clone();
+        async move {
+            yield_once().await;
+            event.set();
+        }
+    }));
+    executor.run();
+    expect_eq("waiters woken", waited.load(Ordering::SeqCst), TASKS / 2)
+}
+
+/// A task aborted while waiting for a `Limiter` permit must neither keep
+/// it nor keep the next waiter from getting it.
+fn limiter_is_cancel_safe(executor: Executor) -> Result<(), String> {
+    let limiter = executor.handle().limiter("conformance", 1);
+    let release = Arc::new(Flag::default());
+    let holder = limiter.spawn({
+        let release = release.clone();
+        async move { release.wait().await }
+    });
+    let aborted = limiter.spawn(async {});
+    let next = limiter.spawn(async {});
+    let outcomes = executor.block_on_all([async move {
+        yield_once().await;
+        aborted.abort();
+        let aborted = aborted.await.is_err_and(|error| error.is_cancelled());
+        release.set();
+        let holder = holder.await.is_ok();
+        let next = next.await.is_ok();
+        (aborted, holder, next)
+    }]);
+    expect_eq(
+        "aborted waiter cancelled, holder and next waiter completed",
+        outcomes.first().copied(),
+        Some((true, true, true)),
+    )?;
+    expect_eq("permits available afterwards", limiter.available(), 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scheduler::{PriorityScheduler, Scheduler},
+        task::Task,
+        Builder,
+    };
+
+    /// Polls the latest scheduled task first, so a yielding task is polled
+    /// again and again while the others wait.
+    #[derive(Default)]
+    struct LifoScheduler {
+        stack: Mutex<Vec<Arc<Task>>>,
+    }
+    impl Scheduler for LifoScheduler {
+        fn schedule(&self, task: Arc<Task>) {
+            lock(&self.stack).push(task);
+        }
+        fn next(&self) -> Option<Arc<Task>> {
+            lock(&self.stack).pop()
+        }
+        fn len(&self) -> usize {
+            lock(&self.stack).len()
+        }
+    }
+
+    #[test]
+    fn the_built_in_schedulers_pass() {
+        let report = run_all(|| Builder::new().build());
+        assert!(report.passed(), "{report}");
+        let report = run_all(|| Builder::new().scheduler(PriorityScheduler::new()).build());
+        assert!(report.passed(), "{report}");
+    }
+
+    #[test]
+    fn a_lifo_scheduler_fails_on_starvation() {
+        let report = run_all(|| Builder::new().scheduler(LifoScheduler::default()).build());
+        assert!(!report.passed());
+        let failed: Vec<_> = report.failures().map(|result| result.property).collect();
+        assert_eq!(failed, ["yielding_does_not_starve"], "{report}");
+    }
+}
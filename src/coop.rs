@@ -0,0 +1,245 @@
+use std::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Checkpoints a task passes in one poll before `maybe_yield` yields.
+const BUDGET: u32 = 128;
+
+thread_local! {
+    /// What is left of the budget of the task being polled on this thread,
+    /// unset while none is.
+    static REMAINING: Cell<Option<u32>> = const { Cell::new(None) };
+    /// Set while an `unconstrained` future of the task being polled on this
+    /// thread is.
+    static UNCONSTRAINED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The budget of a task, put aside while another is polled on its thread.
+#[derive(Clone, Copy)]
+pub(crate) struct Budget {
+    remaining: Option<u32>,
+    unconstrained: bool,
+}
+
+/// Give the task about to be polled a full budget, returning the budget to
+/// restore once the poll returned.
+///
+/// A task polled from within another's `unconstrained` future, e.g. by
+/// `Handle::block_in_task`, is constrained all the same.
+pub(crate) fn reset() -> Budget {
+    Budget {
+        remaining: REMAINING.with(|remaining| remaining.replace(Some(BUDGET))),
+        unconstrained: UNCONSTRAINED.with(|unconstrained| unconstrained.replace(false)),
+    }
+}
+
+pub(crate) fn restore(budget: Budget) {
+    REMAINING.with(|remaining| remaining.set(budget.remaining));
+    UNCONSTRAINED.with(|unconstrained| unconstrained.set(budget.unconstrained));
+}
+
+/// Bypass the budget of the task being polled until the returned guard is
+/// dropped, see `task::unconstrained`.
+pub(crate) fn unconstrain() -> Constrain {
+    Constrain(UNCONSTRAINED.with(|unconstrained| unconstrained.replace(true)))
+}
+
+/// Puts back whether the budget was bypassed before `unconstrain`, even if
+/// the poll in between panicked.
+pub(crate) struct Constrain(bool);
+impl Drop for Constrain {
+    fn drop(&mut self) {
+        UNCONSTRAINED.with(|unconstrained| unconstrained.set(self.0));
+    }
+}
+
+/// Whether an executor is polling a task on this thread.
+pub(crate) fn in_task() -> bool {
+    REMAINING.with(|remaining| remaining.get().is_some())
+}
+
+/// Spend one unit of the current task's budget, returning true if it was
+/// used up already. Nothing is spent while it is bypassed.
+pub(crate) fn exhausted() -> bool {
+    if UNCONSTRAINED.with(Cell::get) {
+        return false;
+    }
+    REMAINING.with(|remaining| match remaining.get() {
+        Some(0) => true,
+        Some(left) => {
+            remaining.set(Some(left - 1));
+            false
+        }
+        None => false,
+    })
+}
+
+/// A checkpoint for long chains of computation that never wait on anything:
+/// yields to the executor's other tasks only once the current task used up
+/// its budget for this poll, and is ready right away otherwise.
+///
+/// Outside of a task, it is always ready right away.
+pub fn maybe_yield() -> MaybeYield {
+    MaybeYield { yielded: false }
+}
+
+/// Future returned by `maybe_yield`.
+#[derive(Debug)]
+pub struct MaybeYield {
+    yielded: bool,
+}
+impl Future for MaybeYield {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded || !exhausted() {
+            return Poll::Ready(());
+        }
+        // Polled again with a fresh budget once the others had their turn.
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::{
+        assert_ready, stream::StreamExt, task, test_util::MockTask, Executor, TimerFuture,
+    };
+
+    #[test]
+    fn a_long_computation_lets_a_timer_through() {
+        const ITERATIONS: u64 = 1_000_000;
+        let executor = Executor::new();
+        let compute = executor.spawn(async {
+            let mut sum = 0u64;
+            for i in 0..ITERATIONS {
+                sum = std::hint::black_box(sum.wrapping_add(i * i));
+                maybe_yield().await;
+            }
+            sum
+        });
+        let started = Instant::now();
+        let timer = executor.spawn(async move {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            started.elapsed()
+        });
+        executor.run();
+        let waited = timer.join_blocking().unwrap();
+        assert!(waited < Duration::from_millis(60), "{waited:?}");
+        compute.join_blocking().unwrap();
+        let polls = executor.metrics().polls_total;
+        assert!(
+            polls <= ITERATIONS / u64::from(BUDGET) + 10,
+            "{polls} polls"
+        );
+    }
+
+    #[test]
+    fn checkpoints_outside_of_a_task_never_yield() {
+        let mut task = MockTask::new();
+        for _ in 0..2 * BUDGET {
+            assert_ready!(task.poll(pin!(maybe_yield())));
+        }
+        let mut every = task::yield_every(2);
+        for _ in 0..10 {
+            assert_ready!(task.poll(pin!(every.checkpoint())));
+        }
+        assert!(!task.is_woken());
+    }
+
+    #[test]
+    fn yield_every_yields_on_every_nth_checkpoint() {
+        let executor = Executor::new();
+        drop(executor.spawn(async {
+            let mut every = task::yield_every(10);
+            for _ in 0..100 {
+                every.checkpoint().await;
+            }
+        }));
+        executor.run();
+        // The first poll, then one after each of the ten yields.
+        assert_eq!(executor.metrics().polls_total, 11);
+    }
+
+    /// Polls taken by a task draining a channel of `ITEMS` reports, sent
+    /// before it starts.
+    fn drain_polls(unconstrained: bool) -> u64 {
+        const ITEMS: usize = 10_000;
+        let executor = Executor::new();
+        let (sent, mut reports) =
+            executor
+                .handle()
+                .spawn_with_bounded_progress(ITEMS, |sender| async move {
+                    for item in 0..ITEMS {
+                        sender.send(item).await.unwrap();
+                    }
+                });
+        executor.run();
+        sent.join_blocking().unwrap();
+        let before = executor.metrics().polls_total;
+        let drain = async move {
+            let mut received = 0;
+            while reports.next().await.is_some() {
+                received += 1;
+            }
+            received
+        };
+        let drained = match unconstrained {
+            true => executor.spawn(task::unconstrained(drain)),
+            false => executor.spawn(drain),
+        };
+        executor.run();
+        assert_eq!(drained.join_blocking().unwrap(), ITEMS);
+        executor.metrics().polls_total - before
+    }
+
+    #[test]
+    fn an_unconstrained_drain_takes_a_single_poll() {
+        assert_eq!(drain_polls(true), 1);
+        let constrained = drain_polls(false);
+        assert!(
+            constrained >= 10_000 / u64::from(BUDGET),
+            "{constrained} polls"
+        );
+    }
+
+    fn polls_of(future: impl Future<Output = ()> + Send + 'static) -> u64 {
+        let executor = Executor::new();
+        drop(executor.spawn(future));
+        executor.run();
+        executor.metrics().polls_total
+    }
+
+    async fn checkpoints() {
+        for _ in 0..1000 {
+            maybe_yield().await;
+        }
+    }
+
+    #[test]
+    fn the_budget_applies_again_after_an_unconstrained_future() {
+        let constrained = polls_of(checkpoints());
+        assert!(constrained > 1);
+        // Nested or not, the unconstrained checkpoints neither yield nor
+        // spend the budget of those after them.
+        let polls = polls_of(async {
+            task::unconstrained(async {
+                task::unconstrained(checkpoints()).await;
+                checkpoints().await;
+            })
+            .await;
+            checkpoints().await;
+        });
+        assert_eq!(polls, constrained);
+    }
+}
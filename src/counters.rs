@@ -0,0 +1,45 @@
+//! Counts of waker clones and lock acquisitions by the scheduler and the
+//! primitives, for the baselines of `bench_util`.
+//!
+//! The hooks only count with the `bench-counters` feature, or under test;
+//! otherwise they compile to nothing.
+
+use std::task::Waker;
+
+#[cfg(any(test, feature = "bench-counters"))]
+use std::cell::Cell;
+
+#[cfg(any(test, feature = "bench-counters"))]
+thread_local! {
+    static WAKER_CLONES: Cell<u64> = const { Cell::new(0) };
+    static LOCK_ACQUISITIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// `waker.clone()`, counted into `waker_clones`: the crate clones wakers
+/// through this only, so that a clone where none is needed shows there.
+#[inline]
+pub(crate) fn clone_waker(waker: &Waker) -> Waker {
+    #[cfg(any(test, feature = "bench-counters"))]
+    // Gone while the thread exits.
+    let _ = WAKER_CLONES.try_with(|count| count.set(count.get() + 1));
+    waker.clone()
+}
+
+/// Count a lock taken by `crate::lock` into `lock_acquisitions`.
+#[inline]
+pub(crate) fn lock_acquired() {
+    #[cfg(any(test, feature = "bench-counters"))]
+    let _ = LOCK_ACQUISITIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+/// Wakers this thread cloned through `clone_waker` so far.
+#[cfg(any(test, feature = "bench-counters"))]
+pub(crate) fn waker_clones() -> u64 {
+    WAKER_CLONES.with(Cell::get)
+}
+
+/// Locks this thread took through `crate::lock` so far.
+#[cfg(any(test, feature = "bench-counters"))]
+pub(crate) fn lock_acquisitions() -> u64 {
+    LOCK_ACQUISITIONS.with(Cell::get)
+}
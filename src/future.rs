@@ -0,0 +1,720 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Debug, Display},
+    future::Future,
+    marker::PhantomData,
+    mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
+use rand::Rng;
+
+use crate::{counters::clone_waker, lock, task::tag_wakes};
+
+/// Extra combinators for every `Future`.
+pub trait FutureExt: Future {
+    /// Turn the future into a cloneable handle to its output.
+    ///
+    /// The future itself is driven only once, whichever clone happens to poll
+    /// it; every clone then resolves to a clone of the output, including
+    /// clones created after it completed.
+    fn shared(self) -> Shared<Self>
+    where
+        Self: Sized,
+        Self::Output: Clone,
+    {
+        Shared::new(self)
+    }
+}
+impl<F: Future + ?Sized> FutureExt for F {}
+
+/* Pending */
+/// Future that never completes. Created by [`pending`].
+pub struct Pending<T> {
+    // `fn() -> T` keeps `Pending<T>` `Send`, `Sync` and `Unpin` whatever `T` is,
+    // since no `T` is ever stored.
+    _output: PhantomData<fn() -> T>,
+}
+
+/// Create a future which is never ready and never wakes its task, e.g. to
+/// see a timeout through:
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use executor::{future::pending, time::timeout, Executor};
+///
+/// let executor = Executor::new();
+/// let [result] = executor
+///     .block_on_all([timeout(Duration::from_millis(10), pending::<()>())])
+///     .try_into()
+///     .unwrap();
+/// assert!(result.is_err());
+/// ```
+pub fn pending<T>() -> Pending<T> {
+    Pending {
+        _output: PhantomData,
+    }
+}
+impl<T> Future for Pending<T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Poll::Pending
+    }
+}
+impl<T> Debug for Pending<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pending").finish()
+    }
+}
+
+/* Ready */
+/// Future that is immediately ready with a value. Created by [`ready`].
+pub struct Ready<T> {
+    value: Option<T>,
+}
+
+/// Create a future which completes with `value` on its first poll.
+///
+/// `Ready` is not fused: the value is handed out exactly once, and polling
+/// again after that panics, like any other future polled after completion.
+pub fn ready<T>(value: T) -> Ready<T> {
+    Ready { value: Some(value) }
+}
+// The value is never pinned, so moving a `Ready` around is always fine.
+impl<T> Unpin for Ready<T> {}
+impl<T> Future for Ready<T> {
+    type Output = T;
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Poll::Ready(self.value.take().expect("`Ready` polled after completion"))
+    }
+}
+impl<T: Debug> Debug for Ready<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Ready").field(&self.value).finish()
+    }
+}
+
+/* Shared */
+/// Cloneable future resolving to a clone of the inner future's output.
+/// Created by [`FutureExt::shared`].
+pub struct Shared<F: Future> {
+    inner: Arc<SharedInner<F>>,
+    /// Key of this clone's waker in the notifier.
+    key: usize,
+}
+
+struct SharedInner<F: Future> {
+    state: Mutex<SharedState<F>>,
+    notifier: Arc<Notifier>,
+    next_key: AtomicUsize,
+}
+
+enum SharedState<F: Future> {
+    Pending(Pin<Box<F>>),
+    Done(F::Output),
+}
+
+/// Wakes every clone waiting on a `Shared`.
+///
+/// The inner future is polled with a waker built from this rather than with the
+/// waker of whichever clone polled it, so that dropping that clone never leaves
+/// the others without a wakeup.
+struct Notifier {
+    wakers: Mutex<HashMap<usize, Waker>>,
+}
+impl Wake for Notifier {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = mem::take(&mut *lock(&self.wakers));
+        for (_, waker) in wakers {
+            waker.wake()
+        }
+    }
+}
+
+impl<F: Future> Shared<F> {
+    fn new(future: F) -> Self {
+        let inner = Arc::new(SharedInner {
+            state: Mutex::new(SharedState::Pending(Box::pin(future))),
+            notifier: Arc::new(Notifier {
+                wakers: Mutex::new(HashMap::new()),
+            }),
+            next_key: AtomicUsize::new(1),
+        });
+        Shared { inner, key: 0 }
+    }
+}
+impl<F: Future> Future for Shared<F>
+where
+    F::Output: Clone,
+{
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.inner.state);
+        let future = match &mut *state {
+            SharedState::Done(output) => return Poll::Ready(output.clone()),
+            SharedState::Pending(future) => future,
+        };
+        // Register before polling so a wake fired during the poll is not lost.
+        lock(&self.inner.notifier.wakers).insert(self.key, clone_waker(cx.waker()));
+        let waker = Waker::from(self.inner.notifier.clone());
+        match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(output) => {
+                *state = SharedState::Done(output.clone());
+                drop(state);
+                // Let every waiting clone come and pick up its copy.
+                self.inner.notifier.wake_by_ref();
+                Poll::Ready(output)
+            }
+        }
+    }
+}
+impl<F: Future> Clone for Shared<F> {
+    fn clone(&self) -> Self {
+        Shared {
+            inner: self.inner.clone(),
+            key: self.inner.next_key.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+impl<F: Future> Drop for Shared<F> {
+    fn drop(&mut self) {
+        lock(&self.inner.notifier.wakers).remove(&self.key);
+    }
+}
+impl<F: Future> Debug for Shared<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let done = matches!(*lock(&self.inner.state), SharedState::Done(_));
+        f.debug_struct("Shared").field("done", &done).finish()
+    }
+}
+
+/* Completion */
+/// Create a future completed by hand, from any thread: `Completion` resolves
+/// once one of the `Completer` clones is given a value.
+///
+/// Completing takes a lock but never allocates nor needs an executor, so it
+/// can be done from FFI callbacks and other plain threads.
+pub fn completion<T>() -> (Completer<T>, Completion<T>) {
+    let state = Arc::new(Mutex::new(CompletionState {
+        value: None,
+        completed: false,
+        waker: None,
+        completers: 1,
+    }));
+    (
+        Completer {
+            state: state.clone(),
+        },
+        Completion { state },
+    )
+}
+
+struct CompletionState<T> {
+    /// The value, until `Completion` takes it.
+    value: Option<T>,
+    /// Set by the first `Completer::complete`.
+    completed: bool,
+    waker: Option<Waker>,
+    /// Number of `Completer` clones alive.
+    completers: usize,
+}
+
+/// Completing half of [`completion`]. Cloneable, the first clone to
+/// complete wins.
+pub struct Completer<T> {
+    state: Arc<Mutex<CompletionState<T>>>,
+}
+impl<T> Completer<T> {
+    /// Hand `value` to the `Completion` and wake its task. Returns the value
+    /// back if another clone completed first.
+    pub fn complete(self, value: T) -> Result<(), T> {
+        let waker = {
+            let mut state = lock(&self.state);
+            if state.completed {
+                return Err(value);
+            }
+            state.completed = true;
+            state.value = Some(value);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            tag_wakes("Completion", || waker.wake());
+        }
+        Ok(())
+    }
+    /// Whether a clone completed already.
+    pub fn is_completed(&self) -> bool {
+        lock(&self.state).completed
+    }
+}
+impl<T> Clone for Completer<T> {
+    fn clone(&self) -> Self {
+        lock(&self.state).completers += 1;
+        Completer {
+            state: self.state.clone(),
+        }
+    }
+}
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = lock(&self.state);
+            state.completers -= 1;
+            if state.completers > 0 || state.completed {
+                return;
+            }
+            state.waker.take()
+        };
+        // The last one gone unfulfilled: `Completion` resolves to `Dropped`.
+        if let Some(waker) = waker {
+            tag_wakes("Completion", || waker.wake());
+        }
+    }
+}
+impl<T> Debug for Completer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Completer")
+            .field("completed", &self.is_completed())
+            .finish()
+    }
+}
+
+/// Future half of [`completion`], resolving to the value completed with, or
+/// to `Dropped` if every `Completer` was dropped without completing.
+pub struct Completion<T> {
+    state: Arc<Mutex<CompletionState<T>>>,
+}
+impl<T> Future for Completion<T> {
+    type Output = Result<T, Dropped>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.state);
+        if let Some(value) = state.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        assert!(!state.completed, "`Completion` polled after completion");
+        if state.completers == 0 {
+            return Poll::Ready(Err(Dropped(())));
+        }
+        state.waker = Some(clone_waker(cx.waker()));
+        Poll::Pending
+    }
+}
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        // Cancelled, for instance by aborting the task awaiting it: the
+        // completers have nobody left to wake.
+        lock(&self.state).waker = None;
+    }
+}
+impl<T> Debug for Completion<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = lock(&self.state);
+        f.debug_struct("Completion")
+            .field("completed", &state.completed)
+            .finish()
+    }
+}
+
+/// Error of a [`Completion`] whose every `Completer` was dropped without
+/// completing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dropped(());
+impl Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "every completer was dropped without completing")
+    }
+}
+impl Error for Dropped {}
+
+/* select! */
+/// Wait on several futures at once, running the handler of the first one to
+/// complete, from within an async context.
+///
+/// ```text
+/// select! {
+///     Some(line) = lines.next() => println!("{line:?}"),
+///     _ = ticks.tick(), if tick_enabled => refresh(),
+///     else => break,
+/// }
+/// ```
+///
+/// Each branch is `pattern = future => handler`, optionally guarded with
+/// `, if condition`: a branch whose condition is false is disabled, without
+/// evaluating its future. A branch whose output does not match its pattern
+/// is disabled too, and the others are waited on. Once every branch is
+/// disabled, the `else` handler runs; without one, `select!` panics.
+///
+/// Branches are polled in a random order each time, so none can starve the
+/// others; start with `biased;` to poll them in order instead. Handlers run
+/// after every future was dropped, and may `break`, `continue` or `return`.
+/// The futures of branches which did not win are dropped unfinished, so
+/// whatever they did so far is lost unless they are cancel safe.
+///
+/// Up to 16 branches are supported.
+///
+/// ```compile_fail
+/// # async fn run() {
+/// // Every branch needs a pattern and a handler.
+/// executor::select! { std::future::ready(1) };
+/// # }
+/// ```
+#[macro_export]
+macro_rules! select {
+    // Normalise every branch into `[variant future (pattern) (future) (condition) (handler)]`.
+    (@munch $biased:expr; [$($done:tt)*] [$($names:tt)*] else => $else:expr $(,)?) => {
+        $crate::select!(@emit $biased; [$($done)*] $else)
+    };
+    (@munch $biased:expr; [$($done:tt)*] [$($names:tt)*]) => {
+        $crate::select!(@emit $biased; [$($done)*]
+            ::core::panic!("every `select!` branch is disabled, and there is no `else` branch"))
+    };
+    (@munch $biased:expr; [$($done:tt)*] [($v:ident $f:ident) $($names:tt)*]
+        $pat:pat = $fut:expr $(, if $cond:expr)? => $handler:block, $($rest:tt)*) => {
+        $crate::select!(@munch $biased; [$($done)* [$v $f ($pat) ($fut) (true $(&& $cond)?) ($handler)]]
+            [$($names)*] $($rest)*)
+    };
+    (@munch $biased:expr; [$($done:tt)*] [($v:ident $f:ident) $($names:tt)*]
+        $pat:pat = $fut:expr $(, if $cond:expr)? => $handler:block $($rest:tt)*) => {
+        $crate::select!(@munch $biased; [$($done)* [$v $f ($pat) ($fut) (true $(&& $cond)?) ($handler)]]
+            [$($names)*] $($rest)*)
+    };
+    (@munch $biased:expr; [$($done:tt)*] [($v:ident $f:ident) $($names:tt)*]
+        $pat:pat = $fut:expr $(, if $cond:expr)? => $handler:expr, $($rest:tt)*) => {
+        $crate::select!(@munch $biased; [$($done)* [$v $f ($pat) ($fut) (true $(&& $cond)?) ($handler)]]
+            [$($names)*] $($rest)*)
+    };
+    (@munch $biased:expr; [$($done:tt)*] [($v:ident $f:ident) $($names:tt)*]
+        $pat:pat = $fut:expr $(, if $cond:expr)? => $handler:expr) => {
+        $crate::select!(@munch $biased; [$($done)* [$v $f ($pat) ($fut) (true $(&& $cond)?) ($handler)]]
+            [$($names)*])
+    };
+    (@munch $biased:expr; [$($done:tt)*] [] $($rest:tt)+) => {
+        ::core::compile_error!("`select!` supports up to 16 branches")
+    };
+
+    (@emit $biased:expr;
+        [$([$v:ident $f:ident ($pat:pat) ($fut:expr) ($cond:expr) ($handler:expr)])+] $else:expr) => {{
+        #[allow(non_camel_case_types)]
+        enum __Output<$($v,)+> {
+            $($v($v),)+
+            Disabled,
+        }
+        #[allow(unused_assignments, unused_variables)]
+        let output = {
+            $(let mut $f = ::core::pin::pin!(if $cond { ::core::option::Option::Some($fut) } else { ::core::option::Option::None });)+
+            let branches = [$(::core::stringify!($v)),+].len();
+            ::core::future::poll_fn(|cx| {
+                let start = if $biased { 0 } else { $crate::future::__select_start(branches) };
+                let mut pending = false;
+                for turn in 0..branches {
+                    let branch = (start + turn) % branches;
+                    let mut index = 0;
+                    $(
+                        if branch == index {
+                            if let ::core::option::Option::Some(future) = $f.as_mut().as_pin_mut() {
+                                match ::core::future::Future::poll(future, cx) {
+                                    ::core::task::Poll::Ready(output) => {
+                                        $f.set(::core::option::Option::None);
+                                        if ::core::matches!(&output, $pat) {
+                                            return ::core::task::Poll::Ready(__Output::$v(output));
+                                        }
+                                    }
+                                    ::core::task::Poll::Pending => pending = true,
+                                }
+                            }
+                        }
+                        index += 1;
+                    )+
+                }
+                if pending {
+                    ::core::task::Poll::Pending
+                } else {
+                    ::core::task::Poll::Ready(__Output::Disabled)
+                }
+            })
+            .await
+        };
+        match output {
+            $(__Output::$v($pat) => $handler,)+
+            __Output::Disabled => $else,
+            #[allow(unreachable_patterns)]
+            _ => ::core::unreachable!("`select!` output checked against its pattern"),
+        }
+    }};
+
+    (biased; $($branches:tt)*) => {
+        $crate::select!(@munch true; []
+            [(_0 __f0) (_1 __f1) (_2 __f2) (_3 __f3) (_4 __f4) (_5 __f5) (_6 __f6) (_7 __f7)
+             (_8 __f8) (_9 __f9) (_10 __f10) (_11 __f11) (_12 __f12) (_13 __f13) (_14 __f14)
+             (_15 __f15)]
+            $($branches)*)
+    };
+    ($($branches:tt)*) => {
+        $crate::select!(@munch false; []
+            [(_0 __f0) (_1 __f1) (_2 __f2) (_3 __f3) (_4 __f4) (_5 __f5) (_6 __f6) (_7 __f7)
+             (_8 __f8) (_9 __f9) (_10 __f10) (_11 __f11) (_12 __f12) (_13 __f13) (_14 __f14)
+             (_15 __f15)]
+            $($branches)*)
+    };
+}
+
+/// Where `select!` starts polling its branches from, picked at random.
+#[doc(hidden)]
+pub fn __select_start(branches: usize) -> usize {
+    rand::thread_rng().gen_range(0..branches)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::pin, sync::atomic::AtomicBool, time::Duration};
+
+    use super::*;
+    use crate::{
+        assert_pending, assert_ready_eq,
+        stream::{from_blocking_iter, StreamExt},
+        sync::Event,
+        test_util::{allocations_during, DropTracker, MockTask},
+        time, Executor, TimerFuture,
+    };
+
+    #[test]
+    fn pending_never_completes_nor_wakes() {
+        let mut task = MockTask::new();
+        let mut future = pending::<u32>();
+        for _ in 0..3 {
+            assert_pending!(task.poll(Pin::new(&mut future)));
+        }
+        assert!(!task.is_woken());
+    }
+
+    #[test]
+    fn ready_hands_out_its_value_once() {
+        let mut task = MockTask::new();
+        let mut future = ready(String::from("value"));
+        assert_ready_eq!(task.poll(Pin::new(&mut future)), "value");
+    }
+
+    #[test]
+    #[should_panic(expected = "`Ready` polled after completion")]
+    fn ready_panics_when_polled_again() {
+        let mut task = MockTask::new();
+        let mut future = ready(1);
+        let _ = task.poll(Pin::new(&mut future));
+        let _ = task.poll(Pin::new(&mut future));
+    }
+
+    #[test]
+    fn timeout_of_pending_elapses() {
+        let executor = Executor::new();
+        let outputs =
+            executor.block_on_all([time::timeout(Duration::from_millis(10), pending::<()>())]);
+        assert!(outputs[0].is_err());
+    }
+
+    #[test]
+    fn timeout_of_ready_completes() {
+        let mut task = MockTask::new();
+        let mut future = pin!(time::timeout(Duration::from_secs(1), ready(7)));
+        assert_ready_eq!(task.poll(future.as_mut()), Ok(7));
+    }
+
+    #[test]
+    fn shared_runs_once_for_every_clone() {
+        let executor = Executor::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = runs.clone();
+        let shared = async move {
+            counted.fetch_add(1, Ordering::SeqCst);
+            TimerFuture::new(Duration::from_millis(50)).await;
+            String::from("config")
+        }
+        .shared();
+        let handles: Vec<_> = (0..5).map(|_| executor.spawn(shared.clone())).collect();
+        // A clone dropped while the others wait takes nothing with it.
+        let dropped = shared.clone();
+        executor.spawn(async move {
+            let mut dropped = pin!(dropped);
+            let _ = crate::test_util::poll_once(&mut dropped).await;
+        });
+        executor.run();
+        for mut handle in handles {
+            assert_eq!(handle.try_take_output().unwrap().unwrap(), "config");
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Clones made after completion get the output too.
+        let mut task = MockTask::new();
+        let mut late = shared.clone();
+        assert_ready_eq!(task.poll(Pin::new(&mut late)), "config");
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn completion_completed_before_its_poll() {
+        let mut task = MockTask::new();
+        let (completer, mut completion) = completion();
+        let (_, allocations) = allocations_during(|| completer.complete(7).unwrap());
+        assert_eq!(allocations, 0);
+        assert_ready_eq!(task.poll(Pin::new(&mut completion)), Ok(7));
+    }
+
+    #[test]
+    fn first_completer_wins() {
+        let mut task = MockTask::new();
+        let (first, mut completion) = completion();
+        let second = first.clone();
+        assert_pending!(task.poll(Pin::new(&mut completion)));
+        assert_eq!(second.complete("second"), Ok(()));
+        assert!(task.is_woken());
+        assert!(first.is_completed());
+        assert_eq!(first.complete("first"), Err("first"));
+        assert_ready_eq!(task.poll(Pin::new(&mut completion)), Ok("second"));
+    }
+
+    #[test]
+    fn completion_fails_once_every_completer_is_dropped() {
+        let mut task = MockTask::new();
+        let (first, mut completion) = completion::<u32>();
+        let second = first.clone();
+        assert_pending!(task.poll(Pin::new(&mut completion)));
+        drop(first);
+        assert!(!task.is_woken());
+        drop(second);
+        assert!(task.is_woken());
+        assert_ready_eq!(task.poll(Pin::new(&mut completion)), Err(Dropped(())));
+    }
+
+    #[test]
+    fn completion_from_a_plain_thread_races_with_its_poll() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all((0..200).map(|i| {
+            let (completer, completion) = completion();
+            std::thread::spawn(move || completer.complete(i).unwrap());
+            completion
+        }));
+        let outputs: Vec<_> = outputs.into_iter().map(Result::unwrap).collect();
+        assert_eq!(outputs, (0..200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn select_loop_handles_messages_and_ticks_until_shut_down() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let shutdown = Arc::new(Event::new());
+        let stopper = shutdown.clone();
+        let thread = std::thread::spawn(move || {
+            for message in 0..5 {
+                std::thread::sleep(Duration::from_millis(15));
+                sender.send(message).unwrap();
+            }
+            std::thread::sleep(Duration::from_millis(30));
+            stopper.set();
+        });
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async move {
+            let mut messages = from_blocking_iter(receiver, 4);
+            let mut ticks = time::interval(Duration::from_millis(10));
+            let (mut received, mut ticked) = (Vec::new(), 0);
+            loop {
+                select! {
+                    Some(message) = messages.next() => received.push(message.unwrap()),
+                    _ = ticks.tick() => ticked += 1,
+                    _ = shutdown.wait() => break,
+                }
+            }
+            (received, ticked)
+        }]);
+        thread.join().unwrap();
+        let (received, ticked) = &outputs[0];
+        assert_eq!(received, &[0, 1, 2, 3, 4]);
+        assert!(*ticked >= 5, "{ticked} ticks");
+    }
+
+    #[test]
+    fn select_skips_disabled_and_mismatched_branches() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let evaluated = AtomicBool::new(false);
+            let guarded = select! {
+                _ = async { evaluated.store(true, Ordering::SeqCst) }, if false => "guarded",
+                Some(_) = ready(None::<u32>) => "mismatched",
+                _ = async {
+                    TimerFuture::new(Duration::from_millis(5)).await;
+                } => "timer",
+            };
+            let disabled = select! {
+                _ = ready(()), if false => "guarded",
+                Some(_) = ready(None::<u32>) => "mismatched",
+                else => "else",
+            };
+            (guarded, disabled, evaluated.load(Ordering::SeqCst))
+        }]);
+        assert_eq!(outputs, [("timer", "else", false)]);
+    }
+
+    #[test]
+    fn biased_select_polls_in_order_and_drops_the_losers_first() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let tracker = DropTracker::new();
+            let mut winners = Vec::new();
+            for round in 0..20 {
+                let label = format!("loser {round}");
+                let guard = tracker.guard(label.clone());
+                let loser = async move {
+                    let _guard = guard;
+                    pending::<()>().await
+                };
+                winners.push(select! {
+                    biased;
+                    _ = ready(()) => {
+                        assert!(tracker.is_dropped(&label));
+                        "first"
+                    },
+                    _ = ready(()) => "second",
+                    _ = loser => "loser",
+                });
+            }
+            winners
+        }]);
+        assert_eq!(outputs, [vec!["first"; 20]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "every `select!` branch is disabled")]
+    fn select_without_else_panics_once_every_branch_is_disabled() {
+        let mut task = MockTask::new();
+        let mut future = pin!(async {
+            select! {
+                _ = ready(()), if false => {},
+            }
+        });
+        let _ = task.poll(future.as_mut());
+    }
+
+    #[test]
+    fn aborted_completion_leaves_no_waker_behind() {
+        let executor = Executor::new();
+        let (completer, completion) = completion::<i32>();
+        let waiting = executor.spawn(completion);
+        let state = completer.state.clone();
+        executor.block_on_all([async move {
+            TimerFuture::new(Duration::from_millis(20)).await;
+            assert!(state.lock().unwrap().waker.is_some());
+            waiting.abort();
+            assert!(waiting.await.unwrap_err().is_cancelled());
+            assert!(state.lock().unwrap().waker.is_none());
+        }]);
+        assert_eq!(completer.complete(5), Ok(()));
+    }
+}
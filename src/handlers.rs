@@ -0,0 +1,128 @@
+use std::{
+    any::Any,
+    fmt::Display,
+    panic::{self, AssertUnwindSafe},
+    sync::{PoisonError, RwLock},
+};
+
+use crate::task::{JoinError, TaskMeta};
+
+type PanicHandlerFn = dyn Fn(&TaskMeta, Box<dyn Any + Send>) + Send + Sync;
+type ErrorHandlerFn = dyn Fn(&TaskMeta, &dyn Display) + Send + Sync;
+
+static PANIC_HANDLER: RwLock<Option<Box<PanicHandlerFn>>> = RwLock::new(None);
+static ERROR_HANDLER: RwLock<Option<Box<ErrorHandlerFn>>> = RwLock::new(None);
+
+/// Replace what happens to the panic of a task nobody can await anymore,
+/// because its `JoinHandle` was dropped, with `handler`. Applies to every
+/// executor of the process, including those built already.
+///
+/// By default the task and the panic message are printed to stderr. Like
+/// lifecycle hooks, the handler runs on the executor thread (or wherever
+/// the `JoinHandle` is dropped), and a panic in it is ignored.
+pub fn set_panic_handler(handler: impl Fn(&TaskMeta, Box<dyn Any + Send>) + Send + Sync + 'static) {
+    *PANIC_HANDLER
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(Box::new(handler));
+}
+
+/// Replace what happens to the error a `spawn_logged` task returns with
+/// `handler`, for every executor of the process. The executor's own
+/// diagnostics about a task go there too: deferred cleanups timing out,
+/// lost wakes (see `Builder::detect_lost_wakes`) and, in debug builds,
+/// tasks alive at shutdown (see `Builder::on_leaked_tasks`).
+///
+/// By default the task and the error are printed to stderr.
+pub fn set_error_handler(handler: impl Fn(&TaskMeta, &dyn Display) + Send + Sync + 'static) {
+    *ERROR_HANDLER
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(Box::new(handler));
+}
+
+/// Hand a detached task's panic to the panic handler.
+pub(crate) fn panicked(meta: &TaskMeta, payload: Box<dyn Any + Send>) {
+    let handler = PANIC_HANDLER.read().unwrap_or_else(PoisonError::into_inner);
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| match &*handler {
+        Some(handler) => handler(meta, payload),
+        None => eprintln!(
+            "{} panicked: {}",
+            describe(meta),
+            JoinError::panic_message(&*payload)
+        ),
+    }));
+}
+
+/// Hand a `spawn_logged` task's error, or a diagnostic about a task, to the
+/// error handler.
+pub(crate) fn failed(meta: &TaskMeta, error: &dyn Display) {
+    let handler = ERROR_HANDLER.read().unwrap_or_else(PoisonError::into_inner);
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| match &*handler {
+        Some(handler) => handler(meta, error),
+        None => eprintln!("{} failed: {error}", describe(meta)),
+    }));
+}
+
+fn describe(meta: &TaskMeta) -> String {
+    match meta.name() {
+        Some(name) => format!("task {} {name:?} spawned at {}", meta.id(), meta.location()),
+        None => format!("task {} spawned at {}", meta.id(), meta.location()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{Mutex, Once};
+
+    use super::*;
+    use crate::Executor;
+
+    /// What the handlers saw, with the file each task was spawned from, as
+    /// handlers are process-wide and tests run alongside.
+    static SEEN: Mutex<Vec<(&'static str, String)>> = Mutex::new(Vec::new());
+
+    /// Install handlers recording what they see, once for every test, for
+    /// `seen` to tell.
+    pub(crate) fn install() {
+        static INSTALLED: Once = Once::new();
+        INSTALLED.call_once(|| {
+            set_panic_handler(|meta, payload| {
+                let message = JoinError::panic_message(&*payload);
+                record(meta, format_args!("panic: {message}"));
+            });
+            set_error_handler(|meta, error| record(meta, format_args!("error: {error}")));
+        });
+    }
+
+    fn record(meta: &TaskMeta, what: impl Display) {
+        let seen = match meta.name() {
+            Some(name) => format!("{name}: {what}"),
+            None => what.to_string(),
+        };
+        SEEN.lock().unwrap().push((meta.location().file(), seen));
+    }
+
+    /// What the handlers saw of the tasks spawned from `file`, in order.
+    pub(crate) fn seen(file: &str) -> Vec<String> {
+        let seen = SEEN.lock().unwrap();
+        let from = seen.iter().filter(|(from, _)| *from == file);
+        from.map(|(_, seen)| seen.clone()).collect()
+    }
+
+    #[test]
+    fn only_detached_panics_and_logged_errors_reach_the_handlers() {
+        let executor = Executor::new();
+        // Built before the handlers are, which still apply to it.
+        install();
+        let handle = executor.handle();
+        drop(handle.spawn(async { panic!("detached") }));
+        let awaited = handle.spawn(async { panic!("awaited") });
+        drop(handle.spawn_logged(async { Err::<(), _>("logged") }));
+        drop(handle.spawn_logged(async { Ok::<(), &str>(()) }));
+        executor.run();
+        assert!(awaited.join_blocking().unwrap_err().is_panic());
+
+        let mut seen = seen(file!());
+        seen.sort();
+        assert_eq!(seen, ["error: logged", "panic: detached"]);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    fmt::{self, Debug},
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use crate::{time::TimerFuture, Handle};
+
+/// A boxed, type-erased future, as returned by `SleepProvider`.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Timers for code generic over the runtime it runs on, such as a retry
+/// helper taking any `impl SleepProvider`. `Handle` provides this
+/// executor's timers.
+pub trait SleepProvider {
+    /// A future completing once `duration` has passed.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+    /// A future completing once `deadline` is reached, right away if it
+    /// already passed.
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+    /// The current time, as timers see it.
+    fn now(&self) -> Instant;
+}
+
+impl SleepProvider for Handle {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(TimerFuture::new(duration))
+    }
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        self.sleep(deadline.saturating_duration_since(self.now()))
+    }
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Wrap `future` so that `handle`'s executor is the current one around each
+/// of its polls, as within `Executor::run`, whoever polls it: `task::spawn`
+/// and `Handle::current` then work inside it even on another runtime, or on
+/// a bare thread. The previous executor is restored after each poll, even
+/// one that panics.
+pub fn attach<F: Future>(handle: Handle, future: F) -> Attach<F> {
+    Attach { handle, future }
+}
+
+/// Future returned by `attach`.
+pub struct Attach<F> {
+    handle: Handle,
+    future: F,
+}
+impl<F: Future> Future for Attach<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: `future` is structurally pinned, it is never moved out;
+        // `handle` is not and is only used through `&`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let _enter = this.handle.enter();
+        unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx)
+    }
+}
+impl<F> Debug for Attach<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Attach").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        pin::pin,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use super::*;
+    use crate::{task, test_util::noop_waker, Executor};
+
+    /// Call `attempt` until it succeeds, sleeping 10ms, then 20ms, then
+    /// 40ms... in between, recording the time of each attempt.
+    async fn retry<P: SleepProvider>(
+        provider: &P,
+        mut attempt: impl FnMut() -> Result<u32, ()>,
+    ) -> (u32, Vec<Instant>) {
+        let mut backoff = Duration::from_millis(10);
+        let mut attempts = Vec::new();
+        loop {
+            attempts.push(provider.now());
+            if let Ok(value) = attempt() {
+                return (value, attempts);
+            }
+            provider.sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    /// Fails `failures` times, then succeeds.
+    fn flaky(mut failures: u32) -> impl FnMut() -> Result<u32, ()> {
+        move || match failures.checked_sub(1) {
+            Some(left) => {
+                failures = left;
+                Err(())
+            }
+            None => Ok(7),
+        }
+    }
+
+    /// Clock which only moves by the sleeps it is asked for, completing them
+    /// at once.
+    struct VirtualClock {
+        now: Arc<Mutex<Instant>>,
+    }
+    impl SleepProvider for VirtualClock {
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            *self.now.lock().unwrap() += duration;
+            Box::pin(async {})
+        }
+        fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+            let mut now = self.now.lock().unwrap();
+            *now = (*now).max(deadline);
+            Box::pin(async {})
+        }
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn retry_keeps_to_its_schedule_on_a_virtual_clock() {
+        let start = Instant::now();
+        let clock = VirtualClock {
+            now: Arc::new(Mutex::new(start)),
+        };
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async move { retry(&clock, flaky(3)).await }]);
+        let (value, attempts) = outputs.into_iter().next().unwrap();
+        assert_eq!(value, 7);
+        let at: Vec<_> = attempts.iter().map(|at| *at - start).collect();
+        let millis = |ms: &[u64]| {
+            ms.iter()
+                .map(|&ms| Duration::from_millis(ms))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(at, millis(&[0, 10, 30, 70]));
+    }
+
+    #[test]
+    fn retry_sleeps_on_the_handle_timers() {
+        let executor = Executor::new();
+        let handle = executor.handle();
+        let outputs = executor.block_on_all([async move { retry(&handle, flaky(3)).await }]);
+        let (value, attempts) = outputs.into_iter().next().unwrap();
+        assert_eq!(value, 7);
+        let gaps: Vec<_> = attempts.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        for (gap, backoff) in gaps.iter().zip([10, 20, 40]) {
+            let backoff = Duration::from_millis(backoff);
+            assert!(
+                *gap >= backoff && *gap < backoff + Duration::from_millis(50),
+                "{gaps:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn an_attached_future_runs_on_a_bare_thread() {
+        let executor = Executor::new();
+        let attached = attach(executor.handle(), async {
+            let child = task::spawn(async { 5 });
+            TimerFuture::new(Duration::from_millis(20)).await;
+            child.await.unwrap()
+        });
+        let polled = thread::spawn(move || {
+            let waker = noop_waker();
+            let mut cx = Context::from_waker(&waker);
+            let mut attached = pin!(attached);
+            loop {
+                if let Poll::Ready(output) = attached.as_mut().poll(&mut cx) {
+                    return output;
+                }
+                assert!(Handle::try_current().is_none());
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        // Runs the child, spawned from the bare thread at any time.
+        while !polled.is_finished() {
+            executor.run();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(polled.join().unwrap(), 5);
+        assert_eq!(executor.snapshot().timers.fired_total, 1);
+    }
+
+    #[test]
+    fn the_previous_executor_is_restored_after_a_panicking_poll() {
+        let executor = Executor::new();
+        let mut attached = pin!(attach(executor.handle(), async {
+            assert!(Handle::try_current().is_some());
+            panic!("in poll")
+        }));
+        let waker = noop_waker();
+        let polled = panic::catch_unwind(AssertUnwindSafe(|| {
+            attached.as_mut().poll(&mut Context::from_waker(&waker))
+        }));
+        assert!(polled.is_err());
+        assert!(Handle::try_current().is_none());
+    }
+}
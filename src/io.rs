@@ -0,0 +1,1161 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug},
+    future::{poll_fn, Future},
+    io::{self, BufRead, ErrorKind},
+    mem,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use crate::{counters::clone_waker, lock, stream::Stream};
+
+/// Size of the buffer `copy` moves bytes through.
+const COPY_BUF_SIZE: usize = 8 * 1024;
+/// Capacity of a `BufReader` created with `BufReader::new`.
+const DEFAULT_BUF_READER_CAPACITY: usize = 8 * 1024;
+
+/// Asynchronous counterpart of `std::io::Read`.
+pub trait AsyncRead {
+    /// Try to read bytes into `buf`, returning how many were read.
+    ///
+    /// `Ok(0)` with a non-empty `buf` means end of file; a reader with nothing
+    /// to give yet must return `Pending` instead, and wake `cx` later.
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>>;
+}
+
+/// Asynchronous counterpart of `std::io::Write`.
+pub trait AsyncWrite {
+    /// Try to write bytes from `buf`, returning how many were written, which
+    /// may be fewer than `buf.len()`.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>;
+    /// Try to push buffered bytes to their destination.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+    /// Try to flush, then shut down the writing side.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+/// Asynchronous counterpart of `std::io::BufRead`.
+pub trait AsyncBufRead: AsyncRead {
+    /// Try to get the contents of the internal buffer, filling it from the
+    /// underlying reader when empty. An empty slice means end of file.
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>>;
+    /// Mark the first `amount` bytes of the buffer as read.
+    fn consume(self: Pin<&mut Self>, amount: usize);
+}
+
+/// Line-oriented reading for every `AsyncBufRead`.
+pub trait AsyncBufReadExt: AsyncBufRead {
+    /// Read bytes into `buf` until `byte` or end of file, returning how many
+    /// were read; the delimiter is kept. `Ok(0)` means end of file.
+    ///
+    /// Bytes read before the future is dropped stay in `buf`.
+    fn read_until<'a>(&'a mut self, byte: u8, buf: &'a mut Vec<u8>) -> ReadUntil<'a, Self>
+    where
+        Self: Unpin,
+    {
+        ReadUntil {
+            reader: self,
+            byte,
+            buf,
+            read: 0,
+        }
+    }
+
+    /// Read a line into `buf`, newline included, returning how many bytes
+    /// were read. `Ok(0)` means end of file.
+    ///
+    /// A line which is not valid UTF-8 fails with `ErrorKind::InvalidData`,
+    /// leaving `buf` untouched; the error wraps the `FromUtf8Error` holding
+    /// the line's bytes. Dropping the future midway loses what it read.
+    fn read_line<'a>(&'a mut self, buf: &'a mut String) -> ReadLine<'a, Self>
+    where
+        Self: Unpin,
+    {
+        ReadLine {
+            reader: self,
+            buf,
+            bytes: Vec::new(),
+            read: 0,
+        }
+    }
+
+    /// Stream of the reader's lines, without their `\n` or `\r\n`. A last
+    /// line lacking a newline comes out too.
+    fn lines(self) -> Lines<Self>
+    where
+        Self: Sized,
+    {
+        Lines {
+            reader: self,
+            bytes: Vec::new(),
+            read: 0,
+        }
+    }
+}
+impl<R: AsyncBufRead + ?Sized> AsyncBufReadExt for R {}
+
+/// Copy everything `reader` produces into `writer`, then flush it.
+///
+/// Returns the number of bytes copied. Short writes are retried until the
+/// whole chunk is written, and reads interrupted by the OS are retried.
+///
+/// Not cancel safe: a chunk read but not yet written is lost if the future
+/// is dropped.
+pub async fn copy<R, W>(reader: &mut R, writer: &mut W) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = vec![0; COPY_BUF_SIZE];
+    let mut copied = 0;
+    loop {
+        let read = poll_fn(|cx| Pin::new(&mut *reader).poll_read(cx, &mut buf)).await;
+        let read = match read {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        write_all(writer, &buf[..read]).await?;
+        copied += read as u64;
+    }
+    poll_fn(|cx| Pin::new(&mut *writer).poll_flush(cx)).await?;
+    Ok(copied)
+}
+
+/// Like `copy`, but writes straight out of `reader`'s own buffer.
+///
+/// Unlike `copy`, this is cancel safe: bytes are only consumed from
+/// `reader` once written, so a later read picks up where it stopped.
+pub async fn copy_buf<R, W>(reader: &mut R, writer: &mut W) -> io::Result<u64>
+where
+    R: AsyncBufRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut copied = 0;
+    loop {
+        let written = poll_fn(|cx| {
+            let buf = match Pin::new(&mut *reader).poll_fill_buf(cx) {
+                Poll::Ready(Ok(buf)) => buf,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if buf.is_empty() {
+                return Poll::Ready(Ok(None));
+            }
+            match Pin::new(&mut *writer).poll_write(cx, buf) {
+                Poll::Ready(Ok(0)) => Poll::Ready(Err(ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(written)) => Poll::Ready(Ok(Some(written))),
+                Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+                Poll::Pending => Poll::Pending,
+            }
+        })
+        .await;
+        match written {
+            Ok(Some(written)) => {
+                Pin::new(&mut *reader).consume(written);
+                copied += written as u64;
+            }
+            Ok(None) => break,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    poll_fn(|cx| Pin::new(&mut *writer).poll_flush(cx)).await?;
+    Ok(copied)
+}
+
+/// Write the whole of `buf`, retrying short writes.
+async fn write_all<W>(writer: &mut W, mut buf: &[u8]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    while !buf.is_empty() {
+        match poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, buf)).await {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(written) => buf = &buf[written..],
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+/* BufReader */
+/// Adds a buffer to an `AsyncRead`, making it an `AsyncBufRead`.
+pub struct BufReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    /// Start of the unread bytes in `buf`.
+    pos: usize,
+    /// End of the unread bytes in `buf`.
+    filled: usize,
+}
+impl<R: AsyncRead> BufReader<R> {
+    /// Buffer `inner` through 8 KiB.
+    pub fn new(inner: R) -> Self {
+        Self::with_capacity(DEFAULT_BUF_READER_CAPACITY, inner)
+    }
+    pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        BufReader {
+            inner,
+            buf: vec![0; capacity].into_boxed_slice(),
+            pos: 0,
+            filled: 0,
+        }
+    }
+}
+impl<R> BufReader<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+    /// Reading from the inner reader directly skips the buffered bytes.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+    /// Give back the inner reader; whatever was buffered is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+    /// The bytes read from the inner reader but not consumed yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.filled]
+    }
+    // SAFETY: `inner` is structurally pinned, it is never moved out while
+    // pinned; the buffer and cursors are not.
+    fn project(self: Pin<&mut Self>) -> (Pin<&mut R>, &mut Box<[u8]>, &mut usize, &mut usize) {
+        let this = unsafe { self.get_unchecked_mut() };
+        (
+            unsafe { Pin::new_unchecked(&mut this.inner) },
+            &mut this.buf,
+            &mut this.pos,
+            &mut this.filled,
+        )
+    }
+}
+impl<R: AsyncRead> AsyncRead for BufReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        // Nothing buffered and a big enough read: skip the copy.
+        if self.pos == self.filled && buf.len() >= self.buf.len() {
+            let (inner, ..) = self.project();
+            return inner.poll_read(cx, buf);
+        }
+        let read = match self.as_mut().poll_fill_buf(cx) {
+            Poll::Ready(Ok(available)) => {
+                let read = available.len().min(buf.len());
+                buf[..read].copy_from_slice(&available[..read]);
+                read
+            }
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            Poll::Pending => return Poll::Pending,
+        };
+        self.consume(read);
+        Poll::Ready(Ok(read))
+    }
+}
+impl<R: AsyncRead> AsyncBufRead for BufReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let (inner, buf, pos, filled) = self.project();
+        if *pos == *filled {
+            match inner.poll_read(cx, buf) {
+                Poll::Ready(Ok(read)) => {
+                    *pos = 0;
+                    *filled = read;
+                }
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(&buf[*pos..*filled]))
+    }
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+        let (_, _, pos, filled) = self.project();
+        *pos = (*pos + amount).min(*filled);
+    }
+}
+impl<R: Debug> Debug for BufReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufReader")
+            .field("inner", &self.inner)
+            .field("buffered", &(self.filled - self.pos))
+            .field("capacity", &self.buf.len())
+            .finish()
+    }
+}
+
+/* ReadUntil */
+/// Future for [`AsyncBufReadExt::read_until`].
+#[derive(Debug)]
+pub struct ReadUntil<'a, R: ?Sized> {
+    reader: &'a mut R,
+    byte: u8,
+    buf: &'a mut Vec<u8>,
+    /// Bytes appended to `buf` so far.
+    read: usize,
+}
+impl<R: AsyncBufRead + Unpin + ?Sized> Future for ReadUntil<'_, R> {
+    type Output = io::Result<usize>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        poll_read_until(
+            Pin::new(&mut *this.reader),
+            cx,
+            this.byte,
+            this.buf,
+            &mut this.read,
+        )
+    }
+}
+
+/* ReadLine */
+/// Future for [`AsyncBufReadExt::read_line`].
+#[derive(Debug)]
+pub struct ReadLine<'a, R: ?Sized> {
+    reader: &'a mut R,
+    buf: &'a mut String,
+    /// The line so far, only checked for UTF-8 once complete.
+    bytes: Vec<u8>,
+    read: usize,
+}
+impl<R: AsyncBufRead + Unpin + ?Sized> Future for ReadLine<'_, R> {
+    type Output = io::Result<usize>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let reader = Pin::new(&mut *this.reader);
+        let read = match poll_read_until(reader, cx, b'\n', &mut this.bytes, &mut this.read) {
+            Poll::Ready(Ok(read)) => read,
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            Poll::Pending => return Poll::Pending,
+        };
+        let line = into_string(mem::take(&mut this.bytes))?;
+        this.buf.push_str(&line);
+        Poll::Ready(Ok(read))
+    }
+}
+
+/* Lines */
+/// Stream for [`AsyncBufReadExt::lines`].
+#[derive(Debug)]
+pub struct Lines<R> {
+    reader: R,
+    bytes: Vec<u8>,
+    read: usize,
+}
+impl<R> Lines<R> {
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+impl<R: AsyncBufRead> Stream for Lines<R> {
+    type Item = io::Result<String>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: `reader` is structurally pinned, it is never moved out
+        // while pinned; the line buffer is not.
+        let this = unsafe { self.get_unchecked_mut() };
+        let reader = unsafe { Pin::new_unchecked(&mut this.reader) };
+        match poll_read_until(reader, cx, b'\n', &mut this.bytes, &mut this.read) {
+            Poll::Ready(Ok(0)) => return Poll::Ready(None),
+            Poll::Ready(Ok(_)) => {}
+            Poll::Ready(Err(error)) => return Poll::Ready(Some(Err(error))),
+            Poll::Pending => return Poll::Pending,
+        }
+        let mut bytes = mem::take(&mut this.bytes);
+        if bytes.ends_with(b"\n") {
+            bytes.pop();
+            if bytes.ends_with(b"\r") {
+                bytes.pop();
+            }
+        }
+        Poll::Ready(Some(into_string(bytes)))
+    }
+}
+
+/// Move bytes from `reader` into `buf` up to and including `byte`, keeping
+/// count in `read` across polls. Returns the count once done, resetting it.
+fn poll_read_until<R: AsyncBufRead + ?Sized>(
+    mut reader: Pin<&mut R>,
+    cx: &mut Context<'_>,
+    byte: u8,
+    buf: &mut Vec<u8>,
+    read: &mut usize,
+) -> Poll<io::Result<usize>> {
+    loop {
+        let (done, used) = match reader.as_mut().poll_fill_buf(cx) {
+            Poll::Ready(Ok(available)) => match available.iter().position(|&b| b == byte) {
+                Some(end) => {
+                    buf.extend_from_slice(&available[..=end]);
+                    (true, end + 1)
+                }
+                None => {
+                    buf.extend_from_slice(available);
+                    (available.is_empty(), available.len())
+                }
+            },
+            Poll::Ready(Err(error)) if error.kind() == ErrorKind::Interrupted => continue,
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            Poll::Pending => return Poll::Pending,
+        };
+        reader.as_mut().consume(used);
+        *read += used;
+        if done {
+            return Poll::Ready(Ok(mem::take(read)));
+        }
+    }
+}
+
+/// A line's bytes as a `String`, or an `InvalidData` error keeping them.
+fn into_string(bytes: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(bytes).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+/* Duplex */
+/// Two connected in-memory streams: bytes written to one can be read from
+/// the other, both ways. Handy to test I/O code without sockets.
+///
+/// At most `max_buf_size` bytes are in flight each way; beyond that writes
+/// wait for the peer to read. Dropping one end makes the peer read end of
+/// file, once it drained what was written, and fail writes with
+/// `BrokenPipe`.
+pub fn duplex(max_buf_size: usize) -> (DuplexStream, DuplexStream) {
+    assert!(max_buf_size > 0, "duplex buffer size must be at least 1");
+    let one = Arc::new(Mutex::new(Pipe::new(max_buf_size)));
+    let two = Arc::new(Mutex::new(Pipe::new(max_buf_size)));
+    (
+        DuplexStream {
+            read: one.clone(),
+            write: two.clone(),
+        },
+        DuplexStream {
+            read: two,
+            write: one,
+        },
+    )
+}
+
+/// One end of a `duplex` pipe.
+pub struct DuplexStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+/// One direction of a `duplex` pipe.
+struct Pipe {
+    buf: VecDeque<u8>,
+    max_buf_size: usize,
+    /// Waiting for bytes to read.
+    read_waker: Option<Waker>,
+    /// Waiting for room to write.
+    write_waker: Option<Waker>,
+    /// Set once the writing end is closed or dropped.
+    write_closed: bool,
+    /// Set once the reading end is dropped.
+    read_closed: bool,
+}
+impl Pipe {
+    fn new(max_buf_size: usize) -> Self {
+        Pipe {
+            buf: VecDeque::new(),
+            max_buf_size,
+            read_waker: None,
+            write_waker: None,
+            write_closed: false,
+            read_closed: false,
+        }
+    }
+    /// Returns the reader's waker, to wake once the lock is released.
+    #[must_use]
+    fn close_write(&mut self) -> Option<Waker> {
+        self.write_closed = true;
+        self.read_waker.take()
+    }
+}
+impl AsyncRead for DuplexStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = lock(&self.read);
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if pipe.buf.is_empty() {
+            if pipe.write_closed {
+                return Poll::Ready(Ok(0));
+            }
+            pipe.read_waker = Some(clone_waker(cx.waker()));
+            return Poll::Pending;
+        }
+        let len = buf.len().min(pipe.buf.len());
+        for (byte, read) in buf.iter_mut().zip(pipe.buf.drain(..len)) {
+            *byte = read;
+        }
+        let waker = pipe.write_waker.take();
+        drop(pipe);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Poll::Ready(Ok(len))
+    }
+}
+impl AsyncWrite for DuplexStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = lock(&self.write);
+        if pipe.read_closed || pipe.write_closed {
+            return Poll::Ready(Err(ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let room = pipe.max_buf_size - pipe.buf.len();
+        if room == 0 {
+            pipe.write_waker = Some(clone_waker(cx.waker()));
+            return Poll::Pending;
+        }
+        let len = buf.len().min(room);
+        pipe.buf.extend(&buf[..len]);
+        let waker = pipe.read_waker.take();
+        drop(pipe);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Poll::Ready(Ok(len))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    /// Shut down this direction: the peer reads end of file once it drained
+    /// the buffer, while this end can still read.
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let waker = lock(&self.write).close_write();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        let reader = lock(&self.write).close_write();
+        let writer = {
+            let mut read = lock(&self.read);
+            read.read_closed = true;
+            read.buf.clear();
+            read.write_waker.take()
+        };
+        for waker in reader.into_iter().chain(writer) {
+            waker.wake();
+        }
+    }
+}
+impl Debug for DuplexStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DuplexStream")
+            .field("readable", &lock(&self.read).buf.len())
+            .field("in_flight", &lock(&self.write).buf.len())
+            .finish()
+    }
+}
+
+/* LineReader */
+/// Lines read ahead of `read_line` by the reader thread.
+const LINE_BUF_LINES: usize = 16;
+
+/// Reads lines from a blocking source on a thread of its own, so they can
+/// be awaited like any other future, and raced against a timer.
+///
+/// Dropping the reader stops its thread at the next line boundary, once the
+/// blocking read in progress returns.
+pub struct LineReader {
+    shared: Arc<LineShared>,
+}
+
+struct LineShared {
+    state: Mutex<LineState>,
+    /// Signalled when the reader thread may push again: a line was taken, or
+    /// the `LineReader` was dropped.
+    space: Condvar,
+}
+
+struct LineState {
+    /// Lines read so far but not yet returned; `Ok(None)` marks end of file.
+    lines: VecDeque<io::Result<Option<String>>>,
+    waker: Option<Waker>,
+    /// Set once the `LineReader` is dropped.
+    closed: bool,
+}
+
+/// Lines of the process's standard input, see `LineReader`.
+pub fn stdin() -> LineReader {
+    LineReader::spawn(|buf| io::stdin().read_line(buf))
+}
+
+impl LineReader {
+    /// Read the lines of `reader` on a new thread.
+    pub fn new(mut reader: impl BufRead + Send + 'static) -> Self {
+        Self::spawn(move |buf| reader.read_line(buf))
+    }
+    fn spawn(mut read_line: impl FnMut(&mut String) -> io::Result<usize> + Send + 'static) -> Self {
+        let shared = Arc::new(LineShared {
+            state: Mutex::new(LineState {
+                lines: VecDeque::new(),
+                waker: None,
+                closed: false,
+            }),
+            space: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        thread::Builder::new()
+            .name("line-reader".to_string())
+            .spawn(move || loop {
+                let mut line = String::new();
+                let line = match read_line(&mut line) {
+                    Ok(0) => Ok(None),
+                    Ok(_) => {
+                        let len = line.trim_end_matches(['\n', '\r']).len();
+                        line.truncate(len);
+                        Ok(Some(line))
+                    }
+                    Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                    Err(error) => Err(error),
+                };
+                let last = !matches!(line, Ok(Some(_)));
+                let state = lock(&thread_shared.state);
+                let mut state = thread_shared
+                    .space
+                    .wait_while(state, |state| {
+                        !state.closed && state.lines.len() >= LINE_BUF_LINES
+                    })
+                    .unwrap_or_else(PoisonError::into_inner);
+                if state.closed {
+                    break;
+                }
+                let failed = line.is_err();
+                state.lines.push_back(line);
+                if failed {
+                    // Nothing is read after an error, so end the lines there.
+                    state.lines.push_back(Ok(None));
+                }
+                let waker = state.waker.take();
+                drop(state);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+                if last {
+                    break;
+                }
+            })
+            .expect("failed to spawn the line reader thread");
+        LineReader { shared }
+    }
+    /// Wait for the next line, without its line ending; `None` at end of
+    /// file.
+    ///
+    /// Cancel safe: a line is only taken once this completes.
+    pub async fn read_line(&mut self) -> io::Result<Option<String>> {
+        poll_fn(|cx| self.poll_read_line(cx)).await
+    }
+    pub fn poll_read_line(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<String>>> {
+        let mut state = lock(&self.shared.state);
+        match state.lines.front() {
+            // End of file stays put, for every later call to see too.
+            Some(Ok(None)) => Poll::Ready(Ok(None)),
+            Some(_) => {
+                let line = state.lines.pop_front().unwrap();
+                self.shared.space.notify_one();
+                Poll::Ready(line)
+            }
+            None => {
+                state.waker = Some(clone_waker(cx.waker()));
+                Poll::Pending
+            }
+        }
+    }
+}
+impl Stream for LineReader {
+    type Item = io::Result<String>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_read_line(cx).map(Result::transpose)
+    }
+}
+impl Drop for LineReader {
+    fn drop(&mut self) {
+        lock(&self.shared.state).closed = true;
+        self.shared.space.notify_one();
+    }
+}
+impl Debug for LineReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LineReader").finish_non_exhaustive()
+    }
+}
+
+/* Forwarding impls */
+impl<T: AsyncRead + Unpin + ?Sized> AsyncRead for &mut T {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_read(cx, buf)
+    }
+}
+impl<T: AsyncRead + Unpin + ?Sized> AsyncRead for Box<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_read(cx, buf)
+    }
+}
+impl<T: AsyncWrite + Unpin + ?Sized> AsyncWrite for &mut T {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_close(cx)
+    }
+}
+impl<T: AsyncWrite + Unpin + ?Sized> AsyncWrite for Box<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_close(cx)
+    }
+}
+impl<T: AsyncBufRead + Unpin + ?Sized> AsyncBufRead for &mut T {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut **self.get_mut()).poll_fill_buf(cx)
+    }
+    fn consume(mut self: Pin<&mut Self>, amount: usize) {
+        Pin::new(&mut **self).consume(amount)
+    }
+}
+
+/* In-memory impls */
+impl AsyncRead for &[u8] {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Read::read(&mut *self, buf))
+    }
+}
+impl AsyncBufRead for &[u8] {
+    fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Poll::Ready(Ok(*self.get_mut()))
+    }
+    fn consume(mut self: Pin<&mut Self>, amount: usize) {
+        *self = &self[amount..];
+    }
+}
+impl AsyncWrite for Vec<u8> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::mpsc, time::Duration};
+
+    use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+
+    use super::*;
+    use crate::{
+        assert_pending, assert_ready, assert_ready_eq, stream::StreamExt, test_util::MockTask,
+        time::timeout, Executor,
+    };
+
+    /// Reads and writes at most a random number of bytes at a time, and
+    /// sometimes returns `Pending` first.
+    struct Chunky<T> {
+        inner: T,
+        rng: StdRng,
+    }
+    impl<T> Chunky<T> {
+        fn new(inner: T, seed: u64) -> Self {
+            Chunky {
+                inner,
+                rng: StdRng::seed_from_u64(seed),
+            }
+        }
+        /// How many bytes of `len` to let through now, if any.
+        fn chunk(&mut self, cx: &mut Context<'_>, len: usize) -> Option<usize> {
+            if self.rng.gen_bool(0.1) {
+                cx.waker().wake_by_ref();
+                return None;
+            }
+            Some(len.min(self.rng.gen_range(1..=5000)))
+        }
+    }
+    impl<T: AsyncRead + Unpin> AsyncRead for Chunky<T> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let Some(len) = this.chunk(cx, buf.len()) else {
+                return Poll::Pending;
+            };
+            Pin::new(&mut this.inner).poll_read(cx, &mut buf[..len])
+        }
+    }
+    impl<T: AsyncWrite + Unpin> AsyncWrite for Chunky<T> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let Some(len) = this.chunk(cx, buf.len()) else {
+                return Poll::Pending;
+            };
+            Pin::new(&mut this.inner).poll_write(cx, &buf[..len])
+        }
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+        }
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_close(cx)
+        }
+    }
+
+    fn random_bytes(len: usize) -> Arc<Vec<u8>> {
+        let mut bytes = vec![0; len];
+        StdRng::seed_from_u64(len as u64).fill_bytes(&mut bytes);
+        Arc::new(bytes)
+    }
+
+    #[test]
+    fn copy_through_pipes_with_random_chunks() {
+        let data = random_bytes(10 * 1024 * 1024);
+        let executor = Executor::new();
+        let (writing, reading) = duplex(64 * 1024);
+        let source = data.clone();
+        let sent = executor.spawn(async move {
+            let mut reader = Chunky::new(&source[..], 1);
+            let mut writer = Chunky::new(writing, 2);
+            let copied = copy(&mut reader, &mut writer).await;
+            // Dropping the writer makes the other side read end of file.
+            drop(writer);
+            copied
+        });
+        let received = executor.spawn(async move {
+            let mut reader = Chunky::new(reading, 3);
+            let mut writer = Chunky::new(Vec::new(), 4);
+            let copied = copy(&mut reader, &mut writer).await;
+            copied.map(|copied| (copied, writer.inner))
+        });
+        executor.run();
+        assert_eq!(sent.join_blocking().unwrap().unwrap(), data.len() as u64);
+        let (copied, bytes) = received.join_blocking().unwrap().unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert!(bytes == *data);
+    }
+
+    #[test]
+    fn copy_buf_from_a_buffered_reader() {
+        let data = random_bytes(1024 * 1024);
+        let executor = Executor::new();
+        let source = data.clone();
+        let copied = executor.spawn(async move {
+            let mut reader = BufReader::with_capacity(4096, Chunky::new(&source[..], 5));
+            let mut writer = Chunky::new(Vec::new(), 6);
+            let copied = copy_buf(&mut reader, &mut writer).await;
+            copied.map(|copied| (copied, writer.inner))
+        });
+        executor.run();
+        let (copied, bytes) = copied.join_blocking().unwrap().unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert!(bytes == *data);
+    }
+
+    /// Blocking reader of the chunks sent to it, telling `stopped` once
+    /// dropped.
+    struct ChannelReader {
+        chunks: mpsc::Receiver<&'static [u8]>,
+        current: &'static [u8],
+        stopped: mpsc::Sender<()>,
+    }
+    impl Read for ChannelReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.current.is_empty() {
+                // End of file once the sender is gone.
+                self.current = self.chunks.recv().unwrap_or_default();
+            }
+            self.current.read(buf)
+        }
+    }
+    impl Drop for ChannelReader {
+        fn drop(&mut self) {
+            let _ = self.stopped.send(());
+        }
+    }
+
+    fn channel_lines() -> (LineReader, mpsc::Sender<&'static [u8]>, mpsc::Receiver<()>) {
+        let (sender, chunks) = mpsc::channel();
+        let (stopped, on_stop) = mpsc::channel();
+        let reader = ChannelReader {
+            chunks,
+            current: &[],
+            stopped,
+        };
+        (LineReader::new(io::BufReader::new(reader)), sender, on_stop)
+    }
+
+    #[test]
+    fn line_reader_splits_lines_until_end_of_file() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let (mut lines, input, _) = channel_lines();
+            input.send(b"one\ntw").unwrap();
+            input.send(b"o\r\n\nlast").unwrap();
+            drop(input);
+            let mut read = Vec::new();
+            for _ in 0..6 {
+                read.push(lines.read_line().await.unwrap());
+            }
+            read
+        }]);
+        let expected = [Some("one"), Some("two"), Some(""), Some("last"), None, None];
+        assert_eq!(outputs[0], expected.map(|line| line.map(str::to_string)));
+    }
+
+    #[test]
+    fn line_reader_races_a_timeout() {
+        let executor = Executor::new();
+        let (mut lines, input, _) = channel_lines();
+        let outputs = executor.block_on_all([async move {
+            // Nothing typed yet: the prompt times out, and loses no line.
+            let prompt = timeout(Duration::from_millis(20), lines.read_line()).await;
+            input.send(b"answer\n").unwrap();
+            let answer = lines.read_line().await.unwrap();
+            (prompt.is_err(), answer)
+        }]);
+        assert_eq!(outputs, [(true, Some("answer".to_string()))]);
+    }
+
+    #[test]
+    fn dropped_line_reader_stops_at_the_next_line() {
+        let (lines, input, on_stop) = channel_lines();
+        drop(lines);
+        // Still blocked in the read of the next line.
+        assert!(on_stop.recv_timeout(Duration::from_millis(20)).is_err());
+        input.send(b"ignored\n").unwrap();
+        on_stop.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    /// Hands out at most three bytes per read.
+    struct Trickle(&'static [u8]);
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let len = buf.len().min(3);
+            Pin::new(&mut self.0).poll_read(cx, &mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn lines_split_across_fills() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let input = "first\r\nsecond line\n\nlast without newline";
+            let lines = BufReader::with_capacity(4, Trickle(input.as_bytes())).lines();
+            lines.collect::<Vec<_>>().await
+        }]);
+        let lines: Vec<_> = outputs.into_iter().flatten().map(Result::unwrap).collect();
+        assert_eq!(lines, ["first", "second line", "", "last without newline"]);
+    }
+
+    #[test]
+    fn read_until_and_read_line_keep_their_delimiters() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let mut reader = BufReader::with_capacity(5, Trickle(b"key=value;rest\nend"));
+            let mut field = Vec::new();
+            reader.read_until(b';', &mut field).await.unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            let mut last = String::new();
+            reader.read_line(&mut last).await.unwrap();
+            let at_end = reader.read_line(&mut String::new()).await.unwrap();
+            (field, line, last, at_end)
+        }]);
+        let (field, line, last, at_end) = &outputs[0];
+        assert_eq!(field, b"key=value;");
+        assert_eq!(line, "rest\n");
+        assert_eq!(last, "end");
+        assert_eq!(*at_end, 0);
+    }
+
+    #[test]
+    fn invalid_utf8_line_fails_keeping_its_bytes() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let mut reader = BufReader::with_capacity(4, Trickle(b"ok\n\xff\xfe\nafter\n"));
+            let mut buf = String::new();
+            reader.read_line(&mut buf).await.unwrap();
+            let error = reader.read_line(&mut buf).await.unwrap_err();
+            let kept = buf.clone();
+            reader.read_line(&mut buf).await.unwrap();
+            (error, kept, buf)
+        }]);
+        let (error, kept, buf) = &outputs[0];
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let utf8 = error.get_ref().unwrap();
+        let utf8 = utf8.downcast_ref::<std::string::FromUtf8Error>().unwrap();
+        assert_eq!(utf8.as_bytes(), b"\xff\xfe\n");
+        assert_eq!(kept, "ok\n");
+        assert_eq!(buf, "ok\nafter\n");
+    }
+
+    fn poll_write(
+        task: &mut MockTask,
+        stream: &mut DuplexStream,
+        bytes: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        task.poll(Pin::new(&mut poll_fn(|cx| {
+            Pin::new(&mut *stream).poll_write(cx, bytes)
+        })))
+    }
+    fn poll_read(
+        task: &mut MockTask,
+        stream: &mut DuplexStream,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        task.poll(Pin::new(&mut poll_fn(|cx| {
+            Pin::new(&mut *stream).poll_read(cx, buf)
+        })))
+    }
+
+    #[test]
+    fn duplex_writes_wait_for_room_and_reads_for_bytes() {
+        let (mut near, mut far) = duplex(4096);
+        let (mut writer, mut reader) = (MockTask::new(), MockTask::new());
+        let mut buf = [0; 1024];
+        assert_pending!(poll_read(&mut reader, &mut far, &mut buf));
+
+        assert_ready_eq!(
+            poll_write(&mut writer, &mut near, &[1; 5000]).map(Result::unwrap),
+            4096
+        );
+        assert!(reader.is_woken());
+        assert_pending!(poll_write(&mut writer, &mut near, &[2]));
+
+        assert_ready_eq!(
+            poll_read(&mut reader, &mut far, &mut buf).map(Result::unwrap),
+            1024
+        );
+        assert!(writer.is_woken());
+        assert_ready_eq!(
+            poll_write(&mut writer, &mut near, &[2; 2048]).map(Result::unwrap),
+            1024
+        );
+    }
+
+    #[test]
+    fn a_million_bytes_through_a_small_duplex() {
+        let data = random_bytes(1024 * 1024);
+        let executor = Executor::new();
+        let (mut writing, mut reading) = duplex(4096);
+        let source = data.clone();
+        let producer = executor.spawn(async move { copy(&mut &source[..], &mut writing).await });
+        let consumer = executor.spawn(async move {
+            let mut received = Vec::new();
+            copy(&mut reading, &mut received).await.map(|_| received)
+        });
+        executor.run();
+        assert_eq!(
+            producer.join_blocking().unwrap().unwrap(),
+            data.len() as u64
+        );
+        assert!(consumer.join_blocking().unwrap().unwrap() == *data);
+    }
+
+    #[test]
+    fn dropping_one_end_ends_both_directions() {
+        let mut task = MockTask::new();
+        let mut buf = [0; 16];
+        for drop_near in [true, false] {
+            let (near, far) = duplex(64);
+            let (gone, mut left) = if drop_near { (near, far) } else { (far, near) };
+            assert_pending!(poll_read(&mut task, &mut left, &mut buf));
+            drop(gone);
+            assert!(task.is_woken());
+            assert_ready_eq!(
+                poll_read(&mut task, &mut left, &mut buf).map(Result::unwrap),
+                0
+            );
+
+            let (near, far) = duplex(64);
+            let (mut gone, mut left) = if drop_near { (near, far) } else { (far, near) };
+            assert_ready!(poll_write(&mut task, &mut gone, b"last words")).unwrap();
+            drop(gone);
+
+            // What was written still comes out, then end of file.
+            assert_ready_eq!(
+                poll_read(&mut task, &mut left, &mut buf).map(Result::unwrap),
+                10
+            );
+            assert_eq!(&buf[..10], b"last words");
+            assert_ready_eq!(
+                poll_read(&mut task, &mut left, &mut buf).map(Result::unwrap),
+                0
+            );
+            let error = assert_ready!(poll_write(&mut task, &mut left, b"anyone?")).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+        }
+    }
+}
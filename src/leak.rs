@@ -0,0 +1,172 @@
+use std::{
+    backtrace::Backtrace,
+    fmt::{self, Display},
+    panic::Location,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use crate::task::{Task, TaskId};
+
+/// Tasks which were still alive when their executor shut down, as returned
+/// by `Executor::shutdown` and handed to `Builder::on_leaked_tasks`.
+#[derive(Clone, Debug, Default)]
+pub struct LeakReport {
+    /// Oldest first. The executor's own background tasks are left out.
+    pub tasks: Vec<LeakedTask>,
+}
+impl LeakReport {
+    pub(crate) fn new<'a>(tasks: impl IntoIterator<Item = &'a Task>) -> Self {
+        let now = Instant::now();
+        let mut tasks: Vec<_> = tasks
+            .into_iter()
+            .filter(|task| !task.meta.background)
+            .map(|task| LeakedTask {
+                id: task.meta.id(),
+                name: task.meta.name().map(str::to_string),
+                runtime: task.meta.runtime().map(str::to_string),
+                location: task.meta.location(),
+                spawn_backtrace: task.meta.spawn_backtrace.clone(),
+                age: now.saturating_duration_since(task.meta.spawned_at()),
+                last_polled: task.meta.last_polled_at(),
+                aborted: task.aborted.load(Ordering::Acquire),
+            })
+            .collect();
+        tasks.sort_by_key(|task| task.id);
+        LeakReport { tasks }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+    /// Tasks which were simply never going to finish, leaving out those
+    /// aborted but not dropped yet.
+    pub fn pending(&self) -> impl Iterator<Item = &LeakedTask> {
+        self.tasks.iter().filter(|task| !task.aborted)
+    }
+}
+impl Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} task(s) still alive at shutdown", self.tasks.len())?;
+        for task in &self.tasks {
+            write!(f, "\n  {task}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A task which had not finished when its executor shut down.
+#[derive(Clone, Debug)]
+pub struct LeakedTask {
+    pub id: TaskId,
+    pub name: Option<String>,
+    /// Name of the `ChildRuntime` it belonged to, if any.
+    pub runtime: Option<String>,
+    /// Where the task was spawned from.
+    pub location: &'static Location<'static>,
+    /// Set if built with `Builder::capture_spawn_backtraces`, and printed
+    /// after the rest.
+    pub spawn_backtrace: Option<Arc<Backtrace>>,
+    /// How long ago the task was spawned.
+    pub age: Duration,
+    /// When its latest poll began, if it was ever polled.
+    pub last_polled: Option<Instant>,
+    /// Set if the task had been aborted through its `JoinHandle` and was
+    /// only waiting to be dropped.
+    pub aborted: bool,
+}
+impl Display for LeakedTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name:?}")?;
+        }
+        if let Some(runtime) = &self.runtime {
+            write!(f, " in {runtime:?}")?;
+        }
+        write!(f, " spawned at {} {:?} ago", self.location, self.age)?;
+        match self.last_polled {
+            Some(at) => write!(f, ", last polled {:?} ago", at.elapsed())?,
+            None => write!(f, ", never polled")?,
+        }
+        if self.aborted {
+            write!(f, ", aborted")?;
+        }
+        if let Some(backtrace) = &self.spawn_backtrace {
+            write!(f, ", spawned by:\n{backtrace}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future, sync::Mutex};
+
+    use crate::{handlers, Builder, Executor};
+
+    #[test]
+    fn report_names_a_forever_pending_task() {
+        let executor = Executor::new();
+        let handle = executor.handle();
+        let line = line!() + 1;
+        drop(handle.spawn_named("stuck", future::pending::<()>()));
+        let aborted = executor.spawn(future::pending::<()>());
+        // Aborted, but dropped by its next poll only, which never comes.
+        aborted.abort();
+
+        let report = executor.shutdown();
+        assert_eq!(report.tasks.len(), 2);
+        let stuck = report.pending().next().unwrap();
+        assert_eq!(stuck.name.as_deref(), Some("stuck"));
+        assert_eq!(stuck.location.file(), file!());
+        assert_eq!(stuck.location.line(), line);
+        assert!(stuck.last_polled.is_none());
+        assert!(report.tasks.iter().any(|task| task.aborted));
+        assert_eq!(report.pending().count(), 1);
+        assert!(report.to_string().contains("\"stuck\""));
+    }
+
+    #[test]
+    fn dropping_the_executor_reports_to_the_hook() {
+        let reports = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let hook_reports = reports.clone();
+        let executor = Builder::new()
+            .on_leaked_tasks(move |report| hook_reports.lock().unwrap().push(report.clone()))
+            .build();
+        drop(executor.spawn(future::pending::<()>()));
+        drop(executor);
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].tasks.len(), 1);
+    }
+
+    #[test]
+    fn nothing_is_reported_once_every_task_finished() {
+        let executor = Builder::new()
+            .on_leaked_tasks(|report| panic!("unexpected report: {report}"))
+            .build();
+        drop(executor.spawn(async {}));
+        executor.run();
+        assert!(executor.shutdown().is_empty());
+    }
+
+    #[test]
+    fn without_a_hook_leaks_go_to_the_error_handler_in_debug_builds() {
+        handlers::tests::install();
+        let executor = Executor::new();
+        drop(
+            executor
+                .handle()
+                .spawn_named("forgotten", future::pending::<()>()),
+        );
+        drop(executor);
+        let seen = handlers::tests::seen(file!());
+        let reported = seen.iter().filter(|seen| seen.starts_with("forgotten: "));
+        let expected =
+            cfg!(debug_assertions).then_some("forgotten: error: still alive at shutdown");
+        assert_eq!(
+            reported.map(String::as_str).collect::<Vec<_>>(),
+            Vec::from_iter(expected)
+        );
+    }
+}
@@ -0,0 +1,1926 @@
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    future::Future,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe, Location},
+    pin::{pin, Pin},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError, TryLockError, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+pub mod alloc_hooks;
+/// Operation counts of the executor on fixed workloads, for the benches,
+/// enabled by the `bench-counters` feature.
+#[cfg(any(test, feature = "bench-counters"))]
+pub mod bench_util;
+mod blocking;
+mod builder;
+#[cfg(test)]
+mod cancel_safety;
+pub mod chaos;
+pub mod child;
+pub mod codec;
+/// Checks of the executor's contract, for custom schedulers, enabled by the
+/// `test-util` feature.
+#[cfg(any(test, feature = "test-util"))]
+pub mod conformance;
+pub mod coop;
+mod counters;
+pub mod future;
+mod handlers;
+pub mod interop;
+pub mod io;
+pub mod leak;
+pub mod limiter;
+pub mod metrics;
+pub mod periodic;
+pub mod permit;
+pub mod progress;
+pub mod scheduler;
+pub mod signal;
+pub mod sink;
+pub mod snapshot;
+pub mod stream;
+pub mod sync;
+pub mod task;
+/// Helpers for unit testing futures by hand, enabled by the `test-util`
+/// feature.
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod time;
+pub mod util;
+pub mod watchdog;
+
+use blocking::BlockingPool;
+use builder::Hooks;
+pub use builder::{Builder, PanicBehavior};
+use chaos::{Chaos, ChaosStats};
+use child::{ChildRuntime, Group};
+pub use handlers::{set_error_handler, set_panic_handler};
+use interop::BoxFuture;
+use leak::LeakReport;
+use limiter::{Bucket, Limiter};
+use metrics::{Gauges, Metrics, MetricsSnapshot};
+use periodic::{Overlap, PeriodicHandle};
+use permit::{AcquirePermit, Permits};
+use progress::{ProgressReceiver, ProgressSender};
+use scheduler::{Aging, Scheduler, WakeRouter};
+use snapshot::RuntimeSnapshot;
+use task::{
+    JoinError, JoinHandle, Outcome, Priority, SharedOutput, SpawnFn, Task, TaskContext, TaskFuture,
+    TaskId, TaskMeta,
+};
+use time::TimerDriver;
+pub use time::TimerFuture;
+use watchdog::{PollingTask, Watchdog, WatchdogReport};
+
+/// Lets tests count allocations, see `test_util::allocations_during`.
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: test_util::CountingAllocator = test_util::CountingAllocator::new();
+
+/// Lock `mutex` even if a panic poisoned it. The executor calls user code
+/// (futures, wakers, hooks) with none of its locks held, or catches its
+/// panics before state is left half-updated, so what a lock guards stays
+/// sound either way; unwrapping would only turn one panic into many.
+pub(crate) fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    counters::lock_acquired();
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub struct Executor {
+    inner: Arc<Inner>,
+}
+
+/// State shared by the executor, its handles and every task it owns.
+pub(crate) struct Inner {
+    scheduler: Box<dyn Scheduler + Send + Sync>,
+    /// Set by `Builder::max_schedule_age`.
+    aging: Option<Aging>,
+    /// Set by `Builder::wake_placement`, unless `WakePlacement::Fifo`.
+    router: Option<WakeRouter>,
+    hooks: Hooks,
+    chaos: Option<Chaos>,
+    metrics: Metrics,
+    /// Tasks being polled right now with when their poll began, outermost
+    /// first, for the watchdog to report on.
+    polling: Mutex<Vec<(Arc<Task>, Instant)>>,
+
+    /// How deeply `Handle::block_in_task` calls may nest on one thread.
+    max_block_in_task_depth: usize,
+    /// Set by `Builder::capture_spawn_backtraces`.
+    capture_spawn_backtraces: bool,
+    /// Set by `Builder::task_stats`.
+    task_stats: bool,
+    /// Set by `Builder::track_task_memory`.
+    track_task_memory: bool,
+    /// Set by `Builder::record_wakes`.
+    record_wakes: bool,
+    /// Set by `Builder::detect_lost_wakes`.
+    detect_lost_wakes: bool,
+    /// Set by `Builder::panic_behavior`.
+    panic_behavior: PanicBehavior,
+    /// The panic which shut the executor down, with
+    /// `PanicBehavior::ShutdownRuntime`, until `run` resumes it.
+    first_panic: Mutex<Option<Box<dyn Any + Send>>>,
+    /// Set by `Builder::max_concurrent_tasks`.
+    admission: Option<Admission>,
+    /// Set by `Builder::spawn_high_water_mark`.
+    permits: Option<Permits>,
+    /// The buckets of `Handle::limiter`, by name.
+    limiters: Mutex<HashMap<String, Arc<Bucket>>>,
+    blocking: BlockingPool,
+    /// Fires the timers first polled while the executor runs.
+    timers: Arc<TimerDriver>,
+    /// Set by `Builder::defer_timeout`.
+    defer_timeout: Duration,
+    /// Set by `Builder::shutdown_grace_period`.
+    shutdown_grace_period: Duration,
+    /// Set by `Builder::on_leaked_tasks`.
+    leak_hook: Option<Box<LeakHookFn>>,
+
+    /// Every task which has not finished yet, for `Executor::shutdown` to
+    /// report on and drop.
+    tasks: Mutex<HashMap<TaskId, Arc<Task>>>,
+    /// The groups of the `ChildRuntime`s created so far, for the metrics.
+    children: Mutex<Vec<Weak<Group>>>,
+    /// Set by `Executor::shutdown`, after which spawned tasks are cancelled
+    /// right away.
+    shut_down: AtomicBool,
+    /// Number of spawned tasks which have not finished yet, leaving out the
+    /// executor's own background tasks so they never keep `run` going.
+    alive: AtomicUsize,
+    /// Set when a task is scheduled, so an idle `run` knows to look again.
+    unparked: Mutex<bool>,
+    unpark_signal: Condvar,
+}
+
+pub(crate) type LeakHookFn = dyn Fn(&LeakReport) + Send + Sync;
+
+/// Holds spawned tasks back while `Builder::max_concurrent_tasks` tasks are
+/// already running.
+pub(crate) struct Admission {
+    limit: usize,
+    state: Mutex<AdmissionState>,
+}
+
+struct AdmissionState {
+    /// Tasks admitted which have not finished yet.
+    running: usize,
+    /// Tasks waiting to be admitted, oldest first.
+    queue: VecDeque<Arc<Task>>,
+}
+
+impl Admission {
+    pub(crate) fn new(limit: usize) -> Self {
+        Admission {
+            limit,
+            state: Mutex::new(AdmissionState {
+                running: 0,
+                queue: VecDeque::new(),
+            }),
+        }
+    }
+    /// Admit `task` if there is room, otherwise queue it. Returns it back if
+    /// admitted, for the caller to schedule.
+    fn admit(&self, task: Arc<Task>) -> Option<Arc<Task>> {
+        let mut state = lock(&self.state);
+        if state.running < self.limit {
+            state.running += 1;
+            task.admitted.store(true, Ordering::Release);
+            return Some(task);
+        }
+        state.queue.push_back(task);
+        None
+    }
+    /// Account for a finished task, returning the next one admitted in its
+    /// stead, if any.
+    fn release(&self, task: &Task) -> Option<Arc<Task>> {
+        let mut state = lock(&self.state);
+        if !task.admitted.load(Ordering::Acquire) {
+            // Aborted while still queued.
+            state.queue.retain(|queued| !ptr::eq(&**queued, task));
+            return None;
+        }
+        state.running -= 1;
+        // Tasks aborted in the queue are finished separately; skip them.
+        while let Some(next) = state.queue.pop_front() {
+            if !next.aborted.load(Ordering::Acquire) {
+                state.running += 1;
+                next.admitted.store(true, Ordering::Release);
+                return Some(next);
+            }
+        }
+        None
+    }
+    fn queue_depth(&self) -> usize {
+        lock(&self.state).queue.len()
+    }
+}
+
+/// Cheap, cloneable reference to an executor, used to spawn onto it from
+/// anywhere.
+#[derive(Clone)]
+pub struct Handle {
+    inner: Arc<Inner>,
+}
+
+thread_local! {
+    /// The executor whose `run` is on this thread's stack, if any.
+    static CURRENT: RefCell<Option<Handle>> = const { RefCell::new(None) };
+
+    /// Number of `Handle::block_in_task` calls on this thread's stack.
+    static BLOCK_IN_TASK_DEPTH: Cell<usize> = const { Cell::new(0) };
+
+    /// The `ChildRuntime` of the task being polled on this thread, which the
+    /// tasks it spawns belong to as well.
+    static CURRENT_GROUP: RefCell<Option<Arc<Group>>> = const { RefCell::new(None) };
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Builder::new().build()
+    }
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+    pub fn handle(&self) -> Handle {
+        Handle {
+            inner: self.inner.clone(),
+        }
+    }
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        self.inner.spawn(meta, future)
+    }
+    /// Like `spawn`, for a future boxed already, see `Handle::spawn_boxed`.
+    #[track_caller]
+    pub fn spawn_boxed<T: Send + 'static>(&self, future: BoxFuture<'static, T>) -> JoinHandle<T> {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        self.inner.spawn(meta, future)
+    }
+    /// Run tasks until every spawned task has finished, sleeping while none of
+    /// them is ready.
+    ///
+    /// A task spawned from another thread counts as soon as its `spawn` call
+    /// returned: `run` never returns before such a task finished. One spawned
+    /// while `run` is returning waits for the next call.
+    ///
+    /// While running, `Handle::current` and `task::spawn` refer to this
+    /// executor.
+    ///
+    /// With `PanicBehavior::ShutdownRuntime`, the first task panic is resumed
+    /// here once every task was dropped; see `try_run` to get it instead.
+    pub fn run(&self) {
+        if let Err(error) = self.try_run() {
+            panic::resume_unwind(error.into_panic());
+        }
+    }
+    /// Like `run`, but returning the panic which shut the executor down with
+    /// `PanicBehavior::ShutdownRuntime` rather than resuming it.
+    pub fn try_run(&self) -> Result<(), JoinError> {
+        // Restores the previous executor even if a panic is resumed through
+        // here, with `PanicBehavior::Resume`.
+        let _enter = self.enter();
+        self.inner.run_queue();
+        match lock(&self.inner.first_panic).take() {
+            Some(payload) => Err(JoinError::panic(payload)),
+            None => Ok(()),
+        }
+    }
+    /// Like `run`, but stopping early once `signal` completes, e.g. on
+    /// Ctrl-C or a supervisor's command: the tasks still alive then get
+    /// `Builder::shutdown_grace_period` to finish, after which they are
+    /// aborted and any spawned later cancelled, as with `shutdown`.
+    ///
+    /// `signal` is polled as a task of its own, so it never interrupts a
+    /// poll of another task, and it does not keep `run_until_signal` from
+    /// returning `Completed` once every other task finished.
+    #[track_caller]
+    pub fn run_until_signal<F>(&self, signal: F) -> RunStopReason
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let signalled = Arc::new(AtomicBool::new(false));
+        let meta = TaskMeta::new(
+            Some("shutdown-signal".to_string()),
+            Priority::Normal,
+            Location::caller(),
+        )
+        .background();
+        // Only a weak reference, or the task would keep its own executor alive.
+        let executor = Arc::downgrade(&self.inner);
+        let grace = self.inner.shutdown_grace_period;
+        let watcher = self.inner.spawn(meta, {
+            let signalled = signalled.clone();
+            async move {
+                signal.await;
+                signalled.store(true, Ordering::SeqCst);
+                if !grace.is_zero() {
+                    TimerFuture::new(grace).await;
+                }
+                if let Some(executor) = executor.upgrade() {
+                    executor.abort_all();
+                }
+            }
+        });
+        self.run();
+        watcher.abort();
+        if signalled.load(Ordering::SeqCst) {
+            RunStopReason::Signalled
+        } else {
+            RunStopReason::Completed
+        }
+    }
+    /// Spawn each of `futures`, run the executor until every task finished,
+    /// and return their outputs in the order the futures came in.
+    ///
+    /// Like `run`, this also drives whatever other tasks are spawned, by
+    /// those futures or anyone else. Should some of the futures panic, the
+    /// first of them in input order has its panic resumed here, once all of
+    /// them are done.
+    #[track_caller]
+    pub fn block_on_all<I>(&self, futures: I) -> Vec<<I::Item as Future>::Output>
+    where
+        I: IntoIterator,
+        I::Item: Future + Send + 'static,
+        <I::Item as Future>::Output: Send + 'static,
+    {
+        let location = Location::caller();
+        let mut handles: Vec<_> = futures
+            .into_iter()
+            .map(|future| {
+                let meta = TaskMeta::new(None, Priority::Normal, location);
+                self.inner.spawn(meta, future)
+            })
+            .collect();
+        self.run();
+        handles
+            .iter_mut()
+            .map(|handle| {
+                match handle
+                    .try_take_output()
+                    .expect("`run` returned before a task finished")
+                {
+                    Ok(output) => output,
+                    Err(error) if error.is_panic() => panic::resume_unwind(error.into_panic()),
+                    Err(error) => panic!("{error}"),
+                }
+            })
+            .collect()
+    }
+    /// Spawn each of `futures`, returning an iterator over their outputs in
+    /// the order they complete.
+    ///
+    /// Each `next` runs the executor, and whatever other tasks and timers it
+    /// has, only until one more of the futures is done. Dropping the
+    /// iterator aborts those not done yet, as `JoinHandle::abort` does.
+    /// Should one of them panic, its panic is resumed by the `next` call that
+    /// reaches it. Those cancelled meanwhile, by the executor shutting down
+    /// for instance, are skipped.
+    #[track_caller]
+    pub fn run_collect<I>(&self, futures: I) -> CompletionIter<'_, <I::Item as Future>::Output>
+    where
+        I: IntoIterator,
+        I::Item: Future + Send + 'static,
+        <I::Item as Future>::Output: Send + 'static,
+    {
+        let location = Location::caller();
+        let done = Arc::new(Completions {
+            order: Mutex::new(VecDeque::new()),
+            waker: Mutex::new(None),
+        });
+        let handles = futures
+            .into_iter()
+            .enumerate()
+            .map(|(index, future)| {
+                let meta = TaskMeta::new(None, Priority::Normal, location);
+                let future = Completing {
+                    future,
+                    index,
+                    done: done.clone(),
+                };
+                Some(self.inner.spawn(meta, future))
+            })
+            .collect();
+        CompletionIter {
+            executor: self,
+            handles,
+            done,
+        }
+    }
+    /// Make this executor the current one on this thread until the guard is
+    /// dropped, see `Handle::enter`.
+    pub fn enter(&self) -> EnterGuard<'_> {
+        EnterGuard::new(self.handle())
+    }
+    /// Current task and poll counters of this executor.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.inner.metrics()
+    }
+    /// Capture what the executor and its tasks are up to, see
+    /// `Handle::snapshot`.
+    pub fn snapshot(&self) -> RuntimeSnapshot {
+        self.inner.snapshot()
+    }
+    /// Watch the executor from a separate OS thread, which checks every
+    /// `interval` that polls are still completing. When none has for
+    /// `timeout` while there is work to do (a task stuck in a blocking call
+    /// or an endless loop), `callback` is called on that thread, once per
+    /// stall, with the tasks being polled.
+    ///
+    /// Never fires while the executor is idle. Dropping the returned
+    /// `Watchdog` stops the thread.
+    pub fn watchdog(
+        &self,
+        interval: Duration,
+        timeout: Duration,
+        callback: impl FnMut(&WatchdogReport) + Send + 'static,
+    ) -> Watchdog {
+        Watchdog::start(Arc::downgrade(&self.inner), interval, timeout, callback)
+    }
+    /// Counters of the faults injected so far, if built with `Builder::chaos`.
+    pub fn chaos_stats(&self) -> Option<ChaosStats> {
+        self.inner.chaos.as_ref().map(Chaos::stats)
+    }
+    /// Drop every task which has not finished, their `JoinHandle`s getting a
+    /// cancellation error, and report on those that were still alive.
+    ///
+    /// The report also goes to `Builder::on_leaked_tasks` unless empty.
+    /// Dropping the executor does the same, minus the return value. Tasks
+    /// spawned afterwards through a remaining `Handle` are cancelled at once.
+    ///
+    /// This never waits on another thread: blocking pool threads and timer
+    /// threads are detached, and exit on their own once done. The executor's
+    /// timer thread stops right away, leaving its pending timers pending for
+    /// good, see `TimerFuture`.
+    pub fn shutdown(self) -> LeakReport {
+        self.inner.shutdown()
+    }
+}
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.inner.shutdown();
+    }
+}
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handle {
+    /// Handle to the executor running the current task.
+    ///
+    /// Panics when called outside of `Executor::run` or `Handle::enter`.
+    pub fn current() -> Self {
+        Self::try_current().expect("no executor is running on this thread")
+    }
+    pub(crate) fn try_current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+    pub(crate) fn blocking(&self) -> &BlockingPool {
+        &self.inner.blocking
+    }
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        self.inner.spawn(meta, future)
+    }
+    /// Like `spawn`, for a future boxed already, e.g. a trait object made by
+    /// a framework: the box is kept in the task as it is, so spawning
+    /// allocates nothing more for the future, and polls go through one
+    /// pointer.
+    #[track_caller]
+    pub fn spawn_boxed<T: Send + 'static>(&self, future: BoxFuture<'static, T>) -> JoinHandle<T> {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        self.inner.spawn(meta, future)
+    }
+    /// Like `spawn`, with the output kept for every clone of the returned
+    /// handle to borrow, e.g. a large parsed file read by many tasks, rather
+    /// than wrapping it into an `Arc` of your own.
+    #[track_caller]
+    pub fn spawn_shared<F>(&self, future: F) -> SharedOutput<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        SharedOutput::new(self.inner.spawn(meta, future))
+    }
+    /// Like `spawn`, with a name reported in the task's `TaskMeta`.
+    #[track_caller]
+    pub fn spawn_named<F>(&self, name: impl Into<String>, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let meta = TaskMeta::new(Some(name.into()), Priority::Normal, Location::caller());
+        self.inner.spawn(meta, future)
+    }
+    /// Like `spawn`, with a priority for schedulers that honour one, such as
+    /// `PriorityScheduler`.
+    #[track_caller]
+    pub fn spawn_with_priority<F>(&self, priority: Priority, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let meta = TaskMeta::new(None, priority, Location::caller());
+        self.inner.spawn(meta, future)
+    }
+    /// Like `spawn`, with a deadline for schedulers that honour one, such as
+    /// `DeadlineScheduler`. A task which completes after it counts in
+    /// `MetricsSnapshot::deadlines_missed`.
+    #[track_caller]
+    pub fn spawn_with_deadline<F>(&self, deadline: Instant, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let meta =
+            TaskMeta::new(None, Priority::Normal, Location::caller()).with_deadline(deadline);
+        self.inner.spawn(meta, future)
+    }
+    /// Spawn the future `f` builds from the new task's `TaskContext`, which
+    /// gives it its id and handle, and lets it spawn children aborted along
+    /// with it.
+    #[track_caller]
+    pub fn spawn_with_context<F, Fut>(&self, f: F) -> JoinHandle<Fut::Output>
+    where
+        F: FnOnce(TaskContext) -> Fut,
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+    {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        let (context, children) = TaskContext::new(self.clone(), meta.id());
+        let future = f(context);
+        self.inner.spawn(meta, async move {
+            // Dropped with the task however it ends, aborting its children.
+            let _children = children;
+            future.await
+        })
+    }
+    /// Spawn the future `f` builds from a `ProgressSender`, returning the
+    /// stream of progress reports the task sends along with its handle.
+    ///
+    /// Only the latest report not received yet is kept; the stream ends once
+    /// the task ended and its last report was received.
+    #[track_caller]
+    pub fn spawn_with_progress<F, Fut, P>(
+        &self,
+        f: F,
+    ) -> (JoinHandle<Fut::Output>, ProgressReceiver<P>)
+    where
+        F: FnOnce(ProgressSender<P>) -> Fut,
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+        P: Send + 'static,
+    {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        self.inner.spawn_progress(meta, None, f)
+    }
+    /// Like `spawn_with_progress`, but keeping up to `capacity` reports not
+    /// received yet, beyond which `ProgressSender::send` waits.
+    #[track_caller]
+    pub fn spawn_with_bounded_progress<F, Fut, P>(
+        &self,
+        capacity: usize,
+        f: F,
+    ) -> (JoinHandle<Fut::Output>, ProgressReceiver<P>)
+    where
+        F: FnOnce(ProgressSender<P>) -> Fut,
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+        P: Send + 'static,
+    {
+        assert!(capacity > 0, "progress capacity must be non-zero");
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        self.inner.spawn_progress(meta, Some(capacity), f)
+    }
+    /// Like `spawn`, for a task whose output is only worth looking at when it
+    /// fails: an error it returns goes to the error handler, see
+    /// `set_error_handler`.
+    #[track_caller]
+    pub fn spawn_logged<F, E>(&self, future: F) -> JoinHandle<()>
+    where
+        F: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        let inner = self.inner.clone();
+        self.inner.spawn(meta, async move {
+            if let Err(error) = future.await {
+                if let Some(task) = inner.polled_task() {
+                    handlers::failed(&task.meta, &error);
+                }
+            }
+        })
+    }
+    /// Run the blocking closure `f` on a thread of the blocking pool, as a
+    /// task resolving to its result, so it does not hold up the executor.
+    ///
+    /// The pool grows up to `Builder::max_blocking_threads`, then queues
+    /// closures until a thread is free. Idle threads exit after
+    /// `Builder::blocking_thread_keep_alive`; the others are detached, and
+    /// finish the closure they run even once the executor is gone.
+    #[track_caller]
+    pub fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        self.inner.spawn(meta, self.inner.blocking.run(f))
+    }
+    /// Spawn every future of `futures`, like calling `spawn` on each, but
+    /// queueing them all at once rather than one at a time, for when a task
+    /// fans out into a lot of subtasks.
+    #[track_caller]
+    pub fn spawn_all<I>(&self, futures: I) -> Vec<JoinHandle<<I::Item as Future>::Output>>
+    where
+        I: IntoIterator,
+        I::Item: Future + Send + 'static,
+        <I::Item as Future>::Output: Send + 'static,
+    {
+        let location = Location::caller();
+        let futures = futures
+            .into_iter()
+            .map(|future| (TaskMeta::new(None, Priority::Normal, location), future));
+        self.inner.spawn_all(futures)
+    }
+    /// Like `spawn_all`, naming each task with `name` called with the
+    /// future's index.
+    #[track_caller]
+    pub fn spawn_all_named<I>(
+        &self,
+        futures: I,
+        mut name: impl FnMut(usize) -> String,
+    ) -> Vec<JoinHandle<<I::Item as Future>::Output>>
+    where
+        I: IntoIterator,
+        I::Item: Future + Send + 'static,
+        <I::Item as Future>::Output: Send + 'static,
+    {
+        let location = Location::caller();
+        let futures = futures.into_iter().enumerate().map(|(index, future)| {
+            let meta = TaskMeta::new(Some(name(index)), Priority::Normal, location);
+            (meta, future)
+        });
+        self.inner.spawn_all(futures)
+    }
+    /// Spawn a task whose future is built by calling `make` on the thread
+    /// running the executor, at its first poll, rather than on the spawning
+    /// thread. The future itself then need not be `Send`, which suits
+    /// futures built from thread-local state.
+    ///
+    /// Should the task later be polled on another thread, because `run` is
+    /// called from there, the poll panics; dropped elsewhere, the future is
+    /// leaked.
+    #[track_caller]
+    pub fn spawn_fn<F, Fut>(&self, make: F) -> JoinHandle<Fut::Output>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future + 'static,
+        Fut::Output: Send + 'static,
+    {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        self.inner.spawn(meta, SpawnFn::new(make))
+    }
+    /// Create a child runtime named `name`: a set of tasks of this executor
+    /// which can be shut down on their own.
+    pub fn child_runtime(&self, name: impl Into<String>) -> ChildRuntime {
+        ChildRuntime::new(self.clone(), name.into())
+    }
+    /// Run the future `f` builds every `period`, the first time right away,
+    /// each run as a task of its own; a tick coming while the previous run
+    /// is still going is let go by. The ticks keep to their schedule however
+    /// long the runs take.
+    ///
+    /// The job keeps `Executor::run` from returning until
+    /// `PeriodicHandle::stop` is called. A run which panics, or is aborted,
+    /// does not stop the job.
+    ///
+    /// Panics if `period` is zero.
+    #[track_caller]
+    pub fn spawn_periodic<F, Fut>(&self, period: Duration, f: F) -> PeriodicHandle
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        periodic::spawn(self, period, Overlap::Skip, f)
+    }
+    /// Like `spawn_periodic`, with what to do about a tick coming while a
+    /// run is still going.
+    #[track_caller]
+    pub fn spawn_periodic_with_overlap<F, Fut>(
+        &self,
+        period: Duration,
+        overlap: Overlap,
+        f: F,
+    ) -> PeriodicHandle
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        periodic::spawn(self, period, overlap, f)
+    }
+    /// The limiter named `name`, letting at most `permits` of its tasks run
+    /// at a time. Every call with the same name returns the same limiter,
+    /// keeping its size: see `Limiter::resize` to change it.
+    pub fn limiter(&self, name: impl Into<String>, permits: usize) -> Limiter {
+        let name = name.into();
+        let bucket = lock(&self.inner.limiters)
+            .entry(name.clone())
+            .or_insert_with(|| Arc::new(Bucket::new(name, permits)))
+            .clone();
+        Limiter::new(self.clone(), bucket)
+    }
+    /// Wait until the executor is not too busy for another task, as set by
+    /// `Builder::spawn_high_water_mark`, and reserve room for it. Resolves
+    /// right away without a high-water mark.
+    ///
+    /// Meant for producers of tasks, such as an accept loop, to slow down
+    /// rather than pile tasks up.
+    ///
+    /// Cancel safe: room is only reserved once this completes.
+    pub fn spawn_permit(&self) -> AcquirePermit<'_> {
+        AcquirePermit::new(self)
+    }
+    /// Current task and poll counters of the executor.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.inner.metrics()
+    }
+    /// Capture the executor's metrics, timers and unfinished tasks, e.g. to
+    /// dump them with `RuntimeSnapshot::write_json` before the process is
+    /// killed. Safe to call from any thread while the executor runs.
+    pub fn snapshot(&self) -> RuntimeSnapshot {
+        self.inner.snapshot()
+    }
+    /// Wake every waker of `wakers` at once, e.g. all the waiters of a
+    /// primitive released together: the tasks among them are scheduled with
+    /// a single queue operation and unpark, rather than one each. Wakers of
+    /// anything else are woken one by one.
+    ///
+    /// The crate's own primitives, such as `sync::Event`, already wake their
+    /// waiters this way.
+    pub fn wake_all(&self, wakers: impl IntoIterator<Item = Waker>) {
+        task::wake_all(wakers);
+    }
+    /// Call `exporter` with a fresh `MetricsSnapshot` every `interval`, e.g.
+    /// to render it with `metrics::prometheus_text` for a scraper.
+    ///
+    /// The exporter runs as a background task of the executor: it does not
+    /// keep `run` from returning, and stops being called once it has.
+    pub fn metrics_exporter(
+        &self,
+        interval: Duration,
+        exporter: impl Fn(&MetricsSnapshot) + Send + 'static,
+    ) {
+        // Only a weak reference, or the task would keep its own executor alive.
+        let executor = Arc::downgrade(&self.inner);
+        let meta = TaskMeta::new(
+            Some("metrics-exporter".to_string()),
+            Priority::Normal,
+            Location::caller(),
+        )
+        .background();
+        self.inner.spawn(meta, async move {
+            loop {
+                TimerFuture::new(interval).await;
+                let Some(executor) = executor.upgrade() else {
+                    break;
+                };
+                exporter(&executor.metrics());
+            }
+        });
+    }
+    /// Wait for `future` from synchronous code running inside one of this
+    /// executor's tasks.
+    ///
+    /// Blocking the thread would deadlock, since the tasks that could complete
+    /// `future` run on that same thread. Instead, this keeps running the
+    /// executor's other tasks until `future` is ready. The calling task itself
+    /// is not polled meanwhile: its wakes are kept for when it returns.
+    ///
+    /// Nesting is bounded by `Builder::max_block_in_task_depth`. Panics when
+    /// called outside of this executor's `run`, or beyond that depth.
+    pub fn block_in_task<F: Future>(&self, future: F) -> F::Output {
+        let is_current =
+            Handle::try_current().is_some_and(|current| Arc::ptr_eq(&current.inner, &self.inner));
+        assert!(
+            is_current,
+            "`block_in_task` called outside of its executor's `run`"
+        );
+        let depth = BLOCK_IN_TASK_DEPTH.with(|depth| depth.get()) + 1;
+        assert!(
+            depth <= self.inner.max_block_in_task_depth,
+            "`block_in_task` nested deeper than {}",
+            self.inner.max_block_in_task_depth
+        );
+        BLOCK_IN_TASK_DEPTH.with(|cell| cell.set(depth));
+        // Restored even if `future` or a task driven meanwhile panics.
+        let _depth = RestoreBlockInTaskDepth(depth - 1);
+        self.inner.drive_until(future)
+    }
+    /// Make this executor the current one on this thread until the guard is
+    /// dropped, as it is within `Executor::run`, so `Handle::current` and
+    /// `task::spawn` work from setup code too.
+    ///
+    /// Guards may nest, each restoring the executor that was current before
+    /// it; they must be dropped in reverse order.
+    pub fn enter(&self) -> EnterGuard<'_> {
+        EnterGuard::new(self.clone())
+    }
+}
+
+struct RestoreBlockInTaskDepth(usize);
+impl Drop for RestoreBlockInTaskDepth {
+    fn drop(&mut self) {
+        BLOCK_IN_TASK_DEPTH.with(|depth| depth.set(self.0));
+    }
+}
+
+/// Keeps an executor current on this thread, see `Handle::enter`.
+#[must_use = "the executor stops being current as soon as the guard is dropped"]
+pub struct EnterGuard<'a> {
+    previous: Option<Handle>,
+    /// Borrows the executor, and keeps the guard on its thread.
+    _executor: PhantomData<(&'a Inner, *const ())>,
+}
+impl EnterGuard<'_> {
+    fn new(handle: Handle) -> Self {
+        let previous = CURRENT.with(|current| current.replace(Some(handle)));
+        EnterGuard {
+            previous,
+            _executor: PhantomData,
+        }
+    }
+}
+impl Drop for EnterGuard<'_> {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Why `Executor::run_until_signal` returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStopReason {
+    /// Every task finished before the signal completed.
+    Completed,
+    /// The signal completed, and the tasks then alive finished or were
+    /// aborted.
+    Signalled,
+}
+
+/// Iterator returned by `Executor::run_collect`.
+pub struct CompletionIter<'a, T> {
+    executor: &'a Executor,
+    /// Indexed like the futures; emptied as their outputs are handed out.
+    handles: Vec<Option<JoinHandle<T>>>,
+    done: Arc<Completions>,
+}
+impl<T> Iterator for CompletionIter<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if self.handles.iter().all(Option::is_none) {
+                return None;
+            }
+            let previous = CURRENT.with(|current| current.replace(Some(self.executor.handle())));
+            let index = self
+                .executor
+                .inner
+                .drive_until(NextCompletion { done: &self.done });
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+            let mut handle = self.handles[index].take().expect("completed twice");
+            match handle
+                .try_take_output()
+                .expect("completed before the task finished")
+            {
+                Ok(output) => return Some(output),
+                Err(error) if error.is_panic() => panic::resume_unwind(error.into_panic()),
+                // Cancelled, with no output to hand out.
+                Err(_) => continue,
+            }
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.handles.iter().flatten().count();
+        (left, Some(left))
+    }
+}
+impl<T> Drop for CompletionIter<'_, T> {
+    fn drop(&mut self) {
+        for handle in self.handles.iter().flatten() {
+            handle.abort();
+        }
+    }
+}
+/// Indexes of the `run_collect` futures done, in the order they got done.
+struct Completions {
+    order: Mutex<VecDeque<usize>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// A `run_collect` future, reporting its index once dropped: after it
+/// completed, panicked or was aborted, its output is in its `JoinHandle`.
+struct Completing<F> {
+    future: F,
+    index: usize,
+    done: Arc<Completions>,
+}
+impl<F: Future> Future for Completing<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is structurally pinned, and never moved out.
+        unsafe { self.map_unchecked_mut(|this| &mut this.future) }.poll(cx)
+    }
+}
+impl<F> Drop for Completing<F> {
+    fn drop(&mut self) {
+        lock(&self.done.order).push_back(self.index);
+        let waker = lock(&self.done.waker).take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Resolves to the index of the next `run_collect` future done.
+struct NextCompletion<'a> {
+    done: &'a Completions,
+}
+impl Future for NextCompletion<'_> {
+    type Output = usize;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<usize> {
+        // The waker is stored first, so an index pushed in between wakes us.
+        *lock(&self.done.waker) = Some(counters::clone_waker(cx.waker()));
+        match lock(&self.done.order).pop_front() {
+            Some(index) => Poll::Ready(index),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Wakes a `block_in_task` caller: flags its future as worth polling again
+/// and makes sure the executor is not left sleeping.
+struct BlockInTaskWaker {
+    woken: AtomicBool,
+    executor: Arc<Inner>,
+}
+impl Wake for BlockInTaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.executor.unpark();
+    }
+}
+
+impl Inner {
+    fn spawn_progress<F, Fut, P>(
+        self: &Arc<Self>,
+        meta: TaskMeta,
+        capacity: Option<usize>,
+        f: F,
+    ) -> (JoinHandle<Fut::Output>, ProgressReceiver<P>)
+    where
+        F: FnOnce(ProgressSender<P>) -> Fut,
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+        P: Send + 'static,
+    {
+        let (sender, receiver, guard) = progress::channel(capacity);
+        let future = f(sender);
+        let handle = self.spawn(meta, async move {
+            // Dropped with the task however it ends, closing the stream.
+            let _guard = guard;
+            future.await
+        });
+        (handle, receiver)
+    }
+    fn spawn<F>(self: &Arc<Self>, meta: TaskMeta, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (task, handle) = self.create(meta, future);
+        if let Some(task) = self.admit(task) {
+            self.schedule(task);
+        }
+        handle
+    }
+    /// Spawn every future, scheduling them all in one go.
+    fn spawn_all<F>(
+        self: &Arc<Self>,
+        futures: impl IntoIterator<Item = (TaskMeta, F)>,
+    ) -> Vec<JoinHandle<F::Output>>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let mut ready = Vec::new();
+        let handles = futures
+            .into_iter()
+            .map(|(meta, future)| {
+                let (task, handle) = self.create(meta, future);
+                ready.extend(self.admit(task));
+                handle
+            })
+            .collect();
+        self.schedule_all(ready);
+        handles
+    }
+    /// Wrap `future` into a new task, counted as alive but not scheduled yet.
+    fn create<F>(
+        self: &Arc<Self>,
+        mut meta: TaskMeta,
+        future: F,
+    ) -> (Arc<Task>, JoinHandle<F::Output>)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        if self.capture_spawn_backtraces {
+            // Forced, as opting in through the builder should not also depend
+            // on `RUST_BACKTRACE`.
+            meta.spawn_backtrace = Some(Arc::new(Backtrace::force_capture()));
+        }
+        if self.task_stats {
+            meta.stats = Some(Default::default());
+        }
+        if self.record_wakes {
+            meta.wake_log = Some(Default::default());
+        }
+        if meta.group.is_none() {
+            meta.group = CURRENT_GROUP.with(|group| group.borrow().clone());
+        }
+        let (task, handle) = Task::new(future, meta, self.clone());
+        if let Some(group) = &task.meta.group {
+            if !group.track(&task) {
+                // Its runtime was shut down: `admit` cancels it.
+                task.aborted.store(true, Ordering::Release);
+            }
+        }
+        lock(&self.tasks).insert(task.meta.id(), task.clone());
+        // Counted before it can be queued, so `run_queue` never sees an empty
+        // queue and nothing alive while the task is on its way in.
+        if !task.meta.background {
+            self.alive.fetch_add(1, Ordering::Relaxed);
+        }
+        self.metrics.tasks_spawned.fetch_add(1, Ordering::Relaxed);
+        self.hooks.spawn.call(&task.meta);
+        (task, handle)
+    }
+    /// Hand a new task to `Builder::max_concurrent_tasks`, getting it back if
+    /// it may be scheduled right away.
+    fn admit(&self, task: Arc<Task>) -> Option<Arc<Task>> {
+        if self.is_shut_down() || task.aborted.load(Ordering::Acquire) {
+            // Cancelled right away, as nothing would ever poll it.
+            let mut future_slot = lock(&task.future);
+            self.drop_future(&task, &mut future_slot);
+            drop(future_slot);
+            self.finish(&task);
+            return None;
+        }
+        match &self.admission {
+            // The executor's own tasks are never held back.
+            Some(admission) if !task.meta.background => admission.admit(task),
+            _ => Some(task),
+        }
+    }
+    pub(crate) fn schedule(&self, task: Arc<Task>) {
+        if let Some(chaos) = &self.chaos {
+            if chaos.duplicate_wake() {
+                self.scheduler.schedule(task.clone());
+            }
+        }
+        if let Some(stats) = &task.meta.stats {
+            stats.scheduled(Instant::now());
+        }
+        if let Some(aging) = &self.aging {
+            aging.runnable(&task);
+        }
+        self.scheduler.schedule(task);
+        self.unpark();
+    }
+    pub(crate) fn schedule_all(&self, tasks: Vec<Arc<Task>>) {
+        if tasks.is_empty() {
+            return;
+        }
+        let mut batch = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            if let Some(chaos) = &self.chaos {
+                if chaos.duplicate_wake() {
+                    batch.push(task.clone());
+                }
+            }
+            if let Some(stats) = &task.meta.stats {
+                stats.scheduled(Instant::now());
+            }
+            if let Some(aging) = &self.aging {
+                aging.runnable(&task);
+            }
+            batch.push(task);
+        }
+        self.scheduler.schedule_all(batch);
+        self.unpark();
+    }
+    /// Schedule a task woken through its waker, as `Builder::wake_placement`
+    /// has it.
+    pub(crate) fn wake(&self, task: Arc<Task>) {
+        let same_thread = self.is_polling_here();
+        self.metrics.count_wake(same_thread);
+        let Some(router) = &self.router else {
+            return self.schedule(task);
+        };
+        // Yielding, rather than jumping the queue.
+        if same_thread
+            && self
+                .polled_task()
+                .is_some_and(|polled| Arc::ptr_eq(&polled, &task))
+        {
+            return self.schedule(task);
+        }
+        if let Some(chaos) = &self.chaos {
+            if chaos.duplicate_wake() {
+                self.scheduler.schedule(task.clone());
+            }
+        }
+        if let Some(stats) = &task.meta.stats {
+            stats.scheduled(Instant::now());
+        }
+        if let Some(aging) = &self.aging {
+            aging.runnable(&task);
+        }
+        if let Some(task) = router.route(task, same_thread) {
+            self.scheduler.schedule(task);
+        }
+        self.unpark();
+    }
+    /// Like `wake` for the tasks of a `Handle::wake_all` batch.
+    pub(crate) fn wake_batch(&self, mut tasks: Vec<Arc<Task>>) {
+        // A batch of one, such as an `Event` with a single waiter, may take
+        // the LIFO slot as a single wake would.
+        if tasks.len() == 1 && self.router.is_some() {
+            return self.wake(tasks.pop().unwrap());
+        }
+        let same_thread = self.is_polling_here();
+        for _ in &tasks {
+            self.metrics.count_wake(same_thread);
+        }
+        match &self.router {
+            Some(router) => {
+                for task in &tasks {
+                    if let Some(stats) = &task.meta.stats {
+                        stats.scheduled(Instant::now());
+                    }
+                    if let Some(aging) = &self.aging {
+                        aging.runnable(task);
+                    }
+                }
+                let tasks = router.route_all(tasks, same_thread);
+                if !tasks.is_empty() {
+                    self.scheduler.schedule_all(tasks);
+                }
+                self.unpark();
+            }
+            None => self.schedule_all(tasks),
+        }
+    }
+    /// Whether one of this executor's tasks is being polled on this thread.
+    fn is_polling_here(&self) -> bool {
+        coop::in_task()
+            && CURRENT.with(|current| {
+                current
+                    .borrow()
+                    .as_ref()
+                    .is_some_and(|handle| ptr::eq(&*handle.inner, self))
+            })
+    }
+    fn unpark(&self) {
+        *lock(&self.unparked) = true;
+        self.unpark_signal.notify_one();
+    }
+    fn run_queue(&self) {
+        loop {
+            // Pop in its own statement so the queue lock is released before
+            // polling: the timer thread needs it to push woken tasks.
+            let Some(task) = self.next_task() else {
+                // A task spawned meanwhile is alive already, and its schedule
+                // will unpark us.
+                if self.alive.load(Ordering::Acquire) == 0 {
+                    break;
+                }
+                self.park();
+                continue;
+            };
+            // Nothing is being polled further up the stack, so no task can be
+            // busy here.
+            self.run_task(&task);
+            self.dequeued();
+        }
+    }
+    /// Poll a task taken out of the scheduler.
+    ///
+    /// Returns false, leaving the task untouched, if its future is already
+    /// being polled further up this thread's stack: that happens when it is
+    /// woken while blocked in `Handle::block_in_task`.
+    fn run_task(&self, task: &Arc<Task>) -> bool {
+        // Left in the queue by a wake which came in before the task
+        // completed, during its last poll or from another thread: nothing to
+        // poll, delay or drop any more.
+        if task.finished.load(Ordering::Acquire) {
+            return true;
+        }
+        if let Some(chaos) = &self.chaos {
+            if chaos.delay_wake() {
+                if let Some(aging) = &self.aging {
+                    aging.runnable(task);
+                }
+                self.scheduler.schedule(task.clone());
+                return true;
+            }
+        }
+        // Take the future, and if it has not yet completed (is still Some),
+        // poll it in an attempt to complete it.
+        let mut future_slot = match task.future.try_lock() {
+            Ok(future_slot) => future_slot,
+            Err(TryLockError::WouldBlock) => return false,
+            // Nothing panics with it held any more, see `drop_future`.
+            Err(TryLockError::Poisoned(error)) => error.into_inner(),
+        };
+        let Some(future) = future_slot.as_mut() else {
+            return true;
+        };
+        // An aborted task is not polled again: dropping its future
+        // hands a cancellation error to the `JoinHandle`.
+        if task.aborted.load(Ordering::Acquire) {
+            self.drop_future(task, &mut future_slot);
+            self.finish(task);
+            return true;
+        }
+        if task.meta.first_polled.set(Instant::now()).is_ok() {
+            self.hooks.start.call(&task.meta);
+        }
+        // Create a `LocalWaker` from the task itself
+        let waker = match &task.wake_tracker {
+            Some(tracker) => tracker.waker(),
+            None => Waker::from(task.clone()),
+        };
+        let context = &mut Context::from_waker(&waker);
+        // SAFETY: the future is never moved out of its slot, only polled and
+        // dropped there, and the task itself stays put behind its `Arc`.
+        let future = unsafe { Pin::new_unchecked(future) };
+        let poll_started = Instant::now();
+        task.meta.polled_at(poll_started);
+        if let Some(group) = &task.meta.group {
+            group.count_poll();
+        }
+        lock(&self.polling).push((task.clone(), poll_started));
+        let group = CURRENT_GROUP.with(|group| group.replace(task.meta.group.clone()));
+        let budget = coop::reset();
+        let attributed = match &task.meta.stats {
+            Some(stats) if self.track_task_memory => {
+                Some(alloc_hooks::enter(task.meta.id(), stats))
+            }
+            _ => None,
+        };
+        let poll = future.poll(context);
+        if let Some(previous) = attributed {
+            alloc_hooks::restore(previous);
+        }
+        coop::restore(budget);
+        CURRENT_GROUP.with(|current| *current.borrow_mut() = group);
+        lock(&self.polling).pop();
+        let busy = poll_started.elapsed();
+        self.metrics.record_poll(busy);
+        if let Some(stats) = &task.meta.stats {
+            stats.polled(poll_started, busy);
+        }
+        match poll {
+            Poll::Pending => {
+                // We're not done processing the future, so leave it in
+                // its task to be run again once woken.
+                if let Some(chaos) = &self.chaos {
+                    if chaos.spurious_wake() {
+                        context.waker().wake_by_ref();
+                    }
+                }
+                if let Some(tracker) = &task.wake_tracker {
+                    tracker.check(waker, &task.meta);
+                }
+            }
+            Poll::Ready(outcome) => {
+                self.drop_future(task, &mut future_slot);
+                if task
+                    .meta
+                    .deadline()
+                    .is_some_and(|deadline| deadline < Instant::now())
+                {
+                    self.metrics
+                        .deadlines_missed
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                let mut resumed = None;
+                if let Outcome::Panicked(payload) = outcome {
+                    self.metrics.tasks_panicked.fetch_add(1, Ordering::Relaxed);
+                    if let Some(group) = &task.meta.group {
+                        group.count_panic();
+                    }
+                    self.hooks.panic.call(&task.meta);
+                    match (payload, self.panic_behavior) {
+                        (None, _) => {}
+                        (Some(payload), PanicBehavior::Isolate) => {
+                            handlers::panicked(&task.meta, payload)
+                        }
+                        (Some(payload), PanicBehavior::ShutdownRuntime) => {
+                            self.shut_down_on_panic(payload)
+                        }
+                        (Some(payload), PanicBehavior::Resume) => resumed = Some(payload),
+                    }
+                }
+                self.finish(task);
+                if let Some(payload) = resumed {
+                    drop(future_slot);
+                    panic::resume_unwind(payload);
+                }
+            }
+        }
+        true
+    }
+    /// Drop a task's future in place. Its `Harness` already caught any panic
+    /// of `poll`, but a panicking `Drop` of the future would unwind through
+    /// the executor with the slot locked: it goes to the panic handler
+    /// instead, like the panic of a detached task.
+    /// The slot is left empty either way, as assignment still writes `None`
+    /// when dropping the old value unwinds.
+    fn drop_future(&self, task: &Task, future_slot: &mut Option<TaskFuture>) {
+        // Dropped in place rather than taken out, as it may be stored inline.
+        let drop_in_place = AssertUnwindSafe(|| *future_slot = None);
+        if let Err(payload) = panic::catch_unwind(drop_in_place) {
+            self.hooks.panic.call(&task.meta);
+            handlers::panicked(&task.meta, payload);
+        }
+    }
+    /// Keep the first panic for `run`, and abort every task, with
+    /// `PanicBehavior::ShutdownRuntime`.
+    fn shut_down_on_panic(&self, payload: Box<dyn Any + Send>) {
+        lock(&self.first_panic).get_or_insert(payload);
+        self.abort_all();
+    }
+    /// Abort every task and cancel any spawned from now on, letting `run`
+    /// drop them and return.
+    fn abort_all(&self) {
+        // Raised first so nothing spawned from here on is left out. Wakes do
+        // nothing any more, so the tasks are scheduled here to be dropped.
+        self.shut_down.store(true, Ordering::SeqCst);
+        let tasks: Vec<_> = lock(&self.tasks).values().cloned().collect();
+        for task in tasks {
+            if !task.aborted.swap(true, Ordering::AcqRel) {
+                self.schedule(task);
+            }
+        }
+    }
+    /// Mark a task whose future was dropped as finished, and start its
+    /// cleanups.
+    fn finish(&self, task: &Arc<Task>) {
+        let _ = task.meta.completed.set(Instant::now());
+        task.finished.store(true, Ordering::Release);
+        self.hooks.complete.call(&task.meta);
+        if let Some(admission) = &self.admission {
+            if let Some(next) = admission.release(task) {
+                self.schedule(next);
+            }
+        }
+        lock(&self.tasks).remove(&task.meta.id());
+        if let Some(group) = &task.meta.group {
+            group.untrack(task.meta.id());
+        }
+        // Spawned before this one stops counting, so `run` waits for them.
+        task.finish_cleanups();
+        if !task.meta.background {
+            self.alive.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+    pub(crate) fn is_shut_down(&self) -> bool {
+        self.shut_down.load(Ordering::SeqCst)
+    }
+    /// Report on the unfinished tasks, then drop them and empty the queue.
+    fn shutdown(&self) -> LeakReport {
+        self.shut_down.store(true, Ordering::SeqCst);
+        let tasks: Vec<_> = lock(&self.tasks).values().cloned().collect();
+        let report = LeakReport::new(tasks.iter().map(|task| &**task));
+        for task in &tasks {
+            // A task being polled further up the stack is left alone.
+            let mut future_slot = match task.future.try_lock() {
+                Ok(future_slot) => future_slot,
+                Err(TryLockError::WouldBlock) => continue,
+                Err(TryLockError::Poisoned(error)) => error.into_inner(),
+            };
+            if future_slot.is_some() {
+                self.drop_future(task, &mut future_slot);
+                drop(future_slot);
+                self.finish(task);
+            }
+        }
+        // Drop the queue's references too, which would otherwise keep the
+        // tasks, and through them the executor, alive.
+        while self.scheduler.next().is_some() {}
+        if let Some(router) = &self.router {
+            router.clear();
+        }
+        if let Some(aging) = &self.aging {
+            aging.clear();
+        }
+        // Last, as dropping the tasks may have run their `defer`s, timeouts
+        // included.
+        self.timers.shut_down();
+        if !report.is_empty() {
+            match &self.leak_hook {
+                Some(hook) => hook(&report),
+                None if cfg!(debug_assertions) => {
+                    let mut leaked: Vec<_> =
+                        tasks.iter().filter(|task| !task.meta.background).collect();
+                    leaked.sort_by_key(|task| task.meta.id());
+                    for task in leaked {
+                        handlers::failed(&task.meta, &"still alive at shutdown");
+                    }
+                }
+                None => {}
+            }
+        }
+        report
+    }
+    /// The task being polled right now, the innermost one when nested.
+    fn polled_task(&self) -> Option<Arc<Task>> {
+        let polling = lock(&self.polling);
+        polling.last().map(|(task, _)| task.clone())
+    }
+    fn snapshot(&self) -> RuntimeSnapshot {
+        let tasks: Vec<_> = lock(&self.tasks).values().cloned().collect();
+        let polling: Vec<_> = lock(&self.polling)
+            .iter()
+            .map(|(task, _)| task.meta.id())
+            .collect();
+        RuntimeSnapshot::new(tasks, &polling, self.metrics(), self.timers.stats())
+    }
+    fn polling_tasks(&self) -> Vec<PollingTask> {
+        let polling = lock(&self.polling);
+        polling
+            .iter()
+            .map(|(task, since)| PollingTask {
+                id: task.meta.id(),
+                name: task.meta.name().map(str::to_string),
+                since: *since,
+            })
+            .collect()
+    }
+    /// Tasks spawned but not polled yet: queued in the scheduler, or held
+    /// back by `Builder::max_concurrent_tasks`.
+    pub(crate) fn queued(&self) -> usize {
+        self.ready_len() + self.admission.as_ref().map_or(0, Admission::queue_depth)
+    }
+    /// Tasks ready to be polled, in the scheduler or held back by the
+    /// `Builder::wake_placement`.
+    pub(crate) fn ready_len(&self) -> usize {
+        self.scheduler.len() + self.router.as_ref().map_or(0, WakeRouter::len)
+    }
+    /// Take the next task to poll out of the scheduler, or the overdue one
+    /// with `Builder::max_schedule_age`.
+    fn next_task(&self) -> Option<Arc<Task>> {
+        let pick = || {
+            self.router
+                .as_ref()
+                .and_then(|router| router.next(&*self.scheduler))
+                .or_else(|| self.scheduler.next())
+        };
+        let Some(aging) = &self.aging else {
+            return pick();
+        };
+        let (task, overridden) = aging.next(pick)?;
+        if overridden {
+            self.metrics
+                .schedule_age_overrides
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        Some(task)
+    }
+    /// A task left the scheduler and was polled, making room for spawn
+    /// permits. Only called after the poll, so that a task waiting for a
+    /// permit does not see its own entry in the queue.
+    fn dequeued(&self) {
+        if let Some(permits) = &self.permits {
+            permits.load_dropped(self.queued());
+        }
+    }
+    fn metrics(&self) -> MetricsSnapshot {
+        let admission_queue_depth = self.admission.as_ref().map_or(0, Admission::queue_depth);
+        let (blocking_threads, blocking_queue_depth) = self.blocking.load();
+        self.metrics.snapshot(Gauges {
+            tasks_alive: self.alive.load(Ordering::Relaxed),
+            queue_depth: self.ready_len(),
+            admission_queue_depth,
+            spawn_permits_reserved: self.permits.as_ref().map_or(0, Permits::reserved),
+            blocking_threads,
+            blocking_queue_depth,
+            runtimes: lock(&self.children)
+                .iter()
+                .filter_map(Weak::upgrade)
+                .map(|group| group.metrics())
+                .collect(),
+        })
+    }
+    /// List the group of a new `ChildRuntime` in the metrics while it lives.
+    pub(crate) fn adopt(&self, group: &Arc<Group>) {
+        let mut children = lock(&self.children);
+        children.retain(|child| child.strong_count() > 0);
+        children.push(Arc::downgrade(group));
+    }
+    /// Run tasks until `future` completes, on behalf of `Handle::block_in_task`.
+    fn drive_until<F: Future>(self: &Arc<Self>, future: F) -> F::Output {
+        let mut future = pin!(future);
+        let block_waker = Arc::new(BlockInTaskWaker {
+            woken: AtomicBool::new(true),
+            executor: self.clone(),
+        });
+        let waker = Waker::from(block_waker.clone());
+        // Tasks being polled further up the stack, rescheduled once done here.
+        let mut busy = Vec::new();
+        let output = loop {
+            if block_waker.woken.swap(false, Ordering::AcqRel) {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker))
+                {
+                    break output;
+                }
+                continue;
+            }
+            match self.next_task() {
+                Some(task) => {
+                    if !self.run_task(&task) {
+                        busy.push(task);
+                    }
+                    self.dequeued();
+                }
+                None => self.park(),
+            }
+        };
+        for task in busy {
+            self.schedule(task);
+        }
+        output
+    }
+    /// Block until a task gets scheduled.
+    fn park(&self) {
+        // A blocking closure may still wake a task before the next timer is
+        // due by a paused clock.
+        if !self.blocking.busy() {
+            self.timers.advance_to_next();
+        }
+        let mut unparked = lock(&self.unparked);
+        while !*unparked {
+            unparked = self
+                .unpark_signal
+                .wait(unparked)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *unparked = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{future::completion, test_util::DropTracker};
+
+    #[test]
+    fn block_in_task_waits_on_another_task() {
+        let executor = Executor::new();
+        let (completer, completion) = completion();
+        let waiter = executor.spawn(async move {
+            // Synchronous code deep inside the task.
+            Handle::current().block_in_task(completion).unwrap()
+        });
+        executor.spawn(async move {
+            completer.complete(5).unwrap();
+        });
+        executor.run();
+        assert_eq!(waiter.join_blocking().unwrap(), 5);
+    }
+
+    #[test]
+    fn block_in_task_depth_is_restored_after_a_panic() {
+        let executor = Builder::new().max_block_in_task_depth(1).build();
+        let panicked = executor
+            .spawn(async { Handle::current().block_in_task(async { panic!("inner future") }) });
+        executor.run();
+        assert!(panicked.join_blocking().unwrap_err().is_panic());
+        assert_eq!(BLOCK_IN_TASK_DEPTH.with(Cell::get), 0);
+
+        let nested = executor.spawn(async { Handle::current().block_in_task(async { 1 }) });
+        executor.run();
+        assert_eq!(nested.join_blocking().unwrap(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "nested deeper than 1")]
+    fn block_in_task_depth_is_bounded() {
+        let executor = Builder::new()
+            .max_block_in_task_depth(1)
+            .panic_behavior(PanicBehavior::Resume)
+            .build();
+        executor.spawn(async {
+            let handle = Handle::current();
+            handle.block_in_task(async { Handle::current().block_in_task(async {}) })
+        });
+        executor.run();
+    }
+
+    #[inline(never)]
+    fn spawn_through_outer_helper(handle: &Handle) -> JoinHandle<()> {
+        spawn_through_inner_helper(handle)
+    }
+
+    #[inline(never)]
+    fn spawn_through_inner_helper(handle: &Handle) -> JoinHandle<()> {
+        handle.spawn(future::pending())
+    }
+
+    #[test]
+    fn spawn_backtraces_show_every_helper() {
+        let executor = Builder::new().capture_spawn_backtraces(true).build();
+        drop(spawn_through_outer_helper(&executor.handle()));
+
+        let snapshot = executor.snapshot();
+        let stuck = snapshot.tasks.iter().find(|task| !task.background).unwrap();
+        let backtrace = stuck.spawn_backtrace.as_ref().unwrap().to_string();
+        assert!(
+            backtrace.contains("spawn_through_outer_helper"),
+            "{backtrace}"
+        );
+        assert!(
+            backtrace.contains("spawn_through_inner_helper"),
+            "{backtrace}"
+        );
+
+        let report = executor.shutdown().to_string();
+        assert!(report.contains("spawn_through_outer_helper"), "{report}");
+        assert!(report.contains("spawn_through_inner_helper"), "{report}");
+    }
+
+    #[test]
+    fn spawn_backtraces_are_off_by_default() {
+        let executor = Executor::new();
+        drop(spawn_through_outer_helper(&executor.handle()));
+        let snapshot = executor.snapshot();
+        assert!(snapshot
+            .tasks
+            .iter()
+            .all(|task| task.spawn_backtrace.is_none()));
+    }
+
+    #[test]
+    fn block_on_all_returns_outputs_in_input_order() {
+        let executor = Executor::new();
+        let sleep = |ms, value| async move {
+            TimerFuture::new(Duration::from_millis(ms)).await;
+            value
+        };
+        let started = Instant::now();
+        let outputs = executor.block_on_all([sleep(60, "a"), sleep(20, "b"), sleep(40, "c")]);
+        let elapsed = started.elapsed();
+        assert_eq!(outputs, ["a", "b", "c"]);
+        // The longest of the sleeps, not their sum.
+        assert!(elapsed >= Duration::from_millis(60), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(110), "{elapsed:?}");
+    }
+
+    #[test]
+    fn block_on_all_drives_the_tasks_spawned_by_the_futures() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let inner = Handle::current().spawn(async {
+                TimerFuture::new(Duration::from_millis(5)).await;
+                2
+            });
+            inner.await.unwrap() * 3
+        }]);
+        assert_eq!(outputs, [6]);
+    }
+
+    #[test]
+    fn block_on_all_resumes_the_first_panic_once_all_are_done() {
+        let executor = Executor::new();
+        let finished = Arc::new(AtomicBool::new(false));
+        let late = finished.clone();
+        let futures: Vec<BoxFuture<'static, ()>> = vec![
+            Box::pin(async {
+                TimerFuture::new(Duration::from_millis(10)).await;
+                panic!("first")
+            }),
+            Box::pin(async { panic!("second") }),
+            Box::pin(async move {
+                TimerFuture::new(Duration::from_millis(30)).await;
+                late.store(true, Ordering::SeqCst);
+            }),
+        ];
+        let payload =
+            panic::catch_unwind(AssertUnwindSafe(|| executor.block_on_all(futures))).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"first"));
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn enter_lets_setup_code_spawn_and_sleep() {
+        std::thread::spawn(|| {
+            let executor = Executor::new();
+            assert!(Handle::try_current().is_none());
+            let guard = executor.enter();
+            let sleep = TimerFuture::new(Duration::from_millis(5));
+            let task = task::spawn(async move {
+                sleep.await;
+                "slept"
+            });
+            drop(guard);
+            assert!(Handle::try_current().is_none());
+            executor.run();
+            assert_eq!(task.join_blocking().unwrap(), "slept");
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn nested_enters_restore_the_previous_executor() {
+        let outer = Executor::new();
+        let inner = Executor::new();
+        let _outer_guard = outer.enter();
+        {
+            let _inner_guard = inner.enter();
+            drop(task::spawn(future::pending::<()>()));
+        }
+        drop(task::spawn(future::pending::<()>()));
+        drop(task::spawn(future::pending::<()>()));
+        assert_eq!(inner.metrics().tasks_alive, 1);
+        assert_eq!(outer.metrics().tasks_alive, 2);
+    }
+
+    #[test]
+    fn shutdown_does_not_wait_and_cancels_later_spawns() {
+        let executor = Executor::new();
+        let handle = executor.handle();
+        drop(handle.spawn_blocking(|| std::thread::sleep(Duration::from_millis(500))));
+        let started = Instant::now();
+        drop(executor.shutdown());
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        let late = handle.spawn(async { 1 });
+        assert!(late.join_blocking().unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn run_finishes_every_task_spawned_before_it_from_another_thread() {
+        const TASKS: usize = 50_000;
+        let executor = Executor::new();
+        let handle = executor.handle();
+        let spawned = Arc::new(Mutex::new(Vec::with_capacity(TASKS)));
+        let spawner = {
+            let spawned = spawned.clone();
+            std::thread::spawn(move || {
+                for i in 0..TASKS {
+                    let task = handle.spawn(async move { i });
+                    spawned.lock().unwrap().push(task);
+                }
+            })
+        };
+        while !spawner.is_finished() {
+            let accepted = spawned.lock().unwrap().len();
+            executor.run();
+            let spawned = spawned.lock().unwrap();
+            assert!(spawned[..accepted].iter().all(JoinHandle::is_finished));
+        }
+        spawner.join().unwrap();
+        executor.run();
+        let spawned = std::mem::take(&mut *spawned.lock().unwrap());
+        for (i, task) in spawned.into_iter().enumerate() {
+            assert_eq!(task.join_blocking().unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn run_collect_yields_in_completion_order_and_aborts_the_rest_when_dropped() {
+        let executor = Executor::new();
+        let tracker = DropTracker::new();
+        // Spawned longest first, so completion order is not input order.
+        let jobs = [250, 200, 150, 100, 50].map(|ms| {
+            let guard = tracker.guard(format!("{ms}ms"));
+            async move {
+                let _guard = guard;
+                TimerFuture::new(Duration::from_millis(ms)).await;
+                ms
+            }
+        });
+        let mut results = executor.run_collect(jobs);
+        let first: Vec<_> = results.by_ref().take(3).collect();
+        assert_eq!(first, [50, 100, 150]);
+        assert!(!tracker.is_dropped("200ms"));
+        drop(results);
+        executor.run();
+        assert!(tracker.is_dropped("200ms") && tracker.is_dropped("250ms"));
+        assert!(executor.metrics().tasks_alive == 0);
+    }
+
+    #[test]
+    fn run_collect_skips_futures_cancelled_by_a_shutdown() {
+        let executor = Builder::new()
+            .panic_behavior(PanicBehavior::ShutdownRuntime)
+            .build();
+        let jobs: [Pin<Box<dyn Future<Output = u64> + Send>>; 2] = [
+            Box::pin(async {
+                TimerFuture::new(Duration::from_millis(5)).await;
+                panic!("shutting down")
+            }),
+            Box::pin(async {
+                TimerFuture::new(Duration::from_millis(50)).await;
+                50
+            }),
+        ];
+        let mut results = executor.run_collect(jobs);
+        // The panic itself is kept for `run`, see `PanicBehavior`.
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| results.next())).is_err());
+        assert_eq!(results.next(), None);
+    }
+
+    #[test]
+    fn run_until_signal_stops_long_sleeps_after_the_grace_period() {
+        let executor = Builder::new()
+            .shutdown_grace_period(Duration::from_millis(20))
+            .build();
+        let sleepers: Vec<_> = (0..3)
+            .map(|_| executor.spawn(TimerFuture::new(Duration::from_secs(10))))
+            .collect();
+        let started = Instant::now();
+        let reason = executor.run_until_signal(TimerFuture::new(Duration::from_millis(50)));
+        let elapsed = started.elapsed();
+        assert_eq!(reason, RunStopReason::Signalled);
+        assert!(elapsed >= Duration::from_millis(70), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(500), "{elapsed:?}");
+        for sleeper in sleepers {
+            assert!(sleeper.join_blocking().unwrap_err().is_cancelled());
+        }
+    }
+
+    #[test]
+    fn run_until_signal_returns_once_the_tasks_finished() {
+        let executor = Executor::new();
+        let worker = executor.spawn(async {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            "done"
+        });
+        let started = Instant::now();
+        let reason = executor.run_until_signal(TimerFuture::new(Duration::from_secs(10)));
+        assert_eq!(reason, RunStopReason::Completed);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(worker.join_blocking().unwrap(), "done");
+    }
+
+    #[test]
+    fn a_signal_does_not_interrupt_a_poll() {
+        let executor = Executor::new();
+        let finished_poll = Arc::new(AtomicBool::new(false));
+        let busy = executor.spawn({
+            let finished_poll = finished_poll.clone();
+            async move {
+                let started = Instant::now();
+                while started.elapsed() < Duration::from_millis(30) {}
+                finished_poll.store(true, Ordering::SeqCst);
+                crate::future::pending::<()>().await
+            }
+        });
+        let reason = executor.run_until_signal(TimerFuture::new(Duration::from_millis(5)));
+        assert_eq!(reason, RunStopReason::Signalled);
+        assert!(finished_poll.load(Ordering::SeqCst));
+        assert!(busy.join_blocking().unwrap_err().is_cancelled());
+    }
+}
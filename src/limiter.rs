@@ -0,0 +1,249 @@
+use std::{
+    fmt::{self, Debug},
+    future::Future,
+    mem,
+    panic::Location,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    lock,
+    task::{tag_wakes, wake_all, JoinHandle, Priority, TaskMeta, Waiters},
+    Handle,
+};
+
+/// The permits of a named `Limiter`, shared by every `Limiter` of that name.
+pub(crate) struct Bucket {
+    name: String,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    permits: usize,
+    /// Permits held by tasks, which may exceed `permits` after a shrink.
+    in_flight: usize,
+    waiters: Waiters,
+}
+
+impl Bucket {
+    pub(crate) fn new(name: String, permits: usize) -> Self {
+        Bucket {
+            name,
+            state: Mutex::new(BucketState {
+                permits,
+                in_flight: 0,
+                waiters: Waiters::default(),
+            }),
+        }
+    }
+    /// Let every waiting task look again, as a permit may be free.
+    fn wake_waiters(&self) {
+        let waiters = lock(&self.state).waiters.take();
+        tag_wakes("Limiter", || wake_all(waiters));
+    }
+}
+
+/// Caps how many tasks of one category run at a time, e.g. those hitting
+/// the database, see `Handle::limiter`.
+///
+/// Tasks spawned through it wait for a permit before their future is first
+/// polled, and hold it until they finish or are aborted.
+#[derive(Clone)]
+pub struct Limiter {
+    handle: Handle,
+    bucket: Arc<Bucket>,
+}
+impl Limiter {
+    pub(crate) fn new(handle: Handle, bucket: Arc<Bucket>) -> Self {
+        Limiter { handle, bucket }
+    }
+    pub fn name(&self) -> &str {
+        &self.bucket.name
+    }
+    /// Spawn a task which runs `future` once it got a permit.
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let meta = TaskMeta::new(None, Priority::Normal, Location::caller());
+        let acquire = Acquire {
+            bucket: self.bucket.clone(),
+            waker: None,
+        };
+        self.handle.inner.spawn(meta, async move {
+            // Dropped with the task however it ends, giving the permit back.
+            let _permit = acquire.await;
+            future.await
+        })
+    }
+    /// Permits free right now.
+    pub fn available(&self) -> usize {
+        let state = lock(&self.bucket.state);
+        state.permits.saturating_sub(state.in_flight)
+    }
+    /// Permits held by running tasks; more than the size until the excess
+    /// drained, after shrinking.
+    pub fn in_flight(&self) -> usize {
+        lock(&self.bucket.state).in_flight
+    }
+    /// Change the number of permits, for every `Limiter` of this name. When
+    /// shrinking, tasks already running keep theirs, and new ones wait until
+    /// enough of those finished.
+    pub fn resize(&self, permits: usize) {
+        let grown = {
+            let mut state = lock(&self.bucket.state);
+            mem::replace(&mut state.permits, permits) < permits
+        };
+        if grown {
+            self.bucket.wake_waiters();
+        }
+    }
+}
+impl Debug for Limiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = lock(&self.bucket.state);
+        f.debug_struct("Limiter")
+            .field("name", &self.bucket.name)
+            .field("permits", &state.permits)
+            .field("in_flight", &state.in_flight)
+            .finish()
+    }
+}
+
+/// Waits for a permit of a `Limiter`, before its task runs.
+struct Acquire {
+    bucket: Arc<Bucket>,
+    /// Registered in the bucket's `Waiters`.
+    waker: Option<Waker>,
+}
+impl Future for Acquire {
+    type Output = LimiterPermit;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<LimiterPermit> {
+        let this = self.get_mut();
+        let mut state = lock(&this.bucket.state);
+        if state.in_flight < state.permits {
+            state.in_flight += 1;
+            state.waiters.remove(&mut this.waker);
+            return Poll::Ready(LimiterPermit {
+                bucket: this.bucket.clone(),
+            });
+        }
+        state.waiters.register(&mut this.waker, cx.waker());
+        Poll::Pending
+    }
+}
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if self.waker.is_some() {
+            lock(&self.bucket.state).waiters.remove(&mut self.waker);
+        }
+    }
+}
+
+/// A permit held by a `Limiter` task, given back when dropped.
+struct LimiterPermit {
+    bucket: Arc<Bucket>,
+}
+impl Drop for LimiterPermit {
+    fn drop(&mut self) {
+        lock(&self.bucket.state).in_flight -= 1;
+        self.bucket.wake_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{Executor, TimerFuture};
+
+    #[test]
+    fn fifty_tasks_share_five_permits_then_two() {
+        let executor = Executor::new();
+        let limiter = executor.handle().limiter("db", 5);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let resized = Arc::new(AtomicBool::new(false));
+        // The most running at once among the tasks started after the resize.
+        let most_after = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..50)
+            .map(|i| {
+                let (running, most) = (running.clone(), most.clone());
+                let (resized, most_after) = (resized.clone(), most_after.clone());
+                limiter.spawn(async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    if resized.load(Ordering::SeqCst) {
+                        most_after.fetch_max(now, Ordering::SeqCst);
+                    }
+                    TimerFuture::new(Duration::from_millis(2)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                })
+            })
+            .collect();
+        let resizer = executor.spawn({
+            let limiter = limiter.clone();
+            async move {
+                TimerFuture::new(Duration::from_millis(5)).await;
+                while running.load(Ordering::SeqCst) < 5 {
+                    TimerFuture::new(Duration::from_millis(1)).await;
+                }
+                limiter.resize(2);
+                resized.store(true, Ordering::SeqCst);
+                // Those running keep their permits until they finish.
+                (limiter.in_flight(), limiter.available())
+            }
+        });
+        executor.run();
+
+        assert_eq!(most.load(Ordering::SeqCst), 5);
+        assert_eq!(resizer.join_blocking().unwrap(), (5, 0));
+        assert!(most_after.load(Ordering::SeqCst) <= 2);
+        for (i, task) in tasks.into_iter().enumerate() {
+            assert_eq!(task.join_blocking().unwrap(), i);
+        }
+        assert_eq!(limiter.in_flight(), 0);
+        assert_eq!(limiter.available(), 2);
+    }
+
+    #[test]
+    fn limiters_of_one_name_share_their_permits() {
+        let executor = Executor::new();
+        let first = executor.handle().limiter("disk", 4);
+        let second = executor.handle().limiter("disk", 10);
+        assert_eq!(second.available(), 4);
+        first.resize(3);
+        assert_eq!(second.available(), 3);
+        assert_eq!(executor.handle().limiter("other", 1).available(), 1);
+    }
+
+    #[test]
+    fn aborting_a_task_gives_its_permit_back() {
+        let executor = Executor::new();
+        let limiter = executor.handle().limiter("db", 1);
+        let stuck = limiter.spawn(crate::future::pending::<()>());
+        let waiting = limiter.spawn(async { "ran" });
+        let aborter = executor.spawn({
+            let limiter = limiter.clone();
+            async move {
+                TimerFuture::new(Duration::from_millis(5)).await;
+                assert_eq!((limiter.in_flight(), limiter.available()), (1, 0));
+                stuck.abort();
+                stuck.await
+            }
+        });
+        executor.run();
+        assert!(aborter.join_blocking().unwrap().unwrap_err().is_cancelled());
+        assert_eq!(waiting.join_blocking().unwrap(), "ran");
+        assert_eq!((limiter.in_flight(), limiter.available()), (0, 1));
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Upper bounds of the poll duration histogram buckets; polls slower than
+/// the last bound land in one extra overflow bucket.
+pub const POLL_DURATION_BOUNDS: [Duration; 6] = [
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+/// Point-in-time view of an executor's counters, from `Handle::metrics`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Spawned tasks which have not finished yet.
+    pub tasks_alive: usize,
+    /// Tasks spawned since the executor was built.
+    pub tasks_spawned: u64,
+    /// Tasks waiting in the scheduler to be polled.
+    pub queue_depth: usize,
+    /// Tasks held back by `Builder::max_concurrent_tasks`.
+    pub admission_queue_depth: usize,
+    /// `SpawnPermit`s handed out and not used nor dropped yet.
+    pub spawn_permits_reserved: usize,
+    /// Threads of the `Handle::spawn_blocking` pool, busy or idle.
+    pub blocking_threads: usize,
+    /// Blocking closures waiting for a pool thread.
+    pub blocking_queue_depth: usize,
+    /// Task polls since the executor was built.
+    pub polls_total: u64,
+    /// Tasks spawned with `Handle::spawn_with_deadline` which completed or
+    /// panicked after their deadline.
+    pub deadlines_missed: u64,
+    /// Tasks which panicked, whatever `Builder::panic_behavior` made of it.
+    pub tasks_panicked: u64,
+    /// Times `Builder::max_schedule_age` made the executor poll an overdue
+    /// task instead of the one the scheduler picked.
+    pub schedule_age_overrides: u64,
+    /// Task wakes made by one of the executor's tasks as it was polled, see
+    /// `WakePlacement`.
+    pub wakes_same_thread: u64,
+    /// Task wakes made from anywhere else: timers, blocking closures, other
+    /// threads.
+    pub wakes_external: u64,
+    /// Number of polls per duration bucket, as bounded by
+    /// `POLL_DURATION_BOUNDS`, plus the overflow bucket last.
+    pub poll_duration_buckets: [u64; POLL_DURATION_BOUNDS.len() + 1],
+    /// Time spent in all those polls.
+    pub poll_duration_total: Duration,
+    /// The counters of each `ChildRuntime` not dropped yet, oldest first;
+    /// those above count the tasks of every runtime.
+    pub runtimes: Vec<RuntimeMetrics>,
+}
+
+/// Counters of one `ChildRuntime`, from `ChildRuntime::metrics`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeMetrics {
+    pub name: String,
+    /// Its tasks which have not finished yet.
+    pub tasks_alive: usize,
+    /// Tasks spawned into it, including those aborted at once for being
+    /// spawned after its shutdown.
+    pub tasks_spawned: u64,
+    /// Polls of its tasks.
+    pub polls_total: u64,
+    /// Its tasks which panicked.
+    pub tasks_panicked: u64,
+}
+
+/// Values of a `MetricsSnapshot` read from the executor's state rather than
+/// counted.
+pub(crate) struct Gauges {
+    pub(crate) tasks_alive: usize,
+    pub(crate) queue_depth: usize,
+    pub(crate) admission_queue_depth: usize,
+    pub(crate) spawn_permits_reserved: usize,
+    pub(crate) blocking_threads: usize,
+    pub(crate) blocking_queue_depth: usize,
+    pub(crate) runtimes: Vec<RuntimeMetrics>,
+}
+
+/// Counters behind `MetricsSnapshot`, updated by the executor as it goes.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    pub(crate) tasks_spawned: AtomicU64,
+    pub(crate) deadlines_missed: AtomicU64,
+    pub(crate) tasks_panicked: AtomicU64,
+    pub(crate) schedule_age_overrides: AtomicU64,
+    wakes_same_thread: AtomicU64,
+    wakes_external: AtomicU64,
+    polls_total: AtomicU64,
+    poll_duration_buckets: [AtomicU64; POLL_DURATION_BOUNDS.len() + 1],
+    poll_duration_nanos: AtomicU64,
+}
+impl Metrics {
+    pub(crate) fn record_poll(&self, duration: Duration) {
+        let bucket = POLL_DURATION_BOUNDS
+            .iter()
+            .position(|bound| duration <= *bound)
+            .unwrap_or(POLL_DURATION_BOUNDS.len());
+        self.polls_total.fetch_add(1, Ordering::Relaxed);
+        self.poll_duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.poll_duration_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+    pub(crate) fn count_wake(&self, same_thread: bool) {
+        let counter = if same_thread {
+            &self.wakes_same_thread
+        } else {
+            &self.wakes_external
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn polls_total(&self) -> u64 {
+        self.polls_total.load(Ordering::Relaxed)
+    }
+    pub(crate) fn snapshot(&self, gauges: Gauges) -> MetricsSnapshot {
+        MetricsSnapshot {
+            tasks_alive: gauges.tasks_alive,
+            tasks_spawned: self.tasks_spawned.load(Ordering::Relaxed),
+            queue_depth: gauges.queue_depth,
+            admission_queue_depth: gauges.admission_queue_depth,
+            spawn_permits_reserved: gauges.spawn_permits_reserved,
+            blocking_threads: gauges.blocking_threads,
+            blocking_queue_depth: gauges.blocking_queue_depth,
+            polls_total: self.polls_total.load(Ordering::Relaxed),
+            deadlines_missed: self.deadlines_missed.load(Ordering::Relaxed),
+            tasks_panicked: self.tasks_panicked.load(Ordering::Relaxed),
+            schedule_age_overrides: self.schedule_age_overrides.load(Ordering::Relaxed),
+            wakes_same_thread: self.wakes_same_thread.load(Ordering::Relaxed),
+            wakes_external: self.wakes_external.load(Ordering::Relaxed),
+            poll_duration_buckets: self
+                .poll_duration_buckets
+                .each_ref()
+                .map(|bucket| bucket.load(Ordering::Relaxed)),
+            poll_duration_total: Duration::from_nanos(
+                self.poll_duration_nanos.load(Ordering::Relaxed),
+            ),
+            runtimes: gauges.runtimes,
+        }
+    }
+}
+
+/// Render `snapshot` in the Prometheus text exposition format, every metric
+/// name starting with `prefix` (e.g. `"executor_"`).
+pub fn prometheus_text(snapshot: &MetricsSnapshot, prefix: &str) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+        let _ = writeln!(text, "# HELP {prefix}{name} {help}");
+        let _ = writeln!(text, "# TYPE {prefix}{name} {kind}");
+        let _ = writeln!(text, "{prefix}{name} {value}");
+    };
+    metric(
+        "tasks_alive",
+        "gauge",
+        "Spawned tasks which have not finished yet.",
+        &snapshot.tasks_alive,
+    );
+    metric(
+        "tasks_spawned_total",
+        "counter",
+        "Tasks spawned since the executor was built.",
+        &snapshot.tasks_spawned,
+    );
+    metric(
+        "queue_depth",
+        "gauge",
+        "Tasks waiting to be polled.",
+        &snapshot.queue_depth,
+    );
+    metric(
+        "admission_queue_depth",
+        "gauge",
+        "Tasks held back by the concurrent task limit.",
+        &snapshot.admission_queue_depth,
+    );
+    metric(
+        "spawn_permits_reserved",
+        "gauge",
+        "Spawn permits handed out and not used yet.",
+        &snapshot.spawn_permits_reserved,
+    );
+    metric(
+        "blocking_threads",
+        "gauge",
+        "Threads of the blocking pool.",
+        &snapshot.blocking_threads,
+    );
+    metric(
+        "blocking_queue_depth",
+        "gauge",
+        "Blocking closures waiting for a pool thread.",
+        &snapshot.blocking_queue_depth,
+    );
+    metric(
+        "polls_total",
+        "counter",
+        "Task polls since the executor was built.",
+        &snapshot.polls_total,
+    );
+    metric(
+        "deadlines_missed_total",
+        "counter",
+        "Deadline tasks which completed after their deadline.",
+        &snapshot.deadlines_missed,
+    );
+    metric(
+        "tasks_panicked_total",
+        "counter",
+        "Tasks which panicked.",
+        &snapshot.tasks_panicked,
+    );
+    metric(
+        "schedule_age_overrides_total",
+        "counter",
+        "Overdue tasks polled ahead of the scheduler's pick.",
+        &snapshot.schedule_age_overrides,
+    );
+    metric(
+        "wakes_same_thread_total",
+        "counter",
+        "Task wakes made by a task being polled.",
+        &snapshot.wakes_same_thread,
+    );
+    metric(
+        "wakes_external_total",
+        "counter",
+        "Task wakes made from timers, blocking closures or other threads.",
+        &snapshot.wakes_external,
+    );
+
+    let name = format!("{prefix}poll_duration_seconds");
+    let _ = writeln!(text, "# HELP {name} Time taken by each task poll.");
+    let _ = writeln!(text, "# TYPE {name} histogram");
+    let mut cumulative = 0;
+    for (bound, count) in POLL_DURATION_BOUNDS
+        .iter()
+        .zip(&snapshot.poll_duration_buckets)
+    {
+        cumulative += count;
+        let le = bound.as_secs_f64();
+        let _ = writeln!(text, "{name}_bucket{{le=\"{le}\"}} {cumulative}");
+    }
+    let _ = writeln!(
+        text,
+        "{name}_bucket{{le=\"+Inf\"}} {}",
+        snapshot.polls_total
+    );
+    let sum = snapshot.poll_duration_total.as_secs_f64();
+    let _ = writeln!(text, "{name}_sum {sum}");
+    let _ = writeln!(text, "{name}_count {}", snapshot.polls_total);
+
+    // One sample per child runtime, labelled with its name.
+    if snapshot.runtimes.is_empty() {
+        return text;
+    }
+    let mut runtime_metric =
+        |name: &str, kind: &str, help: &str, value: fn(&RuntimeMetrics) -> u64| {
+            let _ = writeln!(text, "# HELP {prefix}{name} {help}");
+            let _ = writeln!(text, "# TYPE {prefix}{name} {kind}");
+            for runtime in &snapshot.runtimes {
+                let label = label_value(&runtime.name);
+                let _ = writeln!(
+                    text,
+                    "{prefix}{name}{{runtime=\"{label}\"}} {}",
+                    value(runtime)
+                );
+            }
+        };
+    runtime_metric(
+        "runtime_tasks_alive",
+        "gauge",
+        "Tasks of a child runtime which have not finished yet.",
+        |runtime| runtime.tasks_alive as u64,
+    );
+    runtime_metric(
+        "runtime_tasks_spawned_total",
+        "counter",
+        "Tasks spawned into a child runtime.",
+        |runtime| runtime.tasks_spawned,
+    );
+    runtime_metric(
+        "runtime_polls_total",
+        "counter",
+        "Polls of the tasks of a child runtime.",
+        |runtime| runtime.polls_total,
+    );
+    runtime_metric(
+        "runtime_tasks_panicked_total",
+        "counter",
+        "Tasks of a child runtime which panicked.",
+        |runtime| runtime.tasks_panicked,
+    );
+    text
+}
+
+/// `value` escaped for a label of the text exposition format.
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::{Executor, TimerFuture};
+
+    /// The samples of a text exposition, by metric name and labels, checking
+    /// that every line is well formed.
+    fn parse(text: &str) -> HashMap<String, f64> {
+        let mut samples = HashMap::new();
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let (keyword, rest) = comment.split_once(' ').unwrap();
+                assert!(matches!(keyword, "HELP" | "TYPE"), "{line}");
+                assert!(rest.contains(' '), "{line}");
+                continue;
+            }
+            let (name, value) = line.rsplit_once(' ').unwrap();
+            let value: f64 = value.parse().unwrap_or_else(|_| panic!("{line}"));
+            assert!(samples.insert(name.to_string(), value).is_none(), "{line}");
+        }
+        samples
+    }
+
+    #[test]
+    fn exporter_produces_prometheus_text() {
+        let executor = Executor::new();
+        let exported = Arc::new(Mutex::new(Vec::new()));
+        let sink = exported.clone();
+        executor
+            .handle()
+            .metrics_exporter(Duration::from_millis(10), move |snapshot| {
+                sink.lock().unwrap().push(prometheus_text(snapshot, "app_"));
+            });
+        for _ in 0..10 {
+            drop(executor.spawn(async {}));
+        }
+        drop(executor.spawn(TimerFuture::new(Duration::from_millis(60))));
+        executor.run();
+
+        let exported = exported.lock().unwrap();
+        assert!(!exported.is_empty());
+        let samples = parse(exported.last().unwrap());
+        for name in [
+            "app_tasks_alive",
+            "app_tasks_spawned_total",
+            "app_queue_depth",
+            "app_polls_total",
+            "app_wakes_external_total",
+            "app_poll_duration_seconds_bucket{le=\"+Inf\"}",
+            "app_poll_duration_seconds_sum",
+            "app_poll_duration_seconds_count",
+        ] {
+            assert!(samples.contains_key(name), "{name} missing");
+        }
+        // The sleeper, and the exporter's own task, are spawned; the ten
+        // others are done by the time the sleeper is alone, which the last
+        // export may come after.
+        assert!(samples["app_tasks_spawned_total"] >= 12.0);
+        assert!(exported
+            .iter()
+            .any(|text| parse(text)["app_tasks_alive"] == 1.0));
+        assert!(samples["app_polls_total"] >= 11.0);
+        assert_eq!(
+            samples["app_poll_duration_seconds_count"],
+            samples["app_polls_total"]
+        );
+    }
+}
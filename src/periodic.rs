@@ -0,0 +1,369 @@
+use std::{
+    fmt::{self, Debug},
+    future::{poll_fn, Future},
+    mem,
+    panic::{self, AssertUnwindSafe, Location},
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use crate::{
+    counters::clone_waker,
+    handlers, lock,
+    task::{JoinHandle, Priority, TaskMeta},
+    time, Handle,
+};
+
+/// What `Handle::spawn_periodic` does when a tick comes while an earlier run
+/// has not finished yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overlap {
+    /// Let the tick go by.
+    #[default]
+    Skip,
+    /// Start another run as soon as the last one finishes, once per tick
+    /// missed meanwhile.
+    Queue,
+    /// Start another run right away, alongside.
+    Concurrent,
+}
+
+struct Shared {
+    state: Mutex<PeriodicState>,
+}
+
+struct PeriodicState {
+    paused: bool,
+    stopped: bool,
+    /// Set by `trigger_now` until the driver saw it.
+    triggered: bool,
+    /// Runs not finished yet.
+    in_flight: usize,
+    /// Runs owed with `Overlap::Queue`, started as the one in flight
+    /// finishes.
+    queued: usize,
+    /// The driver task's waker, as of its latest poll.
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    fn update(&self, f: impl FnOnce(&mut PeriodicState)) {
+        let waker = {
+            let mut state = lock(&self.state);
+            f(&mut state);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Spawn the task calling `f` every `period`, on behalf of
+/// `Handle::spawn_periodic`.
+#[track_caller]
+pub(crate) fn spawn<F, Fut>(
+    handle: &Handle,
+    period: Duration,
+    overlap: Overlap,
+    mut f: F,
+) -> PeriodicHandle
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let location = Location::caller();
+    let shared = Arc::new(Shared {
+        state: Mutex::new(PeriodicState {
+            paused: false,
+            stopped: false,
+            triggered: false,
+            in_flight: 0,
+            queued: 0,
+            waker: None,
+        }),
+    });
+    // By the clock of the executor, from wherever this is called.
+    let mut interval = {
+        let _enter = handle.enter();
+        time::interval(period)
+    };
+    let driver_shared = shared.clone();
+    let run_handle = handle.clone();
+    let meta = TaskMeta::new(Some("periodic".to_string()), Priority::Normal, location);
+    let driver = handle.inner.spawn(meta, async move {
+        let shared = driver_shared;
+        loop {
+            // How many runs to start, or `None` once stopped.
+            let start = poll_fn(|cx| {
+                let mut state = lock(&shared.state);
+                if state.stopped {
+                    return Poll::Ready(None);
+                }
+                state.waker = Some(clone_waker(cx.waker()));
+                let mut ticks = usize::from(mem::take(&mut state.triggered));
+                // Ticks are taken even while paused, so the schedule stays
+                // put, only without runs.
+                while interval.poll_tick(cx).is_ready() {
+                    ticks += usize::from(!state.paused);
+                }
+                let idle = state.in_flight == 0;
+                let start = match overlap {
+                    Overlap::Skip => usize::from(idle && ticks > 0),
+                    Overlap::Queue => {
+                        state.queued += ticks;
+                        let start = usize::from(idle && state.queued > 0);
+                        state.queued -= start;
+                        start
+                    }
+                    Overlap::Concurrent => ticks,
+                };
+                if start == 0 {
+                    return Poll::Pending;
+                }
+                state.in_flight += start;
+                Poll::Ready(Some(start))
+            })
+            .await;
+            let Some(start) = start else {
+                break;
+            };
+            for _ in 0..start {
+                let guard = RunGuard {
+                    shared: shared.clone(),
+                };
+                let meta =
+                    TaskMeta::new(Some("periodic-run".to_string()), Priority::Normal, location);
+                // A panic building the run counts as that run's, rather than
+                // ending the driver and every run after it.
+                let run = match panic::catch_unwind(AssertUnwindSafe(&mut f)) {
+                    Ok(run) => run,
+                    Err(payload) => {
+                        drop(guard);
+                        handlers::panicked(&meta, payload);
+                        continue;
+                    }
+                };
+                // Detached: a run which panics goes to the panic handler, and
+                // the next tick starts another one regardless.
+                drop(run_handle.inner.spawn(meta, async move {
+                    let _guard = guard;
+                    run.await
+                }));
+            }
+        }
+    });
+    PeriodicHandle { shared, driver }
+}
+
+/// Counts a run as finished however it ends, letting the driver start any
+/// queued one.
+struct RunGuard {
+    shared: Arc<Shared>,
+}
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.shared.update(|state| state.in_flight -= 1);
+    }
+}
+
+/// Controls a job spawned with `Handle::spawn_periodic`. Dropping it leaves
+/// the job running.
+pub struct PeriodicHandle {
+    shared: Arc<Shared>,
+    driver: JoinHandle<()>,
+}
+impl PeriodicHandle {
+    /// Let ticks go by without runs until `resume`.
+    pub fn pause(&self) {
+        self.shared.update(|state| state.paused = true);
+    }
+    /// Start runs again from the next tick; those missed while paused are
+    /// not made up for.
+    pub fn resume(&self) {
+        self.shared.update(|state| state.paused = false);
+    }
+    pub fn is_paused(&self) -> bool {
+        lock(&self.shared.state).paused
+    }
+    /// Start a run now, as if a tick came, the overlap policy included,
+    /// even while paused. The ticks stay on their schedule.
+    pub fn trigger_now(&self) {
+        self.shared.update(|state| state.triggered = true);
+    }
+    /// Start no more runs, queued ones included. Runs in flight are left
+    /// to finish.
+    pub fn stop(&self) {
+        self.shared.update(|state| state.stopped = true);
+    }
+    /// Whether the job was stopped, and no run is in flight any more.
+    pub fn is_finished(&self) -> bool {
+        self.driver.is_finished() && lock(&self.shared.state).in_flight == 0
+    }
+}
+impl Debug for PeriodicHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = lock(&self.shared.state);
+        f.debug_struct("PeriodicHandle")
+            .field("paused", &state.paused)
+            .field("stopped", &state.stopped)
+            .field("in_flight", &state.in_flight)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{Builder, TimerFuture};
+
+    const PERIOD: Duration = Duration::from_millis(80);
+
+    /// When each run started by a paused clock, for runs taking one and a
+    /// half periods, with the job stopped after six and a half periods.
+    fn run_starts(overlap: Overlap) -> Vec<Duration> {
+        let executor = Builder::new().start_paused(true).build();
+        let started = {
+            let _enter = executor.enter();
+            time::now()
+        };
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let job = executor
+            .handle()
+            .spawn_periodic_with_overlap(PERIOD, overlap, {
+                let starts = starts.clone();
+                move || {
+                    lock(&starts).push(time::now() - started);
+                    TimerFuture::new(PERIOD * 3 / 2)
+                }
+            });
+        drop(executor.spawn(async move {
+            TimerFuture::new(PERIOD * 13 / 2).await;
+            job.stop();
+        }));
+        executor.run();
+        let starts = lock(&starts).clone();
+        starts
+    }
+
+    /// Check `starts` against the times expected, in half periods.
+    fn assert_starts(starts: &[Duration], half_periods: &[u32]) {
+        let expected: Vec<_> = half_periods.iter().map(|&n| PERIOD / 2 * n).collect();
+        assert_eq!(starts, expected);
+    }
+
+    #[test]
+    fn skip_lets_the_ticks_during_a_run_go_by() {
+        assert_starts(&run_starts(Overlap::Skip), &[0, 4, 8, 12]);
+    }
+
+    #[test]
+    fn queue_starts_each_missed_tick_after_the_run() {
+        assert_starts(&run_starts(Overlap::Queue), &[0, 3, 6, 9, 12]);
+    }
+
+    #[test]
+    fn concurrent_starts_on_every_tick() {
+        let expected = [0, 2, 4, 6, 8, 10, 12];
+        assert_starts(&run_starts(Overlap::Concurrent), &expected);
+    }
+
+    #[test]
+    fn stop_lets_the_run_in_flight_finish() {
+        let executor = Builder::new().start_paused(true).build();
+        let (runs, finished) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let job = Arc::new(executor.handle().spawn_periodic(PERIOD, {
+            let (runs, finished) = (runs.clone(), finished.clone());
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                let finished = finished.clone();
+                async move {
+                    TimerFuture::new(PERIOD / 2).await;
+                    finished.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }));
+        let stopper = executor.spawn({
+            let job = job.clone();
+            async move {
+                // Halfway through the second run.
+                TimerFuture::new(PERIOD + PERIOD / 4).await;
+                job.stop();
+                job.is_finished()
+            }
+        });
+        executor.run();
+        assert!(!stopper.join_blocking().unwrap());
+        assert!(job.is_finished());
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn a_panicking_run_does_not_stop_the_next() {
+        crate::handlers::tests::install();
+        let executor = Builder::new().start_paused(true).build();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let job = executor.handle().spawn_periodic(PERIOD / 5, {
+            let runs = runs.clone();
+            move || {
+                let run = runs.fetch_add(1, Ordering::SeqCst);
+                async move { assert!(run != 0, "first run") }
+            }
+        });
+        drop(executor.spawn({
+            let runs = runs.clone();
+            async move {
+                while runs.load(Ordering::SeqCst) < 3 {
+                    TimerFuture::new(PERIOD / 5).await;
+                }
+                job.stop();
+            }
+        }));
+        executor.run();
+        let seen = crate::handlers::tests::seen(file!());
+        assert!(
+            seen.iter().any(|entry| entry.ends_with("first run")),
+            "{seen:?}"
+        );
+    }
+
+    #[test]
+    fn a_panic_building_a_run_does_not_stop_the_next() {
+        crate::handlers::tests::install();
+        let executor = Builder::new().start_paused(true).build();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let job = executor.handle().spawn_periodic(PERIOD / 5, {
+            let runs = runs.clone();
+            move || {
+                let run = runs.fetch_add(1, Ordering::SeqCst);
+                assert!(run != 0, "building the first run");
+                async {}
+            }
+        });
+        drop(executor.spawn({
+            let runs = runs.clone();
+            async move {
+                // Bounded, for the test to fail rather than hang.
+                for _ in 0..100 {
+                    if runs.load(Ordering::SeqCst) >= 3 {
+                        break;
+                    }
+                    TimerFuture::new(PERIOD / 5).await;
+                }
+                job.stop();
+            }
+        }));
+        executor.run();
+        assert!(runs.load(Ordering::SeqCst) >= 3);
+        let seen = crate::handlers::tests::seen(file!());
+        assert!(
+            seen.iter()
+                .any(|entry| entry == "periodic-run: panic: building the first run"),
+            "{seen:?}"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    fmt::{self, Debug},
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    lock,
+    task::{tag_wakes, wake_all, JoinHandle, Waiters},
+    Handle,
+};
+
+/// Reservations made through `Handle::spawn_permit`, held against
+/// `Builder::spawn_high_water_mark`.
+pub(crate) struct Permits {
+    high_water_mark: usize,
+    state: Mutex<PermitState>,
+    /// Set while some `AcquirePermit` may be waiting, so the executor only
+    /// takes the lock to wake them when needed.
+    waiting: AtomicBool,
+}
+
+struct PermitState {
+    /// Permits handed out and neither used nor dropped yet.
+    reserved: usize,
+    waiters: Waiters,
+}
+
+impl Permits {
+    pub(crate) fn new(high_water_mark: usize) -> Self {
+        Permits {
+            high_water_mark,
+            state: Mutex::new(PermitState {
+                reserved: 0,
+                waiters: Waiters::default(),
+            }),
+            waiting: AtomicBool::new(false),
+        }
+    }
+    pub(crate) fn reserved(&self) -> usize {
+        lock(&self.state).reserved
+    }
+    /// Let the waiting `AcquirePermit`s look at the load again, as it went
+    /// down to `queued` tasks, if that leaves room for one.
+    ///
+    /// Without room, they are left waiting: woken, a waiter would only add
+    /// to the queue, and wake itself again once dequeued.
+    pub(crate) fn load_dropped(&self, queued: usize) {
+        if !self.waiting.load(Ordering::SeqCst) {
+            return;
+        }
+        let waiters = {
+            let mut state = lock(&self.state);
+            if queued + state.reserved >= self.high_water_mark {
+                return;
+            }
+            self.waiting.store(false, Ordering::SeqCst);
+            state.waiters.take()
+        };
+        // Woken outside the lock, so waiters polled right away do not block.
+        tag_wakes("SpawnPermit", || wake_all(waiters));
+    }
+}
+
+/// Future returned by `Handle::spawn_permit`.
+pub struct AcquirePermit<'a> {
+    handle: &'a Handle,
+    /// Registered in the `Waiters` of `Permits`.
+    waker: Option<Waker>,
+}
+impl<'a> AcquirePermit<'a> {
+    pub(crate) fn new(handle: &'a Handle) -> Self {
+        AcquirePermit {
+            handle,
+            waker: None,
+        }
+    }
+}
+impl Future for AcquirePermit<'_> {
+    type Output = SpawnPermit;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = &this.handle.inner;
+        let Some(permits) = &inner.permits else {
+            return Poll::Ready(SpawnPermit {
+                handle: this.handle.clone(),
+                reserved: false,
+            });
+        };
+        let mut state = lock(&permits.state);
+        // Raised before reading the load, so a task dequeued right after
+        // the read sees it and wakes us.
+        permits.waiting.store(true, Ordering::SeqCst);
+        if inner.queued() + state.reserved < permits.high_water_mark {
+            state.reserved += 1;
+            state.waiters.remove(&mut this.waker);
+            permits
+                .waiting
+                .store(!state.waiters.is_empty(), Ordering::SeqCst);
+            return Poll::Ready(SpawnPermit {
+                handle: this.handle.clone(),
+                reserved: true,
+            });
+        }
+        state.waiters.register(&mut this.waker, cx.waker());
+        Poll::Pending
+    }
+}
+impl Drop for AcquirePermit<'_> {
+    fn drop(&mut self) {
+        let (Some(_), Some(permits)) = (&self.waker, &self.handle.inner.permits) else {
+            return;
+        };
+        lock(&permits.state).waiters.remove(&mut self.waker);
+    }
+}
+impl Debug for AcquirePermit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AcquirePermit").finish_non_exhaustive()
+    }
+}
+
+/// Room for one more task on a busy executor, from `Handle::spawn_permit`.
+/// Dropping it unused gives the room back.
+pub struct SpawnPermit {
+    handle: Handle,
+    /// Unset when the executor has no high-water mark to reserve against.
+    reserved: bool,
+}
+impl SpawnPermit {
+    /// Spawn `future` in the room reserved, without looking at the load
+    /// again.
+    #[track_caller]
+    pub fn spawn<F>(mut self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        // Queued before the reservation goes, so the load never dips in
+        // between for another permit to slip in.
+        let join = self.handle.spawn(future);
+        if mem::take(&mut self.reserved) {
+            if let Some(permits) = &self.handle.inner.permits {
+                lock(&permits.state).reserved -= 1;
+            }
+        }
+        join
+    }
+}
+impl Drop for SpawnPermit {
+    fn drop(&mut self) {
+        if !self.reserved {
+            return;
+        }
+        let inner = &self.handle.inner;
+        if let Some(permits) = &inner.permits {
+            lock(&permits.state).reserved -= 1;
+            permits.load_dropped(inner.queued());
+        }
+    }
+}
+impl Debug for SpawnPermit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpawnPermit")
+            .field("reserved", &self.reserved)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use crate::{assert_pending, assert_ready, test_util::MockTask, Builder};
+
+    #[test]
+    fn permits_wait_for_the_queue_to_drain() {
+        let executor = Builder::new().spawn_high_water_mark(5).build();
+        let handle = executor.handle();
+        for _ in 0..5 {
+            drop(handle.spawn(async {}));
+        }
+        let mut task = MockTask::new();
+        let mut acquire = pin!(handle.spawn_permit());
+        assert_pending!(task.poll(acquire.as_mut()));
+        assert_eq!(executor.metrics().spawn_permits_reserved, 0);
+
+        executor.run();
+        assert!(task.woken_after_poll());
+        let permit = assert_ready!(task.poll(acquire.as_mut()));
+        assert_eq!(executor.metrics().spawn_permits_reserved, 1);
+        drop(permit);
+        assert_eq!(executor.metrics().spawn_permits_reserved, 0);
+
+        // Reserved ones count towards the mark, the tasks they spawn too.
+        let mut permits = Vec::new();
+        for _ in 0..5 {
+            permits.push(assert_ready!(task.poll(pin!(handle.spawn_permit()))));
+        }
+        assert_eq!(executor.metrics().spawn_permits_reserved, 5);
+        assert_pending!(task.poll(pin!(handle.spawn_permit())));
+        for permit in permits {
+            drop(permit.spawn(async {}));
+        }
+        assert_eq!(executor.metrics().spawn_permits_reserved, 0);
+        assert_pending!(task.poll(pin!(handle.spawn_permit())));
+        executor.run();
+        assert_eq!(executor.metrics().spawn_permits_reserved, 0);
+        drop(assert_ready!(task.poll(pin!(handle.spawn_permit()))));
+        assert_eq!(executor.metrics().spawn_permits_reserved, 0);
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{coop, counters::clone_waker, lock, stream::Stream, task::tag_wakes};
+
+/// A task's progress reports, on their way from its `ProgressSender` to the
+/// `ProgressReceiver` of whoever spawned it.
+struct Channel<P> {
+    state: Mutex<ChannelState<P>>,
+}
+
+struct ChannelState<P> {
+    reports: VecDeque<P>,
+    /// Reports buffered before `send` waits; unset to keep only the latest.
+    capacity: Option<usize>,
+    /// Set once the task ended, however it ended.
+    closed: bool,
+    /// Set once the `ProgressReceiver` was dropped.
+    receiver_dropped: bool,
+    receiver_waker: Option<Waker>,
+    sender_waker: Option<Waker>,
+}
+
+/// A channel for `Handle::spawn_with_progress`, closed when the returned
+/// guard, held by the task, is dropped.
+pub(crate) fn channel<P>(
+    capacity: Option<usize>,
+) -> (ProgressSender<P>, ProgressReceiver<P>, ProgressGuard<P>) {
+    let channel = Arc::new(Channel {
+        state: Mutex::new(ChannelState {
+            reports: VecDeque::new(),
+            capacity,
+            closed: false,
+            receiver_dropped: false,
+            receiver_waker: None,
+            sender_waker: None,
+        }),
+    });
+    (
+        ProgressSender {
+            channel: channel.clone(),
+        },
+        ProgressReceiver {
+            channel: channel.clone(),
+        },
+        ProgressGuard { channel },
+    )
+}
+
+/* ProgressSender */
+/// Reports a task's progress to its `ProgressReceiver`, see
+/// `Handle::spawn_with_progress`.
+pub struct ProgressSender<P> {
+    channel: Arc<Channel<P>>,
+}
+impl<P> ProgressSender<P> {
+    /// Report `progress`.
+    ///
+    /// By default it replaces whatever report was not received yet, and is
+    /// sent right away. With `Handle::spawn_with_bounded_progress`, this
+    /// waits while the receiver is that many reports behind. The report is
+    /// handed back once the receiver was dropped or the task ended.
+    ///
+    /// Not cancel safe: dropping the future while it waits for room drops
+    /// the report with it.
+    pub fn send(&self, progress: P) -> SendProgress<'_, P> {
+        SendProgress {
+            sender: self,
+            progress: Some(progress),
+        }
+    }
+    /// Whether the `ProgressReceiver` was dropped, so reports go nowhere.
+    pub fn is_closed(&self) -> bool {
+        let state = lock(&self.channel.state);
+        state.closed || state.receiver_dropped
+    }
+}
+impl<P> Debug for ProgressSender<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressSender")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+/// Future returned by `ProgressSender::send`.
+pub struct SendProgress<'a, P> {
+    sender: &'a ProgressSender<P>,
+    progress: Option<P>,
+}
+// The report is never pinned, only moved into the channel.
+impl<P> Unpin for SendProgress<'_, P> {}
+impl<P> Future for SendProgress<'_, P> {
+    type Output = Result<(), P>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let progress = this.progress.take().expect("polled after completion");
+        let mut state = lock(&this.sender.channel.state);
+        if state.closed || state.receiver_dropped {
+            return Poll::Ready(Err(progress));
+        }
+        match state.capacity {
+            None => state.reports.clear(),
+            Some(capacity) if state.reports.len() >= capacity => {
+                state.sender_waker = Some(clone_waker(cx.waker()));
+                this.progress = Some(progress);
+                return Poll::Pending;
+            }
+            Some(_) => {}
+        }
+        state.reports.push_back(progress);
+        let waker = state.receiver_waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            tag_wakes("ProgressSender::send", || waker.wake());
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+impl<P> Debug for SendProgress<'_, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendProgress")
+            .field("sent", &self.progress.is_none())
+            .finish()
+    }
+}
+
+/* ProgressReceiver */
+/// Stream of a task's progress reports, from `Handle::spawn_with_progress`.
+///
+/// It ends once the task ended, completed or aborted, and the reports it
+/// sent until then were received.
+pub struct ProgressReceiver<P> {
+    channel: Arc<Channel<P>>,
+}
+impl<P> Stream for ProgressReceiver<P> {
+    type Item = P;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<P>> {
+        // A receiver never waiting, its reports coming faster than it takes
+        // them, lets the task's neighbours through all the same.
+        if coop::exhausted() {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let mut state = lock(&self.channel.state);
+        if let Some(progress) = state.reports.pop_front() {
+            let waker = state.sender_waker.take();
+            drop(state);
+            if let Some(waker) = waker {
+                tag_wakes("ProgressReceiver::next", || waker.wake());
+            }
+            return Poll::Ready(Some(progress));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.receiver_waker = Some(clone_waker(cx.waker()));
+        Poll::Pending
+    }
+}
+impl<P> Drop for ProgressReceiver<P> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = lock(&self.channel.state);
+            state.receiver_dropped = true;
+            state.reports.clear();
+            state.sender_waker.take()
+        };
+        // A sender waiting for room gets its report back.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+impl<P> Debug for ProgressReceiver<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = lock(&self.channel.state);
+        f.debug_struct("ProgressReceiver")
+            .field("buffered", &state.reports.len())
+            .field("closed", &state.closed)
+            .finish()
+    }
+}
+
+/// Closes a progress channel once its task ended, whatever became of the
+/// `ProgressSender`.
+pub(crate) struct ProgressGuard<P> {
+    channel: Arc<Channel<P>>,
+}
+impl<P> Drop for ProgressGuard<P> {
+    fn drop(&mut self) {
+        let (receiver, sender) = {
+            let mut state = lock(&self.channel.state);
+            state.closed = true;
+            (state.receiver_waker.take(), state.sender_waker.take())
+        };
+        for waker in receiver.into_iter().chain(sender) {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{stream::StreamExt, Executor, TimerFuture};
+
+    #[test]
+    fn the_stream_ends_when_the_task_returns() {
+        let executor = Executor::new();
+        let (handle, mut reports) =
+            executor
+                .handle()
+                .spawn_with_bounded_progress(1, |sender| async move {
+                    for i in 1..=10 {
+                        TimerFuture::new(Duration::from_millis(5)).await;
+                        sender.send(i).await.unwrap();
+                    }
+                    "done"
+                });
+        let consumer = executor.spawn(async move {
+            let mut received = Vec::new();
+            while let Some(report) = reports.next().await {
+                assert!(!handle.is_finished() || received.len() == 9);
+                received.push(report);
+            }
+            assert!(handle.is_finished());
+            (received, handle.await.unwrap())
+        });
+        executor.run();
+        let (received, output) = consumer.join_blocking().unwrap();
+        assert_eq!(received, (1..=10).collect::<Vec<_>>());
+        assert_eq!(output, "done");
+    }
+
+    #[test]
+    fn the_stream_ends_when_the_task_is_aborted() {
+        let executor = Executor::new();
+        let (handle, mut reports) = executor.handle().spawn_with_progress(|sender| async move {
+            for i in 0.. {
+                TimerFuture::new(Duration::from_millis(5)).await;
+                if sender.send(i).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let consumer = executor.spawn(async move {
+            let mut received = Vec::new();
+            while let Some(report) = reports.next().await {
+                received.push(report);
+                if received.len() == 3 {
+                    handle.abort();
+                }
+            }
+            assert!(handle.is_finished());
+            (received, handle.await)
+        });
+        executor.run();
+        let (received, output) = consumer.join_blocking().unwrap();
+        assert_eq!(received, [0, 1, 2]);
+        assert!(output.unwrap_err().is_cancelled());
+    }
+}
@@ -0,0 +1,838 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, VecDeque},
+    mem,
+    sync::{atomic::Ordering as AtomicOrdering, Arc, Mutex},
+    time::Instant,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    lock,
+    task::{Priority, Task},
+};
+
+/// Decides in which order ready tasks are polled.
+///
+/// Both freshly spawned and woken tasks go through `schedule`, and the
+/// executor polls whatever `next` returns until it returns `None`. Wakes can
+/// come from any thread, hence the `&self` receivers.
+pub trait Scheduler {
+    /// Queue a task which is ready to be polled.
+    fn schedule(&self, task: Arc<Task>);
+    /// Queue many tasks at once, as spawned by `Handle::spawn_all`.
+    /// Implementations should take their lock only once.
+    fn schedule_all(&self, tasks: Vec<Arc<Task>>) {
+        for task in tasks {
+            self.schedule(task);
+        }
+    }
+    /// Take the task to poll next.
+    fn next(&self) -> Option<Arc<Task>>;
+    /// Number of queued tasks.
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/* Fifo */
+/// Polls tasks in the order they became ready. The default.
+#[derive(Default)]
+pub struct FifoScheduler {
+    ready_queue: Mutex<VecDeque<Arc<Task>>>,
+}
+impl FifoScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl Scheduler for FifoScheduler {
+    fn schedule(&self, task: Arc<Task>) {
+        lock(&self.ready_queue).push_back(task);
+    }
+    fn schedule_all(&self, tasks: Vec<Arc<Task>>) {
+        lock(&self.ready_queue).extend(tasks);
+    }
+    fn next(&self) -> Option<Arc<Task>> {
+        lock(&self.ready_queue).pop_front()
+    }
+    fn len(&self) -> usize {
+        lock(&self.ready_queue).len()
+    }
+}
+
+/* Priority */
+/// Polls higher `Priority` tasks first, in FIFO order within a priority.
+#[derive(Default)]
+pub struct PriorityScheduler {
+    heap: Mutex<PriorityHeap>,
+}
+#[derive(Default)]
+struct PriorityHeap {
+    entries: BinaryHeap<PriorityEntry>,
+    /// Bumped on every push, so that older entries win ties.
+    seq: u64,
+}
+struct PriorityEntry {
+    priority: Priority,
+    seq: u64,
+    task: Arc<Task>,
+}
+impl PriorityScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl Scheduler for PriorityScheduler {
+    fn schedule(&self, task: Arc<Task>) {
+        lock(&self.heap).push(task);
+    }
+    fn schedule_all(&self, tasks: Vec<Arc<Task>>) {
+        let mut heap = lock(&self.heap);
+        for task in tasks {
+            heap.push(task);
+        }
+    }
+    fn next(&self) -> Option<Arc<Task>> {
+        let entry = lock(&self.heap).entries.pop()?;
+        Some(entry.task)
+    }
+    fn len(&self) -> usize {
+        lock(&self.heap).entries.len()
+    }
+}
+impl PriorityHeap {
+    fn push(&mut self, task: Arc<Task>) {
+        self.seq += 1;
+        let entry = PriorityEntry {
+            priority: task.meta().priority(),
+            seq: self.seq,
+            task,
+        };
+        self.entries.push(entry);
+    }
+}
+impl Ord for PriorityEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max-heap: highest priority first, then lowest seq.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+impl PartialOrd for PriorityEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for PriorityEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for PriorityEntry {}
+
+/* Deadline */
+/// Polls the task with the earliest deadline first, as given to
+/// `Handle::spawn_with_deadline`. Tasks without one come after all those
+/// with one, in FIFO order, as do tasks with equal deadlines.
+#[derive(Default)]
+pub struct DeadlineScheduler {
+    heap: Mutex<DeadlineHeap>,
+}
+#[derive(Default)]
+struct DeadlineHeap {
+    entries: BinaryHeap<DeadlineEntry>,
+    /// Bumped on every push, so that older entries win ties.
+    seq: u64,
+}
+struct DeadlineEntry {
+    deadline: Option<Instant>,
+    seq: u64,
+    task: Arc<Task>,
+}
+impl DeadlineScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl Scheduler for DeadlineScheduler {
+    fn schedule(&self, task: Arc<Task>) {
+        lock(&self.heap).push(task);
+    }
+    fn schedule_all(&self, tasks: Vec<Arc<Task>>) {
+        let mut heap = lock(&self.heap);
+        for task in tasks {
+            heap.push(task);
+        }
+    }
+    fn next(&self) -> Option<Arc<Task>> {
+        let entry = lock(&self.heap).entries.pop()?;
+        Some(entry.task)
+    }
+    fn len(&self) -> usize {
+        lock(&self.heap).entries.len()
+    }
+}
+impl DeadlineHeap {
+    fn push(&mut self, task: Arc<Task>) {
+        self.seq += 1;
+        let entry = DeadlineEntry {
+            deadline: task.meta().deadline(),
+            seq: self.seq,
+            task,
+        };
+        self.entries.push(entry);
+    }
+}
+impl Ord for DeadlineEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max-heap: earliest deadline first, any deadline
+        // before none, then lowest seq.
+        let deadline = match (self.deadline, other.deadline) {
+            (Some(ours), Some(theirs)) => theirs.cmp(&ours),
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (None, None) => Ordering::Equal,
+        };
+        deadline.then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+impl PartialOrd for DeadlineEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for DeadlineEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for DeadlineEntry {}
+
+/* Seeded */
+/// Polls a ready task picked by a seeded RNG.
+///
+/// Given the same seed and the same sequence of spawns and wakes, tasks are
+/// polled in the same order every run, so an interleaving found by one seed
+/// can be replayed. Timer threads still wake tasks on wall-clock time, so
+/// workloads depending on timer races are only as reproducible as those are.
+pub struct SeededScheduler {
+    state: Mutex<SeededState>,
+}
+struct SeededState {
+    ready: Vec<Arc<Task>>,
+    rng: StdRng,
+}
+impl SeededScheduler {
+    pub fn new(seed: u64) -> Self {
+        SeededScheduler {
+            state: Mutex::new(SeededState {
+                ready: Vec::new(),
+                rng: StdRng::seed_from_u64(seed),
+            }),
+        }
+    }
+}
+impl Scheduler for SeededScheduler {
+    fn schedule(&self, task: Arc<Task>) {
+        lock(&self.state).ready.push(task);
+    }
+    fn schedule_all(&self, tasks: Vec<Arc<Task>>) {
+        lock(&self.state).ready.extend(tasks);
+    }
+    fn next(&self) -> Option<Arc<Task>> {
+        let state = &mut *lock(&self.state);
+        if state.ready.is_empty() {
+            return None;
+        }
+        let index = state.rng.gen_range(0..state.ready.len());
+        Some(state.ready.swap_remove(index))
+    }
+    fn len(&self) -> usize {
+        lock(&self.state).ready.len()
+    }
+}
+
+/* Aging */
+/// Enforces `Builder::max_schedule_age` on top of whichever scheduler runs:
+/// a task left runnable for that many scheduling decisions is polled next,
+/// whatever the scheduler would pick.
+///
+/// The task stays in the scheduler too, so it is polled for nothing once the
+/// scheduler gets to it, as for a duplicated wake.
+pub(crate) struct Aging {
+    max_age: u64,
+    state: Mutex<AgingState>,
+}
+
+struct AgingState {
+    /// Scheduling decisions made so far, from 1 on.
+    tick: u64,
+    /// Tasks in the order they became runnable, along with the tick when
+    /// that was. Entries of tasks polled since are dropped on the way.
+    runnable: VecDeque<(u64, Arc<Task>)>,
+}
+
+impl Aging {
+    pub(crate) fn new(max_age: u64) -> Self {
+        Aging {
+            max_age,
+            state: Mutex::new(AgingState {
+                tick: 1,
+                runnable: VecDeque::new(),
+            }),
+        }
+    }
+    /// Start counting a task's age, unless it was runnable already.
+    pub(crate) fn runnable(&self, task: &Arc<Task>) {
+        let mut state = lock(&self.state);
+        let tick = state.tick;
+        if task
+            .runnable_since
+            .compare_exchange(0, tick, AtomicOrdering::AcqRel, AtomicOrdering::Acquire)
+            .is_ok()
+        {
+            state.runnable.push_back((tick, task.clone()));
+        }
+    }
+    /// Make a scheduling decision: the oldest runnable task if it is
+    /// overdue, along with true, or else whatever `pick` returns, from the
+    /// LIFO slot or the scheduler.
+    pub(crate) fn next(
+        &self,
+        pick: impl FnOnce() -> Option<Arc<Task>>,
+    ) -> Option<(Arc<Task>, bool)> {
+        let mut state = lock(&self.state);
+        state.tick += 1;
+        while let Some((since, task)) = state.runnable.front() {
+            if task.runnable_since.load(AtomicOrdering::Acquire) == *since {
+                break;
+            }
+            state.runnable.pop_front();
+        }
+        if let Some((since, _)) = state.runnable.front() {
+            if state.tick - since >= self.max_age {
+                let (_, task) = state.runnable.pop_front().unwrap();
+                task.runnable_since.store(0, AtomicOrdering::Release);
+                return Some((task, true));
+            }
+        }
+        drop(state);
+        let task = pick()?;
+        task.runnable_since.store(0, AtomicOrdering::Release);
+        Some((task, false))
+    }
+    /// Drop every runnable task's entry, at shutdown.
+    pub(crate) fn clear(&self) {
+        let runnable = mem::take(&mut lock(&self.state).runnable);
+        // Dropped out of the lock, as they may be the last references.
+        drop(runnable);
+    }
+}
+
+/* WakePlacement */
+/// Where a woken task waits to be polled, see `Builder::wake_placement`.
+///
+/// A wake is same-thread when a task of the executor makes it while being
+/// polled, e.g. by completing what another task waits for; any other wake,
+/// such as from a timer, a blocking closure or another thread, is external.
+/// Spawns, and tasks waking themselves to yield, always go to the scheduler.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WakePlacement {
+    /// Every woken task goes to the scheduler right away.
+    #[default]
+    Fifo,
+    /// A task woken same-thread is polled next, ahead of the scheduler's
+    /// pick, while what it is woken for is likely still in cache. Only the
+    /// latest such task skips ahead, pushing an earlier one to the
+    /// scheduler, and at most three times in a row, so that tasks waking
+    /// each other back and forth cannot starve the rest.
+    LifoSameThread,
+    /// Tasks woken externally are held back, and handed to the scheduler
+    /// together once it ran out of tasks, or 61 polls after the last batch,
+    /// rather than one by one among the same-thread wakes.
+    BatchExternal,
+}
+
+/// Times in a row a task of the LIFO slot may skip the scheduler.
+const MAX_LIFO_STREAK: u32 = 3;
+/// Polls after which externally woken tasks are handed to the scheduler,
+/// even if it still has tasks of its own.
+const EXTERNAL_BATCH_INTERVAL: u32 = 61;
+
+/// Holds woken tasks back from the scheduler, or lets them skip it, as
+/// `WakePlacement`s other than `Fifo` have it.
+pub(crate) struct WakeRouter {
+    placement: WakePlacement,
+    state: Mutex<RouterState>,
+}
+
+#[derive(Default)]
+struct RouterState {
+    /// With `LifoSameThread`: the task to poll next.
+    lifo: Option<Arc<Task>>,
+    /// Times in a row a task was taken from `lifo`.
+    lifo_streak: u32,
+    /// With `BatchExternal`: tasks woken externally since the last batch.
+    staged: Vec<Arc<Task>>,
+    /// Tasks taken since the last batch.
+    since_batch: u32,
+}
+
+impl WakeRouter {
+    /// `None` for `Fifo`, which needs no routing.
+    pub(crate) fn new(placement: WakePlacement) -> Option<Self> {
+        (placement != WakePlacement::Fifo).then(|| WakeRouter {
+            placement,
+            state: Default::default(),
+        })
+    }
+    /// Keep a woken task, returning it back if it is for the scheduler.
+    pub(crate) fn route(&self, task: Arc<Task>, same_thread: bool) -> Option<Arc<Task>> {
+        let mut state = lock(&self.state);
+        match self.placement {
+            WakePlacement::LifoSameThread if same_thread => state.lifo.replace(task),
+            WakePlacement::BatchExternal if !same_thread => {
+                state.staged.push(task);
+                None
+            }
+            _ => Some(task),
+        }
+    }
+    /// Like `route` for many tasks, returning those for the scheduler. The
+    /// LIFO slot only takes single wakes.
+    pub(crate) fn route_all(&self, tasks: Vec<Arc<Task>>, same_thread: bool) -> Vec<Arc<Task>> {
+        if self.placement == WakePlacement::BatchExternal && !same_thread {
+            lock(&self.state).staged.extend(tasks);
+            return Vec::new();
+        }
+        tasks
+    }
+    /// Take the task to poll next if it skips the scheduler, having handed
+    /// any batch due to `scheduler` beforehand.
+    pub(crate) fn next(&self, scheduler: &dyn Scheduler) -> Option<Arc<Task>> {
+        let mut state = lock(&self.state);
+        if let Some(task) = state.lifo.take() {
+            if state.lifo_streak < MAX_LIFO_STREAK {
+                state.lifo_streak += 1;
+                return Some(task);
+            }
+            scheduler.schedule(task);
+        }
+        state.lifo_streak = 0;
+        state.since_batch += 1;
+        if !state.staged.is_empty()
+            && (state.since_batch >= EXTERNAL_BATCH_INTERVAL || scheduler.is_empty())
+        {
+            state.since_batch = 0;
+            scheduler.schedule_all(mem::take(&mut state.staged));
+        }
+        None
+    }
+    /// Number of tasks held back.
+    pub(crate) fn len(&self) -> usize {
+        let state = lock(&self.state);
+        usize::from(state.lifo.is_some()) + state.staged.len()
+    }
+    /// Drop every task held back, at shutdown.
+    pub(crate) fn clear(&self) {
+        let mut state = lock(&self.state);
+        state.lifo = None;
+        state.staged.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::poll_fn,
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        task::Poll,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{sync::Event, Builder, Executor, Handle};
+
+    /// Let the other ready tasks run once.
+    async fn yield_now() {
+        let mut yielded = false;
+        poll_fn(|cx| {
+            if yielded {
+                return Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Polls the latest ready task first.
+    #[derive(Default)]
+    struct ReverseScheduler {
+        stack: Mutex<Vec<Arc<Task>>>,
+    }
+    impl Scheduler for ReverseScheduler {
+        fn schedule(&self, task: Arc<Task>) {
+            self.stack.lock().unwrap().push(task);
+        }
+        fn next(&self) -> Option<Arc<Task>> {
+            self.stack.lock().unwrap().pop()
+        }
+        fn len(&self) -> usize {
+            self.stack.lock().unwrap().len()
+        }
+    }
+
+    /// Spawn one task per label, logging its label when first polled, and
+    /// return the log once they all ran.
+    fn run_order(executor: &Executor, labels: &[(&'static str, Priority)]) -> Vec<&'static str> {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handle = executor.handle();
+        for &(label, priority) in labels {
+            let log = log.clone();
+            handle.spawn_with_priority(priority, async move {
+                log.lock().unwrap().push(label);
+            });
+        }
+        executor.run();
+        let log = log.lock().unwrap().clone();
+        log
+    }
+
+    const LABELS: [(&str, Priority); 3] = [
+        ("low", Priority::Low),
+        ("normal", Priority::Normal),
+        ("high", Priority::High),
+    ];
+
+    #[test]
+    fn fifo_polls_in_spawn_order() {
+        let executor = Executor::new();
+        assert_eq!(run_order(&executor, &LABELS), ["low", "normal", "high"]);
+    }
+
+    #[test]
+    fn custom_scheduler_changes_the_order() {
+        let executor = Builder::new()
+            .scheduler(ReverseScheduler::default())
+            .build();
+        assert_eq!(run_order(&executor, &LABELS), ["high", "normal", "low"]);
+    }
+
+    #[test]
+    fn priority_scheduler_polls_urgent_tasks_first() {
+        let executor = Builder::new().scheduler(PriorityScheduler::new()).build();
+        let labels = [
+            ("normal", Priority::Normal),
+            ("low", Priority::Low),
+            ("high", Priority::High),
+            ("normal 2", Priority::Normal),
+        ];
+        assert_eq!(
+            run_order(&executor, &labels),
+            ["high", "normal", "normal 2", "low"]
+        );
+    }
+
+    /// Ten tasks taking turns, logging each step.
+    fn interleaving(seed: u64) -> Vec<(usize, usize)> {
+        let executor = Builder::new().deterministic(seed).build();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for task in 0..10 {
+            let log = log.clone();
+            executor.spawn(async move {
+                for step in 0..5 {
+                    log.lock().unwrap().push((task, step));
+                    yield_now().await;
+                }
+            });
+        }
+        executor.run();
+        let log = log.lock().unwrap().clone();
+        log
+    }
+
+    #[test]
+    fn seeded_scheduler_replays_the_same_interleaving() {
+        let log = interleaving(42);
+        assert_eq!(log.len(), 50);
+        assert_eq!(interleaving(42), log);
+        assert!((0..10).any(|seed| interleaving(seed) != log));
+    }
+
+    /// A `FifoScheduler` counting how many times its queue is pushed to.
+    struct CountingScheduler {
+        fifo: FifoScheduler,
+        pushes: Arc<AtomicUsize>,
+    }
+    impl Scheduler for CountingScheduler {
+        fn schedule(&self, task: Arc<Task>) {
+            self.pushes.fetch_add(1, Relaxed);
+            self.fifo.schedule(task);
+        }
+        fn schedule_all(&self, tasks: Vec<Arc<Task>>) {
+            self.pushes.fetch_add(1, Relaxed);
+            self.fifo.schedule_all(tasks);
+        }
+        fn next(&self) -> Option<Arc<Task>> {
+            self.fifo.next()
+        }
+        fn len(&self) -> usize {
+            self.fifo.len()
+        }
+    }
+
+    /// Queue pushes taken to spawn 50k tasks, one at a time or all at once.
+    fn spawn_pushes(batched: bool) -> usize {
+        const TASKS: usize = 50_000;
+        let pushes = Arc::new(AtomicUsize::new(0));
+        let executor = Builder::new()
+            .scheduler(CountingScheduler {
+                fifo: FifoScheduler::new(),
+                pushes: pushes.clone(),
+            })
+            .build();
+        let handle = executor.handle();
+        let before = pushes.load(Relaxed);
+        if batched {
+            drop(handle.spawn_all((0..TASKS).map(|_| async {})));
+        } else {
+            for _ in 0..TASKS {
+                drop(handle.spawn(async {}));
+            }
+        }
+        let spawned = pushes.load(Relaxed) - before;
+        assert_eq!(executor.metrics().tasks_spawned, TASKS as u64);
+        executor.run();
+        assert_eq!(executor.metrics().tasks_alive, 0);
+        spawned
+    }
+
+    #[test]
+    fn spawn_all_pushes_to_the_queue_once() {
+        assert_eq!(spawn_pushes(false), 50_000);
+        assert_eq!(spawn_pushes(true), 1);
+    }
+
+    #[test]
+    fn spawn_all_named_names_each_task() {
+        let executor = Executor::new();
+        let handles = executor
+            .handle()
+            .spawn_all_named((0..3).map(|i| async move { i * 10 }), |i| {
+                format!("job {i}")
+            });
+        let snapshot = executor.snapshot();
+        let mut names: Vec<_> = snapshot
+            .tasks
+            .iter()
+            .filter_map(|task| task.name.as_deref())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["job 0", "job 1", "job 2"]);
+        executor.run();
+        let outputs: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join_blocking().unwrap())
+            .collect();
+        assert_eq!(outputs, [0, 10, 20]);
+    }
+
+    #[test]
+    fn deadline_scheduler_polls_the_earliest_deadline_first() {
+        let executor = Builder::new().scheduler(DeadlineScheduler::new()).build();
+        let handle = executor.handle();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let now = Instant::now();
+        let tasks = [
+            ("plain a", None),
+            ("late", Some(now + Duration::from_secs(10))),
+            ("plain b", None),
+            ("soon", Some(now + Duration::from_secs(5))),
+            ("soon 2", Some(now + Duration::from_secs(5))),
+        ];
+        for (label, deadline) in tasks {
+            let log = log.clone();
+            let steps = async move {
+                for step in 0..2 {
+                    log.lock().unwrap().push((label, step));
+                    // Back in the queue, behind tasks of the same deadline.
+                    yield_now().await;
+                }
+            };
+            drop(match deadline {
+                Some(deadline) => handle.spawn_with_deadline(deadline, steps),
+                None => handle.spawn(steps),
+            });
+        }
+        executor.run();
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                ("soon", 0),
+                ("soon 2", 0),
+                ("soon", 1),
+                ("soon 2", 1),
+                ("late", 0),
+                ("late", 1),
+                ("plain a", 0),
+                ("plain b", 0),
+                ("plain a", 1),
+                ("plain b", 1),
+            ]
+        );
+        assert_eq!(executor.metrics().deadlines_missed, 0);
+
+        drop(handle.spawn_with_deadline(Instant::now(), async {}));
+        executor.run();
+        assert_eq!(executor.metrics().deadlines_missed, 1);
+    }
+
+    #[test]
+    fn max_schedule_age_keeps_a_low_priority_canary_polled() {
+        const MAX_AGE: u64 = 16;
+        const ROUNDS: usize = 500;
+        let executor = Builder::new()
+            .scheduler(PriorityScheduler::new())
+            .wake_placement(WakePlacement::LifoSameThread)
+            .max_schedule_age(MAX_AGE as usize)
+            .build();
+        let handle = executor.handle();
+        let flooding = Arc::new(AtomicUsize::new(0));
+        let flood = |future: Pin<Box<dyn Future<Output = ()> + Send>>| {
+            flooding.fetch_add(1, Relaxed);
+            let flooding = flooding.clone();
+            handle.spawn_with_priority(Priority::High, async move {
+                future.await;
+                flooding.fetch_sub(1, Relaxed);
+            });
+        };
+        // Tasks waking themselves, always runnable.
+        for _ in 0..6 {
+            flood(Box::pin(async {
+                for _ in 0..ROUNDS {
+                    yield_now().await;
+                }
+            }));
+        }
+        // Tasks waking each other, through the LIFO slot.
+        let (ping, pong) = (Arc::new(Event::new()), Arc::new(Event::new()));
+        let (ping2, pong2) = (ping.clone(), pong.clone());
+        flood(Box::pin(async move {
+            for _ in 0..ROUNDS {
+                ping2.wait().await;
+                ping2.reset();
+                pong2.set();
+            }
+        }));
+        flood(Box::pin(async move {
+            for _ in 0..ROUNDS {
+                ping.set();
+                pong.wait().await;
+                pong.reset();
+            }
+        }));
+        let canary = handle.spawn_with_priority(Priority::Low, async move {
+            let mut polled_at = Vec::new();
+            while flooding.load(Relaxed) > 0 {
+                polled_at.push(Handle::current().metrics().polls_total);
+                yield_now().await;
+            }
+            polled_at
+        });
+        executor.run();
+        let polled_at = canary.join_blocking().unwrap();
+        assert!(polled_at.len() > 10, "{polled_at:?}");
+        let most = polled_at.windows(2).map(|at| at[1] - at[0]).max().unwrap();
+        assert!(most <= MAX_AGE, "{most} polls between two of the canary");
+        assert!(executor.metrics().schedule_age_overrides >= polled_at.len() as u64 / 2);
+    }
+
+    /// The order in which a ping-pong pair and a storm of tasks woken
+    /// externally run under `placement`, as a string of their labels.
+    ///
+    /// The storm is woken by another thread, as a timer's would be, but from
+    /// within a poll which waits for it, so the order is the same every run.
+    fn ping_pong_and_storm(placement: WakePlacement) -> String {
+        let executor = Builder::new().wake_placement(placement).build();
+        let handle = executor.handle();
+        let log = Arc::new(Mutex::new(String::new()));
+        let storm = Arc::new(Event::new());
+        for _ in 0..3 {
+            let (log, storm) = (log.clone(), storm.clone());
+            handle.spawn(async move {
+                storm.wait().await;
+                log.lock().unwrap().push('s');
+            });
+        }
+        let (ping, pong) = (Arc::new(Event::new()), Arc::new(Event::new()));
+        let (ping2, pong2, log2) = (ping.clone(), pong.clone(), log.clone());
+        handle.spawn(async move {
+            for round in 0..4 {
+                log2.lock().unwrap().push('a');
+                if round == 0 {
+                    std::thread::scope(|scope| {
+                        scope.spawn(|| storm.set());
+                    });
+                }
+                ping2.set();
+                pong2.wait().await;
+                pong2.reset();
+            }
+        });
+        let log2 = log.clone();
+        handle.spawn(async move {
+            for _ in 0..4 {
+                ping.wait().await;
+                ping.reset();
+                log2.lock().unwrap().push('b');
+                pong.set();
+            }
+        });
+        executor.run();
+        let metrics = executor.metrics();
+        assert_eq!(metrics.wakes_external, 3);
+        assert_eq!(metrics.wakes_same_thread, 7);
+        let log = log.lock().unwrap().clone();
+        log
+    }
+
+    #[test]
+    fn wake_placements_order_same_thread_and_external_wakes_apart() {
+        // The storm in turn with the pair.
+        // The storm queued behind the first of the pair, among its turns.
+        assert_eq!(ping_pong_and_storm(WakePlacement::Fifo), "absssababab");
+        // The pair skipping the queue three times in a row, then behind it.
+        assert_eq!(
+            ping_pong_and_storm(WakePlacement::LifoSameThread),
+            "ababasssbab"
+        );
+        // The storm held back until the pair is done.
+        assert_eq!(
+            ping_pong_and_storm(WakePlacement::BatchExternal),
+            "ababababsss"
+        );
+    }
+
+    #[test]
+    fn dropping_an_aging_executor_with_queued_tasks_frees_it() {
+        let executor = Builder::new()
+            .max_schedule_age(4)
+            .on_leaked_tasks(|_| {})
+            .build();
+        for _ in 0..1000 {
+            drop(executor.spawn(async {}));
+        }
+        let inner = Arc::downgrade(&executor.inner);
+        drop(executor);
+        // The tasks queued for aging referred to it.
+        assert!(inner.upgrade().is_none());
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    fmt::{self, Debug},
+    future::Future,
+    io::{self, PipeWriter, Read, Write},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use crate::{counters::clone_waker, lock, task::tag_wakes};
+
+/// Wakes tasks on behalf of a signal handler.
+///
+/// Waking a task allocates and takes locks, which a signal handler must not
+/// do. `notify` does neither: it only writes a byte to a pipe, from which a
+/// thread of the notifier's own turns it into real wakes. Install your own
+/// handler calling `notify` on a notifier created beforehand, e.g. kept in a
+/// `static OnceLock`, and await `notified` in a task.
+///
+/// Notifications coalesce: any number of them before the next `notified`
+/// completes wakes it once. Dropping the notifier stops its thread.
+pub struct SignalNotifier {
+    writer: PipeWriter,
+    shared: Arc<NotifyShared>,
+}
+
+struct NotifyShared {
+    /// Set by `notify` while a byte is in the pipe, so the pipe never fills
+    /// up and the write never blocks.
+    signalled: AtomicBool,
+    state: Mutex<NotifyState>,
+}
+
+struct NotifyState {
+    /// Set once notified, until a `Notified` takes it.
+    pending: bool,
+    waker: Option<Waker>,
+}
+
+impl SignalNotifier {
+    pub fn new() -> io::Result<Self> {
+        let (mut reader, writer) = io::pipe()?;
+        let shared = Arc::new(NotifyShared {
+            signalled: AtomicBool::new(false),
+            state: Mutex::new(NotifyState {
+                pending: false,
+                waker: None,
+            }),
+        });
+        let thread_shared = shared.clone();
+        thread::Builder::new()
+            .name("signal-notifier".to_string())
+            .spawn(move || {
+                let mut byte = [0];
+                loop {
+                    match reader.read(&mut byte) {
+                        // The notifier was dropped.
+                        Ok(0) => break,
+                        Ok(_) => {}
+                        Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(_) => break,
+                    }
+                    // Cleared before waking, so a notification arriving
+                    // meanwhile writes again rather than being lost.
+                    thread_shared.signalled.store(false, Ordering::SeqCst);
+                    let waker = {
+                        let mut state = lock(&thread_shared.state);
+                        state.pending = true;
+                        state.waker.take()
+                    };
+                    if let Some(waker) = waker {
+                        tag_wakes("SignalNotifier", || waker.wake());
+                    }
+                }
+            })?;
+        Ok(SignalNotifier { writer, shared })
+    }
+    /// Wake whoever awaits `notified`. Async-signal-safe: no allocation, no
+    /// lock, at most one `write` on the pipe.
+    pub fn notify(&self) {
+        if self.shared.signalled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // Nothing to do about a failure from within a signal handler.
+        let _ = (&self.writer).write(&[1]);
+    }
+    /// Wait for a notification, or take the one that came since the last
+    /// `notified` completed.
+    ///
+    /// Cancel safe: a notification is only taken once this completes.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notifier: self,
+            waker: None,
+        }
+    }
+}
+impl Debug for SignalNotifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignalNotifier")
+            .field("pending", &lock(&self.shared.state).pending)
+            .finish()
+    }
+}
+
+/// Future returned by `SignalNotifier::notified`.
+pub struct Notified<'a> {
+    notifier: &'a SignalNotifier,
+    /// The waker this future left with the notifier, taken back if dropped
+    /// first.
+    waker: Option<Waker>,
+}
+impl Future for Notified<'_> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let mut state = lock(&this.notifier.shared.state);
+        if state.pending {
+            state.pending = false;
+            this.waker = None;
+            return Poll::Ready(());
+        }
+        state.waker = Some(clone_waker(cx.waker()));
+        this.waker = Some(clone_waker(cx.waker()));
+        Poll::Pending
+    }
+}
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(waker) = self.waker.take() else {
+            return;
+        };
+        let mut state = lock(&self.notifier.shared.state);
+        if state.waker.as_ref().is_some_and(|w| w.will_wake(&waker)) {
+            state.waker = None;
+        }
+    }
+}
+impl Debug for Notified<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notified").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::pin, sync::OnceLock, time::Duration};
+
+    use super::*;
+    use crate::{
+        assert_pending, assert_ready, coop::maybe_yield, test_util::allocations_during,
+        test_util::MockTask, Executor,
+    };
+
+    #[test]
+    fn notifications_coalesce_and_do_not_allocate() {
+        let notifier = SignalNotifier::new().unwrap();
+        let mut task = MockTask::new();
+        let mut notified = pin!(notifier.notified());
+        assert_pending!(task.poll(notified.as_mut()));
+        let ((), allocations) = allocations_during(|| {
+            for _ in 0..10 {
+                notifier.notify();
+            }
+        });
+        assert_eq!(allocations, 0);
+        while !task.is_woken() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_ready!(task.poll(notified.as_mut()));
+        // All ten were taken by that one.
+        thread::sleep(Duration::from_millis(20));
+        assert_pending!(task.poll(pin!(notifier.notified())));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn a_real_signal_handler_wakes_a_task() {
+        const SIGUSR1: i32 = 10;
+        extern "C" {
+            fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+            fn raise(signum: i32) -> i32;
+        }
+        static NOTIFIER: OnceLock<SignalNotifier> = OnceLock::new();
+        /// Only an atomic load and `notify`: no allocation, no lock.
+        extern "C" fn on_sigusr1(_: i32) {
+            if let Some(notifier) = NOTIFIER.get() {
+                notifier.notify();
+            }
+        }
+        NOTIFIER.get_or_init(|| SignalNotifier::new().unwrap());
+        // SAFETY: the handler is async-signal-safe, see above.
+        unsafe { signal(SIGUSR1, on_sigusr1) };
+
+        let executor = Executor::new();
+        let woken = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let woken = woken.clone();
+            executor.spawn(async move {
+                NOTIFIER.get().unwrap().notified().await;
+                woken.store(true, Ordering::SeqCst);
+            })
+        };
+        // Keeps the executor busy until then.
+        let busy = executor.spawn(async move {
+            while !woken.load(Ordering::SeqCst) {
+                maybe_yield().await;
+            }
+        });
+        let raiser = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(10));
+            // SAFETY: plain libc call, the handler is installed.
+            unsafe { raise(SIGUSR1) };
+        });
+        executor.run();
+        raiser.join().unwrap();
+        waiter.join_blocking().unwrap();
+        busy.join_blocking().unwrap();
+    }
+}
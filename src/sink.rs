@@ -0,0 +1,392 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    marker::PhantomData,
+    ops::DerefMut,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::stream::Stream;
+
+/// Asynchronous destination of values: the counterpart of `Stream`.
+///
+/// Sending is split in steps so a sink can push back: wait for
+/// `poll_ready`, hand over one item with `start_send`, and `poll_flush`
+/// for buffered items to actually reach their destination.
+pub trait Sink<Item> {
+    type Error;
+
+    /// Wait until the sink can take one more item.
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>;
+
+    /// Hand an item over; only allowed right after `poll_ready` returned
+    /// `Ready(Ok(()))`.
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error>;
+
+    /// Wait until every item handed over so far has been delivered.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>;
+
+    /// Flush, then close the sink; nothing may be sent after this.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>;
+}
+impl<Item, S: Sink<Item> + Unpin + ?Sized> Sink<Item> for &mut S {
+    type Error = S::Error;
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut **self).poll_ready(cx)
+    }
+    fn start_send(mut self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        Pin::new(&mut **self).start_send(item)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut **self).poll_close(cx)
+    }
+}
+impl<Item, S: Sink<Item> + Unpin + ?Sized> Sink<Item> for Box<S> {
+    type Error = S::Error;
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut **self).poll_ready(cx)
+    }
+    fn start_send(mut self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        Pin::new(&mut **self).start_send(item)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut **self).poll_close(cx)
+    }
+}
+impl<Item, P> Sink<Item> for Pin<P>
+where
+    P: DerefMut<Target: Sink<Item>>,
+{
+    type Error = <P::Target as Sink<Item>>::Error;
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.as_deref_mut().poll_ready(cx)
+    }
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        self.as_deref_mut().start_send(item)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.as_deref_mut().poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.as_deref_mut().poll_close(cx)
+    }
+}
+
+/// Collects every item sent, never pushing back.
+impl<T> Sink<T> for Vec<T> {
+    type Error = Infallible;
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        // SAFETY: a `Vec` never pins its elements, whatever `T` is.
+        unsafe { self.get_unchecked_mut() }.push(item);
+        Ok(())
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Combinators for every `Sink`.
+pub trait SinkExt<Item>: Sink<Item> {
+    /// Send one item and flush it.
+    ///
+    /// Not cancel safe: dropped before the sink was ready, the item is lost;
+    /// dropped afterwards, it was handed over but maybe not flushed.
+    fn send(&mut self, item: Item) -> SendItem<'_, Self, Item>
+    where
+        Self: Unpin,
+    {
+        SendItem {
+            sink: self,
+            item: Some(item),
+        }
+    }
+
+    /// Send every item of `stream`, then flush. Stops at the first error,
+    /// from the stream or from the sink.
+    fn send_all<'a, St>(&'a mut self, stream: &'a mut St) -> SendAll<'a, Self, St, Item>
+    where
+        Self: Unpin,
+        St: Stream<Item = Result<Item, Self::Error>> + Unpin + ?Sized,
+    {
+        SendAll {
+            sink: self,
+            stream,
+            buffered: None,
+        }
+    }
+
+    /// Flush and close the sink.
+    fn close(&mut self) -> Close<'_, Self, Item>
+    where
+        Self: Unpin,
+    {
+        Close {
+            sink: self,
+            _item: PhantomData,
+        }
+    }
+}
+impl<Item, S: Sink<Item> + ?Sized> SinkExt<Item> for S {}
+
+/* SendItem */
+/// Future for [`SinkExt::send`].
+#[derive(Debug)]
+pub struct SendItem<'a, S: ?Sized, Item> {
+    sink: &'a mut S,
+    /// Taken once handed to the sink.
+    item: Option<Item>,
+}
+// The item is never pinned.
+impl<S: ?Sized, Item> Unpin for SendItem<'_, S, Item> {}
+impl<Item, S: Sink<Item> + Unpin + ?Sized> Future for SendItem<'_, S, Item> {
+    type Output = Result<(), S::Error>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut sink = Pin::new(&mut *this.sink);
+        if let Some(item) = this.item.take() {
+            match sink.as_mut().poll_ready(cx) {
+                Poll::Ready(Ok(())) => sink.as_mut().start_send(item)?,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => {
+                    this.item = Some(item);
+                    return Poll::Pending;
+                }
+            }
+        }
+        sink.poll_flush(cx)
+    }
+}
+
+/* SendAll */
+/// Future for [`SinkExt::send_all`].
+#[derive(Debug)]
+pub struct SendAll<'a, S: ?Sized, St: ?Sized, Item> {
+    sink: &'a mut S,
+    stream: &'a mut St,
+    /// Item taken out of the stream while the sink was not ready for it.
+    buffered: Option<Item>,
+}
+// The item is never pinned.
+impl<S: ?Sized, St: ?Sized, Item> Unpin for SendAll<'_, S, St, Item> {}
+impl<Item, S, St> Future for SendAll<'_, S, St, Item>
+where
+    S: Sink<Item> + Unpin + ?Sized,
+    St: Stream<Item = Result<Item, S::Error>> + Unpin + ?Sized,
+{
+    type Output = Result<(), S::Error>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut sink = Pin::new(&mut *this.sink);
+        loop {
+            if let Some(item) = this.buffered.take() {
+                match sink.as_mut().poll_ready(cx) {
+                    Poll::Ready(Ok(())) => sink.as_mut().start_send(item)?,
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => {
+                        this.buffered = Some(item);
+                        return Poll::Pending;
+                    }
+                }
+            }
+            match Pin::new(&mut *this.stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) => this.buffered = Some(item),
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Err(error)),
+                Poll::Ready(None) => return sink.poll_flush(cx),
+                Poll::Pending => {
+                    // Deliver what was sent so far while the stream is idle.
+                    if let Poll::Ready(Err(error)) = sink.poll_flush(cx) {
+                        return Poll::Ready(Err(error));
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+/* Close */
+/// Future for [`SinkExt::close`].
+#[derive(Debug)]
+pub struct Close<'a, S: ?Sized, Item> {
+    sink: &'a mut S,
+    _item: PhantomData<fn(Item)>,
+}
+impl<Item, S: Sink<Item> + Unpin + ?Sized> Future for Close<'_, S, Item> {
+    type Output = Result<(), S::Error>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.sink).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        task::Waker,
+    };
+
+    use super::*;
+    use crate::{stream::StreamExt, Executor};
+
+    /// Items of an iterator, ready at once.
+    struct Iter<I>(I);
+    impl<I: Iterator + Unpin> Stream for Iter<I> {
+        type Item = I::Item;
+        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            Poll::Ready(self.0.next())
+        }
+    }
+
+    /// Bounded queue between a `Sender` and a `Receiver`.
+    #[derive(Default)]
+    struct Channel {
+        items: VecDeque<u32>,
+        closed: bool,
+        /// Most items queued at once.
+        high_water: usize,
+        sender: Option<Waker>,
+        receiver: Option<Waker>,
+    }
+    const CAPACITY: usize = 8;
+
+    fn channel() -> (Sender, Receiver) {
+        let channel = Arc::new(Mutex::new(Channel::default()));
+        (Sender(channel.clone()), Receiver(channel))
+    }
+
+    struct Sender(Arc<Mutex<Channel>>);
+    impl Sink<u32> for Sender {
+        type Error = &'static str;
+        fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            let mut channel = self.0.lock().unwrap();
+            if channel.closed {
+                return Poll::Ready(Err("closed"));
+            }
+            if channel.items.len() >= CAPACITY {
+                channel.sender = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            Poll::Ready(Ok(()))
+        }
+        fn start_send(self: Pin<&mut Self>, item: u32) -> Result<(), Self::Error> {
+            let mut channel = self.0.lock().unwrap();
+            channel.items.push_back(item);
+            channel.high_water = channel.high_water.max(channel.items.len());
+            if let Some(waker) = channel.receiver.take() {
+                waker.wake();
+            }
+            Ok(())
+        }
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_close(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            let mut channel = self.0.lock().unwrap();
+            channel.closed = true;
+            if let Some(waker) = channel.receiver.take() {
+                waker.wake();
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    struct Receiver(Arc<Mutex<Channel>>);
+    impl Stream for Receiver {
+        type Item = u32;
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<u32>> {
+            let mut channel = self.0.lock().unwrap();
+            if let Some(item) = channel.items.pop_front() {
+                if let Some(waker) = channel.sender.take() {
+                    waker.wake();
+                }
+                return Poll::Ready(Some(item));
+            }
+            if channel.closed {
+                return Poll::Ready(None);
+            }
+            channel.receiver = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn send_all_forwards_through_a_bounded_channel() {
+        let executor = Executor::new();
+        let (mut sender, receiver) = channel();
+        let channel = sender.0.clone();
+        let received = executor.spawn(receiver.collect::<Vec<_>>());
+        let sent = executor.spawn(async move {
+            let mut items = Iter((0..1000).map(Ok));
+            sender.send_all(&mut items).await?;
+            sender.close().await
+        });
+        executor.run();
+        assert_eq!(sent.join_blocking().unwrap(), Ok(()));
+        assert_eq!(
+            received.join_blocking().unwrap(),
+            (0..1000).collect::<Vec<_>>()
+        );
+        assert_eq!(channel.lock().unwrap().high_water, CAPACITY);
+    }
+
+    #[test]
+    fn send_all_stops_at_a_stream_error() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let (mut sender, _receiver) = channel();
+            let mut items = Iter([Ok(1), Ok(2), Err("bad item"), Ok(3)].into_iter());
+            let sent = sender.send_all(&mut items).await;
+            let rest = items.next().await;
+            let delivered: Vec<_> = sender.0.lock().unwrap().items.drain(..).collect();
+            (sent, delivered, rest)
+        }]);
+        assert_eq!(outputs, [(Err("bad item"), vec![1, 2], Some(Ok(3)))]);
+    }
+
+    #[test]
+    fn sending_after_close_fails() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let (mut sender, _receiver) = channel();
+            sender.send(1).await.unwrap();
+            sender.close().await.unwrap();
+            sender.send(2).await
+        }]);
+        assert_eq!(outputs, [Err("closed")]);
+    }
+
+    #[test]
+    fn vec_collects_what_is_sent() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let mut sink = Vec::new();
+            sink.send(1).await.unwrap();
+            sink.send_all(&mut Iter([Ok(2), Ok(3)].into_iter()))
+                .await
+                .unwrap();
+            sink.close().await.unwrap();
+            sink
+        }]);
+        assert_eq!(outputs, [vec![1, 2, 3]]);
+    }
+}
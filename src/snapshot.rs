@@ -0,0 +1,674 @@
+use std::{
+    backtrace::Backtrace,
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    fs, io,
+    panic::Location,
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    metrics::{MetricsSnapshot, POLL_DURATION_BOUNDS},
+    task::{Priority, Task, TaskId, TaskStats, WakeEvent},
+    time::TimerStats,
+};
+
+/// Tasks a snapshot describes at most, so taking one stays cheap however
+/// many are alive; the rest are only counted.
+const MAX_TASKS: usize = 10_000;
+
+/// Everything an executor can tell about itself at once, for post-mortem
+/// debugging, from `Executor::snapshot`: e.g. written out with `write_json`
+/// by a process about to be killed.
+///
+/// Advisory only: the parts are read one after the other while the executor
+/// keeps going, so they may disagree a little, e.g. on the number of tasks.
+#[derive(Clone, Debug)]
+pub struct RuntimeSnapshot {
+    /// Wall-clock time the snapshot was taken at.
+    pub taken_at: SystemTime,
+    pub metrics: MetricsSnapshot,
+    /// The executor's timers, those first polled while it ran.
+    pub timers: TimerStats,
+    /// Unfinished tasks, oldest first, the executor's own background tasks
+    /// included.
+    pub tasks: Vec<TaskSnapshot>,
+    /// Tasks left out of `tasks` past the first ten thousand.
+    pub tasks_omitted: usize,
+}
+
+/// A task which had not finished when its executor's snapshot was taken.
+#[derive(Clone, Debug)]
+pub struct TaskSnapshot {
+    pub id: TaskId,
+    pub name: Option<String>,
+    /// Name of the `ChildRuntime` it belongs to, if any.
+    pub runtime: Option<String>,
+    pub priority: Priority,
+    /// Where the task was spawned from.
+    pub location: &'static Location<'static>,
+    /// Set if built with `Builder::capture_spawn_backtraces`.
+    pub spawn_backtrace: Option<Arc<Backtrace>>,
+    pub state: TaskState,
+    /// Set for the executor's own housekeeping tasks.
+    pub background: bool,
+    /// How long ago the task was spawned.
+    pub age: Duration,
+    /// How long ago its latest poll began, if it was ever polled.
+    pub last_polled: Option<Duration>,
+    /// Set if built with `Builder::task_stats`.
+    pub stats: Option<TaskStats>,
+    /// Its latest wakes, oldest first, if built with `Builder::record_wakes`.
+    pub recent_wakes: Vec<WakeEvent>,
+}
+
+/// What a task was up to when the snapshot was taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskState {
+    /// Being polled, possibly nested in another task's `block_in_task`.
+    Polling,
+    /// Waiting to be woken, or in the queue to be polled.
+    Idle,
+    /// Aborted, and waiting to be dropped.
+    Aborted,
+}
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TaskState::Polling => "polling",
+            TaskState::Idle => "idle",
+            TaskState::Aborted => "aborted",
+        })
+    }
+}
+
+impl RuntimeSnapshot {
+    pub(crate) fn new(
+        mut tasks: Vec<Arc<Task>>,
+        polling: &[TaskId],
+        metrics: MetricsSnapshot,
+        timers: TimerStats,
+    ) -> Self {
+        let now = Instant::now();
+        tasks.sort_by_key(|task| task.meta.id());
+        let described: Vec<_> = tasks
+            .iter()
+            .take(MAX_TASKS)
+            .map(|task| {
+                let meta = &task.meta;
+                let state = if task.aborted.load(Ordering::Acquire) {
+                    TaskState::Aborted
+                } else if polling.contains(&meta.id()) {
+                    TaskState::Polling
+                } else {
+                    TaskState::Idle
+                };
+                TaskSnapshot {
+                    id: meta.id(),
+                    name: meta.name().map(str::to_string),
+                    runtime: meta.runtime().map(str::to_string),
+                    priority: meta.priority(),
+                    location: meta.location(),
+                    spawn_backtrace: meta.spawn_backtrace.clone(),
+                    state,
+                    background: meta.background,
+                    age: now.saturating_duration_since(meta.spawned_at()),
+                    last_polled: meta
+                        .last_polled_at()
+                        .map(|at| now.saturating_duration_since(at)),
+                    stats: meta.stats(),
+                    recent_wakes: meta.recent_wakes(),
+                }
+            })
+            .collect();
+        RuntimeSnapshot {
+            taken_at: SystemTime::now(),
+            metrics,
+            timers,
+            tasks_omitted: tasks.len() - described.len(),
+            tasks: described,
+        }
+    }
+    /// The tasks with stats, busiest first, to find out which ones take up
+    /// the executor. Empty unless built with `Builder::task_stats`.
+    pub fn busiest_tasks(&self) -> Vec<&TaskSnapshot> {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .iter()
+            .filter(|task| task.stats.is_some())
+            .collect();
+        tasks.sort_by_key(|task| Reverse(task.stats.map(|stats| stats.busy)));
+        tasks
+    }
+    /// The tasks by the name of the `ChildRuntime` they belong to, those of
+    /// none under `None`.
+    pub fn tasks_by_runtime(&self) -> BTreeMap<Option<&str>, Vec<&TaskSnapshot>> {
+        let mut grouped: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for task in &self.tasks {
+            grouped
+                .entry(task.runtime.as_deref())
+                .or_default()
+                .push(task);
+        }
+        grouped
+    }
+    /// Render the snapshot as a JSON object. Durations are in seconds, and
+    /// `taken_at` and the times of wakes in seconds since the Unix epoch;
+    /// timer deadlines, which only mean something within the process, are
+    /// left out.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let taken_at = self.taken_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let _ = write!(json, "{{\"taken_at\":{}", taken_at.as_secs_f64());
+        // For wakes, recorded with the monotonic clock only.
+        let now = Instant::now();
+        let wall_now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let metrics = &self.metrics;
+        let _ = write!(
+            json,
+            ",\"metrics\":{{\"tasks_alive\":{},\"tasks_spawned\":{},\"queue_depth\":{},\
+             \"admission_queue_depth\":{},\"spawn_permits_reserved\":{},\
+             \"blocking_threads\":{},\"blocking_queue_depth\":{},\"polls_total\":{},\
+             \"deadlines_missed\":{},\"tasks_panicked\":{},\"schedule_age_overrides\":{},\
+             \"wakes_same_thread\":{},\"wakes_external\":{},\
+             \"poll_duration_total\":{},\"poll_duration_buckets\":[",
+            metrics.tasks_alive,
+            metrics.tasks_spawned,
+            metrics.queue_depth,
+            metrics.admission_queue_depth,
+            metrics.spawn_permits_reserved,
+            metrics.blocking_threads,
+            metrics.blocking_queue_depth,
+            metrics.polls_total,
+            metrics.deadlines_missed,
+            metrics.tasks_panicked,
+            metrics.schedule_age_overrides,
+            metrics.wakes_same_thread,
+            metrics.wakes_external,
+            metrics.poll_duration_total.as_secs_f64(),
+        );
+        for (index, count) in metrics.poll_duration_buckets.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            let le = match POLL_DURATION_BOUNDS.get(index) {
+                Some(bound) => bound.as_secs_f64().to_string(),
+                None => "null".to_string(),
+            };
+            let _ = write!(json, "{{\"le\":{le},\"count\":{count}}}");
+        }
+        json.push_str("],\"runtimes\":[");
+        for (index, runtime) in metrics.runtimes.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            json.push_str("{\"name\":");
+            push_string(&mut json, &runtime.name);
+            let _ = write!(
+                json,
+                ",\"tasks_alive\":{},\"tasks_spawned\":{},\"polls_total\":{},\
+                 \"tasks_panicked\":{}}}",
+                runtime.tasks_alive,
+                runtime.tasks_spawned,
+                runtime.polls_total,
+                runtime.tasks_panicked,
+            );
+        }
+
+        let timers = &self.timers;
+        let _ = write!(
+            json,
+            "]}},\"timers\":{{\"pending\":{},\"fired_total\":{},\"oversleep_total\":{},\
+             \"oversleep_max\":{},\"wakeups\":{}}}",
+            timers.pending,
+            timers.fired_total,
+            timers.oversleep_total.as_secs_f64(),
+            timers.oversleep_max.as_secs_f64(),
+            timers.wakeups,
+        );
+
+        json.push_str(",\"tasks\":[");
+        for (index, task) in self.tasks.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"id\":{},\"name\":", task.id);
+            push_optional_string(&mut json, task.name.as_deref());
+            json.push_str(",\"runtime\":");
+            push_optional_string(&mut json, task.runtime.as_deref());
+            let _ = write!(json, ",\"priority\":\"{:?}\",\"location\":", task.priority);
+            push_string(&mut json, &task.location.to_string());
+            json.push_str(",\"spawn_backtrace\":");
+            let backtrace = task.spawn_backtrace.as_ref().map(ToString::to_string);
+            push_optional_string(&mut json, backtrace.as_deref());
+            let _ = write!(
+                json,
+                ",\"state\":\"{}\",\"background\":{},\"age\":{},\"last_polled\":",
+                task.state,
+                task.background,
+                task.age.as_secs_f64(),
+            );
+            match task.last_polled {
+                Some(ago) => {
+                    let _ = write!(json, "{}", ago.as_secs_f64());
+                }
+                None => json.push_str("null"),
+            }
+            json.push_str(",\"stats\":");
+            match &task.stats {
+                Some(stats) => {
+                    let _ = write!(
+                        json,
+                        "{{\"polls\":{},\"busy\":{},\"scheduled\":{},\"allocated\":{},\
+                         \"freed\":{}}}",
+                        stats.polls,
+                        stats.busy.as_secs_f64(),
+                        stats.scheduled.as_secs_f64(),
+                        stats.allocated,
+                        stats.freed,
+                    );
+                }
+                None => json.push_str("null"),
+            }
+            json.push_str(",\"recent_wakes\":[");
+            for (index, wake) in task.recent_wakes.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                json.push_str("{\"source\":");
+                push_string(&mut json, wake.source);
+                json.push_str(",\"thread\":");
+                push_string(&mut json, &format!("{:?}", wake.thread));
+                let at = wall_now - now.saturating_duration_since(wake.at);
+                let _ = write!(json, ",\"at\":{}}}", at.as_secs_f64());
+            }
+            json.push_str("]}");
+        }
+        let _ = write!(json, "],\"tasks_omitted\":{}}}", self.tasks_omitted);
+        json
+    }
+    /// Write `to_json` to the file at `path`, replacing it if it exists.
+    pub fn write_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+fn push_optional_string(json: &mut String, value: Option<&str>) {
+    match value {
+        Some(value) => push_string(json, value),
+        None => json.push_str("null"),
+    }
+}
+
+/// Append `value` as a JSON string literal.
+fn push_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{iter::Peekable, str::Chars};
+
+    use super::*;
+    use crate::{Builder, Executor, Handle, TimerFuture};
+
+    /// A JSON value, as read back by `parse`.
+    #[derive(Clone, Debug, PartialEq)]
+    enum Json {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+    impl Json {
+        fn get(&self, key: &str) -> &Json {
+            match self {
+                Json::Object(fields) => fields
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, value)| value)
+                    .unwrap_or_else(|| panic!("no {key:?} in {self:?}")),
+                _ => panic!("{self:?} is not an object"),
+            }
+        }
+        fn keys(&self) -> Vec<&str> {
+            match self {
+                Json::Object(fields) => fields.iter().map(|(name, _)| name.as_str()).collect(),
+                _ => panic!("{self:?} is not an object"),
+            }
+        }
+        fn items(&self) -> &[Json] {
+            match self {
+                Json::Array(items) => items,
+                _ => panic!("{self:?} is not an array"),
+            }
+        }
+        fn number(&self) -> f64 {
+            match self {
+                Json::Number(number) => *number,
+                _ => panic!("{self:?} is not a number"),
+            }
+        }
+        fn string(&self) -> &str {
+            match self {
+                Json::String(string) => string,
+                _ => panic!("{self:?} is not a string"),
+            }
+        }
+    }
+
+    /// Parse `json` as a single JSON value, strictly: no trailing commas,
+    /// no unescaped control characters, nothing after the value.
+    fn parse(json: &str) -> Result<Json, String> {
+        let mut chars = json.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        match chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("{c:?} after the value")),
+        }
+    }
+
+    fn parse_value(chars: &mut Peekable<Chars<'_>>) -> Result<Json, String> {
+        match chars.peek().copied() {
+            Some('{') => {
+                chars.next();
+                let mut fields = Vec::new();
+                if chars.next_if_eq(&'}').is_some() {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    if chars.peek() != Some(&'"') {
+                        return Err(format!("key expected, got {:?}", chars.peek()));
+                    }
+                    let key = parse_string(chars)?;
+                    expect(chars, ':')?;
+                    fields.push((key, parse_value(chars)?));
+                    match chars.next() {
+                        Some(',') => {}
+                        Some('}') => return Ok(Json::Object(fields)),
+                        c => return Err(format!("',' or '}}' expected, got {c:?}")),
+                    }
+                }
+            }
+            Some('[') => {
+                chars.next();
+                let mut items = Vec::new();
+                if chars.next_if_eq(&']').is_some() {
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(parse_value(chars)?);
+                    match chars.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Json::Array(items)),
+                        c => return Err(format!("',' or ']' expected, got {c:?}")),
+                    }
+                }
+            }
+            Some('"') => parse_string(chars).map(Json::String),
+            Some('-' | '0'..='9') => {
+                let mut number = String::new();
+                while let Some(c) =
+                    chars.next_if(|c| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+                {
+                    number.push(c);
+                }
+                number
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("bad number {number:?}"))
+            }
+            _ => {
+                for (literal, value) in [
+                    ("null", Json::Null),
+                    ("true", Json::Bool(true)),
+                    ("false", Json::Bool(false)),
+                ] {
+                    if chars.clone().take(literal.len()).eq(literal.chars()) {
+                        chars.nth(literal.len() - 1);
+                        return Ok(value);
+                    }
+                }
+                Err(format!("value expected, got {:?}", chars.peek()))
+            }
+        }
+    }
+
+    fn parse_string(chars: &mut Peekable<Chars<'_>>) -> Result<String, String> {
+        expect(chars, '"')?;
+        let mut string = String::new();
+        loop {
+            match chars.next().ok_or("unterminated string")? {
+                '"' => return Ok(string),
+                '\\' => string.push(match chars.next().ok_or("unterminated escape")? {
+                    '"' => '"',
+                    '\\' => '\\',
+                    '/' => '/',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("bad escape \\u{hex}"))?
+                    }
+                    c => return Err(format!("bad escape \\{c}")),
+                }),
+                c if c < ' ' => return Err(format!("unescaped {c:?} in a string")),
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn expect(chars: &mut Peekable<Chars<'_>>, expected: char) -> Result<(), String> {
+        match chars.next() {
+            Some(c) if c == expected => Ok(()),
+            c => Err(format!("{expected:?} expected, got {c:?}")),
+        }
+    }
+
+    #[test]
+    fn busiest_tasks_rank_a_spinning_task_first() {
+        let executor = Builder::new().task_stats(true).build();
+        let handle = executor.handle();
+        let spinner = handle.spawn_named("spinner", async {
+            for _ in 0..3 {
+                let started = Instant::now();
+                while started.elapsed() < Duration::from_millis(20) {}
+                TimerFuture::new(Duration::from_millis(1)).await;
+            }
+            TimerFuture::new(Duration::from_millis(300)).await;
+        });
+        drop(handle.spawn_named("idle", TimerFuture::new(Duration::from_millis(300))));
+        let ranking = executor.spawn(async {
+            TimerFuture::new(Duration::from_millis(150)).await;
+            let snapshot = Handle::current().snapshot();
+            let ranking: Vec<_> = snapshot
+                .busiest_tasks()
+                .into_iter()
+                .map(|task| (task.name.clone(), task.stats.unwrap()))
+                .collect();
+            ranking
+        });
+        executor.run();
+
+        let ranking = ranking.join_blocking().unwrap();
+        assert_eq!(ranking[0].0.as_deref(), Some("spinner"));
+        let spun = ranking[0].1;
+        assert_eq!(spun.polls, 4);
+        assert!(spun.busy >= Duration::from_millis(60), "{spun:?}");
+        assert!(ranking
+            .iter()
+            .any(|(name, _)| name.as_deref() == Some("idle")));
+        // Its stats keep counting after the snapshot.
+        assert_eq!(spinner.stats().unwrap().polls, 5);
+    }
+
+    #[test]
+    fn a_snapshot_of_a_mixed_workload_lists_every_live_task() {
+        let executor = Executor::new();
+        let handle = executor.handle();
+        for i in 0..5 {
+            drop(handle.spawn_named(
+                format!("sleeper {i}"),
+                TimerFuture::new(Duration::from_millis(200)),
+            ));
+        }
+        drop(handle.spawn_named("with \"quotes\"\n", async {
+            TimerFuture::new(Duration::from_millis(200)).await
+        }));
+        for _ in 0..10 {
+            drop(executor.spawn(async {}));
+        }
+        let doomed = executor.spawn(crate::future::pending::<()>());
+        let snapshot = executor.spawn(async move {
+            TimerFuture::new(Duration::from_millis(20)).await;
+            doomed.abort();
+            Handle::current().snapshot()
+        });
+        executor.run();
+        let snapshot = snapshot.join_blocking().unwrap();
+
+        // The sleepers, the doomed task, and the one taking the snapshot.
+        assert_eq!(snapshot.tasks.len(), 8);
+        assert_eq!(snapshot.metrics.tasks_alive, 8);
+        assert_eq!(snapshot.metrics.tasks_spawned, 18);
+        assert_eq!(snapshot.tasks_omitted, 0);
+        let states: Vec<_> = snapshot.tasks.iter().map(|task| task.state).collect();
+        assert_eq!(
+            states.iter().filter(|s| **s == TaskState::Polling).count(),
+            1
+        );
+        assert_eq!(
+            states.iter().filter(|s| **s == TaskState::Aborted).count(),
+            1
+        );
+        let mut ids: Vec<_> = snapshot.tasks.iter().map(|task| task.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 8);
+
+        let json = snapshot.to_json();
+        assert_eq!(json.matches("{\"id\":").count(), 8);
+        assert_eq!(json.matches(",\"state\":\"").count(), 8);
+        assert!(json.contains(r#""name":"with \"quotes\"\n""#), "{json}");
+        assert!(json.contains(",\"tasks_alive\":8,") || json.contains("{\"tasks_alive\":8,"));
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", std::process::id()));
+        snapshot.write_json(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written, json);
+    }
+
+    #[test]
+    fn the_json_of_a_snapshot_parses_back_to_its_contents() {
+        const NAME: &str = "tab\t, \"quote\", back\\slash, bell\u{7}, é";
+        let executor = Builder::new().capture_spawn_backtraces(true).build();
+        let handle = executor.handle();
+        drop(handle.spawn_named(NAME, TimerFuture::new(Duration::from_millis(50))));
+        let snapshot = executor.spawn(async { Handle::current().snapshot() });
+        executor.run();
+        let snapshot = snapshot.join_blocking().unwrap();
+
+        let json = parse(&snapshot.to_json()).unwrap();
+        assert_eq!(
+            json.keys(),
+            ["taken_at", "metrics", "timers", "tasks", "tasks_omitted"]
+        );
+        let metrics = json.get("metrics");
+        assert_eq!(metrics.get("tasks_alive").number(), 2.0);
+        assert_eq!(
+            metrics.get("polls_total").number(),
+            snapshot.metrics.polls_total as f64
+        );
+        // A bucket per bound, and the last one, unbounded, for the rest.
+        let buckets = metrics.get("poll_duration_buckets").items();
+        assert_eq!(buckets.len(), POLL_DURATION_BOUNDS.len() + 1);
+        for (bucket, bound) in buckets.iter().zip(POLL_DURATION_BOUNDS) {
+            assert_eq!(bucket.get("le").number(), bound.as_secs_f64());
+        }
+        assert_eq!(buckets.last().unwrap().get("le"), &Json::Null);
+        let counted: f64 = buckets
+            .iter()
+            .map(|bucket| bucket.get("count").number())
+            .sum();
+        assert_eq!(counted, snapshot.metrics.polls_total as f64);
+
+        let tasks = json.get("tasks").items();
+        assert_eq!(tasks.len(), 2);
+        for (task, described) in tasks.iter().zip(&snapshot.tasks) {
+            assert_eq!(
+                task.get("id").number(),
+                described.id.to_string().parse().unwrap()
+            );
+            assert_eq!(task.get("state").string(), described.state.to_string());
+            assert_eq!(
+                task.get("location").string(),
+                described.location.to_string()
+            );
+            // Multi-line, and quoting paths on some platforms.
+            let backtrace = described.spawn_backtrace.as_ref().unwrap().to_string();
+            assert!(backtrace.contains('\n'));
+            assert_eq!(task.get("spawn_backtrace").string(), backtrace);
+        }
+        assert_eq!(tasks[0].get("name").string(), NAME);
+        assert_eq!(tasks[1].get("name"), &Json::Null);
+        assert_eq!(tasks[0].get("stats"), &Json::Null);
+    }
+
+    #[test]
+    fn the_checker_rejects_malformed_json() {
+        for malformed in [
+            "",
+            "{",
+            r#"{"a":1,}"#,
+            "[1,]",
+            r#"{"a" 1}"#,
+            "\"unterminated",
+            "\"raw\nnewline\"",
+            r#""\x""#,
+            "nul",
+            "[1] 2",
+        ] {
+            assert!(parse(malformed).is_err(), "{malformed:?}");
+        }
+        assert_eq!(
+            parse(r#"{"a":[null,true,-1.5e3,"\u00e9\n"]}"#).unwrap(),
+            Json::Object(vec![(
+                "a".to_string(),
+                Json::Array(vec![
+                    Json::Null,
+                    Json::Bool(true),
+                    Json::Number(-1500.0),
+                    Json::String("é\n".to_string()),
+                ]),
+            )])
+        );
+    }
+}
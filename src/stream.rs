@@ -0,0 +1,1477 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug},
+    future::Future,
+    marker::PhantomData,
+    mem,
+    ops::DerefMut,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    counters::clone_waker,
+    lock,
+    task::JoinError,
+    time::{self, saturating_add, Elapsed, TimerFuture},
+    Handle,
+};
+
+/// Asynchronous sequence of values: the async counterpart of `Iterator`.
+pub trait Stream {
+    type Item;
+
+    /// Try to pull the next value out of the stream.
+    ///
+    /// `Ready(None)` means the stream is exhausted. `Pending` means the next
+    /// value is not available yet, and `cx`'s waker will be woken once it is.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+}
+impl<S: Stream + Unpin + ?Sized> Stream for &mut S {
+    type Item = S::Item;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut **self).poll_next(cx)
+    }
+}
+impl<S: Stream + Unpin + ?Sized> Stream for Box<S> {
+    type Item = S::Item;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut **self).poll_next(cx)
+    }
+}
+impl<P> Stream for Pin<P>
+where
+    P: DerefMut<Target: Stream>,
+{
+    type Item = <P::Target as Stream>::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.as_deref_mut().poll_next(cx)
+    }
+}
+
+/// Combinators for every `Stream`.
+pub trait StreamExt: Stream {
+    /// Future resolving to the next item, or `None` once the stream ended.
+    ///
+    /// Cancel safe as long as the stream's `poll_next` is, as it only takes
+    /// an item when returning it.
+    fn next(&mut self) -> Next<'_, Self>
+    where
+        Self: Unpin,
+    {
+        Next { stream: self }
+    }
+
+    /// Yield `Err(Elapsed)` whenever more than `duration` passes without the
+    /// stream producing an item, then keep waiting for the next one.
+    ///
+    /// The clock restarts after every item and every `Elapsed`. No timer is
+    /// created while the stream keeps being ready.
+    fn timeout(self, duration: Duration) -> Timeout<Self>
+    where
+        Self: Sized,
+    {
+        Timeout {
+            stream: self,
+            duration,
+            last_item: None,
+            timer: None,
+        }
+    }
+
+    /// End the stream at `deadline`, even if it still has items coming.
+    fn take_until_deadline(self, deadline: Instant) -> TakeUntilDeadline<Self>
+    where
+        Self: Sized,
+    {
+        TakeUntilDeadline {
+            stream: self,
+            deadline,
+            timer: None,
+            done: false,
+        }
+    }
+
+    /// Hold items back so they come out at least `min_gap` apart, without
+    /// dropping any. The first one goes through right away.
+    fn throttle(self, min_gap: Duration) -> Throttle<Self>
+    where
+        Self: Sized,
+    {
+        Throttle {
+            stream: self,
+            min_gap,
+            last_item: None,
+            held: None,
+            timer: None,
+        }
+    }
+
+    /// Only yield an item once the stream has been quiet for `quiet` after
+    /// it, dropping the items it superseded. When the stream ends, the item
+    /// still held back comes out right away.
+    fn debounce(self, quiet: Duration) -> Debounce<Self>
+    where
+        Self: Sized,
+    {
+        Debounce {
+            stream: self,
+            quiet,
+            latest: None,
+            timer: None,
+            done: false,
+        }
+    }
+
+    /// Interleave the items of both streams as they come, ending once both
+    /// ended.
+    ///
+    /// When both have an item ready, they take turns, so neither can starve
+    /// the other.
+    fn merge<T>(self, other: T) -> Merge<Self, T>
+    where
+        Self: Sized,
+        T: Stream<Item = Self::Item>,
+    {
+        Merge {
+            first: Some(self),
+            second: Some(other),
+            second_first: false,
+        }
+    }
+
+    /// Gather every item into a `C`, such as a `Vec`, once the stream
+    /// ended. Works with any `FromIterator`, so `Result<Vec<T>, E>` from a
+    /// stream of results too; `try_collect` stops at the first error instead
+    /// of draining the stream.
+    fn collect<C>(self) -> Collect<Self, C>
+    where
+        Self: Sized,
+        C: FromIterator<Self::Item>,
+    {
+        Collect {
+            stream: self,
+            items: Vec::new(),
+            _collection: PhantomData,
+        }
+    }
+
+    /// Like `next`, for a stream of results: resolves to `Ok(None)` once the
+    /// stream ended.
+    fn try_next<T, E>(&mut self) -> TryNext<'_, Self>
+    where
+        Self: Stream<Item = Result<T, E>> + Unpin,
+    {
+        TryNext { stream: self }
+    }
+
+    /// Gather the `Ok` items into a `C`, resolving to the first `Err`
+    /// instead as soon as there is one, without polling the stream again.
+    fn try_collect<T, E, C>(self) -> TryCollect<Self, C>
+    where
+        Self: Stream<Item = Result<T, E>> + Sized,
+        C: Default + Extend<T>,
+    {
+        TryCollect {
+            stream: self,
+            items: C::default(),
+        }
+    }
+
+    /// Run `f` on every `Ok` item in turn, awaiting each future it returns,
+    /// until the stream ends or either of them yields an error.
+    fn try_for_each<T, E, F, Fut>(self, f: F) -> TryForEach<Self, F, Fut>
+    where
+        Self: Stream<Item = Result<T, E>> + Sized,
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+    {
+        TryForEach {
+            stream: self,
+            f,
+            future: None,
+        }
+    }
+}
+impl<S: Stream + ?Sized> StreamExt for S {}
+
+/* Next */
+/// Future for [`StreamExt::next`].
+#[derive(Debug)]
+pub struct Next<'a, S: ?Sized> {
+    stream: &'a mut S,
+}
+impl<S: Stream + Unpin + ?Sized> Future for Next<'_, S> {
+    type Output = Option<S::Item>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+/* Collect */
+/// Future for [`StreamExt::collect`].
+pub struct Collect<S: Stream, C> {
+    stream: S,
+    items: Vec<S::Item>,
+    _collection: PhantomData<fn() -> C>,
+}
+impl<S: Stream, C: FromIterator<S::Item>> Future for Collect<S, C> {
+    type Output = C;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `stream` is structurally pinned, it is never moved out of
+        // `Collect`; the items are not pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+        loop {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => this.items.push(item),
+                Poll::Ready(None) => {
+                    return Poll::Ready(mem::take(&mut this.items).into_iter().collect())
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/* TryNext */
+/// Future for [`StreamExt::try_next`].
+#[derive(Debug)]
+pub struct TryNext<'a, S: ?Sized> {
+    stream: &'a mut S,
+}
+impl<T, E, S> Future for TryNext<'_, S>
+where
+    S: Stream<Item = Result<T, E>> + Unpin + ?Sized,
+{
+    type Output = Result<Option<T>, E>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream)
+            .poll_next(cx)
+            .map(Option::transpose)
+    }
+}
+
+/* TryCollect */
+/// Future for [`StreamExt::try_collect`].
+pub struct TryCollect<S, C> {
+    stream: S,
+    items: C,
+}
+impl<T, E, S, C> Future for TryCollect<S, C>
+where
+    S: Stream<Item = Result<T, E>>,
+    C: Default + Extend<T>,
+{
+    type Output = Result<C, E>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `stream` is structurally pinned, it is never moved out of
+        // `TryCollect`; the collection is not pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+        loop {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) => this.items.extend([item]),
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Err(error)),
+                Poll::Ready(None) => return Poll::Ready(Ok(mem::take(&mut this.items))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/* TryForEach */
+/// Future for [`StreamExt::try_for_each`].
+pub struct TryForEach<S, F, Fut> {
+    stream: S,
+    f: F,
+    /// Future returned by `f` for the current item, until it completes.
+    future: Option<Fut>,
+}
+impl<T, E, S, F, Fut> Future for TryForEach<S, F, Fut>
+where
+    S: Stream<Item = Result<T, E>>,
+    F: FnMut(T) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+{
+    type Output = Result<(), E>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `stream` and `future` are structurally pinned: neither is
+        // moved out, and the future is dropped in place once complete.
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+        loop {
+            if let Some(future) = &mut this.future {
+                match unsafe { Pin::new_unchecked(future) }.poll(cx) {
+                    Poll::Ready(Ok(())) => this.future = None,
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) => this.future = Some((this.f)(item)),
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Err(error)),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/* Timeout */
+/// Stream for [`StreamExt::timeout`].
+pub struct Timeout<S> {
+    stream: S,
+    duration: Duration,
+    /// When the last item or `Elapsed` was yielded, or the first poll happened.
+    last_item: Option<Instant>,
+    /// Armed the first time the stream is found pending since `last_item`.
+    timer: Option<TimerFuture>,
+}
+impl<S: Stream> Stream for Timeout<S> {
+    type Item = Result<S::Item, Elapsed>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: `stream` is structurally pinned, it is never moved out of
+        // `Timeout`; the other fields are not pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+        let last_item = *this.last_item.get_or_insert_with(time::now);
+        match stream.poll_next(cx) {
+            Poll::Ready(Some(item)) => {
+                this.restart();
+                return Poll::Ready(Some(Ok(item)));
+            }
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => {}
+        }
+        let duration = this.duration;
+        let timer = this.timer.get_or_insert_with(|| {
+            TimerFuture::new(
+                duration.saturating_sub(time::now().saturating_duration_since(last_item)),
+            )
+        });
+        match Pin::new(timer).poll(cx) {
+            Poll::Ready(()) => {
+                this.restart();
+                Poll::Ready(Some(Err(Elapsed::new())))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+impl<S> Timeout<S> {
+    fn restart(&mut self) {
+        self.last_item = Some(time::now());
+        self.timer = None;
+    }
+}
+
+/* TakeUntilDeadline */
+/// Stream for [`StreamExt::take_until_deadline`].
+pub struct TakeUntilDeadline<S> {
+    stream: S,
+    deadline: Instant,
+    timer: Option<TimerFuture>,
+    done: bool,
+}
+impl<S: Stream> Stream for TakeUntilDeadline<S> {
+    type Item = S::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: `stream` is structurally pinned, it is never moved out of
+        // `TakeUntilDeadline`; the other fields are not pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+        if this.done || time::now() >= this.deadline {
+            this.done = true;
+            return Poll::Ready(None);
+        }
+        match stream.poll_next(cx) {
+            Poll::Ready(Some(item)) => return Poll::Ready(Some(item)),
+            Poll::Ready(None) => {
+                this.done = true;
+                return Poll::Ready(None);
+            }
+            Poll::Pending => {}
+        }
+        let deadline = this.deadline;
+        let timer = this.timer.get_or_insert_with(|| {
+            TimerFuture::new(deadline.saturating_duration_since(time::now()))
+        });
+        match Pin::new(timer).poll(cx) {
+            Poll::Ready(()) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/* Throttle */
+/// Stream for [`StreamExt::throttle`].
+pub struct Throttle<S: Stream> {
+    stream: S,
+    min_gap: Duration,
+    /// When the last item was yielded.
+    last_item: Option<Instant>,
+    /// Item pulled from the stream, waiting for the gap to pass.
+    held: Option<S::Item>,
+    /// Armed while an item is held.
+    timer: Option<TimerFuture>,
+}
+impl<S: Stream> Stream for Throttle<S> {
+    type Item = S::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: `stream` is structurally pinned, it is never moved out of
+        // `Throttle`; the other fields are not pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+        if this.held.is_none() {
+            match stream.poll_next(cx) {
+                Poll::Ready(Some(item)) => this.held = Some(item),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        if let Some(last_item) = this.last_item {
+            let wait = this
+                .min_gap
+                .saturating_sub(time::now().saturating_duration_since(last_item));
+            if !wait.is_zero() {
+                let timer = this.timer.get_or_insert_with(|| TimerFuture::new(wait));
+                if Pin::new(timer).poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+        }
+        this.timer = None;
+        this.last_item = Some(time::now());
+        Poll::Ready(this.held.take())
+    }
+}
+
+/* Debounce */
+/// Stream for [`StreamExt::debounce`].
+pub struct Debounce<S: Stream> {
+    stream: S,
+    quiet: Duration,
+    /// Latest item, until the stream has been quiet for long enough.
+    latest: Option<S::Item>,
+    /// Armed for `quiet` after the latest item.
+    timer: Option<TimerFuture>,
+    /// Set once the stream ended.
+    done: bool,
+}
+impl<S: Stream> Stream for Debounce<S> {
+    type Item = S::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: `stream` is structurally pinned, it is never moved out of
+        // `Debounce`; the other fields are not pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+        while !this.done {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    this.latest = Some(item);
+                    let deadline = saturating_add(time::now(), this.quiet);
+                    match &mut this.timer {
+                        Some(timer) => timer.reset(deadline),
+                        None => this.timer = Some(TimerFuture::new(this.quiet)),
+                    }
+                }
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+        if this.done {
+            this.timer = None;
+            return Poll::Ready(this.latest.take());
+        }
+        let Some(timer) = &mut this.timer else {
+            return Poll::Pending;
+        };
+        if Pin::new(timer).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        this.timer = None;
+        Poll::Ready(this.latest.take())
+    }
+}
+
+/* Merge */
+/// Stream for [`StreamExt::merge`].
+pub struct Merge<A, B> {
+    /// `None` once ended.
+    first: Option<A>,
+    second: Option<B>,
+    /// Whose turn it is to be polled first.
+    second_first: bool,
+}
+impl<A, B> Stream for Merge<A, B>
+where
+    A: Stream,
+    B: Stream<Item = A::Item>,
+{
+    type Item = A::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: both streams are structurally pinned: they are only ever
+        // polled in place, and dropped in place once ended.
+        let this = unsafe { self.get_unchecked_mut() };
+        let second_first = this.second_first;
+        this.second_first = !second_first;
+        for second in [second_first, !second_first] {
+            let poll = unsafe {
+                if second {
+                    poll_or_end(&mut this.second, cx)
+                } else {
+                    poll_or_end(&mut this.first, cx)
+                }
+            };
+            if let Poll::Ready(Some(item)) = poll {
+                return Poll::Ready(Some(item));
+            }
+        }
+        if this.first.is_none() && this.second.is_none() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Poll a stream of a `Merge` unless it already ended, dropping it in place
+/// when it does.
+///
+/// SAFETY: the stream in `slot` must be pinned, never to be moved.
+unsafe fn poll_or_end<S: Stream>(
+    slot: &mut Option<S>,
+    cx: &mut Context<'_>,
+) -> Poll<Option<S::Item>> {
+    let Some(stream) = slot else {
+        return Poll::Ready(None);
+    };
+    let poll = Pin::new_unchecked(stream).poll_next(cx);
+    if let Poll::Ready(None) = poll {
+        *slot = None;
+    }
+    poll
+}
+
+/* SelectAll */
+/// Stream merging any number of streams, created by [`select_all`].
+///
+/// Each stream is polled with a waker of its own, so only the streams which
+/// were woken get polled again, and those with an item ready take turns.
+pub struct SelectAll<S> {
+    /// The streams with their wakers; `None` for slots of streams which
+    /// ended, reused by `push`.
+    streams: Vec<Option<(S, Arc<SlotWaker>)>>,
+    ready: Arc<ReadyQueue>,
+}
+
+/// Indices of the `SelectAll` streams worth polling, and the waker of the
+/// task polling the `SelectAll`.
+struct ReadyQueue {
+    indices: Mutex<VecDeque<usize>>,
+    waker: Mutex<Option<Waker>>,
+    /// Polls cut short with members still queued, see `next_turn`.
+    yields: AtomicUsize,
+    /// Set for `FuturesOrdered`: the only member whose wake wakes the task
+    /// polling the set, the front of its queue.
+    head: Option<AtomicUsize>,
+}
+impl ReadyQueue {
+    fn new() -> Self {
+        ReadyQueue {
+            indices: Mutex::new(VecDeque::new()),
+            waker: Mutex::new(None),
+            yields: AtomicUsize::new(0),
+            head: None,
+        }
+    }
+    fn ordered() -> Self {
+        ReadyQueue {
+            head: Some(AtomicUsize::new(0)),
+            ..Self::new()
+        }
+    }
+    /// Members to poll at most in one poll of the set: those queued when it
+    /// began.
+    fn turns(&self) -> usize {
+        lock(&self.indices).len()
+    }
+    /// The next member to poll, once `turns` are used up none.
+    ///
+    /// Each member queued gets one turn per poll of the set, and one woken
+    /// meanwhile, even by its own poll, waits for the next: a member which
+    /// is always ready can neither starve the others nor keep the set from
+    /// returning. Those left queued woke the task polling the set already.
+    fn next_turn(&self, turns: &mut usize) -> Option<usize> {
+        let mut indices = lock(&self.indices);
+        if *turns == 0 {
+            if !indices.is_empty() {
+                self.yields.fetch_add(1, Ordering::Relaxed);
+            }
+            return None;
+        }
+        *turns -= 1;
+        indices.pop_front()
+    }
+}
+
+struct SlotWaker {
+    index: usize,
+    /// Set while `index` sits in the ready queue, so it is only there once.
+    queued: AtomicBool,
+    ready: Arc<ReadyQueue>,
+}
+impl Wake for SlotWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        lock(&self.ready.indices).push_back(self.index);
+        if let Some(head) = &self.ready.head {
+            // Polled along with the front, whenever that is woken.
+            if head.load(Ordering::Acquire) != self.index {
+                return;
+            }
+        }
+        let waker = lock(&self.ready.waker).take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Merge `streams` into one, yielding items from whichever stream has one.
+/// More streams can be added later with `SelectAll::push`.
+pub fn select_all<S: Stream + Unpin>(streams: impl IntoIterator<Item = S>) -> SelectAll<S> {
+    let mut select = SelectAll {
+        streams: Vec::new(),
+        ready: Arc::new(ReadyQueue::new()),
+    };
+    for stream in streams {
+        select.push(stream);
+    }
+    select
+}
+impl<S: Stream + Unpin> SelectAll<S> {
+    /// Add a stream, polled from the next poll on.
+    pub fn push(&mut self, stream: S) {
+        let index = self
+            .streams
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or_else(|| {
+                self.streams.push(None);
+                self.streams.len() - 1
+            });
+        let waker = Arc::new(SlotWaker {
+            index,
+            queued: AtomicBool::new(false),
+            ready: self.ready.clone(),
+        });
+        waker.wake_by_ref();
+        self.streams[index] = Some((stream, waker));
+    }
+    /// Number of streams which have not ended yet.
+    pub fn len(&self) -> usize {
+        self.streams.iter().flatten().count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+impl<S: Stream + Unpin> Stream for SelectAll<S> {
+    type Item = S::Item;
+    /// `Ready(None)` once every stream ended; pushing another one starts the
+    /// stream up again.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        // Registered first, so a wake racing with the polls below is not lost.
+        *lock(&this.ready.waker) = Some(clone_waker(cx.waker()));
+        let mut turns = this.ready.turns();
+        while let Some(index) = this.ready.next_turn(&mut turns) {
+            // Left over from a stream which ended since.
+            let Some(Some((stream, waker))) = this.streams.get_mut(index) else {
+                continue;
+            };
+            if waker.index != index {
+                continue;
+            }
+            waker.queued.store(false, Ordering::Release);
+            let slot_waker = Waker::from(waker.clone());
+            match Pin::new(stream).poll_next(&mut Context::from_waker(&slot_waker)) {
+                Poll::Ready(Some(item)) => {
+                    // It may well have more: back in line behind the others.
+                    waker.wake_by_ref();
+                    return Poll::Ready(Some(item));
+                }
+                Poll::Ready(None) => this.streams[index] = None,
+                Poll::Pending => {}
+            }
+        }
+        if this.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+impl<S> Debug for SelectAll<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SelectAll")
+            .field("len", &self.streams.iter().flatten().count())
+            .field("yields", &self.ready.yields.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+/* FuturesOrdered */
+/// Runs futures concurrently, yielding their outputs in the order they were
+/// queued rather than in the order they complete.
+///
+/// Outputs of futures done before the one at the front of the queue are
+/// buffered until it is done: a slow future at the front lets that buffer
+/// grow as large as the queue. `buffered` tells how large it is.
+///
+/// Each future is polled with a waker of its own, so only the futures which
+/// were woken get polled again. Only a wake of the future at the front wakes
+/// the task polling the set: the others woken meanwhile are polled along
+/// with it, as their outputs could not be yielded any sooner.
+pub struct FuturesOrdered<F: Future> {
+    queue: VecDeque<OrderedSlot<F>>,
+    /// Id of the front of `queue`; ids go up towards the back, wrapping.
+    head: usize,
+    ready: Arc<ReadyQueue>,
+}
+
+enum OrderedSlot<F: Future> {
+    Pending(F, Arc<SlotWaker>),
+    Done(F::Output),
+}
+
+impl<F: Future + Unpin> FuturesOrdered<F> {
+    pub fn new() -> Self {
+        FuturesOrdered {
+            queue: VecDeque::new(),
+            head: 0,
+            ready: Arc::new(ReadyQueue::ordered()),
+        }
+    }
+    /// Queue a future, its output to come after those of every future
+    /// queued so far.
+    pub fn push_back(&mut self, future: F) {
+        let id = self.head.wrapping_add(self.queue.len());
+        let slot = self.slot(id, future);
+        self.queue.push_back(slot);
+    }
+    /// Queue a future, its output to come before those of every future
+    /// queued so far.
+    pub fn push_front(&mut self, future: F) {
+        self.set_head(self.head.wrapping_sub(1));
+        let slot = self.slot(self.head, future);
+        self.queue.push_front(slot);
+    }
+    fn set_head(&mut self, head: usize) {
+        self.head = head;
+        let shared = self.ready.head.as_ref().expect("an ordered ready queue");
+        shared.store(head, Ordering::Release);
+    }
+    fn slot(&self, id: usize, future: F) -> OrderedSlot<F> {
+        let waker = Arc::new(SlotWaker {
+            index: id,
+            queued: AtomicBool::new(false),
+            ready: self.ready.clone(),
+        });
+        waker.wake_by_ref();
+        OrderedSlot::Pending(future, waker)
+    }
+    /// Number of futures queued whose output was not yielded yet, done or
+    /// not.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+    /// Number of outputs held back behind a future which is not done yet.
+    pub fn buffered(&self) -> usize {
+        self.queue
+            .iter()
+            .filter(|slot| matches!(slot, OrderedSlot::Done(_)))
+            .count()
+    }
+}
+// Outputs are never pinned, and the futures are `Unpin` themselves.
+impl<F: Future + Unpin> Unpin for FuturesOrdered<F> {}
+impl<F: Future + Unpin> Default for FuturesOrdered<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<F: Future + Unpin> FromIterator<F> for FuturesOrdered<F> {
+    fn from_iter<I: IntoIterator<Item = F>>(futures: I) -> Self {
+        let mut ordered = Self::new();
+        for future in futures {
+            ordered.push_back(future);
+        }
+        ordered
+    }
+}
+impl<F: Future + Unpin> Stream for FuturesOrdered<F> {
+    type Item = F::Output;
+    /// `Ready(None)` once the queue is empty; queueing another future starts
+    /// the stream up again.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        // Registered first, so a wake racing with the polls below is not lost.
+        *lock(&this.ready.waker) = Some(clone_waker(cx.waker()));
+        let mut turns = this.ready.turns();
+        while let Some(id) = this.ready.next_turn(&mut turns) {
+            // Left over from a future which was done since.
+            let position = id.wrapping_sub(this.head);
+            let Some(OrderedSlot::Pending(future, waker)) = this.queue.get_mut(position) else {
+                continue;
+            };
+            waker.queued.store(false, Ordering::Release);
+            let slot_waker = Waker::from(waker.clone());
+            if let Poll::Ready(output) =
+                Pin::new(future).poll(&mut Context::from_waker(&slot_waker))
+            {
+                this.queue[position] = OrderedSlot::Done(output);
+            }
+        }
+        match this.queue.front() {
+            None => return Poll::Ready(None),
+            Some(OrderedSlot::Pending(..)) => return Poll::Pending,
+            Some(OrderedSlot::Done(_)) => {}
+        }
+        this.set_head(this.head.wrapping_add(1));
+        let Some(OrderedSlot::Done(output)) = this.queue.pop_front() else {
+            unreachable!("the front was just found done");
+        };
+        Poll::Ready(Some(output))
+    }
+}
+impl<F: Future> Debug for FuturesOrdered<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FuturesOrdered")
+            .field("len", &self.queue.len())
+            .field("yields", &self.ready.yields.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+/* FromBlockingIter */
+/// Stream of the items of a blocking iterator, created by
+/// [`from_blocking_iter`].
+pub struct FromBlockingIter<T> {
+    shared: Arc<IterShared<T>>,
+}
+
+struct IterShared<T> {
+    state: Mutex<IterState<T>>,
+    /// Signalled when the iterator thread may go on: an item was taken, or
+    /// the stream was dropped.
+    space: Condvar,
+}
+
+struct IterState<T> {
+    items: VecDeque<Result<T, JoinError>>,
+    /// Set once the iterator returned `None` or panicked.
+    done: bool,
+    waker: Option<Waker>,
+    /// Set once the stream is dropped.
+    closed: bool,
+}
+
+/// Run `iter` on the blocking pool of the executor running the current
+/// task, and stream its items as they come, so an iterator whose `next`
+/// blocks does not stall the executor.
+///
+/// At most `buffer` items are read ahead: `next` is only called once there
+/// is room for its item. If `next` panics, the stream yields a `JoinError`
+/// carrying the panic, then ends. Dropping the stream stops the iterator once
+/// its current `next` call returns; the iterator holds a pool thread until
+/// then.
+///
+/// Panics if `buffer` is zero, or when called outside of `Executor::run` or
+/// `Handle::enter`.
+pub fn from_blocking_iter<I>(iter: I, buffer: usize) -> FromBlockingIter<I::Item>
+where
+    I: IntoIterator + Send + 'static,
+    I::Item: Send + 'static,
+{
+    assert!(buffer > 0, "buffer must hold at least one item");
+    let shared = Arc::new(IterShared {
+        state: Mutex::new(IterState {
+            items: VecDeque::new(),
+            done: false,
+            waker: None,
+            closed: false,
+        }),
+        space: Condvar::new(),
+    });
+    let pool_shared = shared.clone();
+    // Detached: the job keeps running without its `BlockingJoin`.
+    drop(Handle::current().blocking().run(move || {
+        let mut iter = iter.into_iter();
+        loop {
+            // Only this job adds items, so the room is still there below.
+            let state = lock(&pool_shared.state);
+            let state = pool_shared
+                .space
+                .wait_while(state, |state| !state.closed && state.items.len() >= buffer)
+                .unwrap_or_else(PoisonError::into_inner);
+            if state.closed {
+                return;
+            }
+            drop(state);
+            let item = match panic::catch_unwind(AssertUnwindSafe(|| iter.next())) {
+                Ok(Some(item)) => Some(Ok(item)),
+                Ok(None) => None,
+                Err(payload) => Some(Err(JoinError::panic(payload))),
+            };
+            let mut state = lock(&pool_shared.state);
+            if state.closed {
+                return;
+            }
+            let last = !matches!(item, Some(Ok(_)));
+            state.items.extend(item);
+            state.done = last;
+            let waker = state.waker.take();
+            drop(state);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+            if last {
+                return;
+            }
+        }
+    }));
+    FromBlockingIter { shared }
+}
+impl<T> Stream for FromBlockingIter<T> {
+    type Item = Result<T, JoinError>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = lock(&self.shared.state);
+        if let Some(item) = state.items.pop_front() {
+            self.shared.space.notify_one();
+            return Poll::Ready(Some(item));
+        }
+        if state.done {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(clone_waker(cx.waker()));
+        Poll::Pending
+    }
+}
+impl<T> Drop for FromBlockingIter<T> {
+    fn drop(&mut self) {
+        lock(&self.shared.state).closed = true;
+        self.shared.space.notify_one();
+    }
+}
+impl<T> Debug for FromBlockingIter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FromBlockingIter").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::atomic::AtomicUsize};
+
+    use super::*;
+    use crate::{
+        assert_pending, assert_ready_eq,
+        future::{completion, Completion},
+        test_util::MockTask,
+        Builder, Executor,
+    };
+
+    /// Yields each item once its delay passed since the previous one.
+    struct Delayed<T> {
+        items: VecDeque<(Duration, T)>,
+        timer: Option<TimerFuture>,
+    }
+    fn delayed<T>(items: impl IntoIterator<Item = (u64, T)>) -> Delayed<T> {
+        Delayed {
+            items: items
+                .into_iter()
+                .map(|(millis, item)| (Duration::from_millis(millis), item))
+                .collect(),
+            timer: None,
+        }
+    }
+    impl<T> Unpin for Delayed<T> {}
+    impl<T> Stream for Delayed<T> {
+        type Item = T;
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+            let Some(&(delay, _)) = self.items.front() else {
+                return Poll::Ready(None);
+            };
+            if delay.is_zero() {
+                return Poll::Ready(self.items.pop_front().map(|(_, item)| item));
+            }
+            let timer = self.timer.get_or_insert_with(|| TimerFuture::new(delay));
+            match Pin::new(timer).poll(cx) {
+                Poll::Ready(()) => {
+                    self.timer = None;
+                    Poll::Ready(self.items.pop_front().map(|(_, item)| item))
+                }
+                Poll::Pending => Poll::Pending,
+            }
+        }
+    }
+
+    /// Collect `stream` on an executor of its own.
+    fn collect_all<S>(stream: S) -> Vec<S::Item>
+    where
+        S: Stream + Send + 'static,
+        S::Item: Send,
+    {
+        let executor = Executor::new();
+        let [items] = executor
+            .block_on_all([stream.collect::<Vec<_>>()])
+            .try_into()
+            .unwrap_or_else(|_| unreachable!());
+        items
+    }
+
+    /// Collect `stream` on an executor of its own whose clock is paused, with
+    /// how far the clock had moved by each item.
+    fn collect_timed<S>(stream: S) -> Vec<(Duration, S::Item)>
+    where
+        S: Stream + Send + 'static,
+        S::Item: Send,
+    {
+        let executor = Builder::new().start_paused(true).build();
+        let [items] = executor
+            .block_on_all([async move {
+                let started = time::now();
+                let mut stream = Box::pin(stream);
+                let mut items = Vec::new();
+                while let Some(item) = stream.next().await {
+                    items.push((time::now() - started, item));
+                }
+                items
+            }])
+            .try_into()
+            .unwrap_or_else(|_| unreachable!());
+        items
+    }
+
+    #[test]
+    fn timeout_yields_one_elapsed_within_a_pause() {
+        let stream = delayed([(0, 1), (0, 2), (0, 3), (80, 4), (0, 5), (0, 6)]);
+        let items = collect_all(stream.timeout(Duration::from_millis(50)));
+        let elapsed = Err(Elapsed::new());
+        assert_eq!(items, [Ok(1), Ok(2), Ok(3), elapsed, Ok(4), Ok(5), Ok(6)],);
+    }
+
+    #[test]
+    fn take_until_deadline_ends_the_stream() {
+        let stream = delayed([(0, 1), (10, 2), (500, 3), (0, 4)]);
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let items = collect_all(stream.take_until_deadline(deadline));
+        assert_eq!(items, [1, 2]);
+        assert!(Instant::now() >= deadline);
+    }
+
+    #[test]
+    fn merge_follows_the_timeline() {
+        // Each item 30ms away from the nearest of the other stream, as
+        // late wakes add up along a stream.
+        let ticks = delayed([60; 5].map(|delay| (delay, "tick")));
+        let messages = delayed([(90, "first"), (120, "second")]);
+        let merged = collect_all(ticks.merge(messages));
+        assert_eq!(
+            merged,
+            ["tick", "first", "tick", "tick", "second", "tick", "tick"]
+        );
+    }
+
+    #[test]
+    fn merge_alternates_between_ready_streams() {
+        let first = delayed([(0, "a1"), (0, "a2"), (0, "a3"), (0, "a4")]);
+        let second = delayed([(0, "b1"), (0, "b2")]);
+        let merged = collect_all(first.merge(second));
+        assert_eq!(merged, ["a1", "b1", "a2", "b2", "a3", "a4"]);
+    }
+
+    /// Counts the polls of the stream it wraps.
+    struct Counted<S> {
+        stream: S,
+        polls: Arc<AtomicUsize>,
+    }
+    impl<S: Stream + Unpin> Stream for Counted<S> {
+        type Item = S::Item;
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+            self.polls.fetch_add(1, Ordering::Relaxed);
+            Pin::new(&mut self.stream).poll_next(cx)
+        }
+    }
+
+    #[test]
+    fn select_all_only_polls_woken_streams() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let slow = Counted {
+            stream: delayed(vec![(30, "slow")]),
+            polls: polls.clone(),
+        };
+        let busy = Counted {
+            stream: delayed(vec![(0, "busy"); 20]),
+            polls: Arc::new(AtomicUsize::new(0)),
+        };
+        let mut items = collect_all(select_all([slow, busy]));
+        assert_eq!(items.pop(), Some("slow"));
+        assert_eq!(items, ["busy"; 20]);
+        // Once pending, once woken by its timer, and once to end.
+        assert_eq!(polls.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn select_all_takes_streams_pushed_later() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let mut select = select_all([delayed(vec![(0, 1), (5, 2)])]);
+            let first = select.next().await;
+            select.push(delayed(vec![(0, 10)]));
+            let mut rest: Vec<_> = select.collect::<Vec<_>>().await;
+            rest.sort();
+            (first, rest)
+        }]);
+        assert_eq!(outputs, [(Some(1), vec![2, 10])]);
+    }
+
+    /// Counts its `next` calls, sleeping `pause` in each.
+    fn counting_iter(pause: Duration, reads: Arc<AtomicUsize>) -> impl Iterator<Item = usize> {
+        (0..).inspect(move |_| {
+            std::thread::sleep(pause);
+            reads.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    #[test]
+    fn blocking_iter_keeps_timers_responsive() {
+        let executor = Executor::new();
+        let done = Arc::new(AtomicBool::new(false));
+        let ticker_done = done.clone();
+        let ticker = executor.spawn(async move {
+            let mut ticks = 0;
+            while !ticker_done.load(Ordering::SeqCst) {
+                TimerFuture::new(Duration::from_millis(1)).await;
+                ticks += 1;
+            }
+            ticks
+        });
+        let reader = executor.spawn(async move {
+            let reads = Arc::new(AtomicUsize::new(0));
+            let iter = counting_iter(Duration::from_millis(5), reads).take(20);
+            let items: Vec<_> = from_blocking_iter(iter, 4).collect::<Vec<_>>().await;
+            done.store(true, Ordering::SeqCst);
+            items.into_iter().map(Result::unwrap).collect::<Vec<_>>()
+        });
+        executor.run();
+        assert_eq!(reader.join_blocking().unwrap(), (0..20).collect::<Vec<_>>());
+        // The 100ms of reading leave the executor thread free for the timers.
+        assert!(ticker.join_blocking().unwrap() >= 20);
+    }
+
+    #[test]
+    fn blocking_iter_reads_at_most_buffer_ahead() {
+        let executor = Executor::new();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let iter_reads = reads.clone();
+        let outputs = executor.block_on_all([async move {
+            let mut stream = from_blocking_iter(counting_iter(Duration::ZERO, iter_reads), 3);
+            let first = stream.next().await.unwrap().unwrap();
+            TimerFuture::new(Duration::from_millis(30)).await;
+            first
+        }]);
+        assert_eq!(outputs, [0]);
+        // The item taken, and the three buffered after it.
+        assert_eq!(reads.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn blocking_iter_ends_with_the_panic() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let iter = (0..).map(|i| if i < 2 { i } else { panic!("bad row") });
+            from_blocking_iter(iter, 1).collect::<Vec<_>>().await
+        }]);
+        let [items] = &outputs[..] else {
+            unreachable!()
+        };
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].as_ref().unwrap(), &0);
+        assert_eq!(items[1].as_ref().unwrap(), &1);
+        assert!(items[2].as_ref().unwrap_err().is_panic());
+    }
+
+    #[test]
+    fn dropped_blocking_iter_stops_reading() {
+        let executor = Executor::new();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let iter_reads = reads.clone();
+        executor.block_on_all([async move {
+            let pause = Duration::from_millis(2);
+            let mut stream = from_blocking_iter(counting_iter(pause, iter_reads), 2);
+            stream.next().await;
+            drop(stream);
+        }]);
+        std::thread::sleep(Duration::from_millis(20));
+        let stopped_at = reads.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(reads.load(Ordering::SeqCst), stopped_at);
+        assert!(stopped_at <= 4);
+    }
+
+    /// Hands out its items every other poll, like a channel whose sender
+    /// keeps up, counting those taken.
+    struct Popped {
+        items: VecDeque<Result<u32, &'static str>>,
+        taken: Arc<AtomicUsize>,
+        ready: bool,
+    }
+    fn popped(items: impl IntoIterator<Item = Result<u32, &'static str>>) -> Popped {
+        Popped {
+            items: items.into_iter().collect(),
+            taken: Arc::new(AtomicUsize::new(0)),
+            ready: false,
+        }
+    }
+    impl Stream for Popped {
+        type Item = Result<u32, &'static str>;
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let item = self.items.pop_front();
+            if item.is_some() {
+                self.taken.fetch_add(1, Ordering::Relaxed);
+            }
+            Poll::Ready(item)
+        }
+    }
+
+    #[test]
+    fn try_collect_stops_at_the_first_error() {
+        let items = (1..=4).map(Ok).chain([Err("fifth")]).chain((6..10).map(Ok));
+        let stream = popped(items);
+        let taken = stream.taken.clone();
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([stream.try_collect::<_, _, Vec<_>>()]);
+        assert_eq!(outputs, [Err("fifth")]);
+        assert_eq!(taken.load(Ordering::Relaxed), 5);
+
+        let outputs =
+            executor.block_on_all([popped((1..=4).map(Ok)).try_collect::<_, _, Vec<_>>()]);
+        assert_eq!(outputs, [Ok(vec![1, 2, 3, 4])]);
+    }
+
+    #[test]
+    fn try_next_and_collect_into_a_result() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let mut stream = popped((1..=3).map(Ok));
+            let first = stream.try_next().await;
+            let rest: Result<Vec<_>, _> = stream.collect().await;
+            let failed: Result<Vec<_>, _> = popped([Ok(1), Err("bad"), Ok(3)]).collect().await;
+            let mut empty = popped([]);
+            (first, rest, failed, empty.try_next().await)
+        }]);
+        assert_eq!(
+            outputs,
+            [(Ok(Some(1)), Ok(vec![2, 3]), Err("bad"), Ok(None))]
+        );
+    }
+
+    #[test]
+    fn try_for_each_stops_at_the_error_of_its_closure() {
+        let stream = popped((1..10).map(Ok));
+        let taken = stream.taken.clone();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([stream.try_for_each(move |item| {
+            log.lock().unwrap().push(item);
+            async move {
+                if item == 3 {
+                    Err("three")
+                } else {
+                    Ok(())
+                }
+            }
+        })]);
+        assert_eq!(outputs, [Err("three")]);
+        assert_eq!(*seen.lock().unwrap(), [1, 2, 3]);
+        assert_eq!(taken.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn throttle_spaces_items_out_without_dropping_any() {
+        let ms = Duration::from_millis;
+        let stream = delayed([(0, 1), (0, 2), (0, 3), (50, 4), (0, 5)]);
+        let items = collect_timed(stream.throttle(ms(20)));
+        // A gap already past by the next item holds nothing back.
+        let expected = [
+            (ms(0), 1),
+            (ms(20), 2),
+            (ms(40), 3),
+            (ms(90), 4),
+            (ms(110), 5),
+        ];
+        assert_eq!(items, expected);
+    }
+
+    #[test]
+    fn debounce_yields_the_last_item_of_each_burst() {
+        let stream = delayed([(0, 1), (0, 2), (0, 3), (80, 4), (5, 5), (80, 6)]);
+        let ms = Duration::from_millis;
+        let items = collect_timed(stream.debounce(ms(40)));
+        // The last one comes out as the stream ends, quiet or not.
+        assert_eq!(items, [(ms(40), 3), (ms(125), 5), (ms(165), 6)]);
+    }
+
+    #[test]
+    fn debounce_flushes_its_item_when_the_stream_ends() {
+        let stream = delayed([(0, 1), (0, 2)]);
+        let items = collect_timed(stream.debounce(Duration::from_secs(3600)));
+        assert_eq!(items, [(Duration::ZERO, 2)]);
+
+        // A quiet period too long to add to an `Instant` saturates.
+        let stream = delayed([(0, 1), (0, 2)]);
+        assert_eq!(collect_all(stream.debounce(Duration::MAX)), [2]);
+    }
+
+    #[test]
+    fn futures_ordered_yields_in_queue_order_concurrently() {
+        let executor = Executor::new();
+        let started = Instant::now();
+        let outputs = executor.block_on_all([async {
+            let ordered: FuturesOrdered<_> = (1..=5u64)
+                .map(|i| {
+                    Box::pin(async move {
+                        TimerFuture::new(Duration::from_millis(60 - 10 * i)).await;
+                        i
+                    })
+                })
+                .collect();
+            ordered.collect::<Vec<_>>().await
+        }]);
+        let elapsed = started.elapsed();
+        assert_eq!(outputs, [vec![1, 2, 3, 4, 5]]);
+        // The longest timer, not the sum of them.
+        assert!(elapsed >= Duration::from_millis(50), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(120), "{elapsed:?}");
+    }
+
+    #[test]
+    fn futures_ordered_only_wakes_for_its_front() {
+        let mut task = MockTask::new();
+        let (first, first_done) = completion();
+        let (second, second_done) = completion();
+        let mut ordered: FuturesOrdered<_> = [first_done, second_done].into_iter().collect();
+        assert_pending!(poll_next(&mut task, &mut ordered));
+
+        second.complete("second").unwrap();
+        assert!(!task.is_woken());
+        first.complete("first").unwrap();
+        assert!(task.is_woken());
+
+        assert_ready_eq!(poll_next(&mut task, &mut ordered), Some(Ok("first")));
+        assert_ready_eq!(poll_next(&mut task, &mut ordered), Some(Ok("second")));
+        assert_ready_eq!(poll_next(&mut task, &mut ordered), None);
+    }
+
+    #[test]
+    fn futures_ordered_buffers_outputs_behind_its_front() {
+        let mut task = MockTask::new();
+        let (first, first_done) = completion::<u32>();
+        let mut ordered = FuturesOrdered::new();
+        ordered.push_back(first_done);
+        for i in 2..=4 {
+            ordered.push_back(completion_of(i));
+        }
+        assert_pending!(poll_next(&mut task, &mut ordered));
+        assert_eq!(ordered.buffered(), 3);
+        assert_eq!(ordered.len(), 4);
+
+        first.complete(1).unwrap();
+        let mut outputs = Vec::new();
+        while let Poll::Ready(Some(output)) = poll_next(&mut task, &mut ordered) {
+            outputs.push(output.unwrap());
+        }
+        assert_eq!(outputs, [1, 2, 3, 4]);
+        assert_eq!(ordered.buffered(), 0);
+    }
+
+    /// Member of a set: either waking itself at every poll, never ready, or
+    /// yielding its items on schedule.
+    enum Member {
+        Hot,
+        Timed(Delayed<usize>),
+    }
+    impl Stream for Member {
+        type Item = usize;
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<usize>> {
+            match self.get_mut() {
+                Member::Hot => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+                Member::Timed(stream) => Pin::new(stream).poll_next(cx),
+            }
+        }
+    }
+
+    #[test]
+    fn a_self_waking_member_does_not_starve_the_others() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let started = Instant::now();
+            let timed = (0..10).map(|i| Member::Timed(delayed([(10 * (i as u64 + 1), i)])));
+            let mut select = select_all(std::iter::once(Member::Hot).chain(timed));
+            let mut late = Vec::new();
+            for expected in 0..10 {
+                assert_eq!(select.next().await, Some(expected));
+                let due = Duration::from_millis(10 * (expected as u64 + 1));
+                if started.elapsed() > due + Duration::from_millis(50) {
+                    late.push((expected, started.elapsed()));
+                }
+            }
+            (late, select.ready.yields.load(Ordering::Relaxed))
+        }]);
+        let (late, yields) = outputs.into_iter().next().unwrap();
+        assert!(late.is_empty(), "{late:?}");
+        // Every poll of the set left the hot member queued for the next.
+        assert!(yields > 0);
+    }
+
+    fn poll_next<S: Stream + Unpin>(task: &mut MockTask, stream: &mut S) -> Poll<Option<S::Item>> {
+        task.poll(Pin::new(&mut stream.next()))
+    }
+
+    /// A completion given `value` already.
+    fn completion_of<T>(value: T) -> Completion<T> {
+        let (completer, completion) = completion();
+        completer.complete(value).unwrap_or_else(|_| unreachable!());
+        completion
+    }
+}
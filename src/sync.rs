@@ -0,0 +1,502 @@
+use std::{
+    convert::Infallible,
+    fmt::{self, Debug},
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{Mutex, OnceLock},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    counters::clone_waker,
+    lock,
+    task::{tag_wakes, wake_all, Waiters},
+};
+
+/* Event */
+/// Manual-reset event: a latch that tasks wait on until it is set.
+///
+/// Unlike a notification, being set is a persistent state rather than a
+/// permit used up by a waiter: every waiter is released, and later waits
+/// complete immediately until `reset` is called.
+pub struct Event {
+    state: Mutex<EventState>,
+}
+
+struct EventState {
+    set: bool,
+    /// Bumped by every `set`, so a waiter released by it completes even if
+    /// the event is reset before the waiter gets polled again.
+    generation: u64,
+    /// Wakers of the current generation's waiters, indexed by their slot; a
+    /// dropped waiter leaves `None` behind.
+    waiters: Vec<Option<Waker>>,
+}
+
+impl Event {
+    /// Create an event which is not set.
+    pub fn new() -> Self {
+        Event {
+            state: Mutex::new(EventState {
+                set: false,
+                generation: 0,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+    /// Wait until the event is set; completes right away if it already is.
+    ///
+    /// Cancel safe: nothing is consumed, dropping it only deregisters.
+    pub fn wait(&self) -> Wait<'_> {
+        Wait {
+            event: self,
+            slot: None,
+        }
+    }
+    /// Set the event, releasing every current waiter.
+    pub fn set(&self) {
+        let waiters = {
+            let mut state = lock(&self.state);
+            if state.set {
+                return;
+            }
+            state.set = true;
+            state.generation += 1;
+            mem::take(&mut state.waiters)
+        };
+        // Woken outside the lock, so waiters polled right away do not block.
+        tag_wakes("Event::set", || wake_all(waiters.into_iter().flatten()));
+    }
+    /// Clear the event, so waits started from now on wait for the next `set`.
+    pub fn reset(&self) {
+        lock(&self.state).set = false;
+    }
+    pub fn is_set(&self) -> bool {
+        lock(&self.state).set
+    }
+}
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Event")
+            .field("set", &self.is_set())
+            .finish()
+    }
+}
+
+/// Future returned by `Event::wait`.
+pub struct Wait<'a> {
+    event: &'a Event,
+    /// Generation and index of this waiter's waker, once registered.
+    slot: Option<(u64, usize)>,
+}
+impl Future for Wait<'_> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.event.state);
+        if state.set {
+            return Poll::Ready(());
+        }
+        match self.slot {
+            Some((generation, _)) if generation != state.generation => Poll::Ready(()),
+            Some((_, index)) => {
+                let waker = &mut state.waiters[index];
+                if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                    *waker = Some(clone_waker(cx.waker()));
+                }
+                Poll::Pending
+            }
+            None => {
+                let slot = (state.generation, state.waiters.len());
+                state.waiters.push(Some(clone_waker(cx.waker())));
+                drop(state);
+                self.slot = Some(slot);
+                Poll::Pending
+            }
+        }
+    }
+}
+impl Drop for Wait<'_> {
+    fn drop(&mut self) {
+        let Some((generation, index)) = self.slot else {
+            return;
+        };
+        let mut state = lock(&self.event.state);
+        if generation == state.generation {
+            state.waiters[index] = None;
+        }
+    }
+}
+impl Debug for Wait<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wait").finish_non_exhaustive()
+    }
+}
+
+/* OnceCell */
+/// Cell written once, by the first task to await `get_or_init`, while the
+/// others wait for that value.
+pub struct OnceCell<T> {
+    value: OnceLock<T>,
+    init: Mutex<InitState>,
+}
+
+struct InitState {
+    /// Set while some task runs its initializer.
+    running: bool,
+    /// Tasks waiting for that initializer to end.
+    waiters: Waiters,
+}
+
+impl<T> OnceCell<T> {
+    /// Create an empty cell.
+    pub fn new() -> Self {
+        OnceCell {
+            value: OnceLock::new(),
+            init: Mutex::new(InitState {
+                running: false,
+                waiters: Waiters::default(),
+            }),
+        }
+    }
+    pub fn get(&self) -> Option<&T> {
+        self.value.get()
+    }
+    /// Store `value` unless the cell is already full, in which case it is
+    /// handed back.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        self.value.set(value)?;
+        self.wake_waiters();
+        Ok(())
+    }
+    /// Get the value, initializing the cell with the output of `init` if it
+    /// is empty.
+    ///
+    /// Of all the tasks racing here, only one runs its initializer while the
+    /// others wait. Should that task be cancelled halfway through, one of
+    /// them takes over with its own.
+    pub async fn get_or_init<F, Fut>(&self, init: F) -> &T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let init = || async { Ok::<_, Infallible>(init().await) };
+        match self.get_or_try_init(init).await {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+    /// Like `get_or_init`, but an initializer that fails leaves the cell
+    /// empty, for a later call to try again, and its error is returned.
+    pub async fn get_or_try_init<F, Fut, E>(&self, init: F) -> Result<&T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut init = Some(init);
+        loop {
+            if let Some(value) = self.get() {
+                return Ok(value);
+            }
+            if self.start_init() {
+                // Hands over to a waiter if this future is dropped mid-init.
+                let _running = InitGuard { cell: self };
+                let init = init.take().expect("initializer already ran");
+                let value = init().await?;
+                // Can only fail if `set` got there first, which wins.
+                let _ = self.value.set(value);
+                return Ok(self.get().unwrap());
+            }
+            InitDone {
+                cell: self,
+                waker: None,
+            }
+            .await;
+        }
+    }
+    /// Claim the right to run an initializer, if nobody else is.
+    fn start_init(&self) -> bool {
+        let mut init = lock(&self.init);
+        !mem::replace(&mut init.running, true)
+    }
+    fn wake_waiters(&self) {
+        let waiters = lock(&self.init).waiters.take();
+        tag_wakes("OnceCell", || wake_all(waiters));
+    }
+}
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T: Debug> Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OnceCell").field(&self.get()).finish()
+    }
+}
+
+/// Ends an initializer's run, however it ends, and lets the waiters look at
+/// the cell again.
+struct InitGuard<'a, T> {
+    cell: &'a OnceCell<T>,
+}
+impl<T> Drop for InitGuard<'_, T> {
+    fn drop(&mut self) {
+        lock(&self.cell.init).running = false;
+        self.cell.wake_waiters();
+    }
+}
+
+/// Waits for the initializer running in another task to end.
+struct InitDone<'a, T> {
+    cell: &'a OnceCell<T>,
+    /// Registered in the cell's `Waiters`.
+    waker: Option<Waker>,
+}
+impl<T> Future for InitDone<'_, T> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut init = lock(&this.cell.init);
+        if !init.running || this.cell.get().is_some() {
+            this.waker = None;
+            return Poll::Ready(());
+        }
+        init.waiters.register(&mut this.waker, cx.waker());
+        Poll::Pending
+    }
+}
+impl<T> Drop for InitDone<'_, T> {
+    fn drop(&mut self) {
+        // A waiter cancelled before the initializer ended, for instance by
+        // aborting its task, leaves no waker behind.
+        if self.waker.is_some() {
+            lock(&self.cell.init).waiters.remove(&mut self.waker);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        assert_pending, assert_ready,
+        future::pending,
+        test_util::{CountingScheduler, MockTask},
+        Builder, Executor, Handle, TimerFuture,
+    };
+
+    #[test]
+    fn waiters_before_set_all_wake() {
+        let event = Event::new();
+        let mut waiters: Vec<_> = (0..1000)
+            .map(|_| (MockTask::new(), Box::pin(event.wait())))
+            .collect();
+        for (task, wait) in &mut waiters {
+            assert_pending!(task.poll(wait.as_mut()));
+        }
+        event.set();
+        for (task, wait) in &mut waiters {
+            assert!(task.woken_after_poll());
+            assert_ready!(task.poll(wait.as_mut()));
+        }
+    }
+
+    #[test]
+    fn waiter_after_set_returns_at_once() {
+        let event = Event::new();
+        event.set();
+        let mut task = MockTask::new();
+        assert_ready!(task.poll(pin!(event.wait())));
+        assert!(!task.is_woken());
+        assert!(event.is_set());
+    }
+
+    #[test]
+    fn reset_only_blocks_new_waiters() {
+        let event = Event::new();
+        let (mut early, mut late) = (MockTask::new(), MockTask::new());
+        let mut released = pin!(event.wait());
+        assert_pending!(early.poll(released.as_mut()));
+        event.set();
+        event.reset();
+        assert!(!event.is_set());
+        // Released by the `set`, though only polled after the `reset`.
+        assert_ready!(early.poll(released.as_mut()));
+        let mut blocked = pin!(event.wait());
+        assert_pending!(late.poll(blocked.as_mut()));
+        event.set();
+        assert!(late.woken_after_poll());
+        assert_ready!(late.poll(blocked.as_mut()));
+    }
+
+    #[test]
+    fn racing_tasks_initialize_once() {
+        let executor = Executor::new();
+        let cell = Arc::new(OnceCell::new());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..10)
+            .map(|i| {
+                let (cell, runs) = (cell.clone(), runs.clone());
+                executor.spawn(async move {
+                    *cell
+                        .get_or_init(|| async {
+                            runs.fetch_add(1, Ordering::Relaxed);
+                            TimerFuture::new(Duration::from_millis(10)).await;
+                            i
+                        })
+                        .await
+                })
+            })
+            .collect();
+        executor.run();
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        let winner = *cell.get().unwrap();
+        for handle in handles {
+            assert_eq!(handle.join_blocking().unwrap(), winner);
+        }
+    }
+
+    #[test]
+    fn failed_initializer_leaves_the_cell_empty() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let cell = OnceCell::new();
+            let failed = cell.get_or_try_init(|| async { Err("unreachable") }).await;
+            assert_eq!(failed, Err("unreachable"));
+            assert!(cell.get().is_none());
+            let retried = cell.get_or_try_init(|| async { Ok::<_, &str>(3) }).await;
+            (retried.copied(), cell.get().copied())
+        }]);
+        assert_eq!(outputs, [(Ok(3), Some(3))]);
+    }
+
+    #[test]
+    fn waiter_takes_over_from_a_cancelled_initializer() {
+        let executor = Executor::new();
+        let cell = Arc::new(OnceCell::new());
+        let stuck = executor.spawn({
+            let cell = cell.clone();
+            async move { *cell.get_or_init(pending::<&str>).await }
+        });
+        let waiter = executor.spawn({
+            let cell = cell.clone();
+            async move { *cell.get_or_init(|| async { "waiter" }).await }
+        });
+        let stuck = stuck.abort_on_drop();
+        executor.spawn(async move {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            drop(stuck);
+        });
+        executor.run();
+        assert_eq!(waiter.join_blocking().unwrap(), "waiter");
+        assert_eq!(cell.get(), Some(&"waiter"));
+    }
+
+    #[test]
+    fn aborted_waiters_leave_nothing_behind() {
+        let executor = Executor::new();
+        let event = Arc::new(Event::new());
+        let gate = Arc::new(Event::new());
+        let cell = Arc::new(OnceCell::new());
+        let (event2, gate2, cell2) = (event.clone(), gate.clone(), cell.clone());
+        executor.block_on_all([async move {
+            let handle = crate::Handle::current();
+            let initializer = {
+                let cell = cell2.clone();
+                handle.spawn(async move {
+                    *cell
+                        .get_or_init(|| async move {
+                            gate2.wait().await;
+                            "initializer"
+                        })
+                        .await
+                })
+            };
+            let waiting = {
+                let event = event2.clone();
+                handle.spawn(async move { event.wait().await })
+            };
+            let initializing = {
+                let cell = cell2.clone();
+                handle.spawn(async move { *cell.get_or_init(|| async { "aborted" }).await })
+            };
+            let sleeping = handle.spawn(TimerFuture::new(Duration::from_secs(3600)));
+            TimerFuture::new(Duration::from_millis(20)).await;
+            assert!(event2.state.lock().unwrap().waiters[0].is_some());
+            assert_eq!(cell2.init.lock().unwrap().waiters.len(), 1);
+            assert_eq!(crate::time::driver_stats().pending, 1);
+
+            waiting.abort();
+            initializing.abort();
+            sleeping.abort();
+            TimerFuture::new(Duration::from_millis(20)).await;
+            let waiters = event2.state.lock().unwrap().waiters.clone();
+            assert!(waiters.iter().all(Option::is_none));
+            assert!(cell2.init.lock().unwrap().waiters.is_empty());
+            assert_eq!(crate::time::driver_stats().pending, 0);
+
+            // The initializer was not the aborted waiter's to take.
+            event2.set();
+            gate.set();
+            assert_eq!(initializer.await.unwrap(), "initializer");
+        }]);
+        assert_eq!(cell.get(), Some(&"initializer"));
+        assert!(event.is_set());
+    }
+
+    #[test]
+    fn setting_an_event_queues_every_waiter_at_once() {
+        const WAITERS: usize = 10_000;
+        let scheduler = CountingScheduler::default();
+        let schedules = scheduler.schedules.clone();
+        let executor = Builder::new().scheduler(scheduler).build();
+        let event = Arc::new(Event::new());
+        let woken = Arc::new(AtomicUsize::new(0));
+        for _ in 0..WAITERS {
+            let (event, woken) = (event.clone(), woken.clone());
+            drop(executor.spawn(async move {
+                event.wait().await;
+                woken.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        // Polled after every waiter waits, and each after the one before.
+        let before = Arc::new(AtomicUsize::new(0));
+        let setter = executor.spawn({
+            let (schedules, before) = (schedules.clone(), before.clone());
+            async move {
+                before.store(schedules.load(Ordering::SeqCst), Ordering::SeqCst);
+                let wakes = Handle::current().metrics();
+                event.set();
+                wakes.wakes_same_thread
+            }
+        });
+        let checker = executor.spawn({
+            let schedules = schedules.clone();
+            async move { schedules.load(Ordering::SeqCst) - before.load(Ordering::SeqCst) }
+        });
+        executor.run();
+
+        assert_eq!(checker.join_blocking().unwrap(), 1);
+        assert_eq!(woken.load(Ordering::SeqCst), WAITERS);
+        let wakes_before = setter.join_blocking().unwrap();
+        assert_eq!(
+            executor.metrics().wakes_same_thread - wakes_before,
+            WAITERS as u64
+        );
+    }
+}
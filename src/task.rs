@@ -0,0 +1,2767 @@
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    fmt::{self, Debug, Display},
+    future::Future,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop, MaybeUninit},
+    ops::{Deref, DerefMut},
+    panic::{self, AssertUnwindSafe, Location},
+    pin::Pin,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    builder::PanicBehavior,
+    child::Group,
+    coop,
+    counters::clone_waker,
+    handlers,
+    interop::BoxFuture,
+    lock,
+    progress::{ProgressReceiver, ProgressSender},
+    time::TimerFuture,
+    Handle, Inner,
+};
+
+pub mod raw;
+
+/// Spawn a task onto the executor running the current task.
+///
+/// Panics when called outside of `Executor::run` or `Handle::enter`; use
+/// `Handle::spawn` there.
+#[track_caller]
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Handle::current().spawn(future)
+}
+
+/// Spawn a task whose error, if it returns one, goes to the error handler,
+/// onto the executor running the current task. See `Handle::spawn_logged`.
+#[track_caller]
+pub fn spawn_logged<F, E>(future: F) -> JoinHandle<()>
+where
+    F: Future<Output = Result<(), E>> + Send + 'static,
+    E: Display,
+{
+    Handle::current().spawn_logged(future)
+}
+
+/// Run a blocking closure on the blocking pool of the executor running the
+/// current task. See `Handle::spawn_blocking`.
+#[track_caller]
+pub fn spawn_blocking<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Handle::current().spawn_blocking(f)
+}
+
+/// Spawn a task whose future is built by `make` on the thread running it,
+/// onto the executor running the current task. See `Handle::spawn_fn`.
+#[track_caller]
+pub fn spawn_fn<F, Fut>(make: F) -> JoinHandle<Fut::Output>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future + 'static,
+    Fut::Output: Send + 'static,
+{
+    Handle::current().spawn_fn(make)
+}
+
+/// Spawn a task built by `f` from its `TaskContext`, onto the executor
+/// running the current task. See `Handle::spawn_with_context`.
+#[track_caller]
+pub fn spawn_with_context<F, Fut>(f: F) -> JoinHandle<Fut::Output>
+where
+    F: FnOnce(TaskContext) -> Fut,
+    Fut: Future + Send + 'static,
+    Fut::Output: Send + 'static,
+{
+    Handle::current().spawn_with_context(f)
+}
+
+/// Spawn a task reporting progress through the `ProgressSender` given to
+/// `f`, onto the executor running the current task. See
+/// `Handle::spawn_with_progress`.
+#[track_caller]
+pub fn spawn_with_progress<F, Fut, P>(f: F) -> (JoinHandle<Fut::Output>, ProgressReceiver<P>)
+where
+    F: FnOnce(ProgressSender<P>) -> Fut,
+    Fut: Future + Send + 'static,
+    Fut::Output: Send + 'static,
+    P: Send + 'static,
+{
+    Handle::current().spawn_with_progress(f)
+}
+
+/// A spawned future, as seen by a `Scheduler`.
+pub struct Task {
+    /// In-progress future that should be pushed to completion.
+    ///
+    /// The `Mutex` is not necessary for correctness, since we only have
+    /// one thread executing tasks at once. However, Rust isn't smart
+    /// enough to know that `future` is only mutated from one thread,
+    /// so we need to use the `Mutex` to prove thread-safety. A production
+    /// executor would not need this, and could use `UnsafeCell` instead.
+    ///
+    /// The future may be stored inline, so it is polled and dropped in place:
+    /// it must never be moved out of the slot.
+    pub(crate) future: Mutex<Option<TaskFuture>>,
+
+    /// Where the output waits for the `JoinHandle`, in the task allocation
+    /// rather than one of its own.
+    join: Mutex<JoinState>,
+
+    pub(crate) meta: TaskMeta,
+
+    /// The executor this task is scheduled on when woken.
+    executor: Arc<Inner>,
+
+    /// Set by the executor once the future has returned `Ready`, after its
+    /// output was handed to the `JoinState`.
+    pub(crate) finished: AtomicBool,
+
+    /// Set by `JoinHandle::abort`; the executor drops the future instead of
+    /// polling it the next time the task comes out of the queue.
+    pub(crate) aborted: AtomicBool,
+
+    /// Set once the task holds one of the slots of
+    /// `Builder::max_concurrent_tasks`.
+    pub(crate) admitted: AtomicBool,
+
+    /// Waker handed to the future instead of the task itself, with
+    /// `Builder::detect_lost_wakes`.
+    pub(crate) wake_tracker: Option<Arc<WakeTracker>>,
+
+    /// Scheduling decision since which the task waits to be polled, or 0,
+    /// with `Builder::max_schedule_age`.
+    pub(crate) runnable_since: AtomicU64,
+
+    /// Registered with `defer`, until taken as the task finishes.
+    cleanups: Mutex<Option<Vec<Cleanup>>>,
+}
+impl Task {
+    pub fn meta(&self) -> &TaskMeta {
+        &self.meta
+    }
+
+    /// Wrap `future` into a task and the handle to its output.
+    pub(crate) fn new<F>(
+        future: F,
+        meta: TaskMeta,
+        executor: Arc<Inner>,
+    ) -> (Arc<Task>, JoinHandle<F::Output>)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let isolate = executor.panic_behavior == PanicBehavior::Isolate;
+        let task = Arc::new_cyclic(|this| Task {
+            future: Mutex::new(Some(TaskFuture::new(Harness {
+                future,
+                task: this.clone(),
+                done: false,
+                isolate,
+            }))),
+            join: Mutex::new(JoinState {
+                output: None,
+                waker: None,
+                detached: false,
+            }),
+            finished: AtomicBool::new(false),
+            aborted: AtomicBool::new(false),
+            admitted: AtomicBool::new(false),
+            wake_tracker: executor
+                .detect_lost_wakes
+                .then(|| Arc::new(WakeTracker::new(this.clone()))),
+            runnable_since: AtomicU64::new(0),
+            cleanups: Mutex::new(Some(Vec::new())),
+            meta,
+            executor,
+        });
+        let handle = JoinHandle {
+            task: task.clone(),
+            output: PhantomData,
+        };
+        (task, handle)
+    }
+    /// Register a cleanup, run right away if the task finished already.
+    fn defer(self: &Arc<Self>, cleanup: Cleanup) {
+        let mut cleanups = lock(&self.cleanups);
+        match &mut *cleanups {
+            Some(cleanups) => cleanups.push(cleanup),
+            None => {
+                drop(cleanups);
+                self.run_cleanups(vec![cleanup]);
+            }
+        }
+    }
+    /// Start the cleanups of a task which just finished, see `defer`.
+    pub(crate) fn finish_cleanups(self: &Arc<Self>) {
+        let cleanups = lock(&self.cleanups).take().unwrap_or_default();
+        if !cleanups.is_empty() {
+            self.run_cleanups(cleanups);
+        }
+    }
+    fn run_cleanups(self: &Arc<Self>, cleanups: Vec<Cleanup>) {
+        let meta = TaskMeta::new(
+            Some("defer".to_string()),
+            Priority::Normal,
+            self.meta.location(),
+        );
+        let run = RunCleanups {
+            owner: self.clone(),
+            pending: cleanups,
+            current: None,
+            timeout: self.executor.defer_timeout,
+        };
+        // Cancelled at once if the executor is shutting down, which drives
+        // the cleanups on the spot.
+        drop(self.executor.spawn(meta, run));
+    }
+    /// See `JoinHandle::abort`.
+    pub(crate) fn abort(self: &Arc<Self>) {
+        if self.finished.load(Ordering::Acquire) || self.aborted.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.wake_by_ref();
+    }
+}
+/// A wake arriving once the task finished, or once its executor shut down,
+/// does nothing: the task would never be polled again, only kept alive by
+/// the queue. Wakers can thus safely outlive both.
+///
+/// One arriving earlier, e.g. from within the poll which then completes,
+/// leaves the task in the queue: it is skipped when dequeued, its future
+/// being gone. The task itself is freed with the last of its queue entries,
+/// wakers and `JoinHandle`.
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.finished.load(Ordering::Acquire) || self.executor.is_shut_down() {
+            return;
+        }
+        if let Some(log) = &self.meta.wake_log {
+            log.record();
+        }
+        let batched = WAKE_BATCH.with(|batch| match &mut *batch.borrow_mut() {
+            Some(tasks) => {
+                tasks.push(self.clone());
+                true
+            }
+            None => false,
+        });
+        if !batched {
+            self.executor.wake(self.clone());
+        }
+    }
+}
+impl Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task {}", self.meta.id)
+    }
+}
+
+/* WakeTracker */
+/// Waker of a task which notices the task going dark: a poll returning
+/// `Pending` without the future waking it or keeping any clone of it, see
+/// `Builder::detect_lost_wakes`.
+///
+/// The same tracker is handed out at every poll, so clones kept from an
+/// earlier poll still count, and `will_wake` holds across polls.
+pub(crate) struct WakeTracker {
+    task: Weak<Task>,
+    /// Set by a wake since the start of the latest poll.
+    woken: AtomicBool,
+    /// Set once the task was reported, to report it only once.
+    reported: AtomicBool,
+}
+impl WakeTracker {
+    fn new(task: Weak<Task>) -> Self {
+        WakeTracker {
+            task,
+            woken: AtomicBool::new(false),
+            reported: AtomicBool::new(false),
+        }
+    }
+    /// The waker for the next poll.
+    pub(crate) fn waker(self: &Arc<Self>) -> Waker {
+        self.woken.store(false, Ordering::SeqCst);
+        Waker::from(self.clone())
+    }
+    /// Report the task to the error handler if the poll which just returned
+    /// `Pending` left it with no way to be woken. `poll_waker` is the waker
+    /// that poll got.
+    pub(crate) fn check(self: &Arc<Self>, poll_waker: Waker, meta: &TaskMeta) {
+        drop(poll_waker);
+        // Only the task itself still refers to the tracker: no clone is left.
+        let lost = Arc::strong_count(self) == 1 && !self.woken.load(Ordering::SeqCst);
+        if lost && !self.reported.swap(true, Ordering::Relaxed) {
+            handlers::failed(
+                meta,
+                &"returned Pending without waking or keeping its waker, it will likely never \
+                  be polled again",
+            );
+        }
+    }
+}
+impl Wake for WakeTracker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        if let Some(task) = self.task.upgrade() {
+            task.wake_by_ref();
+        }
+    }
+}
+
+/* WakeBatch */
+thread_local! {
+    /// Tasks woken from within `wake_all` on this thread, to be scheduled
+    /// together once it is done.
+    static WAKE_BATCH: RefCell<Option<Vec<Arc<Task>>>> = const { RefCell::new(None) };
+}
+
+/// Wake every waker of `wakers`, scheduling the tasks of this crate's
+/// executors among them with one queue operation and one unpark per
+/// executor; other wakers are woken one by one, as usual.
+pub(crate) fn wake_all(wakers: impl IntoIterator<Item = Waker>) {
+    batch_wakes(|| {
+        for waker in wakers {
+            waker.wake();
+        }
+    })
+}
+
+/// Run `f`, scheduling the tasks it wakes as `wake_all` does once it
+/// returned or panicked.
+pub(crate) fn batch_wakes<R>(f: impl FnOnce() -> R) -> R {
+    let nested = WAKE_BATCH.with(|batch| {
+        let mut batch = batch.borrow_mut();
+        let nested = batch.is_some();
+        batch.get_or_insert_with(Vec::new);
+        nested
+    });
+    // A waker waking more from within, or panicking, leaves the batch to
+    // whoever opened it.
+    let _flush = (!nested).then_some(FlushWakeBatch);
+    f()
+}
+
+/// Schedules the tasks of the `wake_all` batch once it is done, even if a
+/// waker panicked.
+struct FlushWakeBatch;
+impl Drop for FlushWakeBatch {
+    fn drop(&mut self) {
+        let mut tasks = WAKE_BATCH.with(|batch| batch.take()).unwrap_or_default();
+        // Almost always a single executor.
+        while let Some(first) = tasks.first() {
+            let executor = first.executor.clone();
+            let (own, rest) = tasks
+                .into_iter()
+                .partition(|task| Arc::ptr_eq(&task.executor, &executor));
+            executor.wake_batch(own);
+            tasks = rest;
+        }
+    }
+}
+
+/* Waiters */
+/// Wakers of the tasks waiting on a primitive, such as a `Limiter`'s
+/// permits, each in once however often its future is polled.
+///
+/// A waiting future keeps the waker it registered, to take it back with
+/// `remove` if it is dropped or ready before being woken.
+#[derive(Default)]
+pub(crate) struct Waiters {
+    wakers: Vec<Waker>,
+}
+impl Waiters {
+    /// Register `waker` unless it is in already, keeping it in `registered`.
+    pub(crate) fn register(&mut self, registered: &mut Option<Waker>, waker: &Waker) {
+        if !self.wakers.iter().any(|w| w.will_wake(waker)) {
+            self.wakers.push(clone_waker(waker));
+            *registered = Some(clone_waker(waker));
+        }
+    }
+    /// Take back the waker of `registered`, if still in.
+    pub(crate) fn remove(&mut self, registered: &mut Option<Waker>) {
+        let Some(waker) = registered.take() else {
+            return;
+        };
+        if let Some(index) = self.wakers.iter().position(|w| w.will_wake(&waker)) {
+            self.wakers.swap_remove(index);
+        }
+    }
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.wakers.len()
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.wakers.is_empty()
+    }
+    /// Take every waker, to wake with `wake_all` once the lock is released.
+    pub(crate) fn take(&mut self) -> Vec<Waker> {
+        mem::take(&mut self.wakers)
+    }
+}
+
+/* TaskMeta */
+/// Unique identifier of a task, never reused within a process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(u64);
+impl TaskId {
+    /// Stands for no task, e.g. for allocations made outside of any, see
+    /// `alloc_hooks::record`.
+    pub const NONE: TaskId = TaskId(0);
+
+    fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+impl Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How urgent a task is; only meaningful to schedulers that look at it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Description of a task, as handed to the executor's lifecycle hooks.
+pub struct TaskMeta {
+    id: TaskId,
+    name: Option<String>,
+    priority: Priority,
+    location: &'static Location<'static>,
+    spawned: Instant,
+    pub(crate) first_polled: OnceLock<Instant>,
+    /// Nanoseconds from `spawned` to the start of the latest poll, plus one
+    /// so that zero means never polled.
+    last_polled: AtomicU64,
+    pub(crate) completed: OnceLock<Instant>,
+    pub(crate) spawn_backtrace: Option<Arc<Backtrace>>,
+    pub(crate) stats: Option<TaskCounters>,
+    pub(crate) wake_log: Option<WakeLog>,
+    /// Set for the executor's own housekeeping tasks, which do not count as
+    /// alive.
+    pub(crate) background: bool,
+    /// The `ChildRuntime` the task belongs to, if any.
+    pub(crate) group: Option<Arc<Group>>,
+    /// Set by `Handle::spawn_with_deadline`.
+    deadline: Option<Instant>,
+}
+impl TaskMeta {
+    pub(crate) fn new(
+        name: Option<String>,
+        priority: Priority,
+        location: &'static Location<'static>,
+    ) -> Self {
+        TaskMeta {
+            id: TaskId::next(),
+            name,
+            priority,
+            location,
+            spawned: Instant::now(),
+            first_polled: OnceLock::new(),
+            last_polled: AtomicU64::new(0),
+            completed: OnceLock::new(),
+            spawn_backtrace: None,
+            stats: None,
+            wake_log: None,
+            background: false,
+            group: None,
+            deadline: None,
+        }
+    }
+    pub(crate) fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+    pub(crate) fn background(mut self) -> Self {
+        self.background = true;
+        self
+    }
+    pub(crate) fn in_group(mut self, group: Arc<Group>) -> Self {
+        self.group = Some(group);
+        self
+    }
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+    /// Name given with `Handle::spawn_named`.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    /// Name of the `ChildRuntime` the task belongs to, if any.
+    pub fn runtime(&self) -> Option<&str> {
+        self.group.as_deref().map(Group::name)
+    }
+    /// Priority given with `Handle::spawn_with_priority`.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+    /// When the task should be done by, if spawned with
+    /// `Handle::spawn_with_deadline`.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+    /// Where the task was spawned from.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+    /// Full backtrace of the spawn, if built with
+    /// `Builder::capture_spawn_backtraces`. Symbols are only resolved once it
+    /// is printed.
+    pub fn spawn_backtrace(&self) -> Option<&Backtrace> {
+        self.spawn_backtrace.as_deref()
+    }
+    /// Time and polls the task took so far, if built with
+    /// `Builder::task_stats`.
+    pub fn stats(&self) -> Option<TaskStats> {
+        self.stats.as_ref().map(TaskCounters::get)
+    }
+    /// The latest wakes of the task, oldest first, if built with
+    /// `Builder::record_wakes`.
+    pub fn recent_wakes(&self) -> Vec<WakeEvent> {
+        self.wake_log
+            .as_ref()
+            .map_or_else(Vec::new, WakeLog::events)
+    }
+    pub fn spawned_at(&self) -> Instant {
+        self.spawned
+    }
+    /// When the task was first polled, if it has been yet.
+    pub fn first_polled_at(&self) -> Option<Instant> {
+        self.first_polled.get().copied()
+    }
+    /// When the latest poll of the task began, if it has been polled yet.
+    pub fn last_polled_at(&self) -> Option<Instant> {
+        match self.last_polled.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(self.spawned + Duration::from_nanos(nanos - 1)),
+        }
+    }
+    pub(crate) fn polled_at(&self, at: Instant) {
+        let nanos = at.saturating_duration_since(self.spawned).as_nanos();
+        self.last_polled.store(
+            u64::try_from(nanos).unwrap_or(u64::MAX - 1) + 1,
+            Ordering::Relaxed,
+        );
+    }
+    /// When the task completed, panicked or was aborted, if it has yet.
+    pub fn completed_at(&self) -> Option<Instant> {
+        self.completed.get().copied()
+    }
+}
+impl Debug for TaskMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskMeta")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("priority", &self.priority)
+            .field("location", &self.location)
+            .field("runtime", &self.runtime())
+            .finish()
+    }
+}
+
+/// Output slot shared between a task's `Harness` and its `JoinHandle`.
+struct JoinState {
+    /// Set once the task completed; taken by whoever reads it first.
+    output: Option<TaskOutput>,
+
+    /// The waker of the task currently awaiting the `JoinHandle`.
+    waker: Option<Waker>,
+
+    /// Set once the `JoinHandle` is dropped, after which a panic goes to
+    /// the panic handler instead.
+    detached: bool,
+}
+
+/// How a task's future ended, as reported to the executor by its `Harness`.
+pub(crate) enum Outcome {
+    Completed,
+    /// With the payload if the task was detached, for the executor to hand
+    /// to the panic handler.
+    Panicked(Option<Box<dyn Any + Send>>),
+}
+
+/// A task's type-erased `Harness`.
+///
+/// Most tasks are small async fns, so harnesses that fit are stored inline in
+/// the `Task` allocation itself rather than behind another `Box`, saving an
+/// allocation per spawn and a pointer hop per poll.
+pub(crate) enum TaskFuture {
+    Inline(InlineFuture),
+    Boxed(Pin<Box<dyn Future<Output = Outcome> + Send>>),
+}
+
+/// Largest harness stored inline by `TaskFuture`, in bytes.
+const INLINE_SIZE: usize = 64;
+/// Largest alignment of a harness stored inline; must match `InlineStorage`.
+const INLINE_ALIGN: usize = 16;
+
+#[repr(C, align(16))]
+struct InlineStorage(MaybeUninit<[u8; INLINE_SIZE]>);
+
+/// Whether a `T` can be stored in `InlineStorage`.
+const fn fits_inline<T>() -> bool {
+    mem::size_of::<T>() <= INLINE_SIZE && mem::align_of::<T>() <= INLINE_ALIGN
+}
+
+// What `Handle::spawn_boxed` relies on to keep the box as the future.
+const _: () = assert!(fits_inline::<Harness<BoxFuture<'static, ()>>>());
+
+/// A harness living in `InlineStorage`, with the functions to poll and drop
+/// it, monomorphised for its type when it was stored.
+pub(crate) struct InlineFuture {
+    storage: InlineStorage,
+    poll: unsafe fn(*mut u8, &mut Context<'_>) -> Poll<Outcome>,
+    drop: unsafe fn(*mut u8),
+}
+
+impl TaskFuture {
+    fn new<H>(harness: H) -> Self
+    where
+        H: Future<Output = Outcome> + Send + 'static,
+    {
+        if !fits_inline::<H>() {
+            return TaskFuture::Boxed(Box::pin(harness));
+        }
+        unsafe fn poll<H: Future<Output = Outcome>>(
+            harness: *mut u8,
+            cx: &mut Context<'_>,
+        ) -> Poll<Outcome> {
+            Pin::new_unchecked(&mut *harness.cast::<H>()).poll(cx)
+        }
+        unsafe fn drop<H>(harness: *mut u8) {
+            ptr::drop_in_place(harness.cast::<H>())
+        }
+        let mut storage = InlineStorage(MaybeUninit::uninit());
+        // SAFETY: the storage is big and aligned enough for `H`, as checked
+        // above. Moving it around until it is first polled is fine, as
+        // nothing is pinned yet.
+        unsafe { storage.0.as_mut_ptr().cast::<H>().write(harness) };
+        TaskFuture::Inline(InlineFuture {
+            storage,
+            poll: poll::<H>,
+            drop: drop::<H>,
+        })
+    }
+}
+impl Future for TaskFuture {
+    type Output = Outcome;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: the inline harness is structurally pinned: it is only ever
+        // polled here, and dropped in place by `InlineFuture::drop`.
+        match unsafe { self.get_unchecked_mut() } {
+            TaskFuture::Inline(inline) => unsafe {
+                (inline.poll)(inline.storage.0.as_mut_ptr().cast(), cx)
+            },
+            TaskFuture::Boxed(future) => future.as_mut().poll(cx),
+        }
+    }
+}
+impl Drop for InlineFuture {
+    fn drop(&mut self) {
+        // SAFETY: `storage` holds the harness `drop` was monomorphised for,
+        // and it is not used again.
+        unsafe { (self.drop)(self.storage.0.as_mut_ptr().cast()) }
+    }
+}
+
+/// A task's type-erased `Result<F::Output, JoinError>`, in `InlineStorage`
+/// if it fits, or else behind a `Box` made as the task completes. Only the
+/// `JoinHandle` of the task, which knows the type, takes it out.
+///
+/// Only outputs which are `Send` are stored, as `Task::new` requires.
+struct TaskOutput {
+    storage: InlineStorage,
+    drop: unsafe fn(*mut u8),
+}
+impl TaskOutput {
+    fn new<T>(output: Result<T, JoinError>) -> Self {
+        unsafe fn drop<T>(output: *mut u8) {
+            ptr::drop_in_place(output.cast::<T>())
+        }
+        let mut storage = InlineStorage(MaybeUninit::uninit());
+        let slot = storage.0.as_mut_ptr();
+        // SAFETY: the storage is big and aligned enough for the output, as
+        // checked by `fits_inline`, and always for a `Box`.
+        let drop = if fits_inline::<Result<T, JoinError>>() {
+            unsafe { slot.cast::<Result<T, JoinError>>().write(output) };
+            drop::<Result<T, JoinError>> as unsafe fn(*mut u8)
+        } else {
+            unsafe {
+                slot.cast::<Box<Result<T, JoinError>>>()
+                    .write(Box::new(output))
+            };
+            drop::<Box<Result<T, JoinError>>>
+        };
+        TaskOutput { storage, drop }
+    }
+    /// # Safety
+    ///
+    /// The output must have been made by `new::<T>`.
+    unsafe fn take<T>(self) -> Result<T, JoinError> {
+        let this = ManuallyDrop::new(self);
+        let slot = this.storage.0.as_ptr();
+        if fits_inline::<Result<T, JoinError>>() {
+            slot.cast::<Result<T, JoinError>>().read()
+        } else {
+            *slot.cast::<Box<Result<T, JoinError>>>().read()
+        }
+    }
+    /// # Safety
+    ///
+    /// As for `take`.
+    unsafe fn get<T>(&self) -> &Result<T, JoinError> {
+        let slot = self.storage.0.as_ptr();
+        if fits_inline::<Result<T, JoinError>>() {
+            &*slot.cast::<Result<T, JoinError>>()
+        } else {
+            &*slot.cast::<Box<Result<T, JoinError>>>()
+        }
+    }
+}
+impl Drop for TaskOutput {
+    fn drop(&mut self) {
+        // SAFETY: as for `InlineFuture`.
+        unsafe { (self.drop)(self.storage.0.as_mut_ptr().cast()) }
+    }
+}
+
+/// Type-erasing wrapper that runs the user future and stores its output,
+/// turning a panic into a `JoinError` instead of unwinding through the
+/// executor.
+///
+/// If the harness is dropped before the future completed, the task was
+/// aborted and the `JoinHandle` gets a cancellation error instead.
+struct Harness<F: Future> {
+    future: F,
+    /// The task the harness lives in, whose `JoinState` gets the output.
+    task: Weak<Task>,
+    done: bool,
+    /// Unset unless `PanicBehavior::Isolate`, for the executor to get the
+    /// panic itself.
+    isolate: bool,
+}
+impl<F: Future> Future for Harness<F> {
+    type Output = Outcome;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is structurally pinned, it is never moved out of
+        // the harness; `task` is not and is only used through `&`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let output = match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => return Poll::Pending,
+            Ok(Poll::Ready(value)) => Ok(value),
+            Err(payload) => Err(payload),
+        };
+        this.done = true;
+        // Whoever polls the task holds on to it.
+        let task = this.task.upgrade().expect("task polled after being freed");
+        let mut state = lock(&task.join);
+        let (outcome, waker) = match output {
+            // Nobody is to read it: dropped right away, out of the lock.
+            Ok(value) if state.detached => {
+                drop(state);
+                drop(value);
+                return Poll::Ready(Outcome::Completed);
+            }
+            Ok(value) => (Outcome::Completed, state.complete::<F::Output>(Ok(value))),
+            Err(payload) if state.detached => (Outcome::Panicked(Some(payload)), None),
+            // The executor gets the panic, the handle its message alone.
+            Err(payload) if !this.isolate => {
+                let message = JoinError::panic_message(&*payload).to_string();
+                let waker = state.complete::<F::Output>(Err(JoinError::panic(Box::new(message))));
+                (Outcome::Panicked(Some(payload)), waker)
+            }
+            Err(payload) => (
+                Outcome::Panicked(None),
+                state.complete::<F::Output>(Err(JoinError::panic(payload))),
+            ),
+        };
+        drop(state);
+        if let Some(waker) = waker {
+            tag_wakes("JoinHandle", || waker.wake());
+        }
+        Poll::Ready(outcome)
+    }
+}
+impl<F: Future> Drop for Harness<F> {
+    fn drop(&mut self) {
+        // Dropped along with a task nothing refers to, `JoinHandle` included,
+        // nobody is waiting for the cancellation.
+        let Some(task) = self.task.upgrade().filter(|_| !self.done) else {
+            return;
+        };
+        let waker = lock(&task.join).complete::<F::Output>(Err(JoinError::cancelled()));
+        if let Some(waker) = waker {
+            tag_wakes("JoinHandle", || waker.wake());
+        }
+    }
+}
+impl JoinState {
+    /// Store the output, returning the waker to wake once the lock is
+    /// released: a foreign waker panicking would otherwise poison it.
+    #[must_use]
+    fn complete<T>(&mut self, output: Result<T, JoinError>) -> Option<Waker> {
+        self.output = Some(TaskOutput::new(output));
+        self.waker.take()
+    }
+}
+
+/* TaskStats */
+/// How much executor time a task took over its life, see `TaskMeta::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TaskStats {
+    pub polls: u64,
+    /// Time spent polling it.
+    pub busy: Duration,
+    /// Time it spent scheduled, waiting in the queue to be polled.
+    pub scheduled: Duration,
+    /// Bytes allocated during its polls, with `Builder::track_task_memory`.
+    pub allocated: u64,
+    /// Bytes freed during its polls, wherever they were allocated.
+    pub freed: u64,
+}
+
+/// Counters behind `TaskStats`, updated by the executor.
+#[derive(Default)]
+pub(crate) struct TaskCounters {
+    stats: Mutex<TaskStats>,
+    /// When the task was last scheduled, until it is next polled.
+    scheduled_at: Mutex<Option<Instant>>,
+    /// Updated from within the allocator, see `alloc_hooks::record`.
+    pub(crate) allocated: AtomicU64,
+    pub(crate) freed: AtomicU64,
+}
+impl TaskCounters {
+    pub(crate) fn scheduled(&self, now: Instant) {
+        // A task scheduled twice has waited since the first time.
+        lock(&self.scheduled_at).get_or_insert(now);
+    }
+    pub(crate) fn polled(&self, started: Instant, busy: Duration) {
+        let scheduled_at = lock(&self.scheduled_at).take();
+        let mut stats = lock(&self.stats);
+        stats.polls += 1;
+        stats.busy += busy;
+        if let Some(scheduled_at) = scheduled_at {
+            stats.scheduled += started.saturating_duration_since(scheduled_at);
+        }
+    }
+    fn get(&self) -> TaskStats {
+        TaskStats {
+            allocated: self.allocated.load(Ordering::Relaxed),
+            freed: self.freed.load(Ordering::Relaxed),
+            ..*lock(&self.stats)
+        }
+    }
+}
+
+/* WakeLog */
+/// Wakes a task keeps a record of, with `Builder::record_wakes`.
+const WAKE_LOG_LEN: usize = 16;
+
+/// Set once an executor is built with `Builder::record_wakes`, so that
+/// `tag_wakes` costs nothing until then.
+pub(crate) static RECORDING_WAKES: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The label of the innermost `tag_wakes` on this thread.
+    static WAKE_SOURCE: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Run `f`, attributing the task wakes it makes to `source` in the records
+/// of `Builder::record_wakes`, e.g. `"cache-refresh"`.
+///
+/// The crate's own primitives label their wakes this way, such as `"timer"`
+/// or `"Event::set"`; wakes without a label are recorded as `"external"`.
+pub fn tag_wakes<R>(source: &'static str, f: impl FnOnce() -> R) -> R {
+    if !RECORDING_WAKES.load(Ordering::Relaxed) {
+        return f();
+    }
+    let previous = WAKE_SOURCE.with(|current| current.replace(Some(source)));
+    // Restored even if a waker panics.
+    let _restore = RestoreWakeSource(previous);
+    f()
+}
+
+struct RestoreWakeSource(Option<&'static str>);
+impl Drop for RestoreWakeSource {
+    fn drop(&mut self) {
+        WAKE_SOURCE.with(|current| current.set(self.0));
+    }
+}
+
+/// A wake of a task, as recorded with `Builder::record_wakes`.
+#[derive(Clone, Debug)]
+pub struct WakeEvent {
+    /// The `tag_wakes` label it was made under, or `"external"`.
+    pub source: &'static str,
+    /// The thread it was made from.
+    pub thread: ThreadId,
+    pub at: Instant,
+}
+
+/// A task's latest wakes.
+#[derive(Default)]
+pub(crate) struct WakeLog {
+    events: Mutex<VecDeque<WakeEvent>>,
+}
+impl WakeLog {
+    fn record(&self) {
+        let event = WakeEvent {
+            source: WAKE_SOURCE.with(Cell::get).unwrap_or("external"),
+            thread: thread::current().id(),
+            at: Instant::now(),
+        };
+        let mut events = lock(&self.events);
+        if events.len() == WAKE_LOG_LEN {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+    fn events(&self) -> Vec<WakeEvent> {
+        lock(&self.events).iter().cloned().collect()
+    }
+}
+
+/* SpawnFn */
+/// Future of a task spawned with `Handle::spawn_fn`: builds the real future
+/// on its first poll, and only lets that thread touch it afterwards.
+pub(crate) struct SpawnFn<F, Fut> {
+    make: Option<F>,
+    /// The built future and the thread it belongs to.
+    future: Option<(ThreadId, Pin<Box<Fut>>)>,
+}
+impl<F, Fut> SpawnFn<F, Fut> {
+    pub(crate) fn new(make: F) -> Self {
+        SpawnFn {
+            make: Some(make),
+            future: None,
+        }
+    }
+}
+// SAFETY: `make` is `Send`, and the future it builds is never polled nor
+// dropped on any other thread than the one which built it, see below.
+unsafe impl<F: Send, Fut> Send for SpawnFn<F, Fut> {}
+// Neither `make` nor the boxed future is pinned in place.
+impl<F, Fut> Unpin for SpawnFn<F, Fut> {}
+impl<F, Fut> Future for SpawnFn<F, Fut>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future,
+{
+    type Output = Fut::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let (thread, future) = this.future.get_or_insert_with(|| {
+            let make = this.make.take().expect("`SpawnFn` polled after completion");
+            (thread::current().id(), Box::pin(make()))
+        });
+        assert_eq!(
+            *thread,
+            thread::current().id(),
+            "task spawned with `spawn_fn` polled on another thread than its first poll"
+        );
+        future.as_mut().poll(cx)
+    }
+}
+impl<F, Fut> Drop for SpawnFn<F, Fut> {
+    fn drop(&mut self) {
+        if let Some((thread, future)) = self.future.take() {
+            // Leaked rather than dropped on a thread it does not belong to.
+            if thread != thread::current().id() {
+                mem::forget(future);
+            }
+        }
+    }
+}
+
+/* TaskContext */
+/// What a task spawned with `Handle::spawn_with_context` knows about itself:
+/// its id, its executor, and the children it spawned.
+#[derive(Clone)]
+pub struct TaskContext {
+    handle: Handle,
+    id: TaskId,
+    children: Arc<Mutex<Option<Vec<Arc<Task>>>>>,
+}
+impl TaskContext {
+    /// Context for the task `id`, and the guard aborting its children, to be
+    /// dropped along with the task's future.
+    pub(crate) fn new(handle: Handle, id: TaskId) -> (Self, ChildGuard) {
+        let children = Arc::new(Mutex::new(Some(Vec::new())));
+        let guard = ChildGuard(children.clone());
+        let context = TaskContext {
+            handle,
+            id,
+            children,
+        };
+        (context, guard)
+    }
+    /// The executor the task runs on.
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+    /// Spawn a child task, aborted when this task completes, panics or is
+    /// aborted, unless it finished first. Spawned once this task is done, the
+    /// child is aborted right away.
+    #[track_caller]
+    pub fn spawn_child<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let handle = self.handle.spawn(future);
+        let mut children = lock(&self.children);
+        match &mut *children {
+            Some(children) => {
+                // Finished children are forgotten, as there is nothing left
+                // to abort.
+                children.retain(|child| !child.finished.load(Ordering::Acquire));
+                children.push(handle.task.clone());
+            }
+            None => handle.abort(),
+        }
+        handle
+    }
+}
+impl Debug for TaskContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskContext")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Aborts the children of a task once dropped with its future.
+pub(crate) struct ChildGuard(Arc<Mutex<Option<Vec<Arc<Task>>>>>);
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        // Taken once, so every child is aborted at most once; one which
+        // finished meanwhile is left alone by `abort`.
+        let children = lock(&self.0).take();
+        for child in children.into_iter().flatten() {
+            child.abort();
+        }
+    }
+}
+
+/* Defer */
+type CleanupFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A cleanup registered with `defer`.
+struct Cleanup {
+    make: Box<dyn FnOnce() -> CleanupFuture + Send>,
+}
+impl Cleanup {
+    fn new<F, Fut>(cleanup: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Cleanup {
+            make: Box::new(move || Box::pin(cleanup())),
+        }
+    }
+}
+
+/// Run the future built by `cleanup` once the current task finished,
+/// whether it completed, panicked or was aborted, or is dropped at
+/// shutdown: the async counterpart of a `Drop` guard, e.g. to release a
+/// distributed lock.
+///
+/// A task's cleanups run one after the other in a task of their own, the
+/// latest registered first, each dropped if still running after
+/// `Builder::defer_timeout`. If the executor shuts down before they
+/// completed, the thread dropping them drives the rest on the spot, blocked
+/// until done; spawning from them does nothing any more then. Each runs
+/// exactly once either way.
+///
+/// Panics outside of a task; see `JoinHandle::defer` to register one from
+/// elsewhere.
+pub fn defer<F, Fut>(cleanup: F)
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let task = Handle::try_current()
+        .and_then(|handle| handle.inner.polled_task())
+        .expect("`task::defer` called outside of a task");
+    task.defer(Cleanup::new(cleanup));
+}
+
+/// Drives the cleanups of a finished task, latest registered first.
+struct RunCleanups {
+    /// The task they belong to, which their panics and timeouts are
+    /// reported against.
+    owner: Arc<Task>,
+    /// Not started yet, the next one last.
+    pending: Vec<Cleanup>,
+    /// The one running, along with its deadline.
+    current: Option<(CleanupFuture, TimerFuture)>,
+    /// Set by `Builder::defer_timeout`.
+    timeout: Duration,
+}
+impl RunCleanups {
+    fn poll_cleanups(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            let (future, timer) = match &mut self.current {
+                Some(current) => current,
+                None => {
+                    let Some(cleanup) = self.pending.pop() else {
+                        return Poll::Ready(());
+                    };
+                    match panic::catch_unwind(AssertUnwindSafe(cleanup.make)) {
+                        Ok(future) => self
+                            .current
+                            .insert((future, TimerFuture::new(self.timeout))),
+                        Err(payload) => {
+                            handlers::panicked(&self.owner.meta, payload);
+                            continue;
+                        }
+                    }
+                }
+            };
+            // A cleanup panicking or timing out leaves the others to run.
+            match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+                Ok(Poll::Ready(())) => {}
+                Ok(Poll::Pending) => match Pin::new(timer).poll(cx) {
+                    Poll::Ready(()) => handlers::failed(
+                        &self.owner.meta,
+                        &format_args!("deferred cleanup timed out after {:?}", self.timeout),
+                    ),
+                    Poll::Pending => return Poll::Pending,
+                },
+                Err(payload) => handlers::panicked(&self.owner.meta, payload),
+            }
+            self.current = None;
+        }
+    }
+}
+impl Future for RunCleanups {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.get_mut().poll_cleanups(cx)
+    }
+}
+impl Drop for RunCleanups {
+    fn drop(&mut self) {
+        if self.current.is_none() && self.pending.is_empty() {
+            return;
+        }
+        // Dropped unfinished, by an executor shutting down.
+        let waker = Waker::from(Arc::new(UnparkWaker(thread::current())));
+        let context = &mut Context::from_waker(&waker);
+        while self.poll_cleanups(context).is_pending() {
+            thread::park();
+        }
+    }
+}
+
+/* JoinHandle */
+/// Owned permission to wait for a spawned task's output.
+///
+/// Awaiting the handle resolves to the task's output, or to a `JoinError`
+/// if the task panicked or was aborted. Dropping it detaches the task, which
+/// keeps running.
+///
+/// The task's future is dropped by the thread running the executor, as soon
+/// as it completes or panics, or once aborted, when the task next comes out
+/// of the queue. The output is then kept until taken through the handle, or
+/// dropped along with it; with the handle gone already, it is dropped right
+/// away. Futures still alive at shutdown are dropped by the thread calling
+/// `Executor::shutdown` or dropping the executor.
+pub struct JoinHandle<T> {
+    task: Arc<Task>,
+    output: PhantomData<T>,
+}
+impl<T> JoinHandle<T> {
+    fn take_output(state: &mut JoinState) -> Option<Result<T, JoinError>> {
+        // SAFETY: the output of the task is that of the future `Task::new`
+        // made this handle for.
+        state.output.take().map(|output| unsafe { output.take() })
+    }
+
+    /// Whether the task has run to completion (or panicked, or was aborted).
+    ///
+    /// This only reads the task's atomic state, so it never blocks and can be
+    /// called from any thread.
+    pub fn is_finished(&self) -> bool {
+        self.task.finished.load(Ordering::Acquire)
+    }
+
+    /// Take the task's output if it already completed, without registering a
+    /// waker.
+    ///
+    /// Returns `None` while the task is still running, and once the output has
+    /// been taken. Taking `&mut self` keeps this exclusive with awaiting the
+    /// handle; awaiting it after the output was taken panics.
+    pub fn try_take_output(&mut self) -> Option<Result<T, JoinError>> {
+        if !self.is_finished() {
+            return None;
+        }
+        Self::take_output(&mut lock(&self.task.join))
+    }
+
+    /// Block the calling thread until the task finished, and return its
+    /// output, for threads outside of the executor, such as a `Drop` impl or
+    /// an FFI callback which must return synchronously.
+    ///
+    /// Panics when called from a task, as it would block the executor that
+    /// has to run the task being joined.
+    pub fn join_blocking(mut self) -> Result<T, JoinError> {
+        self.wait_blocking(None).expect("no deadline to wait for")
+    }
+
+    /// Like `join_blocking`, giving up after `timeout`: returns `None` if
+    /// the task is still running by then, leaving the handle usable. A
+    /// timeout too far off to be represented waits without one.
+    pub fn join_blocking_timeout(&mut self, timeout: Duration) -> Option<Result<T, JoinError>> {
+        self.wait_blocking(Instant::now().checked_add(timeout))
+    }
+
+    fn wait_blocking(&mut self, deadline: Option<Instant>) -> Option<Result<T, JoinError>> {
+        assert!(
+            !coop::in_task(),
+            "`join_blocking` called from a task, which would block its executor"
+        );
+        let waker = Waker::from(Arc::new(UnparkWaker(thread::current())));
+        let context = &mut Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = Pin::new(&mut *self).poll(context) {
+                return Some(output);
+            }
+            // Parked until woken through the handle, spuriously at worst.
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        }
+    }
+
+    /// Abort the task.
+    ///
+    /// The task is woken, even if parked on a timer, and its future is dropped
+    /// instead of being polled again; awaiting the handle then resolves to a
+    /// cancelled `JoinError`. This does nothing if the task already finished.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+
+    /// Time and polls the task took so far, if built with
+    /// `Builder::task_stats`.
+    pub fn stats(&self) -> Option<TaskStats> {
+        self.task.meta.stats()
+    }
+
+    /// The latest wakes of the task, oldest first, if built with
+    /// `Builder::record_wakes`.
+    pub fn recent_wakes(&self) -> Vec<WakeEvent> {
+        self.task.meta.recent_wakes()
+    }
+
+    /// Register a cleanup with the task, as `defer` would from within it,
+    /// e.g. before it is first polled. Run right away if the task finished
+    /// already.
+    pub fn defer<F, Fut>(&self, cleanup: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.task.defer(Cleanup::new(cleanup));
+    }
+
+    /// Turn the handle into a guard which aborts the task when dropped.
+    pub fn abort_on_drop(self) -> AbortOnDropHandle<T> {
+        AbortOnDropHandle::new(self)
+    }
+
+    /// Resolve to the output passed through `f`, run by whoever awaits it.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Map<T, F> {
+        Map {
+            handle: self,
+            f: Some(f),
+        }
+    }
+    /// Resolve to the output, turning a `JoinError` into an error of `f`.
+    pub fn map_err<E, F: FnOnce(JoinError) -> E>(self, f: F) -> MapErr<T, F> {
+        MapErr {
+            handle: self,
+            f: Some(f),
+        }
+    }
+    /// Resolve to the output, or to what `f` makes of a `JoinError`.
+    pub fn unwrap_or_else<F: FnOnce(JoinError) -> T>(self, f: F) -> UnwrapOrElse<T, F> {
+        UnwrapOrElse {
+            handle: self,
+            f: Some(f),
+        }
+    }
+    /// Resolve to the output along with how long the task ran, from its
+    /// first poll to its completion as recorded by the executor, waiting in
+    /// the queue included.
+    pub fn timed(self) -> Timed<T> {
+        Timed { handle: self }
+    }
+}
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        let mut state = lock(&self.task.join);
+        state.detached = true;
+        // Dropped now rather than along with the task, out of the lock.
+        let output = Self::take_output(&mut state);
+        drop(state);
+        // A panic nobody read yet would otherwise go unnoticed.
+        if let Some(Err(error)) =
+            output.filter(|output| output.as_ref().is_err_and(JoinError::is_panic))
+        {
+            handlers::panicked(&self.task.meta, error.into_panic());
+        }
+    }
+}
+/// Wakes a thread blocked in `JoinHandle::join_blocking`.
+struct UnparkWaker(thread::Thread);
+impl Wake for UnparkWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+// The output is never pinned, so moving a `JoinHandle` around is always fine.
+impl<T> Unpin for JoinHandle<T> {}
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.task.join);
+        if let Some(output) = Self::take_output(&mut state) {
+            return Poll::Ready(output);
+        }
+        assert!(
+            !self.is_finished(),
+            "`JoinHandle` polled after its output was taken"
+        );
+        state.waker = Some(clone_waker(cx.waker()));
+        Poll::Pending
+    }
+}
+impl<T> Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+/* Map */
+/// Future returned by `JoinHandle::map`.
+pub struct Map<T, F> {
+    handle: JoinHandle<T>,
+    f: Option<F>,
+}
+impl<T, F> Map<T, F> {
+    /// The wrapped handle, to check on or abort the task.
+    pub fn inner(&self) -> &JoinHandle<T> {
+        &self.handle
+    }
+    pub fn into_inner(self) -> JoinHandle<T> {
+        self.handle
+    }
+}
+// `f` is only ever called by value, never pinned.
+impl<T, F> Unpin for Map<T, F> {}
+impl<T, U, F: FnOnce(T) -> U> Future for Map<T, F> {
+    type Output = Result<U, JoinError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx).map(|output| {
+            let f = self.f.take().expect("`Map` polled after completion");
+            output.map(f)
+        })
+    }
+}
+impl<T, F> Debug for Map<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Map").field(&self.handle).finish()
+    }
+}
+
+/* MapErr */
+/// Future returned by `JoinHandle::map_err`.
+pub struct MapErr<T, F> {
+    handle: JoinHandle<T>,
+    f: Option<F>,
+}
+impl<T, F> MapErr<T, F> {
+    /// The wrapped handle, to check on or abort the task.
+    pub fn inner(&self) -> &JoinHandle<T> {
+        &self.handle
+    }
+    pub fn into_inner(self) -> JoinHandle<T> {
+        self.handle
+    }
+}
+// `f` is only ever called by value, never pinned.
+impl<T, F> Unpin for MapErr<T, F> {}
+impl<T, E, F: FnOnce(JoinError) -> E> Future for MapErr<T, F> {
+    type Output = Result<T, E>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx).map(|output| {
+            let f = self.f.take().expect("`MapErr` polled after completion");
+            output.map_err(f)
+        })
+    }
+}
+impl<T, F> Debug for MapErr<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MapErr").field(&self.handle).finish()
+    }
+}
+
+/* UnwrapOrElse */
+/// Future returned by `JoinHandle::unwrap_or_else`.
+pub struct UnwrapOrElse<T, F> {
+    handle: JoinHandle<T>,
+    f: Option<F>,
+}
+impl<T, F> UnwrapOrElse<T, F> {
+    /// The wrapped handle, to check on or abort the task.
+    pub fn inner(&self) -> &JoinHandle<T> {
+        &self.handle
+    }
+    pub fn into_inner(self) -> JoinHandle<T> {
+        self.handle
+    }
+}
+// `f` is only ever called by value, never pinned.
+impl<T, F> Unpin for UnwrapOrElse<T, F> {}
+impl<T, F: FnOnce(JoinError) -> T> Future for UnwrapOrElse<T, F> {
+    type Output = T;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx).map(|output| {
+            let f = self
+                .f
+                .take()
+                .expect("`UnwrapOrElse` polled after completion");
+            output.unwrap_or_else(f)
+        })
+    }
+}
+impl<T, F> Debug for UnwrapOrElse<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("UnwrapOrElse").field(&self.handle).finish()
+    }
+}
+
+/* Timed */
+/// Future returned by `JoinHandle::timed`.
+pub struct Timed<T> {
+    handle: JoinHandle<T>,
+}
+impl<T> Timed<T> {
+    /// The wrapped handle, to check on or abort the task.
+    pub fn inner(&self) -> &JoinHandle<T> {
+        &self.handle
+    }
+    pub fn into_inner(self) -> JoinHandle<T> {
+        self.handle
+    }
+}
+impl<T> Future for Timed<T> {
+    type Output = Result<(T, Duration), JoinError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx).map(|output| {
+            let meta = &self.handle.task.meta;
+            let started = meta.first_polled_at().unwrap_or(meta.spawned_at());
+            // The output can be seen a moment before the executor records the end.
+            let completed = meta.completed_at().unwrap_or_else(Instant::now);
+            Ok((output?, completed.saturating_duration_since(started)))
+        })
+    }
+}
+impl<T> Debug for Timed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Timed").field(&self.handle).finish()
+    }
+}
+
+/* AbortOnDropHandle */
+/// `JoinHandle` which aborts its task when dropped.
+///
+/// Meant for helper tasks whose lifetime is tied to some owner: embed the
+/// guard in the owner and the task dies with it. Use `into_inner` to detach
+/// the task again.
+pub struct AbortOnDropHandle<T> {
+    handle: JoinHandle<T>,
+}
+impl<T> AbortOnDropHandle<T> {
+    pub fn new(handle: JoinHandle<T>) -> Self {
+        AbortOnDropHandle { handle }
+    }
+
+    /// Give back the plain `JoinHandle`, without aborting the task.
+    pub fn into_inner(self) -> JoinHandle<T> {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped, so `handle` is read out exactly once.
+        unsafe { ptr::read(&this.handle) }
+    }
+}
+impl<T> Drop for AbortOnDropHandle<T> {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+impl<T> Deref for AbortOnDropHandle<T> {
+    type Target = JoinHandle<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+impl<T> DerefMut for AbortOnDropHandle<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.handle
+    }
+}
+impl<T> Future for AbortOnDropHandle<T> {
+    type Output = Result<T, JoinError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx)
+    }
+}
+impl<T> Debug for AbortOnDropHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AbortOnDropHandle")
+            .field(&self.handle)
+            .finish()
+    }
+}
+
+/* SharedOutput */
+/// Cloneable handle to a task's output, which every clone borrows in place
+/// rather than getting a copy, see `Handle::spawn_shared`.
+///
+/// The output stays in the task, as it would for a `JoinHandle`, until the
+/// last clone is dropped. Dropping them all before the task finished
+/// detaches it, as dropping a `JoinHandle` does.
+pub struct SharedOutput<T> {
+    task: Arc<Task>,
+    waiters: Arc<SharedWaiters>,
+    /// Clones borrow the output from any thread, as they would through an
+    /// `Arc`.
+    output: PhantomData<Arc<T>>,
+}
+
+/// Tasks awaiting `SharedOutput::ready`, woken together as the task
+/// finishes: its `JoinState` has room for one waker, this one.
+#[derive(Default)]
+struct SharedWaiters {
+    wakers: Mutex<Waiters>,
+    /// Clones of the `SharedOutput`, the last of which drops the output.
+    handles: AtomicUsize,
+    /// Set once the output was looked at, for a panic to be reported only
+    /// if nobody saw it.
+    seen: AtomicBool,
+}
+impl Wake for SharedWaiters {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = lock(&self.wakers).take();
+        wake_all(wakers);
+    }
+}
+impl<T> SharedOutput<T> {
+    pub(crate) fn new(handle: JoinHandle<T>) -> Self {
+        let handle = ManuallyDrop::new(handle);
+        // SAFETY: the handle is neither used nor dropped again, its reference
+        // to the task moving here.
+        let task = unsafe { ptr::read(&handle.task) };
+        let waiters = Arc::new(SharedWaiters {
+            handles: AtomicUsize::new(1),
+            ..Default::default()
+        });
+        lock(&task.join).waker = Some(Waker::from(waiters.clone()));
+        SharedOutput {
+            task,
+            waiters,
+            output: PhantomData,
+        }
+    }
+    /// Wait for the task to finish, resolving to its output, or to why
+    /// there is none: a panic's payload is then its message as a `String`.
+    ///
+    /// Cancel safe: the output stays with the task.
+    pub fn ready(&self) -> SharedReady<'_, T> {
+        SharedReady {
+            output: self,
+            waker: None,
+        }
+    }
+    /// The output, if the task completed already.
+    pub fn get(&self) -> Option<&T> {
+        self.try_output()?.ok()
+    }
+    /// Why the task failed, if it did.
+    pub fn error(&self) -> Option<JoinError> {
+        self.try_output()?.err()
+    }
+    pub fn is_finished(&self) -> bool {
+        self.task.finished.load(Ordering::Acquire)
+    }
+    /// Abort the task, see `JoinHandle::abort`: `ready` then resolves to a
+    /// cancelled `JoinError`, unless it completed already.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+    fn try_output(&self) -> Option<Result<&T, JoinError>> {
+        let state = lock(&self.task.join);
+        // SAFETY: the output of the task is that of the future `Task::new`
+        // made the handle for.
+        let output = unsafe { state.output.as_ref()?.get::<T>() };
+        self.waiters.seen.store(true, Ordering::Relaxed);
+        match output {
+            // SAFETY: the output stays in the task, unmoved, until the last
+            // clone is dropped: only that takes it out of the `JoinState`.
+            Ok(output) => Some(Ok(unsafe { &*(output as *const T) })),
+            // Copied under the lock, the payload not being `Sync`.
+            Err(error) => Some(Err(match &error.repr {
+                Repr::Cancelled => JoinError::cancelled(),
+                Repr::Panic(payload) => {
+                    JoinError::panic(Box::new(JoinError::panic_message(&**payload).to_string()))
+                }
+            })),
+        }
+    }
+}
+impl<T> Clone for SharedOutput<T> {
+    fn clone(&self) -> Self {
+        self.waiters.handles.fetch_add(1, Ordering::Relaxed);
+        SharedOutput {
+            task: self.task.clone(),
+            waiters: self.waiters.clone(),
+            output: PhantomData,
+        }
+    }
+}
+impl<T> Drop for SharedOutput<T> {
+    fn drop(&mut self) {
+        if self.waiters.handles.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        // As `JoinHandle::drop`, with the waiters' waker going too.
+        let mut state = lock(&self.task.join);
+        state.detached = true;
+        let waker = state.waker.take();
+        // SAFETY: as in `try_output`.
+        let output = state
+            .output
+            .take()
+            .map(|output| unsafe { output.take::<T>() });
+        drop(state);
+        drop(waker);
+        if let Some(Err(error)) = output.filter(|output| {
+            output.as_ref().is_err_and(JoinError::is_panic)
+                && !self.waiters.seen.load(Ordering::Relaxed)
+        }) {
+            handlers::panicked(&self.task.meta, error.into_panic());
+        }
+    }
+}
+/// Panics unless the task completed, as `get` would return `None`: await
+/// `ready` first.
+impl<T> Deref for SharedOutput<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        match self.try_output() {
+            Some(Ok(output)) => output,
+            Some(Err(error)) => {
+                panic!("`SharedOutput` dereferenced after its task failed: {error}")
+            }
+            None => panic!("`SharedOutput` dereferenced before its task completed"),
+        }
+    }
+}
+impl<T> Debug for SharedOutput<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedOutput")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+/// Future of `SharedOutput::ready`.
+pub struct SharedReady<'a, T> {
+    output: &'a SharedOutput<T>,
+    /// The waker this future left in the waiters, taken back if dropped
+    /// first.
+    waker: Option<Waker>,
+}
+impl<'a, T> SharedReady<'a, T> {
+    fn deregister(&mut self) {
+        if self.waker.is_some() {
+            lock(&self.output.waiters.wakers).remove(&mut self.waker);
+        }
+    }
+}
+impl<'a, T> Unpin for SharedReady<'a, T> {}
+impl<'a, T> Future for SharedReady<'a, T> {
+    type Output = Result<&'a T, JoinError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(output) = this.output.try_output() {
+            this.deregister();
+            return Poll::Ready(output);
+        }
+        lock(&this.output.waiters.wakers).register(&mut this.waker, cx.waker());
+        // Checked again once registered, so a completion in between is not
+        // missed.
+        match this.output.try_output() {
+            Some(output) => {
+                this.deregister();
+                Poll::Ready(output)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+impl<'a, T> Drop for SharedReady<'a, T> {
+    fn drop(&mut self) {
+        self.deregister();
+    }
+}
+impl<'a, T> Debug for SharedReady<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedReady")
+            .field("output", self.output)
+            .finish()
+    }
+}
+
+/* JoinError */
+/// Why a task failed to produce its output.
+pub struct JoinError {
+    repr: Repr,
+}
+enum Repr {
+    Cancelled,
+    Panic(Box<dyn Any + Send + 'static>),
+}
+impl JoinError {
+    fn cancelled() -> Self {
+        JoinError {
+            repr: Repr::Cancelled,
+        }
+    }
+
+    pub(crate) fn panic(payload: Box<dyn Any + Send + 'static>) -> Self {
+        JoinError {
+            repr: Repr::Panic(payload),
+        }
+    }
+
+    /// Whether the task was aborted before completing.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.repr, Repr::Cancelled)
+    }
+
+    /// Whether the task panicked.
+    pub fn is_panic(&self) -> bool {
+        matches!(self.repr, Repr::Panic(_))
+    }
+
+    /// Consume the error, returning the payload the task panicked with.
+    ///
+    /// Panics if the task was cancelled rather than panicking.
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        match self.repr {
+            Repr::Panic(payload) => payload,
+            Repr::Cancelled => panic!("`JoinError::into_panic` called on a cancelled task"),
+        }
+    }
+
+    pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+        if let Some(message) = payload.downcast_ref::<&str>() {
+            message
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message
+        } else {
+            "Box<dyn Any>"
+        }
+    }
+}
+impl Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.repr {
+            Repr::Cancelled => write!(f, "task was cancelled"),
+            Repr::Panic(payload) => {
+                write!(f, "task panicked: {}", Self::panic_message(&**payload))
+            }
+        }
+    }
+}
+impl Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.repr {
+            Repr::Cancelled => write!(f, "JoinError::Cancelled"),
+            Repr::Panic(payload) => f
+                .debug_tuple("JoinError::Panic")
+                .field(&Self::panic_message(&**payload))
+                .finish(),
+        }
+    }
+}
+impl std::error::Error for JoinError {}
+
+/* Unconstrained */
+/// Poll `future` without the cooperative budget of its task: neither
+/// `coop::maybe_yield` nor the primitives yielding once the budget is used
+/// up, such as `ProgressReceiver`, yield within it, and it spends none of
+/// the budget left to the rest of the task.
+///
+/// A loop inside which never waits starves every other task of the thread,
+/// timers included, until it ends: keep it to work known to be bounded, like
+/// draining a channel. Tasks polled from within, e.g. by
+/// `Handle::block_in_task`, keep budgets of their own.
+pub fn unconstrained<F: Future>(future: F) -> Unconstrained<F> {
+    Unconstrained { future }
+}
+
+/// Future returned by `unconstrained`.
+pub struct Unconstrained<F> {
+    future: F,
+}
+impl<F: Future> Future for Unconstrained<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: `future` is structurally pinned, it is never moved out of
+        // `Unconstrained`.
+        let future = unsafe { self.map_unchecked_mut(|this| &mut this.future) };
+        let _constrain = coop::unconstrain();
+        future.poll(cx)
+    }
+}
+impl<F> Debug for Unconstrained<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Unconstrained").finish_non_exhaustive()
+    }
+}
+
+/* YieldEvery */
+/// Checkpoints yielding to the executor's other tasks every `n`-th time
+/// they are passed, and costing a counter otherwise.
+///
+/// Outside of a task, checkpoints never yield. Panics if `n` is zero.
+pub fn yield_every(n: usize) -> YieldEvery {
+    assert!(n > 0, "`yield_every` needs a non-zero interval");
+    YieldEvery {
+        every: n,
+        passed: 0,
+    }
+}
+
+/// Counter returned by `yield_every`.
+#[derive(Debug)]
+pub struct YieldEvery {
+    every: usize,
+    /// Checkpoints passed since the last yield.
+    passed: usize,
+}
+impl YieldEvery {
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.passed += 1;
+        let due = self.passed == self.every;
+        if due {
+            self.passed = 0;
+        }
+        Checkpoint {
+            yield_now: due && coop::in_task(),
+        }
+    }
+}
+
+/// Future returned by `YieldEvery::checkpoint`.
+#[derive(Debug)]
+pub struct Checkpoint {
+    yield_now: bool,
+}
+impl Future for Checkpoint {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if !mem::take(&mut self.yield_now) {
+            return Poll::Ready(());
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+
+    use super::*;
+    use crate::{
+        future::completion,
+        handlers,
+        stream::StreamExt,
+        sync::Event,
+        test_util::{allocations_during, DropTracker},
+        Builder, Executor, TimerFuture,
+    };
+
+    #[test]
+    fn is_finished_and_try_take_output() {
+        let executor = Executor::new();
+        let event = Arc::new(Event::new());
+        let started = event.clone();
+        let mut handle = executor.spawn(async move {
+            started.wait().await;
+            7
+        });
+        assert!(!handle.is_finished());
+        assert!(handle.try_take_output().is_none());
+
+        event.set();
+        executor.run();
+        assert!(handle.is_finished());
+        assert_eq!(handle.try_take_output().unwrap().unwrap(), 7);
+        // Taken already.
+        assert!(handle.is_finished());
+        assert!(handle.try_take_output().is_none());
+    }
+
+    #[test]
+    fn try_take_output_from_another_thread() {
+        let executor = Executor::new();
+        let mut handle = executor.spawn(async { "done" });
+        executor.run();
+        let output = thread::spawn(move || handle.try_take_output())
+            .join()
+            .unwrap();
+        assert_eq!(output.unwrap().unwrap(), "done");
+    }
+
+    #[test]
+    fn abort_on_drop_stops_a_task_parked_on_a_timer() {
+        struct Owner {
+            _background: AbortOnDropHandle<()>,
+        }
+        let executor = Executor::new();
+        let tracker = DropTracker::new();
+        let guard = tracker.guard("background");
+        let owner = Owner {
+            _background: executor
+                .spawn(async move {
+                    let _guard = guard;
+                    TimerFuture::new(Duration::from_secs(3600)).await;
+                })
+                .abort_on_drop(),
+        };
+        let started = Instant::now();
+        // Dropped once the task is parked on its timer.
+        executor.spawn(async move {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            drop(owner);
+        });
+        executor.run();
+        assert!(tracker.is_dropped("background"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn abort_on_drop_after_completion_is_a_no_op() {
+        let executor = Executor::new();
+        let mut guard = executor.spawn(async { 3 }).abort_on_drop();
+        executor.run();
+        assert_eq!(guard.try_take_output().unwrap().unwrap(), 3);
+        drop(guard);
+        let mut handle = executor.spawn(async { 4 }).abort_on_drop().into_inner();
+        executor.run();
+        assert_eq!(handle.try_take_output().unwrap().unwrap(), 4);
+    }
+
+    #[test]
+    fn spawning_allocates_once_per_task() {
+        const TASKS: u64 = 100_000;
+        let executor = Executor::new();
+        let (handles, allocations) = allocations_during(|| {
+            (0..TASKS)
+                .map(|i| executor.spawn(async move { i * 2 }))
+                .collect::<Vec<_>>()
+        });
+        // Amortized growth of the executor's queues and of the `Vec` above
+        // takes a few more, not one per task.
+        assert!(
+            allocations < TASKS + 100,
+            "{allocations} allocations for {TASKS} spawns"
+        );
+        let (_, allocations) = allocations_during(|| executor.run());
+        assert!(allocations < 100, "{allocations} allocations to run them");
+        for (i, handle) in (0..TASKS).zip(handles) {
+            assert_eq!(handle.join_blocking().unwrap(), i * 2);
+        }
+    }
+
+    #[test]
+    fn oversized_futures_and_outputs_are_boxed() {
+        let tracker = DropTracker::new();
+        let guard = tracker.guard("huge");
+        let huge = async move {
+            let state = [7u8; 2048];
+            // Held across an await, so part of the future.
+            crate::future::ready(()).await;
+            drop(guard);
+            state
+        };
+        assert!(mem::size_of_val(&huge) > 2048);
+        let executor = Executor::new();
+        // Leaves room in the executor's queues.
+        drop(executor.spawn(async {}));
+        let (handle, allocations) = allocations_during(|| executor.spawn(huge));
+        // The task, and the future's box.
+        assert_eq!(allocations, 2);
+        executor.run();
+        assert!(tracker.is_dropped("huge"));
+        assert_eq!(handle.join_blocking().unwrap(), [7; 2048]);
+
+        // Aborted before it ran, it is dropped in its box all the same.
+        let guard = tracker.guard("aborted");
+        let handle = executor.spawn(async move {
+            let _state = [0u8; 2048];
+            crate::future::ready(()).await;
+            drop(guard);
+        });
+        handle.abort();
+        executor.run();
+        assert!(tracker.is_dropped("aborted"));
+        assert!(handle.join_blocking().unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn spawn_boxed_keeps_the_box() {
+        let executor = Executor::new();
+        // Leaves room in the executor's queues.
+        drop(executor.spawn(async {}));
+        let future: BoxFuture<'static, u32> = Box::pin(async { 7 });
+        let (boxed, allocations) = allocations_during(|| executor.spawn_boxed(future));
+        // The task alone: the box is the future's storage.
+        assert_eq!(allocations, 1);
+
+        let panicking: BoxFuture<'static, u32> = Box::pin(async { panic!("boom") });
+        let panicked = executor.spawn_boxed(panicking);
+        let aborted = executor.spawn_boxed(Box::pin(crate::future::pending::<u32>()));
+        aborted.abort();
+        executor.run();
+        assert_eq!(boxed.join_blocking().unwrap(), 7);
+        assert!(panicked.join_blocking().unwrap_err().is_panic());
+        assert!(aborted.join_blocking().unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn spawn_fn_builds_and_polls_on_the_executor_thread() {
+        let executor = Executor::new();
+        let handle = executor.handle();
+        let (spawner, joined) = thread::spawn(move || {
+            let joined = handle.spawn_fn(|| {
+                let built_on = thread::current().id();
+                // Not `Send`, and never needs to be.
+                let polls = std::rc::Rc::new(Cell::new(Vec::new()));
+                async move {
+                    for _ in 0..3 {
+                        let mut seen = polls.take();
+                        seen.push(thread::current().id());
+                        polls.set(seen);
+                        TimerFuture::new(Duration::from_millis(1)).await;
+                    }
+                    (built_on, polls.take())
+                }
+            });
+            (thread::current().id(), joined)
+        })
+        .join()
+        .unwrap();
+        executor.run();
+        let (built_on, polled_on) = joined.join_blocking().unwrap();
+        assert_eq!(built_on, thread::current().id());
+        assert_ne!(built_on, spawner);
+        assert_eq!(polled_on, [built_on; 3]);
+    }
+
+    #[test]
+    fn wakers_outliving_the_executor_are_no_ops() {
+        let executor = Executor::new();
+        let stash = Arc::new(Mutex::new(Vec::new()));
+        let stashed = stash.clone();
+        let parked = executor.spawn(std::future::poll_fn(move |cx| {
+            stashed.lock().unwrap().push(cx.waker().clone());
+            Poll::<()>::Pending
+        }));
+        let (completer, completion) = completion::<()>();
+        let waiting = executor.spawn(completion);
+        let sleeping = executor.spawn(TimerFuture::new(Duration::from_secs(3600)));
+        // Polls each of them once.
+        let mut first = executor.run_collect([TimerFuture::new(Duration::from_millis(10))]);
+        first.next();
+        drop(first);
+
+        let tasks = [&parked.task, &waiting.task, &sleeping.task].map(Arc::downgrade);
+        drop((parked, waiting, sleeping));
+        drop(executor.shutdown());
+        std::thread::spawn(move || {
+            for waker in stash.lock().unwrap().drain(..) {
+                waker.wake_by_ref();
+                waker.wake();
+            }
+            let _ = completer.complete(());
+        })
+        .join()
+        .unwrap();
+        // The last wakers gone, so are the tasks.
+        for task in tasks {
+            assert!(task.upgrade().is_none());
+        }
+    }
+
+    #[test]
+    fn adapters_map_the_output_without_another_task() {
+        let executor = Executor::new();
+        let handle = executor.handle();
+        let doubled = handle.spawn(async { 21 }).map(|n: i32| n * 2);
+        let described = handle.spawn(std::future::pending::<()>()).map_err(|error| {
+            if error.is_cancelled() {
+                "cancelled"
+            } else {
+                "panicked"
+            }
+        });
+        described.inner().abort();
+        let recovered = handle
+            .spawn(async { panic!("boom") })
+            .unwrap_or_else(|error| if error.is_panic() { -1 } else { 0 });
+        assert_eq!(executor.metrics().tasks_spawned, 3);
+        let outputs = executor.block_on_all([async move {
+            (doubled.await.unwrap(), described.await, recovered.await)
+        }]);
+        assert_eq!(outputs, [(42, Err("cancelled"), -1)]);
+        // The adapters ran on the awaiting task: one more, and no others.
+        assert_eq!(executor.metrics().tasks_spawned, 4);
+    }
+
+    #[test]
+    fn timed_reports_how_long_the_task_ran() {
+        let executor = Executor::new();
+        let timed = executor
+            .spawn(async {
+                TimerFuture::new(Duration::from_millis(50)).await;
+                "slept"
+            })
+            .timed();
+        assert!(!timed.inner().is_finished());
+        let outputs = executor.block_on_all([timed]);
+        let (output, ran) = outputs.into_iter().next().unwrap().unwrap();
+        assert_eq!(output, "slept");
+        assert!(ran >= Duration::from_millis(50), "{ran:?}");
+        assert!(ran < Duration::from_millis(150), "{ran:?}");
+    }
+
+    #[test]
+    fn children_are_aborted_once_their_parent_returns() {
+        let executor = Executor::new();
+        let tracker = DropTracker::new();
+        let guards: Vec<_> = (0..3)
+            .map(|i| tracker.guard(format!("child {i}")))
+            .collect();
+        let parent = executor.handle().spawn_with_context(|context| async move {
+            let children: Vec<_> = guards
+                .into_iter()
+                .map(|guard| {
+                    context.spawn_child(async move {
+                        let _guard = guard;
+                        crate::future::pending::<()>().await
+                    })
+                })
+                .collect();
+            // Back before any child got anywhere.
+            children
+        });
+        let outputs = executor.block_on_all([parent]);
+        let children = outputs.into_iter().next().unwrap().unwrap();
+        for i in 0..3 {
+            assert!(tracker.is_dropped(&format!("child {i}")));
+        }
+        let outputs = executor.block_on_all(children);
+        assert!(outputs
+            .iter()
+            .all(|output| output.as_ref().unwrap_err().is_cancelled()));
+    }
+
+    #[test]
+    fn a_child_finished_first_keeps_its_output() {
+        let executor = Executor::new();
+        let (finished, aborted) = executor
+            .block_on_all([executor.handle().spawn_with_context(|context| async move {
+                let finished = context.spawn_child(async { 5 });
+                // Joined by polling, leaving the handle to be awaited later.
+                while !finished.is_finished() {
+                    TimerFuture::new(Duration::from_millis(1)).await;
+                }
+                let aborted = context.spawn_child(crate::future::pending::<()>());
+                (finished, aborted)
+            })])
+            .pop()
+            .unwrap()
+            .unwrap();
+        let outputs = executor.block_on_all([async move {
+            (
+                finished.await.unwrap(),
+                aborted.await.unwrap_err().is_cancelled(),
+            )
+        }]);
+        assert_eq!(outputs, [(5, true)]);
+    }
+
+    #[test]
+    fn completed_task_drops_its_future_at_once_and_its_output_when_taken() {
+        let executor = Executor::new();
+        let tracker = DropTracker::new();
+        let (future_guard, output_guard) = (tracker.guard("future"), tracker.guard("output"));
+        let handle = executor.spawn(async move {
+            let _future = future_guard;
+            output_guard
+        });
+        let runner = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    executor.run();
+                    thread::current().id()
+                })
+                .join()
+                .unwrap()
+        });
+        assert_eq!(tracker.dropped("future").unwrap().thread, runner);
+        assert!(!tracker.is_dropped("output"));
+        drop(handle.join_blocking().unwrap());
+        assert_eq!(
+            tracker.dropped("output").unwrap().thread,
+            thread::current().id()
+        );
+    }
+
+    #[test]
+    fn detached_task_drops_its_output_at_once() {
+        let executor = Executor::new();
+        let tracker = DropTracker::new();
+        let output = tracker.guard("output");
+        drop(executor.spawn(async move { output }));
+        executor.run();
+        assert!(tracker.is_dropped("output"));
+    }
+
+    #[test]
+    fn aborted_task_drops_its_future_on_the_executor_thread() {
+        let executor = Executor::new();
+        let tracker = DropTracker::new();
+        let guard = tracker.guard("sleeping");
+        let sleeping = executor.spawn(async move {
+            let _guard = guard;
+            TimerFuture::new(Duration::from_secs(3600)).await
+        });
+        let aborted = {
+            let tracker = tracker.clone();
+            async move {
+                TimerFuture::new(Duration::from_millis(10)).await;
+                assert!(!tracker.is_dropped("sleeping"));
+                sleeping.abort();
+                assert!(sleeping.await.unwrap_err().is_cancelled());
+                tracker.is_dropped("sleeping")
+            }
+        };
+        assert_eq!(executor.block_on_all([aborted]), [true]);
+        let dropped = tracker.dropped("sleeping").unwrap();
+        assert_eq!(dropped.thread, thread::current().id());
+    }
+
+    #[test]
+    fn futures_alive_at_shutdown_are_dropped_by_the_dropping_thread() {
+        let executor = Executor::new();
+        let tracker = DropTracker::new();
+        let guard = tracker.guard("pending");
+        let pending = executor.spawn(async move {
+            let _guard = guard;
+            crate::future::pending::<()>().await
+        });
+        assert!(!tracker.is_dropped("pending"));
+        let dropper = thread::spawn(move || {
+            drop(executor);
+            thread::current().id()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(tracker.dropped("pending").unwrap().thread, dropper);
+        assert!(pending.join_blocking().unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn lost_wakes_are_reported_once_and_kept_wakers_not_at_all() {
+        handlers::tests::install();
+        let executor = Builder::new().detect_lost_wakes(true).build();
+        let handle = executor.handle();
+        let done = Arc::new(AtomicBool::new(false));
+        let dark = {
+            let done = done.clone();
+            handle.spawn_named(
+                "dark",
+                std::future::poll_fn(move |_| match done.load(Ordering::SeqCst) {
+                    true => Poll::Ready(()),
+                    // Neither woken nor kept: only the test can wake it.
+                    false => Poll::Pending,
+                }),
+            )
+        };
+        let kept = Arc::new(Mutex::new(None::<Waker>));
+        let keeping = {
+            let (done, kept) = (done.clone(), kept.clone());
+            handle.spawn_named(
+                "keeping",
+                std::future::poll_fn(move |cx| {
+                    if done.load(Ordering::SeqCst) {
+                        return Poll::Ready(());
+                    }
+                    // Stored at the first poll only, as later ones would wake
+                    // the same task.
+                    let mut kept = kept.lock().unwrap();
+                    if !kept.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                        *kept = Some(cx.waker().clone());
+                    }
+                    Poll::Pending
+                }),
+            )
+        };
+        let sleeping = handle.spawn_named("sleeping", TimerFuture::new(Duration::from_millis(10)));
+        let wake_dark = Waker::from(dark.task.clone());
+        executor.block_on_all([async move {
+            for _ in 0..3 {
+                TimerFuture::new(Duration::from_millis(5)).await;
+                wake_dark.wake_by_ref();
+                kept.lock().unwrap().as_ref().unwrap().wake_by_ref();
+            }
+            done.store(true, Ordering::SeqCst);
+            wake_dark.wake_by_ref();
+            kept.lock().unwrap().as_ref().unwrap().wake_by_ref();
+            (dark.await, keeping.await, sleeping.await)
+        }]);
+        let seen = handlers::tests::seen(file!());
+        let names = ["dark: ", "keeping: ", "sleeping: "];
+        let reported: Vec<_> = seen
+            .iter()
+            .filter(|seen| names.iter().any(|name| seen.starts_with(name)))
+            .collect();
+        assert_eq!(reported.len(), 1, "{reported:?}");
+        assert!(reported[0].starts_with("dark: error: returned Pending without waking"));
+    }
+
+    /// Poison `mutex`, as a panic while holding it would.
+    fn poison<T>(mutex: &Mutex<T>) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = mutex.lock();
+            panic!("poisoning");
+        }));
+        assert!(mutex.is_poisoned());
+    }
+
+    #[test]
+    fn poisoned_internal_locks_do_not_stop_the_executor() {
+        handlers::tests::install();
+        let executor = Executor::new();
+        let sleeper = executor.spawn(async {
+            TimerFuture::new(Duration::from_millis(5)).await;
+            "slept"
+        });
+        poison(&sleeper.task.future);
+        poison(&sleeper.task.join);
+        poison(&executor.inner.tasks);
+        poison(&executor.inner.polling);
+        let mut outputs = executor.block_on_all([sleeper]);
+        assert_eq!(outputs.pop().unwrap().unwrap(), "slept");
+        assert_eq!(executor.block_on_all([async { 7 }]), [7]);
+    }
+
+    #[test]
+    fn a_future_panicking_on_drop_is_reported_and_the_rest_runs() {
+        struct PanicOnDrop;
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                panic!("dropping");
+            }
+        }
+        handlers::tests::install();
+        let executor = Executor::new();
+        let exploding = executor.handle().spawn_named("exploding", async {
+            let _bomb = PanicOnDrop;
+            crate::future::pending::<()>().await
+        });
+        let later = executor.spawn(async {
+            TimerFuture::new(Duration::from_millis(20)).await;
+            "still running"
+        });
+        let outputs = executor.block_on_all([async move {
+            // Polled first, for the guard to be in the future at all.
+            TimerFuture::new(Duration::from_millis(1)).await;
+            exploding.abort();
+            assert!(exploding.await.unwrap_err().is_cancelled());
+            later.await.unwrap()
+        }]);
+        assert_eq!(outputs, ["still running"]);
+        let seen = handlers::tests::seen(file!());
+        assert!(
+            seen.contains(&"exploding: panic: dropping".to_string()),
+            "{seen:?}"
+        );
+    }
+
+    #[test]
+    fn a_thread_joins_a_task_waiting_on_a_timer() {
+        let executor = Executor::new();
+        let handle = executor.spawn(async {
+            TimerFuture::new(Duration::from_millis(50)).await;
+            "woke"
+        });
+        let started = Instant::now();
+        let joiner = thread::spawn(move || handle.join_blocking());
+        executor.run();
+        assert_eq!(joiner.join().unwrap().unwrap(), "woke");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn join_blocking_timeout_gives_up_and_leaves_the_handle_usable() {
+        let executor = Executor::new();
+        let event = Arc::new(Event::new());
+        let waited = event.clone();
+        let mut handle = executor.spawn(async move { waited.wait().await });
+        let started = Instant::now();
+        assert!(handle
+            .join_blocking_timeout(Duration::from_millis(30))
+            .is_none());
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        event.set();
+        executor.run();
+        assert!(handle.join_blocking_timeout(Duration::MAX).unwrap().is_ok());
+    }
+
+    #[test]
+    fn join_blocking_from_a_task_panics() {
+        let executor = Executor::new();
+        let awaited = executor.spawn(async {});
+        let joining = executor.spawn(async move { awaited.join_blocking() });
+        executor.run();
+        let error = joining.join_blocking().unwrap_err();
+        let payload = error.into_panic();
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or(payload.downcast_ref::<String>().map(String::as_str));
+        assert!(
+            message.unwrap().contains("called from a task"),
+            "{message:?}"
+        );
+    }
+
+    /// Wakes itself `wakes` times in its last poll, then completes; panics
+    /// if polled again after that.
+    struct WakeThenComplete {
+        wakes: usize,
+        polls: Arc<AtomicUsize>,
+        done: bool,
+    }
+    impl Future for WakeThenComplete {
+        type Output = ();
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            assert!(!self.done, "polled after completion");
+            self.polls.fetch_add(1, Ordering::SeqCst);
+            for _ in 0..self.wakes {
+                cx.waker().wake_by_ref();
+            }
+            self.done = true;
+            Poll::Ready(())
+        }
+    }
+
+    #[test]
+    fn waking_itself_then_completing_polls_once() {
+        let tracker = DropTracker::new();
+        let executor = Executor::new();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let guard = tracker.guard("future");
+        let handle = executor.spawn({
+            let polls = polls.clone();
+            async move {
+                let _guard = guard;
+                WakeThenComplete {
+                    wakes: 100,
+                    polls,
+                    done: false,
+                }
+                .await
+            }
+        });
+        executor.run();
+        assert!(handle.join_blocking().is_ok());
+        assert_eq!(polls.load(Ordering::SeqCst), 1);
+        assert_eq!(executor.metrics().polls_total, 1);
+        assert!(tracker.is_dropped("future"));
+        assert_eq!(executor.metrics().tasks_alive, 0);
+    }
+
+    #[test]
+    fn completing_while_another_thread_wakes_it_polls_no_more() {
+        let executor = Executor::new();
+        let (wakers_tx, wakers_rx) = std::sync::mpsc::channel::<Waker>();
+        let stop = Arc::new(AtomicBool::new(false));
+        let waking = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let waker = wakers_rx.recv().unwrap();
+                while !stop.load(Ordering::SeqCst) {
+                    waker.wake_by_ref();
+                }
+                // The last reference to the task, once it completed.
+                waker
+            })
+        };
+        let polls = Arc::new(AtomicUsize::new(0));
+        let handle = executor.spawn({
+            let polls = polls.clone();
+            let mut wakers_tx = Some(wakers_tx);
+            poll_fn(move |cx| {
+                let poll = polls.fetch_add(1, Ordering::SeqCst) + 1;
+                if let Some(wakers_tx) = wakers_tx.take() {
+                    wakers_tx.send(cx.waker().clone()).unwrap();
+                }
+                if poll < 50 {
+                    return Poll::Pending;
+                }
+                Poll::Ready(poll)
+            })
+        });
+        executor.run();
+        assert_eq!(handle.join_blocking().unwrap(), 50);
+        // Wakes after completion leave nothing to poll.
+        thread::sleep(Duration::from_millis(10));
+        let tracked = Arc::new(AtomicUsize::new(0));
+        drop(executor.spawn({
+            let tracked = tracked.clone();
+            async move {
+                tracked.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
+        executor.run();
+        stop.store(true, Ordering::SeqCst);
+        drop(waking.join().unwrap());
+        assert_eq!(polls.load(Ordering::SeqCst), 50);
+        assert_eq!(tracked.load(Ordering::SeqCst), 1);
+        assert_eq!(executor.metrics().tasks_alive, 0);
+    }
+
+    #[test]
+    fn the_wake_log_names_the_timer_then_the_channel() {
+        let executor = Builder::new().record_wakes(true).build();
+        let (_, mut reports) = executor.handle().spawn_with_progress(|sender| async move {
+            TimerFuture::new(Duration::from_millis(30)).await;
+            sender.send(()).await.unwrap();
+        });
+        let watcher = executor.spawn(async move {
+            let mut timer = TimerFuture::new(Duration::from_millis(5));
+            let (mut fired, mut won) = (false, Vec::new());
+            while won.len() < 2 {
+                crate::select! {
+                    _ = &mut timer, if !fired => {
+                        fired = true;
+                        won.push("timer");
+                    }
+                    report = reports.next() => {
+                        report.unwrap();
+                        won.push("report");
+                    }
+                }
+            }
+            won
+        });
+        executor.run();
+        let sources: Vec<_> = watcher
+            .recent_wakes()
+            .iter()
+            .map(|wake| wake.source)
+            .collect();
+        assert_eq!(sources, ["timer", "ProgressSender::send"]);
+        assert_eq!(watcher.join_blocking().unwrap(), ["timer", "report"]);
+    }
+
+    #[test]
+    fn consumers_of_a_shared_output_borrow_one_buffer_freed_with_the_last() {
+        const LEN: usize = 10 << 20;
+        let executor = Builder::new().track_task_memory(true).build();
+        let shared = executor
+            .handle()
+            .spawn_shared(async { std::hint::black_box(vec![1u8; LEN]) });
+        let consumers: Vec<_> = (0..3)
+            .map(|_| {
+                let shared = shared.clone();
+                // The last of them to finish frees the buffer, in its poll.
+                executor.spawn(async move {
+                    let data = shared.ready().await.unwrap();
+                    assert_eq!(data.len(), LEN);
+                    data.as_ptr() as usize
+                })
+            })
+            .collect();
+        drop(shared);
+        executor.run();
+
+        let freed: u64 = consumers.iter().map(|c| c.stats().unwrap().freed).sum();
+        assert!(freed >= LEN as u64);
+        let pointers: Vec<_> = consumers
+            .into_iter()
+            .map(|consumer| consumer.join_blocking().unwrap())
+            .collect();
+        assert!(pointers.iter().all(|&pointer| pointer == pointers[0]));
+    }
+
+    #[test]
+    fn aborting_a_shared_task_resolves_ready_to_cancelled() {
+        let executor = Executor::new();
+        let shared = executor
+            .handle()
+            .spawn_shared(async { TimerFuture::new(Duration::from_secs(60)).await });
+        shared.abort();
+        let waiter = executor.spawn({
+            let shared = shared.clone();
+            async move { shared.ready().await.unwrap_err().is_cancelled() }
+        });
+        executor.run();
+        assert!(waiter.join_blocking().unwrap());
+        assert!(shared.is_finished());
+        assert!(shared.get().is_none());
+    }
+
+    /// A cleanup logging `label` once it went through an await.
+    fn logged_cleanup(
+        log: &Arc<Mutex<Vec<&'static str>>>,
+        label: &'static str,
+    ) -> impl FnOnce() -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let log = log.clone();
+        move || {
+            Box::pin(async move {
+                let mut yielded = false;
+                poll_fn(|cx| {
+                    if mem::replace(&mut yielded, true) {
+                        return Poll::Ready(());
+                    }
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                })
+                .await;
+                lock(&log).push(label);
+            })
+        }
+    }
+
+    #[test]
+    fn defers_of_a_task_aborted_mid_sleep_run_latest_first() {
+        let executor = Executor::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sleeper = executor.spawn({
+            let log = log.clone();
+            async move {
+                defer(logged_cleanup(&log, "first"));
+                defer(logged_cleanup(&log, "second"));
+                TimerFuture::new(Duration::from_secs(60)).await;
+            }
+        });
+        let aborter = executor.spawn(async move {
+            TimerFuture::new(Duration::from_millis(5)).await;
+            sleeper.abort();
+            sleeper.await.unwrap_err().is_cancelled()
+        });
+        executor.run();
+        assert!(aborter.join_blocking().unwrap());
+        assert_eq!(*lock(&log), ["second", "first"]);
+    }
+
+    #[test]
+    fn defers_of_a_task_aborted_before_its_first_poll_run() {
+        let executor = Executor::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let polled = Arc::new(AtomicBool::new(false));
+        let task = executor.spawn({
+            let polled = polled.clone();
+            async move { polled.store(true, Ordering::SeqCst) }
+        });
+        task.defer(logged_cleanup(&log, "cleanup"));
+        task.abort();
+        executor.run();
+        assert!(!polled.load(Ordering::SeqCst));
+        assert_eq!(*lock(&log), ["cleanup"]);
+    }
+
+    #[test]
+    fn shutdown_runs_the_defers_of_live_tasks() {
+        let executor = Executor::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for label in ["a", "b"] {
+            let log = log.clone();
+            let meta = TaskMeta::new(None, Priority::Normal, Location::caller()).background();
+            // Not waited for by `run`, which returns once both are asleep.
+            drop(executor.inner.spawn(meta, async move {
+                defer(logged_cleanup(&log, label));
+                TimerFuture::new(Duration::from_secs(60)).await;
+            }));
+        }
+        executor.run();
+        assert!(lock(&log).is_empty());
+        drop(executor.shutdown());
+        let mut log = lock(&log).clone();
+        log.sort_unstable();
+        assert_eq!(log, ["a", "b"]);
+    }
+}
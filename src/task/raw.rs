@@ -0,0 +1,294 @@
+use std::{
+    any::Any,
+    fmt::{self, Debug},
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex, TryLockError,
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
+use super::{Outcome, TaskFuture};
+
+type ScheduleFn = dyn Fn(RawTask) + Send + Sync;
+
+/// A bare task, for frameworks with a scheduler of their own, such as an
+/// actor layer routing wakes to its mailboxes: no `JoinHandle`, no output,
+/// and no executor. Futures are stored and polled as the executor's own
+/// tasks are, with panics caught.
+///
+/// Waking the task hands a `RawTask` to the schedule function, at most once
+/// until it runs again; whoever gets it is to call `run` on it, from any
+/// thread. A wake while it runs schedules it again once `run` returns. The
+/// allocation is freed once the last `RawTask` and waker of the task are
+/// gone, which also drops the future if it did not complete.
+#[derive(Clone)]
+pub struct RawTask {
+    inner: Arc<RawInner>,
+}
+
+struct RawInner {
+    future: Mutex<Option<TaskFuture>>,
+    schedule: Box<ScheduleFn>,
+    /// One of the states below.
+    state: AtomicU8,
+}
+
+/// Waiting for a wake.
+const IDLE: u8 = 0;
+/// Handed to the schedule function, and not run since.
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+/// Woken while running, to be scheduled again once the poll returns.
+const NOTIFIED: u8 = 3;
+/// The future completed or panicked; wakes do nothing.
+const DONE: u8 = 4;
+
+/// What `RawTask::run` made of the task.
+pub enum RawPoll {
+    /// The future is waiting, and will be scheduled when woken.
+    Pending,
+    /// The future completed, now or before.
+    Completed,
+    /// The future panicked, with this payload.
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl RawTask {
+    /// Wrap `future` into a task which `schedule` is given whenever it is
+    /// woken. Nothing runs before the first `schedule` or wake.
+    pub fn new<F, S>(future: F, schedule: S) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+        S: Fn(RawTask) + Send + Sync + 'static,
+    {
+        RawTask {
+            inner: Arc::new(RawInner {
+                future: Mutex::new(Some(TaskFuture::new(RawHarness { future }))),
+                schedule: Box::new(schedule),
+                state: AtomicU8::new(IDLE),
+            }),
+        }
+    }
+    /// Hand the task to its schedule function, as waking it would.
+    pub fn schedule(&self) {
+        self.inner.schedule();
+    }
+    pub fn waker(&self) -> Waker {
+        Waker::from(self.inner.clone())
+    }
+    pub fn is_done(&self) -> bool {
+        self.inner.state.load(Ordering::Acquire) == DONE
+    }
+    /// Poll the task's future once.
+    ///
+    /// Must not be called again before it returned, on this thread or any
+    /// other: debug builds panic if it is, release builds return `Pending`
+    /// without polling.
+    pub fn run(self) -> RawPoll {
+        let mut future_slot = match self.inner.future.try_lock() {
+            Ok(future_slot) => future_slot,
+            Err(TryLockError::WouldBlock) => {
+                debug_assert!(false, "`RawTask::run` called while it was running");
+                return RawPoll::Pending;
+            }
+            Err(TryLockError::Poisoned(error)) => error.into_inner(),
+        };
+        let Some(future) = future_slot.as_mut() else {
+            return RawPoll::Completed;
+        };
+        self.inner.state.store(RUNNING, Ordering::Release);
+        let waker = self.waker();
+        // SAFETY: the future is never moved out of its slot, only polled and
+        // dropped there, and the slot stays put behind its `Arc`.
+        let future = unsafe { Pin::new_unchecked(future) };
+        let outcome = match future.poll(&mut Context::from_waker(&waker)) {
+            Poll::Pending => None,
+            Poll::Ready(outcome) => Some(outcome),
+        };
+        let Some(outcome) = outcome else {
+            drop(future_slot);
+            let idle = self.inner.state.compare_exchange(
+                RUNNING,
+                IDLE,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            if idle.is_err() {
+                // Woken meanwhile.
+                self.inner.state.store(SCHEDULED, Ordering::Release);
+                let inner = self.inner.clone();
+                (inner.schedule)(self);
+            }
+            return RawPoll::Pending;
+        };
+        self.inner.state.store(DONE, Ordering::Release);
+        let drop_in_place = AssertUnwindSafe(|| *future_slot = None);
+        let dropped = panic::catch_unwind(drop_in_place);
+        match (outcome, dropped) {
+            (Outcome::Panicked(Some(payload)), _) | (_, Err(payload)) => RawPoll::Panicked(payload),
+            _ => RawPoll::Completed,
+        }
+    }
+}
+impl Debug for RawTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawTask")
+            .field("done", &self.is_done())
+            .finish()
+    }
+}
+impl Debug for RawPoll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawPoll::Pending => write!(f, "RawPoll::Pending"),
+            RawPoll::Completed => write!(f, "RawPoll::Completed"),
+            RawPoll::Panicked(_) => write!(f, "RawPoll::Panicked(..)"),
+        }
+    }
+}
+
+impl RawInner {
+    fn schedule(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self
+                .state
+                .compare_exchange(state, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+        if state == IDLE {
+            (self.schedule)(RawTask {
+                inner: self.clone(),
+            });
+        }
+    }
+}
+impl Wake for RawInner {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.schedule();
+    }
+}
+
+/// Polls a `RawTask`'s future, catching its panic.
+struct RawHarness<F> {
+    future: F,
+}
+impl<F: Future<Output = ()>> Future for RawHarness<F> {
+    type Output = Outcome;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Outcome> {
+        // SAFETY: `future` is structurally pinned, it is never moved out.
+        let future = unsafe { self.map_unchecked_mut(|this| &mut this.future) };
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(())) => Poll::Ready(Outcome::Completed),
+            Err(payload) => Poll::Ready(Outcome::Panicked(Some(payload))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::{lock, test_util::DropTracker, TimerFuture};
+
+    /// A tiny scheduler: a queue of tasks, drained in a loop.
+    #[derive(Clone, Default)]
+    struct Queue(Arc<Mutex<VecDeque<RawTask>>>);
+    impl Queue {
+        fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) -> RawTask {
+            let queue = self.clone();
+            let task = RawTask::new(future, move |task| lock(&queue.0).push_back(task));
+            task.schedule();
+            task
+        }
+        /// Run tasks until every one of `tasks` is done.
+        fn drain(&self, tasks: &[RawTask]) -> Vec<RawPoll> {
+            let mut polls = Vec::new();
+            while !tasks.iter().all(RawTask::is_done) {
+                let next = lock(&self.0).pop_front();
+                match next {
+                    Some(task) => polls.push(task.run()),
+                    None => thread::sleep(Duration::from_millis(1)),
+                }
+            }
+            polls
+        }
+    }
+
+    #[test]
+    fn a_queue_of_raw_tasks_runs_the_timer_demo() {
+        let queue = Queue::default();
+        let done = Arc::new(Mutex::new(Vec::new()));
+        let tasks: Vec<_> = [30, 10, 20]
+            .into_iter()
+            .map(|millis| {
+                let done = done.clone();
+                queue.spawn(async move {
+                    TimerFuture::new(Duration::from_millis(millis)).await;
+                    lock(&done).push(millis);
+                })
+            })
+            .collect();
+        let started = Instant::now();
+        let polls = queue.drain(&tasks);
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert_eq!(*lock(&done), [10, 20, 30]);
+        // Each polled once waiting, once woken by its timer.
+        assert_eq!(polls.len(), 6);
+        let completed = polls
+            .iter()
+            .filter(|poll| matches!(poll, RawPoll::Completed))
+            .count();
+        assert_eq!(completed, 3);
+    }
+
+    #[test]
+    fn a_panic_is_caught_and_wakes_after_completion_do_nothing() {
+        let queue = Queue::default();
+        let task = queue.spawn(async { panic!("raw") });
+        let waker = task.waker();
+        let polls = queue.drain(&[task]);
+        assert!(matches!(&polls[..], [RawPoll::Panicked(_)]));
+        waker.wake_by_ref();
+        assert!(lock(&queue.0).is_empty());
+    }
+
+    #[test]
+    fn the_future_is_dropped_with_the_last_reference() {
+        let tracker = DropTracker::new();
+        let guard = tracker.guard("future");
+        let task = RawTask::new(
+            async move {
+                let _guard = guard;
+                crate::future::pending::<()>().await
+            },
+            |_| {},
+        );
+        let waker = task.waker();
+        drop(task);
+        assert!(!tracker.is_dropped("future"));
+        drop(waker);
+        assert!(tracker.is_dropped("future"));
+    }
+}
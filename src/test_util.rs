@@ -0,0 +1,408 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    fmt::{self, Debug},
+    future::{poll_fn, Future},
+    mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, Once,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, ThreadId},
+    time::Instant,
+};
+
+use crate::{alloc_hooks, lock};
+#[cfg(test)]
+use crate::{
+    scheduler::{FifoScheduler, Scheduler},
+    task::Task,
+};
+
+/* Wakers */
+/// Waker which does nothing when woken.
+pub fn noop_waker() -> Waker {
+    Waker::noop().clone()
+}
+
+/// Poll `future` exactly once with the calling task's context, and return
+/// what it returned rather than waiting for it.
+pub async fn poll_once<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+    poll_fn(|cx| Poll::Ready(Pin::new(&mut *future).poll(cx))).await
+}
+
+/* MockTask */
+/// Stand-in for a task, polling futures with a waker which records whether
+/// it was woken.
+///
+/// Wakes are told apart by when they happened: from within the poll itself
+/// (a future yielding on purpose), or after it returned (the event the
+/// future was waiting for, possibly from another thread).
+#[derive(Debug, Default)]
+pub struct MockTask {
+    tracker: Arc<WakeTracker>,
+}
+
+#[derive(Debug, Default)]
+struct WakeTracker {
+    /// Set for the duration of `MockTask::poll`.
+    polling: AtomicBool,
+    woken_during_poll: AtomicBool,
+    woken_after_poll: AtomicBool,
+    wakes: AtomicUsize,
+}
+impl Wake for WakeTracker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::SeqCst);
+        if self.polling.load(Ordering::SeqCst) {
+            self.woken_during_poll.store(true, Ordering::SeqCst);
+        } else {
+            self.woken_after_poll.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl MockTask {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Poll `future` once, forgetting the wakes seen by earlier polls.
+    pub fn poll<F: Future + ?Sized>(&mut self, future: Pin<&mut F>) -> Poll<F::Output> {
+        let tracker = &self.tracker;
+        tracker.woken_during_poll.store(false, Ordering::SeqCst);
+        tracker.woken_after_poll.store(false, Ordering::SeqCst);
+        tracker.polling.store(true, Ordering::SeqCst);
+        let waker = self.waker();
+        let poll = future.poll(&mut Context::from_waker(&waker));
+        tracker.polling.store(false, Ordering::SeqCst);
+        poll
+    }
+    /// A waker for this task, as handed to the futures it polls.
+    pub fn waker(&self) -> Waker {
+        Waker::from(self.tracker.clone())
+    }
+    /// Whether the task was woken at all since its last poll began.
+    pub fn is_woken(&self) -> bool {
+        self.woken_during_poll() || self.woken_after_poll()
+    }
+    /// Whether its last poll woke the task before returning.
+    pub fn woken_during_poll(&self) -> bool {
+        self.tracker.woken_during_poll.load(Ordering::SeqCst)
+    }
+    /// Whether the task was woken since its last poll returned.
+    pub fn woken_after_poll(&self) -> bool {
+        self.tracker.woken_after_poll.load(Ordering::SeqCst)
+    }
+    /// Total number of wakes, over every poll.
+    pub fn wake_count(&self) -> usize {
+        self.tracker.wakes.load(Ordering::SeqCst)
+    }
+}
+
+/* DropTracker */
+/// Log of `DropGuard` drops, to check when and on which thread the futures
+/// and outputs holding the guards were dropped. Clones share the log.
+#[derive(Clone, Debug, Default)]
+pub struct DropTracker {
+    log: Arc<Mutex<Vec<DropRecord>>>,
+}
+
+/// A guard's drop, as logged by its `DropTracker`.
+#[derive(Clone, Debug)]
+pub struct DropRecord {
+    pub label: String,
+    pub thread: ThreadId,
+    pub at: Instant,
+}
+
+impl DropTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A guard logging its drop under `label`, to move into a future or an
+    /// output.
+    pub fn guard(&self, label: impl Into<String>) -> DropGuard {
+        DropGuard {
+            label: label.into(),
+            log: self.log.clone(),
+        }
+    }
+    /// Every drop so far, in the order they happened.
+    pub fn records(&self) -> Vec<DropRecord> {
+        lock(&self.log).clone()
+    }
+    /// The drop of the guard labelled `label`, if it was dropped yet.
+    pub fn dropped(&self, label: &str) -> Option<DropRecord> {
+        let log = lock(&self.log);
+        log.iter().find(|record| record.label == label).cloned()
+    }
+    pub fn is_dropped(&self, label: &str) -> bool {
+        self.dropped(label).is_some()
+    }
+}
+
+/// Guard handed out by `DropTracker::guard`.
+pub struct DropGuard {
+    label: String,
+    log: Arc<Mutex<Vec<DropRecord>>>,
+}
+impl DropGuard {
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        let record = DropRecord {
+            label: mem::take(&mut self.label),
+            thread: thread::current().id(),
+            at: Instant::now(),
+        };
+        lock(&self.log).push(record);
+    }
+}
+impl Debug for DropGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DropGuard").field(&self.label).finish()
+    }
+}
+
+/* CountingAllocator */
+/// Global allocator reporting every allocation and deallocation to
+/// `alloc_hooks::record`, for `Builder::track_task_memory`, and otherwise
+/// leaving the work to the allocator it wraps, `System` by default:
+///
+/// ```text
+/// #[global_allocator]
+/// static ALLOCATOR: CountingAllocator = CountingAllocator::new();
+/// ```
+#[derive(Debug, Default)]
+pub struct CountingAllocator<A = System> {
+    inner: A,
+}
+impl CountingAllocator {
+    pub const fn new() -> Self {
+        CountingAllocator { inner: System }
+    }
+}
+impl<A> CountingAllocator<A> {
+    pub const fn wrap(inner: A) -> Self {
+        CountingAllocator { inner }
+    }
+}
+// SAFETY: every call is forwarded as is to `inner`, only reported on the
+// side, and `alloc_hooks::record` never allocates.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            alloc_hooks::record(layout.size() as isize);
+        }
+        ptr
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            alloc_hooks::record(layout.size() as isize);
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        alloc_hooks::record(-(layout.size() as isize));
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            alloc_hooks::record(-(layout.size() as isize));
+            alloc_hooks::record(new_size as isize);
+        }
+        new_ptr
+    }
+}
+
+thread_local! {
+    /// Allocations by this thread, counted by the hook of `allocations_during`.
+    static THREAD_ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Allocations made by this thread so far, once `allocations_during` was
+/// first called.
+pub(crate) fn thread_allocations() -> u64 {
+    THREAD_ALLOCATIONS.with(Cell::get)
+}
+
+/// Count the allocations of each thread into `thread_allocations` from now
+/// on, replacing any hook installed with `alloc_hooks::set`.
+pub(crate) fn count_thread_allocations() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        alloc_hooks::set(|_, delta| {
+            if delta > 0 {
+                // Gone while the thread exits.
+                let _ = THREAD_ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            }
+        });
+    });
+}
+
+/// Run `f`, counting the allocations this thread made meanwhile, with
+/// `CountingAllocator` as the global allocator.
+///
+/// Other threads, such as parallel tests, are left out. Replaces any hook
+/// installed with `alloc_hooks::set`.
+pub fn allocations_during<R>(f: impl FnOnce() -> R) -> (R, u64) {
+    count_thread_allocations();
+    let before = thread_allocations();
+    let output = f();
+    (output, thread_allocations() - before)
+}
+
+/* CountingScheduler */
+/// The FIFO scheduler, counting the calls made to queue tasks.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct CountingScheduler {
+    inner: FifoScheduler,
+    pub(crate) schedules: Arc<AtomicUsize>,
+}
+#[cfg(test)]
+impl Scheduler for CountingScheduler {
+    fn schedule(&self, task: Arc<Task>) {
+        self.schedules.fetch_add(1, Ordering::SeqCst);
+        self.inner.schedule(task);
+    }
+    fn schedule_all(&self, tasks: Vec<Arc<Task>>) {
+        self.schedules.fetch_add(1, Ordering::SeqCst);
+        self.inner.schedule_all(tasks);
+    }
+    fn next(&self) -> Option<Arc<Task>> {
+        self.inner.next()
+    }
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+/* Assertions */
+/// Assert that a `Poll` is `Pending`.
+#[macro_export]
+macro_rules! assert_pending {
+    ($poll:expr) => {
+        match $poll {
+            ::std::task::Poll::Pending => {}
+            ::std::task::Poll::Ready(value) => {
+                panic!("expected Pending, got Ready({:?})", value)
+            }
+        }
+    };
+}
+
+/// Assert that a `Poll` is `Ready`, evaluating to the value inside.
+#[macro_export]
+macro_rules! assert_ready {
+    ($poll:expr) => {
+        match $poll {
+            ::std::task::Poll::Ready(value) => value,
+            ::std::task::Poll::Pending => panic!("expected Ready, got Pending"),
+        }
+    };
+}
+
+/// Assert that a `Poll` is `Ready` with a value equal to `$expected`.
+#[macro_export]
+macro_rules! assert_ready_eq {
+    ($poll:expr, $expected:expr) => {
+        assert_eq!($crate::assert_ready!($poll), $expected)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::pin, time::Duration};
+
+    use super::*;
+    use crate::{sync::Event, Executor, TimerFuture};
+
+    #[test]
+    fn mock_task_tells_wakes_during_and_after_a_poll_apart() {
+        let mut task = MockTask::new();
+        let mut yielded = false;
+        let mut yield_once = pin!(poll_fn(|cx| {
+            if std::mem::replace(&mut yielded, true) {
+                return Poll::Ready(());
+            }
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }));
+        assert_pending!(task.poll(yield_once.as_mut()));
+        assert!(task.woken_during_poll() && !task.woken_after_poll());
+        assert_ready!(task.poll(yield_once.as_mut()));
+        assert!(!task.is_woken());
+
+        let event = Event::new();
+        let mut wait = pin!(event.wait());
+        assert_pending!(task.poll(wait.as_mut()));
+        thread::scope(|scope| {
+            scope.spawn(|| event.set());
+        });
+        assert!(!task.woken_during_poll() && task.woken_after_poll());
+        assert_ready!(task.poll(wait.as_mut()));
+        assert_eq!(task.wake_count(), 2);
+    }
+
+    #[test]
+    fn timer_wakes_its_task_from_the_timer_thread() {
+        let mut task = MockTask::new();
+        let mut timer = pin!(TimerFuture::new(Duration::from_millis(5)));
+        assert_pending!(task.poll(timer.as_mut()));
+        assert!(!task.is_woken());
+        let started = Instant::now();
+        while !task.woken_after_poll() {
+            assert!(started.elapsed() < Duration::from_secs(5), "never woken");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_ready!(task.poll(timer.as_mut()));
+    }
+
+    #[test]
+    fn poll_once_hands_back_pending() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            let event = Event::new();
+            let mut wait = Box::pin(event.wait());
+            let before = poll_once(&mut wait).await.is_pending();
+            event.set();
+            let after = poll_once(&mut wait).await.is_ready();
+            (before, after)
+        }]);
+        assert_eq!(outputs, [(true, true)]);
+    }
+
+    #[test]
+    fn noop_waker_does_nothing() {
+        let waker = noop_waker();
+        waker.wake_by_ref();
+        waker.wake();
+    }
+
+    #[test]
+    fn drop_tracker_logs_drops_in_order() {
+        let tracker = DropTracker::new();
+        let (first, second) = (tracker.guard("first"), tracker.guard("second"));
+        assert!(!tracker.is_dropped("first"));
+        drop(second);
+        drop(first);
+        let labels: Vec<_> = tracker.records().into_iter().map(|r| r.label).collect();
+        assert_eq!(labels, ["second", "first"]);
+        assert_eq!(
+            tracker.dropped("first").unwrap().thread,
+            thread::current().id()
+        );
+    }
+}
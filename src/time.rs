@@ -0,0 +1,1371 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fmt::{self, Debug, Display},
+    future::{poll_fn, Future},
+    mem,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard, Once, OnceLock, PoisonError},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    counters::clone_waker,
+    lock,
+    stream::Stream,
+    task::{self, tag_wakes},
+    Handle,
+};
+
+/* Sleep */
+/// Completes once a duration elapsed.
+///
+/// Each executor has a timer thread of its own, firing the timers first
+/// polled while it runs. Timers first polled outside of any executor, by
+/// another one or a hand-rolled loop, go to a thread shared by the process
+/// instead, so they need no runtime.
+///
+/// Once the executor whose thread a timer went to shuts down, the timer
+/// never completes: the thread stops, forgetting the wakers it held.
+///
+/// Deadlines are on the clock of that same executor, see [`now`], which
+/// tests may pause.
+pub struct TimerFuture {
+    shared_state: Arc<Mutex<SharedState>>,
+    /// The driver the timer is registered with, that of its first poll once
+    /// armed.
+    driver: Arc<TimerDriver>,
+    /// Key in the timer registry while the timer is pending.
+    entry: Option<TimerEntry>,
+    /// Whether the timer thread fires it yet. Not until the first poll, so
+    /// timers created ahead of time cost nothing until awaited.
+    armed: bool,
+}
+
+/// Shared state between the future and the timer thread
+struct SharedState {
+    /// Whether or not the sleep time has elapsed
+    completed: bool,
+
+    /// The waker for the task that `TimerFuture` is running on.
+    /// The thread can use this after setting `completed = true` to tell
+    /// `TimerFuture`'s task to wake up, see that `completed = true`, and
+    /// move forward.
+    waker: Option<Waker>,
+}
+
+/// Timeouts beyond this (about 30 years) are treated as never elapsing.
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+/// `instant + duration`, or a deadline past `FAR_FUTURE` from `instant`,
+/// which never comes, if that overflows: e.g. for `Duration::MAX`.
+pub(crate) fn saturating_add(instant: Instant, duration: Duration) -> Instant {
+    instant
+        .checked_add(duration)
+        .unwrap_or_else(|| instant + FAR_FUTURE * 2)
+}
+
+impl TimerFuture {
+    /// Create a new `TimerFuture` which will complete after the provided
+    /// timeout.
+    ///
+    /// The deadline is set right away, but the timer is only handed to the
+    /// timer thread on the first poll, so timers can be created in bulk
+    /// ahead of time, with or without an executor running.
+    ///
+    /// A timeout beyond about 30 years, `Duration::MAX` included, never
+    /// completes: the future then only keeps its waker, and the timer
+    /// thread never hears of it.
+    pub fn new(duration: Duration) -> Self {
+        let driver = TimerDriver::current();
+        let (shared_state, entry) = Self::start(&driver, driver.now().checked_add(duration));
+        TimerFuture {
+            shared_state,
+            driver,
+            entry,
+            armed: false,
+        }
+    }
+    /// Re-arm the timer to complete at `deadline` instead, whether or not it
+    /// already completed, so a loop can keep reusing one timer. The timer is
+    /// `Unpin`, so this works while it is pinned too.
+    ///
+    /// A deadline already past makes the next poll ready.
+    pub fn reset(&mut self, deadline: Instant) {
+        if let Some(entry) = self.entry.take() {
+            self.driver.lock().cancel(entry);
+        }
+        // Cancelling took the old state off the timer thread, if armed.
+        let waker = lock(&self.shared_state).waker.take();
+        let (shared_state, entry) = Self::start(&self.driver, Some(deadline));
+        let waiting = {
+            let mut state = lock(&shared_state);
+            match waker {
+                // Already due: make sure whoever waits on it polls again,
+                // once the lock is released.
+                Some(waker) if state.completed => {
+                    drop(state);
+                    tag_wakes("timer", || waker.wake());
+                    false
+                }
+                waker => {
+                    let waiting = waker.is_some();
+                    state.waker = waker;
+                    waiting
+                }
+            }
+        };
+        self.shared_state = shared_state;
+        self.entry = entry;
+        self.armed = false;
+        // Someone waits on it already, and may not poll again before it fires.
+        if waiting {
+            self.arm();
+        }
+    }
+    /// Register a timer for `deadline`, or one that never fires for `None`.
+    fn start(
+        driver: &TimerDriver,
+        deadline: Option<Instant>,
+    ) -> (Arc<Mutex<SharedState>>, Option<TimerEntry>) {
+        let now = driver.now();
+        let due = deadline.is_some_and(|deadline| deadline <= now);
+        let shared_state = Arc::new(Mutex::new(SharedState {
+            completed: due,
+            waker: None,
+        }));
+        let Some(deadline) = deadline.filter(|&deadline| !due && deadline - now <= FAR_FUTURE)
+        else {
+            return (shared_state, None);
+        };
+        let entry = driver.lock().register(deadline);
+        (shared_state, Some(entry))
+    }
+    /// Hand the timer to the timer thread of the executor polling it, or
+    /// fire it right away if it is already due. Returns whether it completed.
+    fn arm(&mut self) -> bool {
+        self.armed = true;
+        let Some(mut entry) = self.entry else {
+            return false;
+        };
+        let driver = TimerDriver::current();
+        if !Arc::ptr_eq(&driver, &self.driver) {
+            self.driver.lock().cancel(entry);
+            entry = driver.lock().register(entry.0);
+            self.entry = Some(entry);
+            self.driver = driver;
+        }
+        let deadline = entry.0;
+        let now = self.driver.now();
+        if deadline <= now {
+            self.driver.lock().fire(entry, now - deadline);
+            let mut shared_state = lock(&self.shared_state);
+            shared_state.completed = true;
+            shared_state.waker = None;
+            return true;
+        }
+
+        self.driver.start_thread();
+        let wake = {
+            let mut timers = self.driver.lock();
+            // Shut down since it was picked: left pending for good.
+            if timers.shut_down {
+                return false;
+            }
+            timers.armed.insert(entry, self.shared_state.clone());
+            // Only if due before it wakes up anyway, once. On a paused
+            // clock, only `advance` makes timers due.
+            let wake = timers.paused.is_none()
+                && match timers.thread {
+                    ThreadState::Awake => false,
+                    ThreadState::Sleeping(until) => until.is_none_or(|until| deadline < until),
+                };
+            if wake {
+                timers.thread = ThreadState::Awake;
+                timers.wakeups += 1;
+            }
+            wake
+        };
+        if wake {
+            self.driver.wakeup.notify_one();
+        }
+        false
+    }
+}
+impl Drop for TimerFuture {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry {
+            self.driver.lock().cancel(entry);
+        }
+    }
+}
+impl Future for TimerFuture {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        {
+            // Look at the shared state to see if the timer has already completed.
+            let mut shared_state = lock(&this.shared_state);
+            if shared_state.completed {
+                return Poll::Ready(());
+            }
+            // Set waker so that the thread can wake up the current task
+            // when the timer has completed, ensuring that the future is polled
+            // again and sees that `completed = true`.
+            //
+            // It's tempting to do this once rather than repeatedly cloning
+            // the waker each time. However, the `TimerFuture` can move between
+            // tasks on the executor, which could cause a stale waker pointing
+            // to the wrong task, preventing `TimerFuture` from waking up
+            // correctly.
+            //
+            // N.B. it's possible to check for this using the `Waker::will_wake`
+            // function, but we omit that here to keep things simple.
+            shared_state.waker = Some(clone_waker(cx.waker()));
+        }
+        if !this.armed && this.arm() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+/* Interval */
+/// Ticks every `period`, the first tick being immediate. Created by
+/// [`interval`].
+///
+/// Ticks are scheduled relative to the previous tick's deadline rather than
+/// to when it was awaited, so a late consumer gets the missed ticks back to
+/// back, and timers waking up late do not make the schedule drift.
+pub struct Interval {
+    period: Duration,
+    /// Deadline of the next tick, which `timer` is armed for.
+    next: Instant,
+    timer: TimerFuture,
+}
+
+/// Create a stream of ticks `period` apart, starting right away.
+///
+/// Panics if `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval {
+        period,
+        next: now(),
+        timer: TimerFuture::new(Duration::ZERO),
+    }
+}
+impl Interval {
+    /// Wait for the next tick, returning its deadline.
+    pub async fn tick(&mut self) -> Instant {
+        poll_fn(|cx| self.poll_tick(cx)).await
+    }
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.timer).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let tick = self.next;
+        self.next = saturating_add(tick, self.period);
+        self.timer.reset(self.next);
+        Poll::Ready(tick)
+    }
+    /// Restart the schedule so the next tick is a full period from now.
+    pub fn reset(&mut self) {
+        self.reset_at(saturating_add(now(), self.period));
+    }
+    /// Restart the schedule so the next tick is at `deadline`, and the ones
+    /// after it a period apart.
+    pub fn reset_at(&mut self, deadline: Instant) {
+        self.next = deadline;
+        self.timer.reset(deadline);
+    }
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+impl Stream for Interval {
+    type Item = Instant;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
+impl Debug for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interval")
+            .field("period", &self.period)
+            .field("next", &self.next)
+            .finish()
+    }
+}
+
+/* DelayQueue */
+/// Collection of values which each become available once their own delay
+/// has passed, such as connections to expire or jobs to run later.
+///
+/// Only the earliest deadline has a timer armed at any time.
+pub struct DelayQueue<T> {
+    values: HashMap<Key, (T, Instant)>,
+    deadlines: BTreeSet<(Instant, Key)>,
+    next_key: u64,
+    /// Timer for the earliest deadline, as of the last poll.
+    timer: Option<(Instant, TimerFuture)>,
+}
+
+/// Identifies a value inserted into a `DelayQueue`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Key(u64);
+
+/// A value whose delay has passed, taken out of its `DelayQueue`.
+#[derive(Debug)]
+pub struct Expired<T> {
+    value: T,
+    key: Key,
+    deadline: Instant,
+}
+impl<T> Expired<T> {
+    pub fn key(&self) -> Key {
+        self.key
+    }
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> DelayQueue<T> {
+    pub fn new() -> Self {
+        DelayQueue {
+            values: HashMap::new(),
+            deadlines: BTreeSet::new(),
+            next_key: 0,
+            timer: None,
+        }
+    }
+    /// Insert `value`, to come out of the queue after `timeout`.
+    pub fn insert(&mut self, value: T, timeout: Duration) -> Key {
+        self.insert_at(value, saturating_add(now(), timeout))
+    }
+    /// Insert `value`, to come out of the queue at `deadline`.
+    pub fn insert_at(&mut self, value: T, deadline: Instant) -> Key {
+        let key = Key(self.next_key);
+        self.next_key += 1;
+        self.values.insert(key, (value, deadline));
+        self.deadlines.insert((deadline, key));
+        key
+    }
+    /// Take a value out before it expired; `None` if it is not in the queue
+    /// (any more).
+    pub fn remove(&mut self, key: &Key) -> Option<T> {
+        let (value, deadline) = self.values.remove(key)?;
+        self.deadlines.remove(&(deadline, *key));
+        Some(value)
+    }
+    /// Push a value's deadline back, or forward, to `timeout` from now.
+    /// Returns false if it is not in the queue (any more).
+    pub fn reset(&mut self, key: &Key, timeout: Duration) -> bool {
+        self.reset_at(key, saturating_add(now(), timeout))
+    }
+    /// Move a value's deadline to `deadline`. Returns false if it is not in
+    /// the queue (any more).
+    pub fn reset_at(&mut self, key: &Key, deadline: Instant) -> bool {
+        let Some((_, old)) = self.values.get_mut(key) else {
+            return false;
+        };
+        self.deadlines.remove(&(*old, *key));
+        *old = deadline;
+        self.deadlines.insert((deadline, *key));
+        true
+    }
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    /// Poll for the value with the earliest deadline, once that deadline has
+    /// passed.
+    ///
+    /// `Ready(None)` means the queue is empty. It does not stay that way:
+    /// values inserted afterwards come out of later polls as usual.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<Expired<T>>> {
+        loop {
+            let Some(&(deadline, key)) = self.deadlines.first() else {
+                self.timer = None;
+                return Poll::Ready(None);
+            };
+            let now = now();
+            if deadline <= now {
+                self.deadlines.pop_first();
+                let (value, _) = self.values.remove(&key).unwrap();
+                return Poll::Ready(Some(Expired {
+                    value,
+                    key,
+                    deadline,
+                }));
+            }
+            // Re-arm whenever the earliest deadline changed since the last
+            // poll, through inserts, removes or resets.
+            if !matches!(&self.timer, Some((armed, _)) if *armed == deadline) {
+                self.timer = Some((deadline, TimerFuture::new(deadline - now)));
+            }
+            let (_, timer) = self.timer.as_mut().unwrap();
+            if Pin::new(timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.timer = None;
+        }
+    }
+    /// Wait for the next value to expire; `None` right away if the queue is
+    /// empty.
+    ///
+    /// Cancel safe: a value is only removed once this completes.
+    pub async fn next_expired(&mut self) -> Option<Expired<T>> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+}
+impl<T> Default for DelayQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+// Values are never pinned, so moving the queue around is always fine.
+impl<T> Unpin for DelayQueue<T> {}
+impl<T> Stream for DelayQueue<T> {
+    type Item = Expired<T>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+impl<T: Debug> Debug for DelayQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DelayQueue")
+            .field("values", &self.values)
+            .finish_non_exhaustive()
+    }
+}
+
+/* Clock */
+/// The time on the clock of the executor running the current task, or
+/// outside of one, the process's: that of `Instant::now`, unless paused.
+/// Timers, intervals and delay queues all go by it.
+pub fn now() -> Instant {
+    TimerDriver::current().now()
+}
+
+/// Stop the clock of the executor running the current task, or outside of
+/// one the process's, for tests: time then only moves by `advance`, and by
+/// the executor jumping to the next timer once it has nothing else to do,
+/// so sleeps complete at once and in deadline order. Other executors keep
+/// their own clocks.
+///
+/// A clock which is paused already stays as it is. See also
+/// `Builder::start_paused`.
+#[cfg(any(test, feature = "test-util"))]
+pub fn pause() {
+    TimerDriver::current().pause();
+}
+
+/// Start a paused clock again, from the real time: timers armed for later
+/// than that fire late by as much as the clock was advanced.
+#[cfg(any(test, feature = "test-util"))]
+pub fn resume() {
+    TimerDriver::current().resume();
+}
+
+/// Move a paused clock `duration` forward, firing the timers due by then
+/// before returning.
+///
+/// Panics if the clock is not paused.
+#[cfg(any(test, feature = "test-util"))]
+pub fn advance(duration: Duration) {
+    TimerDriver::current().advance(duration);
+}
+
+/* Stats */
+/// Snapshot of the pending timers of one timer thread, see [`driver_stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerStats {
+    /// Timers which have neither fired nor been dropped.
+    pub pending: usize,
+    /// Deadline of the earliest pending timer.
+    pub next_deadline: Option<Instant>,
+    /// Timers which fired since the thread started.
+    pub fired_total: u64,
+    /// How late past their deadlines those timers fired, added up. Sleeping
+    /// threads wake up late by up to a scheduler time slice.
+    pub oversleep_total: Duration,
+    /// The latest any of those timers fired.
+    pub oversleep_max: Duration,
+    /// Times the thread was woken for a newly armed timer due before the
+    /// deadline it was sleeping until.
+    pub wakeups: u64,
+}
+
+/// What the timers of the executor running the current task are up to, or
+/// outside of one, those shared by the process; handy to tell whether
+/// timers are what keeps an executor from returning.
+///
+/// Timers that never fire (see `TimerFuture::new`) are not counted.
+pub fn driver_stats() -> TimerStats {
+    TimerDriver::current().stats()
+}
+
+/// Deadline of a pending timer, with a sequence number to tell apart timers
+/// sharing a deadline.
+type TimerEntry = (Instant, u64);
+
+/// Book-keeping behind `driver_stats`, and the timers the timer thread is to
+/// fire.
+struct TimerRegistry {
+    pending: BTreeSet<TimerEntry>,
+    /// Polled timers, by deadline, with the state to complete.
+    armed: BTreeMap<TimerEntry, Arc<Mutex<SharedState>>>,
+    next_seq: u64,
+    fired_total: u64,
+    oversleep_total: Duration,
+    oversleep_max: Duration,
+    /// Set by `TimerDriver::shut_down`, stopping the thread.
+    shut_down: bool,
+    /// The time of the clock while paused, see `pause`.
+    paused: Option<Instant>,
+    thread: ThreadState,
+    wakeups: u64,
+}
+
+/// What the timer thread is up to, for `TimerFuture::arm` to wake it only
+/// for a timer due before it would wake anyway.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ThreadState {
+    /// Not started yet, or going through the armed timers: it sees those
+    /// armed meanwhile before it sleeps again.
+    Awake,
+    /// Waiting until the deadline, or until woken if none.
+    Sleeping(Option<Instant>),
+}
+impl TimerRegistry {
+    fn register(&mut self, deadline: Instant) -> TimerEntry {
+        self.next_seq += 1;
+        let entry = (deadline, self.next_seq);
+        self.pending.insert(entry);
+        entry
+    }
+    fn fire(&mut self, entry: TimerEntry, oversleep: Duration) {
+        // A timer dropped before its deadline was cancelled, not fired.
+        if self.pending.remove(&entry) {
+            self.fired_total += 1;
+            self.oversleep_total += oversleep;
+            self.oversleep_max = self.oversleep_max.max(oversleep);
+        }
+    }
+    fn cancel(&mut self, entry: TimerEntry) {
+        self.pending.remove(&entry);
+        self.armed.remove(&entry);
+    }
+    fn now(&self) -> Instant {
+        self.paused.unwrap_or_else(Instant::now)
+    }
+    /// Take the armed timers due by `now` out, counting them as fired, for
+    /// `wake_due`.
+    fn take_due(&mut self, now: Instant) -> Vec<Arc<Mutex<SharedState>>> {
+        let mut due = Vec::new();
+        while let Some(first) = self.armed.first_entry() {
+            // Never before the deadline, should the wait end early.
+            if first.key().0 > now {
+                break;
+            }
+            let (entry, shared_state) = first.remove_entry();
+            self.fire(entry, now - entry.0);
+            due.push(shared_state);
+        }
+        due
+    }
+}
+
+/// Complete the timers `TimerRegistry::take_due` took out, and wake their
+/// tasks, with none of the driver's locks held so that a panicking waker
+/// cannot poison them, nor stop the others from being woken.
+fn wake_due(due: Vec<Arc<Mutex<SharedState>>>) {
+    let wakers: Vec<_> = due
+        .iter()
+        .filter_map(|shared_state| {
+            let mut shared_state = lock(shared_state);
+            shared_state.completed = true;
+            shared_state.waker.take()
+        })
+        .collect();
+    // The tasks of an executor are queued all at once.
+    task::batch_wakes(|| {
+        for waker in wakers {
+            let wake = || tag_wakes("timer", || waker.wake());
+            let _ = panic::catch_unwind(AssertUnwindSafe(wake));
+        }
+    });
+}
+
+/// The timers of one executor, or those first polled outside of any, with
+/// the thread firing them.
+pub(crate) struct TimerDriver {
+    timers: Mutex<TimerRegistry>,
+    /// Woken when a timer is armed ahead of those the thread waits for, and
+    /// on shutdown.
+    wakeup: Condvar,
+    started: Once,
+}
+impl TimerDriver {
+    pub(crate) fn new() -> Self {
+        TimerDriver {
+            timers: Mutex::new(TimerRegistry {
+                pending: BTreeSet::new(),
+                armed: BTreeMap::new(),
+                next_seq: 0,
+                fired_total: 0,
+                oversleep_total: Duration::ZERO,
+                oversleep_max: Duration::ZERO,
+                shut_down: false,
+                paused: None,
+                thread: ThreadState::Awake,
+                wakeups: 0,
+            }),
+            wakeup: Condvar::new(),
+            started: Once::new(),
+        }
+    }
+    /// The driver of the executor running the current task, or outside of
+    /// one or once it shut down, the process's.
+    fn current() -> Arc<TimerDriver> {
+        static PROCESS: OnceLock<Arc<TimerDriver>> = OnceLock::new();
+        Handle::try_current()
+            .map(|handle| handle.inner.timers.clone())
+            .filter(|driver| !driver.lock().shut_down)
+            .unwrap_or_else(|| PROCESS.get_or_init(|| Arc::new(TimerDriver::new())).clone())
+    }
+    fn lock(&self) -> MutexGuard<'_, TimerRegistry> {
+        lock(&self.timers)
+    }
+    pub(crate) fn now(&self) -> Instant {
+        self.lock().now()
+    }
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn pause(&self) {
+        let mut timers = self.lock();
+        if timers.paused.is_none() {
+            timers.paused = Some(Instant::now());
+        }
+    }
+    #[cfg(any(test, feature = "test-util"))]
+    fn resume(&self) {
+        {
+            let mut timers = self.lock();
+            timers.paused = None;
+            timers.thread = ThreadState::Awake;
+        }
+        // Asleep for good while paused.
+        self.wakeup.notify_one();
+    }
+    #[cfg(any(test, feature = "test-util"))]
+    fn advance(&self, duration: Duration) {
+        let due = {
+            let mut timers = self.lock();
+            let now = timers
+                .paused
+                .as_mut()
+                .expect("advancing a clock which is not paused");
+            *now = saturating_add(*now, duration);
+            let now = *now;
+            timers.take_due(now)
+        };
+        wake_due(due);
+    }
+    /// Move a paused clock forward to the earliest armed deadline, firing the
+    /// timers due then, for an executor with nothing else to do. Returns
+    /// whether any fired.
+    pub(crate) fn advance_to_next(&self) -> bool {
+        let due = {
+            let mut timers = self.lock();
+            let Some(paused) = timers.paused else {
+                return false;
+            };
+            let Some((first, _)) = timers.armed.first_key_value() else {
+                return false;
+            };
+            let now = paused.max(first.0);
+            timers.paused = Some(now);
+            timers.take_due(now)
+        };
+        let fired = !due.is_empty();
+        wake_due(due);
+        fired
+    }
+    pub(crate) fn stats(&self) -> TimerStats {
+        let timers = self.lock();
+        TimerStats {
+            pending: timers.pending.len(),
+            next_deadline: timers.pending.first().map(|entry| entry.0),
+            fired_total: timers.fired_total,
+            oversleep_total: timers.oversleep_total,
+            oversleep_max: timers.oversleep_max,
+            wakeups: timers.wakeups,
+        }
+    }
+    /// Start the thread firing the armed timers, if not yet.
+    fn start_thread(self: &Arc<Self>) {
+        self.started.call_once(|| {
+            let driver = self.clone();
+            thread::Builder::new()
+                .name("executor-timer".to_string())
+                .spawn(move || driver.fire_timers())
+                .expect("failed to spawn the timer thread");
+        });
+    }
+    /// Stop the thread, for good: the timers armed by then never complete,
+    /// and their wakers are dropped. Timers first polled afterwards go to the
+    /// process's driver.
+    pub(crate) fn shut_down(&self) {
+        let armed = {
+            let mut timers = self.lock();
+            timers.shut_down = true;
+            mem::take(&mut timers.armed)
+        };
+        self.wakeup.notify_one();
+        // Dropped outside the locks, as in `fire_timers`.
+        for shared_state in armed.into_values() {
+            let waker = lock(&shared_state).waker.take();
+            drop(waker);
+        }
+    }
+    /// Body of the timer thread: sleep until the earliest armed deadline,
+    /// then complete and wake every timer due by then, until shut down.
+    fn fire_timers(&self) {
+        let mut timers = self.lock();
+        loop {
+            if timers.shut_down {
+                return;
+            }
+            let now = timers.now();
+            let due = timers.take_due(now);
+            if !due.is_empty() {
+                drop(timers);
+                wake_due(due);
+                timers = self.lock();
+                continue;
+            }
+            // A paused clock only moves by `advance`, which fires the timers.
+            let until = match timers.paused {
+                Some(_) => None,
+                None => timers.armed.first_key_value().map(|(entry, _)| entry.0),
+            };
+            timers.thread = ThreadState::Sleeping(until);
+            timers = match until {
+                Some(until) => {
+                    self.wakeup
+                        .wait_timeout(timers, until - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .wakeup
+                    .wait(timers)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+            timers.thread = ThreadState::Awake;
+        }
+    }
+}
+
+/* Timeout */
+/// Future for [`timeout`].
+pub struct Timeout<F> {
+    future: F,
+    timer: TimerFuture,
+}
+
+/// Wait for `future`, or fail with `Elapsed` if `duration` passes first.
+///
+/// The timer starts right away rather than on the first poll. A duration
+/// which never elapses (see `TimerFuture::new`) amounts to awaiting
+/// `future` alone.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        timer: TimerFuture::new(duration),
+    }
+}
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is structurally pinned, it is never moved out of
+        // `Timeout`; the timer is `Unpin`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.timer).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed::new())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+impl<F> Debug for Timeout<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timeout").finish_non_exhaustive()
+    }
+}
+
+/* Elapsed */
+/// Error returned when a deadline passes before what was awaited is ready.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed(());
+impl Elapsed {
+    pub(crate) fn new() -> Self {
+        Elapsed(())
+    }
+}
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+impl Error for Elapsed {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    use crate::{
+        assert_pending, assert_ready, assert_ready_eq,
+        future::ready,
+        test_util::{CountingScheduler, MockTask},
+        Builder, Executor,
+    };
+
+    #[test]
+    fn extreme_sleeps_never_fire() {
+        let executor = Builder::new().start_paused(true).build();
+        let handle = executor.handle();
+        let pending = [
+            Duration::MAX,
+            Duration::MAX - Duration::from_nanos(1),
+            FAR_FUTURE + Duration::from_secs(1),
+        ]
+        .map(|duration| handle.spawn(TimerFuture::new(duration)));
+        // Thirty years on, by the executor's clock.
+        executor.run_until_signal(async { TimerFuture::new(FAR_FUTURE).await });
+        // None of them held the driver, and none completed.
+        let stats = executor.snapshot().timers;
+        assert_eq!((stats.pending, stats.fired_total), (0, 1));
+        for handle in pending {
+            assert!(handle.join_blocking().unwrap_err().is_cancelled());
+        }
+    }
+
+    #[test]
+    fn extreme_sleep_only_keeps_a_waker() {
+        let mut task = MockTask::new();
+        let mut timer = TimerFuture::new(Duration::MAX);
+        assert_pending!(task.poll(Pin::new(&mut timer)));
+        assert!(timer.entry.is_none());
+        assert!(!task.is_woken());
+    }
+
+    #[test]
+    fn timeout_of_max_is_the_future_alone() {
+        let mut task = MockTask::new();
+        let mut future = pin!(timeout(Duration::MAX, ready(3)));
+        assert_ready_eq!(task.poll(future.as_mut()), Ok(3));
+
+        let executor = Builder::new().start_paused(true).build();
+        let outputs = executor.block_on_all([async {
+            let started = now();
+            let output = timeout(Duration::MAX, async {
+                TimerFuture::new(Duration::from_secs(3600)).await;
+                "done"
+            });
+            (output.await, now() - started)
+        }]);
+        assert_eq!(outputs, [(Ok("done"), Duration::from_secs(3600))]);
+    }
+
+    #[test]
+    fn driver_stats_track_registered_and_dropped_timers() {
+        let executor = Builder::new().start_paused(true).build();
+        let outputs = executor.block_on_all([async {
+            let now = now();
+            let mut timers = [1, 2, 3].map(|secs| {
+                let mut timer = TimerFuture::new(Duration::from_secs(60));
+                timer.reset(now + Duration::from_secs(secs));
+                Some(timer)
+            });
+            // The earliest goes away at once, not at its deadline.
+            timers[0] = None;
+            (driver_stats(), now)
+        }]);
+        let (stats, now) = outputs[0];
+        assert_eq!(stats.pending, 2);
+        assert_eq!(stats.next_deadline, Some(now + Duration::from_secs(2)));
+        assert_eq!(stats.fired_total, 0);
+        // The executor's own timers, none left once the task was dropped.
+        assert_eq!(executor.snapshot().timers.pending, 0);
+    }
+
+    /// Values taken out of `queue` as they expire, until it is empty.
+    async fn drain(queue: &mut DelayQueue<&'static str>) -> Vec<&'static str> {
+        let mut expired = Vec::new();
+        while let Some(entry) = queue.next_expired().await {
+            expired.push(entry.into_inner());
+        }
+        expired
+    }
+
+    #[test]
+    fn delay_queue_expires_in_deadline_order() {
+        let executor = Builder::new().start_paused(true).build();
+        let outputs = executor.block_on_all([async {
+            let ms = Duration::from_millis;
+            let started = now();
+            let mut queue = DelayQueue::new();
+            let a = queue.insert("a", ms(30));
+            let b = queue.insert("b", ms(10));
+            queue.insert("c", ms(20));
+            let d = queue.insert("d", ms(5));
+            // The earliest goes away, and the next earliest moves last.
+            assert_eq!(queue.remove(&d), Some("d"));
+            assert!(queue.reset(&b, ms(40)));
+            queue.insert("e", ms(1));
+            assert_eq!(queue.remove(&a), Some("a"));
+            assert_eq!(queue.remove(&a), None);
+            assert!(!queue.reset(&a, ms(1)));
+            let mut expired = Vec::new();
+            while let Some(entry) = queue.next_expired().await {
+                expired.push((entry.into_inner(), now() - started));
+            }
+            assert!(queue.is_empty());
+            expired
+        }]);
+        let ms = Duration::from_millis;
+        assert_eq!(outputs, [[("e", ms(1)), ("c", ms(20)), ("b", ms(40))]]);
+    }
+
+    #[test]
+    fn delay_queue_rearms_for_an_earlier_deadline() {
+        let executor = Builder::new().start_paused(true).build();
+        let outputs = executor.block_on_all([async {
+            let started = now();
+            let mut queue = DelayQueue::new();
+            let late = queue.insert("late", Duration::from_secs(60));
+            // Arms the timer for the minute-long deadline.
+            let mut task = MockTask::new();
+            assert_pending!(task.poll(pin!(queue.next_expired())));
+            queue.reset(&late, Duration::from_millis(5));
+            let early = queue.next_expired().await.unwrap();
+            (early.key() == late, early.into_inner(), now() - started)
+        }]);
+        // At the new deadline, not the minute first armed.
+        assert_eq!(outputs, [(true, "late", Duration::from_millis(5))]);
+    }
+
+    #[test]
+    fn empty_delay_queue_returns_none() {
+        let executor = Builder::new().start_paused(true).build();
+        let outputs = executor.block_on_all([async {
+            let mut queue = DelayQueue::<&str>::new();
+            let empty = queue.next_expired().await.is_none();
+            // Usable again afterwards.
+            queue.insert("again", Duration::ZERO);
+            (empty, drain(&mut queue).await)
+        }]);
+        assert_eq!(outputs, [(true, vec!["again"])]);
+    }
+
+    #[test]
+    fn delay_queue_saturates_extreme_timeouts() {
+        let executor = Builder::new().start_paused(true).build();
+        let _enter = executor.enter();
+        let mut task = MockTask::new();
+        let mut queue = DelayQueue::new();
+        let never = queue.insert("never", Duration::MAX);
+        queue.insert("also never", Duration::MAX - Duration::from_nanos(1));
+        assert!(queue.reset(&never, Duration::MAX));
+        assert_pending!(task.poll(pin!(queue.next_expired())));
+        advance(FAR_FUTURE);
+        assert!(!task.is_woken());
+        assert_pending!(task.poll(pin!(queue.next_expired())));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.remove(&never), Some("never"));
+    }
+
+    #[test]
+    fn pinned_timer_fires_again_once_reset() {
+        let executor = Builder::new().start_paused(true).build();
+        let outputs = executor.block_on_all([async {
+            let mut timer = pin!(TimerFuture::new(Duration::from_millis(5)));
+            timer.as_mut().await;
+            let deadline = now() + Duration::from_millis(10);
+            timer.reset(deadline);
+            let mut task = MockTask::new();
+            assert_pending!(task.poll(timer.as_mut()));
+            timer.as_mut().await;
+            let fired_at_deadline = now() == deadline;
+            // A deadline already past is ready right away.
+            timer.reset(now() - Duration::from_millis(1));
+            let past_is_ready = task.poll(timer.as_mut()).is_ready();
+            (fired_at_deadline, past_is_ready)
+        }]);
+        assert_eq!(outputs, [(true, true)]);
+    }
+
+    #[test]
+    fn interval_reset_shifts_later_ticks() {
+        let executor = Builder::new().start_paused(true).build();
+        let outputs = executor.block_on_all([async {
+            let period = Duration::from_millis(10);
+            let mut ticks = interval(period);
+            let first = ticks.tick().await;
+            assert_eq!(ticks.tick().await, first + period);
+            let shifted = now() + Duration::from_millis(25);
+            ticks.reset_at(shifted);
+            let after = [ticks.tick().await, ticks.tick().await];
+            after == [shifted, shifted + period] && now() == shifted + period
+        }]);
+        assert_eq!(outputs, [true]);
+    }
+
+    #[test]
+    fn interval_of_max_ticks_once() {
+        let executor = Builder::new().start_paused(true).build();
+        let _enter = executor.enter();
+        let mut task = MockTask::new();
+        let mut ticks = interval(Duration::MAX);
+        assert!(task.poll(pin!(ticks.tick())).is_ready());
+        assert_pending!(task.poll(pin!(ticks.tick())));
+        advance(FAR_FUTURE);
+        assert!(!task.is_woken());
+        assert_pending!(task.poll(pin!(ticks.tick())));
+        ticks.reset();
+        assert_pending!(task.poll(pin!(ticks.tick())));
+        assert_eq!(ticks.period(), Duration::MAX);
+    }
+
+    #[test]
+    fn sleeps_never_complete_early() {
+        let executor = Executor::new();
+        let sleeps = (1..=50).map(|ms| async move {
+            let duration = Duration::from_millis(ms);
+            let started = Instant::now();
+            TimerFuture::new(duration).await;
+            started.elapsed() >= duration
+        });
+        let on_time = executor.block_on_all(sleeps);
+        assert!(on_time.into_iter().all(|on_time| on_time));
+
+        let stats = executor.snapshot().timers;
+        assert_eq!(stats.fired_total, 50);
+        assert!(stats.oversleep_max <= stats.oversleep_total);
+    }
+
+    #[test]
+    fn interval_ticks_do_not_drift() {
+        let executor = Executor::new();
+        let period = Duration::from_millis(2);
+        let outputs = executor.block_on_all([async move {
+            let mut ticks = interval(period);
+            let first = ticks.tick().await;
+            let mut last = first;
+            for _ in 0..100 {
+                let tick = ticks.tick().await;
+                // Each a period past the previous deadline, never early.
+                assert_eq!(tick - last, period);
+                assert!(Instant::now() >= tick);
+                last = tick;
+            }
+            first.elapsed()
+        }]);
+        // Late wakes are not carried over to the next tick.
+        assert!(outputs[0] < period * 100 + Duration::from_millis(50));
+    }
+
+    #[test]
+    fn timers_created_before_run_fire_in_deadline_order() {
+        // Made outside of any executor, before it even exists.
+        let sleeps: Vec<_> = (0..1000u64)
+            // None due yet by the first polls, which would complete the
+            // late ones in poll order.
+            .map(|i| TimerFuture::new(Duration::from_millis(100 + i * 7919 % 50)))
+            .collect();
+        let mut expected: Vec<_> = sleeps.iter().map(|sleep| sleep.entry.unwrap()).collect();
+        expected.sort();
+
+        let executor = Executor::new();
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        for sleep in sleeps {
+            let fired = fired.clone();
+            let entry = sleep.entry.unwrap();
+            drop(executor.spawn(async move {
+                sleep.await;
+                fired.lock().unwrap().push(entry);
+            }));
+        }
+        executor.run();
+        assert_eq!(*fired.lock().unwrap(), expected);
+        let stats = executor.snapshot().timers;
+        assert_eq!(stats.fired_total, 1000);
+        // None is due before the first one armed, which the thread sleeps
+        // until ever after.
+        assert_eq!(stats.wakeups, 0);
+    }
+
+    #[test]
+    fn only_timers_due_before_the_thread_wakes_wake_it() {
+        let executor = Executor::new();
+        let _enter = executor.enter();
+        let driver = executor.inner.timers.clone();
+        let wait_until_asleep = || {
+            for _ in 0..1000 {
+                if matches!(driver.lock().thread, ThreadState::Sleeping(Some(_))) {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            panic!("the timer thread never slept");
+        };
+        let mut task = MockTask::new();
+        let mut arm = |secs| {
+            let mut sleep = TimerFuture::new(Duration::from_secs(secs));
+            assert_pending!(task.poll(Pin::new(&mut sleep)));
+            sleep
+        };
+
+        let _first = arm(60);
+        wait_until_asleep();
+        let _later: Vec<_> = (61..71).map(&mut arm).collect();
+        assert_eq!(driver.stats().wakeups, 0);
+        let earlier = arm(30);
+        assert_eq!(driver.stats().wakeups, 1);
+        // Still due to wake up for the dropped timer, so earlier than any
+        // armed one is not enough.
+        wait_until_asleep();
+        drop(earlier);
+        let _between = arm(45);
+        assert_eq!(driver.stats().wakeups, 1);
+        let _earliest = arm(15);
+        assert_eq!(driver.stats().wakeups, 2);
+    }
+
+    #[test]
+    fn timers_fire_after_their_registry_was_poisoned() {
+        let executor = Executor::new();
+        let timers = &executor.inner.timers.timers;
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = timers.lock();
+            panic!("poisoning");
+        }));
+        assert!(timers.is_poisoned());
+        let slept = executor.spawn(async {
+            TimerFuture::new(Duration::from_millis(5)).await;
+            "slept"
+        });
+        executor.run();
+        assert_eq!(slept.join_blocking().unwrap(), "slept");
+        assert_eq!(executor.snapshot().timers.fired_total, 1);
+    }
+
+    /// Poll `future` on this thread, parking it between polls.
+    fn park_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(thread::Thread);
+        impl std::task::Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut future = pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn sleeps_complete_under_any_poller() {
+        const SLEEP: Duration = Duration::from_millis(50);
+        let started = Instant::now();
+        Executor::new().block_on_all([TimerFuture::new(SLEEP)]);
+        assert!(started.elapsed() >= SLEEP);
+
+        let started = Instant::now();
+        park_on(TimerFuture::new(SLEEP));
+        assert!(started.elapsed() >= SLEEP);
+
+        let started = Instant::now();
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(TimerFuture::new(SLEEP));
+        assert!(started.elapsed() >= SLEEP);
+    }
+
+    #[test]
+    fn a_sleep_keeps_the_driver_of_its_first_poll() {
+        let mut task = MockTask::new();
+        let mut sleep = TimerFuture::new(Duration::from_millis(20));
+        // Outside of any executor: on the process's driver.
+        assert_pending!(task.poll(Pin::new(&mut sleep)));
+        let executor = Executor::new();
+        executor.block_on_all([sleep]);
+        assert_eq!(executor.snapshot().timers.fired_total, 0);
+    }
+
+    #[test]
+    fn a_paused_and_a_real_clock_run_side_by_side() {
+        const HOUR: Duration = Duration::from_secs(3600);
+        // Run meanwhile, on real time.
+        let real = thread::spawn(|| {
+            let executor = Executor::new();
+            let started = Instant::now();
+            let slept = executor.spawn(async {
+                let before = now();
+                TimerFuture::new(Duration::from_millis(50)).await;
+                now() - before
+            });
+            executor.run();
+            (slept.join_blocking().unwrap(), started.elapsed())
+        });
+        let executor = Builder::new().start_paused(true).build();
+        let started = Instant::now();
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let slept = executor.spawn({
+            let fired = fired.clone();
+            async move {
+                let before = now();
+                let sleeps = [3, 1, 2].map(|hours| {
+                    let fired = fired.clone();
+                    crate::task::spawn(async move {
+                        TimerFuture::new(HOUR * hours).await;
+                        fired.lock().unwrap().push(hours);
+                    })
+                });
+                for sleep in sleeps {
+                    sleep.await.unwrap();
+                }
+                now() - before
+            }
+        });
+        executor.run();
+
+        // At once, in deadline order, each by its own clock.
+        assert!(started.elapsed() < HOUR / 1000);
+        assert_eq!(slept.join_blocking().unwrap(), HOUR * 3);
+        assert_eq!(*fired.lock().unwrap(), [1, 2, 3]);
+        let (slept, elapsed) = real.join().unwrap();
+        assert!(slept >= Duration::from_millis(50));
+        assert!(elapsed >= slept);
+    }
+
+    #[test]
+    fn advancing_a_paused_clock_fires_the_timers_due_by_then() {
+        let executor = Builder::new().start_paused(true).build();
+        let other = Builder::new().start_paused(true).build();
+        let other_now = {
+            let _enter = other.enter();
+            now()
+        };
+        let enter = executor.enter();
+        let mut task = MockTask::new();
+        let mut sleep = TimerFuture::new(Duration::from_secs(10));
+        assert_pending!(task.poll(Pin::new(&mut sleep)));
+
+        advance(Duration::from_secs(9));
+        assert!(!task.is_woken());
+        advance(Duration::from_secs(1));
+        assert!(task.is_woken());
+        assert_ready!(task.poll(Pin::new(&mut sleep)));
+        assert_eq!(driver_stats().fired_total, 1);
+        drop(enter);
+        // Left alone by the other's advances.
+        let _enter = other.enter();
+        assert_eq!(now(), other_now);
+    }
+
+    #[test]
+    fn executors_fire_their_own_timers() {
+        const SLEEP: Duration = Duration::from_millis(50);
+        // Run meanwhile, and past the other's shutdown.
+        let other = thread::spawn(|| {
+            let executor = Executor::new();
+            let started = Instant::now();
+            executor.block_on_all((0..5).map(|_| TimerFuture::new(SLEEP * 2)));
+            (started.elapsed(), executor.snapshot().timers.fired_total)
+        });
+        let executor = Executor::new();
+        executor.block_on_all((0..3).map(|_| TimerFuture::new(SLEEP)));
+        assert_eq!(executor.snapshot().timers.fired_total, 3);
+        drop(executor.shutdown());
+        let (elapsed, fired) = other.join().unwrap();
+        assert!(elapsed >= SLEEP * 2);
+        assert_eq!(fired, 5);
+    }
+
+    #[test]
+    fn timers_due_together_are_queued_at_once() {
+        const TIMERS: usize = 10_000;
+        let scheduler = CountingScheduler::default();
+        let schedules = scheduler.schedules.clone();
+        let executor = Builder::new().scheduler(scheduler).build();
+        let deadline = Instant::now() + Duration::from_millis(200);
+        let woken = Arc::new(AtomicUsize::new(0));
+        for _ in 0..TIMERS {
+            let woken = woken.clone();
+            drop(executor.spawn(async move {
+                let mut sleep = TimerFuture::new(Duration::MAX);
+                sleep.reset(deadline);
+                sleep.await;
+                woken.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        // Polled once every timer is armed.
+        let armed = executor.spawn({
+            let schedules = schedules.clone();
+            async move { schedules.load(Ordering::SeqCst) }
+        });
+        executor.run();
+
+        assert_eq!(woken.load(Ordering::SeqCst), TIMERS);
+        let queued = schedules.load(Ordering::SeqCst) - armed.join_blocking().unwrap();
+        assert!(queued < TIMERS / 100, "{queued} scheduler calls");
+    }
+
+    #[test]
+    fn sleeps_armed_on_a_shut_down_driver_never_complete() {
+        let executor = Executor::new();
+        let mut task = MockTask::new();
+        let mut sleep = TimerFuture::new(Duration::from_millis(5));
+        {
+            let _enter = executor.enter();
+            assert_pending!(task.poll(Pin::new(&mut sleep)));
+        }
+        let driver = Arc::downgrade(&executor.inner.timers);
+        drop(executor);
+        thread::sleep(Duration::from_millis(20));
+        assert!(!task.is_woken());
+        assert_pending!(task.poll(Pin::new(&mut sleep)));
+        // With the sleep gone, only the thread could keep the driver.
+        drop(sleep);
+        let started = Instant::now();
+        while driver.upgrade().is_some() {
+            assert!(started.elapsed() < Duration::from_secs(5), "driver leaked");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug},
+    future::{poll_fn, Future},
+    sync::Mutex,
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::{counters::clone_waker, lock, time, TimerFuture};
+
+/* RateLimiter */
+/// Token bucket capping how often an operation may happen, for instance API
+/// calls per second across the whole program.
+///
+/// Permits refill continuously at the sustained rate, up to the burst
+/// capacity, and waiting tasks are served in the order they arrived.
+pub struct RateLimiter {
+    /// Permits added per second.
+    rate: f64,
+    burst: f64,
+    state: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Permits available as of `refilled`.
+    tokens: f64,
+    refilled: Instant,
+    next_ticket: u64,
+    /// Tasks waiting in `acquire`, by ticket, first in line at the front.
+    queue: VecDeque<(u64, Option<Waker>)>,
+}
+
+impl RateLimiter {
+    /// Allow `rate` permits every `per`, with a burst capacity of `rate`.
+    pub fn new(rate: u32, per: Duration) -> Self {
+        Self::with_burst(rate, per, rate)
+    }
+    /// Allow `rate` permits every `per` on average, and up to `burst` in a
+    /// row after a quiet period. The limiter starts out full.
+    ///
+    /// Panics if `rate`, `per` or `burst` is zero.
+    pub fn with_burst(rate: u32, per: Duration, burst: u32) -> Self {
+        assert!(
+            rate > 0 && !per.is_zero() && burst > 0,
+            "rate limiter must allow some permits"
+        );
+        RateLimiter {
+            rate: f64::from(rate) / per.as_secs_f64(),
+            burst: f64::from(burst),
+            state: Mutex::new(Bucket {
+                tokens: f64::from(burst),
+                refilled: time::now(),
+                next_ticket: 0,
+                queue: VecDeque::new(),
+            }),
+        }
+    }
+    /// Take a permit if one is available and nobody is waiting for one.
+    pub fn try_acquire(&self) -> bool {
+        let mut bucket = lock(&self.state);
+        self.refill(&mut bucket);
+        if !bucket.queue.is_empty() || bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+    /// Wait for a permit, behind every task which started waiting earlier.
+    pub async fn acquire(&self) {
+        let ticket = {
+            let mut bucket = lock(&self.state);
+            let ticket = bucket.next_ticket;
+            bucket.next_ticket += 1;
+            bucket.queue.push_back((ticket, None));
+            ticket
+        };
+        // Gives up the place in line if this future is dropped while waiting.
+        let _in_line = InLine {
+            limiter: self,
+            ticket,
+        };
+        loop {
+            self.first_in_line(ticket).await;
+            let wait = {
+                let mut bucket = lock(&self.state);
+                self.refill(&mut bucket);
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+            };
+            TimerFuture::new(wait).await;
+        }
+    }
+    fn first_in_line(&self, ticket: u64) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| {
+            let mut bucket = lock(&self.state);
+            let Some(position) = bucket.queue.iter().position(|(t, _)| *t == ticket) else {
+                return Poll::Ready(());
+            };
+            if position == 0 {
+                return Poll::Ready(());
+            }
+            bucket.queue[position].1 = Some(clone_waker(cx.waker()));
+            Poll::Pending
+        })
+    }
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = time::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled = now;
+    }
+}
+impl Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("rate", &self.rate)
+            .field("burst", &self.burst)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A task's place in a `RateLimiter`'s line, left once it got its permit or
+/// stopped waiting.
+struct InLine<'a> {
+    limiter: &'a RateLimiter,
+    ticket: u64,
+}
+impl Drop for InLine<'_> {
+    fn drop(&mut self) {
+        let mut bucket = lock(&self.limiter.state);
+        bucket.queue.retain(|(ticket, _)| *ticket != self.ticket);
+        if let Some(waker) = bucket.queue.front_mut().and_then(|(_, w)| w.take()) {
+            drop(bucket);
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::Executor;
+
+    /// What a timer may fire too late by on a loaded machine.
+    const SLACK: Duration = Duration::from_millis(150);
+
+    #[test]
+    fn permits_past_the_burst_come_at_the_rate() {
+        let executor = Executor::new();
+        let outputs = executor.block_on_all([async {
+            // A permit every 20ms, five at once to start with.
+            let limiter = RateLimiter::new(5, Duration::from_millis(100));
+            let start = Instant::now();
+            let mut times = Vec::new();
+            for _ in 0..10 {
+                limiter.acquire().await;
+                times.push(start.elapsed());
+            }
+            times
+        }]);
+        for (i, elapsed) in outputs[0].iter().enumerate() {
+            let expected = Duration::from_millis(20) * (i as u32).saturating_sub(4);
+            // Never early, the refill being computed from elapsed time.
+            assert!(
+                *elapsed + Duration::from_millis(1) >= expected,
+                "permit {i} at {elapsed:?}"
+            );
+            assert!(*elapsed <= expected + SLACK, "permit {i} at {elapsed:?}");
+        }
+    }
+
+    #[test]
+    fn tasks_share_the_rate() {
+        let executor = Executor::new();
+        // 200 permits a second, ten at once.
+        let limiter = Arc::new(RateLimiter::new(10, Duration::from_millis(50)));
+        let acquired = Arc::new(AtomicU32::new(0));
+        let period = Duration::from_millis(300);
+        let end = Instant::now() + period;
+        for _ in 0..4 {
+            let (limiter, acquired) = (limiter.clone(), acquired.clone());
+            drop(executor.spawn(async move {
+                loop {
+                    limiter.acquire().await;
+                    if Instant::now() >= end {
+                        break;
+                    }
+                    acquired.fetch_add(1, Ordering::Relaxed);
+                }
+            }));
+        }
+        executor.run();
+        let acquired = acquired.load(Ordering::Relaxed);
+        // The burst, then the rate over the period.
+        assert!(acquired <= 10 + 60, "{acquired} permits");
+        assert!(acquired >= 10 + 30, "{acquired} permits");
+    }
+
+    #[test]
+    fn waiters_are_served_in_order() {
+        let executor = Executor::new();
+        let limiter = Arc::new(RateLimiter::with_burst(1, Duration::from_millis(10), 1));
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        let served = Arc::new(Mutex::new(Vec::new()));
+        for label in ["first", "second", "third"] {
+            let (limiter, served) = (limiter.clone(), served.clone());
+            drop(executor.spawn(async move {
+                limiter.acquire().await;
+                served.lock().unwrap().push(label);
+            }));
+        }
+        executor.run();
+        assert_eq!(*served.lock().unwrap(), ["first", "second", "third"]);
+    }
+}
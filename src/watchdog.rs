@@ -0,0 +1,148 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{task::TaskId, Inner};
+
+/// What a wedged executor was doing, as handed to the `Executor::watchdog`
+/// callback.
+#[derive(Clone, Debug)]
+pub struct WatchdogReport {
+    /// How long the executor has gone without finishing a single poll.
+    pub stalled_for: Duration,
+    /// Tasks in the middle of a poll, outermost first; more than one means
+    /// the ones after the first are nested by `Handle::block_in_task`.
+    pub polling: Vec<PollingTask>,
+    /// Tasks waiting in the scheduler to be polled.
+    pub queue_depth: usize,
+}
+
+/// A task which was being polled when the watchdog fired.
+#[derive(Clone, Debug)]
+pub struct PollingTask {
+    pub id: TaskId,
+    pub name: Option<String>,
+    /// When its current poll began.
+    pub since: Instant,
+}
+
+/// Keeps an `Executor::watchdog` thread running; dropping it stops the
+/// thread and waits for it to exit.
+#[must_use = "the watchdog stops as soon as it is dropped"]
+pub struct Watchdog {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+impl Watchdog {
+    pub(crate) fn start(
+        executor: Weak<Inner>,
+        interval: Duration,
+        timeout: Duration,
+        mut callback: impl FnMut(&WatchdogReport) + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name("executor-watchdog".to_string())
+            .spawn({
+                let stop = stop.clone();
+                move || {
+                    let mut last_polls = 0;
+                    let mut progress_at = Instant::now();
+                    let mut reported = false;
+                    while !stop.load(Ordering::Acquire) {
+                        thread::park_timeout(interval);
+                        let Some(executor) = executor.upgrade() else {
+                            break;
+                        };
+                        let polls = executor.metrics.polls_total();
+                        let polling = executor.polling_tasks();
+                        let queue_depth = executor.ready_len();
+                        // An executor with nothing to do is idle, not stuck.
+                        if polls != last_polls || (polling.is_empty() && queue_depth == 0) {
+                            last_polls = polls;
+                            progress_at = Instant::now();
+                            reported = false;
+                            continue;
+                        }
+                        let stalled_for = progress_at.elapsed();
+                        if stalled_for >= timeout && !reported {
+                            // Once per stall, not on every tick until it clears.
+                            reported = true;
+                            callback(&WatchdogReport {
+                                stalled_for,
+                                polling,
+                                queue_depth,
+                            });
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn the watchdog thread");
+        Watchdog {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{Executor, TimerFuture};
+
+    fn recording_watchdog(executor: &Executor) -> (Watchdog, Arc<Mutex<Vec<WatchdogReport>>>) {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        let watchdog = executor.watchdog(
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+            move |report| sink.lock().unwrap().push(report.clone()),
+        );
+        (watchdog, reports)
+    }
+
+    #[test]
+    fn watchdog_reports_a_wedged_task() {
+        let executor = Executor::new();
+        let (watchdog, reports) = recording_watchdog(&executor);
+        drop(executor.handle().spawn_named("wedged", async {
+            thread::sleep(Duration::from_millis(400));
+        }));
+        executor.run();
+        drop(watchdog);
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 1, "one report per stall");
+        let report = &reports[0];
+        assert!(report.stalled_for >= Duration::from_millis(100));
+        assert_eq!(report.polling.len(), 1);
+        assert_eq!(report.polling[0].name.as_deref(), Some("wedged"));
+    }
+
+    #[test]
+    fn watchdog_stays_quiet_while_idle() {
+        let executor = Executor::new();
+        let (watchdog, reports) = recording_watchdog(&executor);
+        // Waits on a timer for longer than the timeout, doing nothing.
+        drop(executor.spawn(TimerFuture::new(Duration::from_millis(300))));
+        executor.run();
+        drop(watchdog);
+        assert!(reports.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+//! Runs the examples, which `cargo test` builds alongside the tests, and
+//! checks what they print.
+
+use std::{
+    env,
+    io::Read,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long an example may run before it counts as hung.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Run the example `name`, returning its standard output, or panic if it
+/// fails or runs for longer than `TIMEOUT`.
+fn run_example(name: &str) -> String {
+    // The test binary sits in `target/<profile>/deps`, the examples in
+    // `target/<profile>/examples`.
+    let mut path: PathBuf = env::current_exe().unwrap();
+    path.pop();
+    path.pop();
+    path.push("examples");
+    path.push(format!("{name}{}", env::consts::EXE_SUFFIX));
+    let mut child = Command::new(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|error| panic!("cannot run {}: {error}", path.display()));
+    // Read meanwhile, so that a full pipe cannot stall the example.
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut read = String::new();
+            pipe.read_to_string(&mut read).unwrap();
+            read
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().unwrap()));
+    let stderr = read(Box::new(child.stderr.take().unwrap()));
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if started.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            panic!("{name} did not finish within {TIMEOUT:?}");
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let (stdout, stderr) = (stdout.join().unwrap(), stderr.join().unwrap());
+    assert!(status.success(), "{name} failed with {status}: {stderr}");
+    stdout
+}
+
+#[test]
+fn fan_out() {
+    let output = run_example("fan_out");
+    let mut lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.pop(), Some("crawled 600 and 1500 bytes"));
+    // Both crawls fetch at once, so their pages come in interleaved.
+    assert_eq!(
+        lines,
+        [
+            "fetched page 1",
+            "fetched page 1",
+            "fetched page 2",
+            "fetched page 2",
+            "fetched page 3",
+            "fetched page 3",
+            "fetched page 4",
+            "fetched page 5",
+        ]
+    );
+}
+
+#[test]
+fn framed_echo() {
+    assert_eq!(
+        run_example("framed_echo"),
+        "echoed \"hello\"\nechoed \"world\"\nserver done\n"
+    );
+}
+
+#[test]
+fn timers() {
+    let output = run_example("timers");
+    let lines: Vec<_> = output.lines().collect();
+    // Every task starts at once, and they finish shortest timer first.
+    assert_eq!(
+        lines,
+        [
+            "Hello 10!",
+            "waiting 10",
+            "Hello 5!",
+            "waiting 5",
+            "Hello 2!",
+            "waiting 2",
+            "Hello 1!",
+            "waiting 1",
+            "World 1!",
+            "World 2!",
+            "World 5!",
+            "World 10!",
+        ]
+    );
+}